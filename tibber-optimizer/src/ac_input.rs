@@ -0,0 +1,65 @@
+use tracing::{info, warn};
+
+use crate::config::AcInputLimitConfig;
+use crate::mqtt::MqttClient;
+use crate::optimizer::BatteryMode;
+
+/// Raises the charger's AC input current limit while charging at full power,
+/// so a conservative limit set in the GX doesn't cap the charge power
+#[derive(Debug)]
+pub struct AcInputLimitController {
+    config: AcInputLimitConfig,
+    /// Last commanded state; None until the first decision is published
+    raised: Option<bool>,
+}
+
+impl AcInputLimitController {
+    pub fn new(config: AcInputLimitConfig) -> Self {
+        Self { config, raised: None }
+    }
+
+    pub fn is_raised(&self) -> bool {
+        self.raised == Some(true)
+    }
+
+    /// Returns the current limit to publish when the charge mode changes
+    pub fn update(&mut self, mode: BatteryMode) -> Option<f64> {
+        let raise = mode == BatteryMode::ChargeFull;
+        if self.raised == Some(raise) {
+            return None;
+        }
+
+        self.raised = Some(raise);
+        if raise {
+            info!("Full-power charging, raising AC input current limit to {:.1}A", self.config.charge_current_a);
+            Some(self.config.charge_current_a)
+        } else {
+            info!("Restoring AC input current limit to {:.1}A", self.config.normal_current_a);
+            Some(self.config.normal_current_a)
+        }
+    }
+
+    /// Forget the last published state so it is re-sent on the next update
+    pub fn reset(&mut self) {
+        self.raised = None;
+    }
+
+    /// Restore the normal limit while control is given up, so the charger
+    /// doesn't keep drawing full power. A failed publish is retried on the
+    /// next call; the limit is published again when control returns.
+    pub async fn release(&mut self, mqtt_client: &MqttClient) {
+        if self.raised == Some(true) {
+            match mqtt_client.publish_value(&self.config.topic, self.config.normal_current_a).await {
+                Ok(()) => info!(
+                    "Restoring AC input current limit to {:.1}A: control handed back",
+                    self.config.normal_current_a
+                ),
+                Err(e) => {
+                    warn!("Failed to restore AC input current limit: {}", e);
+                    return;
+                }
+            }
+        }
+        self.raised = None;
+    }
+}
@@ -0,0 +1,61 @@
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
+
+use crate::config::ApplianceProfile;
+use crate::prices::PriceCache;
+
+/// When to run an appliance, from the cheapest contiguous price window
+#[derive(Debug, Clone, Serialize)]
+pub struct ApplianceRecommendation {
+    pub name: String,
+    pub start_at: String,
+    /// Energy cost of a run starting at `start_at` (EUR)
+    pub cost: f64,
+    /// Energy cost of a run starting now, if prices cover it (EUR)
+    pub cost_now: Option<f64>,
+    /// Human-readable summary, e.g. "dishwasher: start at 13:15, cost 0.31 EUR vs 0.58 now"
+    pub message: String,
+}
+
+/// The cheapest start per appliance over the known prices
+pub fn recommend(appliances: &[ApplianceProfile], prices: &PriceCache, now: DateTime<Utc>) -> Vec<ApplianceRecommendation> {
+    appliances
+        .iter()
+        .filter_map(|appliance| {
+            let length = Duration::minutes(appliance.duration_minutes as i64);
+            let run_kwh = appliance.power_w / 1000.0 * appliance.duration_minutes as f64 / 60.0;
+            let deadline = appliance
+                .finish_within_hours
+                .map(|hours| now + Duration::seconds((hours * 3600.0) as i64));
+
+            let (start, _, avg_price) = prices.cheapest_window(now, length, deadline)?;
+            let cost = avg_price * run_kwh;
+            // Starting now means starting in the current slot
+            let cost_now = prices.window_at(now, length).map(|(_, price)| price * run_kwh);
+
+            let local_start = start.starts_at;
+            let start_at = if start.ends_at > now && start.starts_at <= now {
+                "now".to_string()
+            } else if local_start.date_naive() == now.with_timezone(local_start.offset()).date_naive() {
+                format!("at {}", local_start.format("%H:%M"))
+            } else {
+                format!("tomorrow at {}", local_start.format("%H:%M"))
+            };
+            let message = match cost_now {
+                Some(cost_now) => format!(
+                    "{}: start {}, cost {:.2} EUR vs {:.2} now",
+                    appliance.name, start_at, cost, cost_now
+                ),
+                None => format!("{}: start {}, cost {:.2} EUR", appliance.name, start_at, cost),
+            };
+
+            Some(ApplianceRecommendation {
+                name: appliance.name.clone(),
+                start_at: local_start.to_rfc3339(),
+                cost,
+                cost_now,
+                message,
+            })
+        })
+        .collect()
+}
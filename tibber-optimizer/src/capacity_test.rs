@@ -0,0 +1,206 @@
+//! Guided capacity test: fill the battery, discharge it to the minimum SoC and
+//! recharge it at a fixed power, integrating the AC battery power on the way.
+//! The energy delivered over the discharged SoC range gives the usable
+//! capacity, and delivered against recharged energy the round-trip efficiency.
+
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Duration, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::config::CapacityTestConfig;
+use crate::optimizer::{BatteryMode, OptimizationResult};
+use crate::persist;
+use crate::stats::MAX_SAMPLE_GAP_HOURS;
+
+/// SoC range a test must cover for its result to mean anything (percentage points)
+const MIN_SOC_RANGE: f64 = 30.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Phase {
+    /// Charging to the maximum SoC before measuring
+    Filling,
+    /// Discharging to the minimum SoC, counting energy out
+    Discharging,
+    /// Charging back to the maximum SoC, counting energy in
+    Recharging,
+}
+
+/// Measured usable capacity and efficiency, published and persisted
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct CapacityTestReport {
+    pub started_at: String,
+    pub finished_at: String,
+    /// AC energy delivered over the discharge, scaled to the full SoC range (kWh)
+    pub usable_capacity_kwh: f64,
+    pub round_trip_efficiency: f64,
+    pub energy_out_kwh: f64,
+    pub energy_in_kwh: f64,
+    /// SoC range of the discharge and of the recharge (percentage points)
+    pub discharge_soc_range: f64,
+    pub recharge_soc_range: f64,
+}
+
+/// What the control loop does this cycle while a test runs
+pub enum Step {
+    Control(OptimizationResult),
+    Done(CapacityTestReport),
+    Aborted(String),
+}
+
+/// A running capacity test
+#[derive(Debug)]
+pub struct CapacityTest {
+    config: CapacityTestConfig,
+    min_soc: f64,
+    max_soc: f64,
+    started_at: DateTime<Utc>,
+    phase: Phase,
+    /// SoC at the start of the current measuring phase
+    phase_soc: f64,
+    discharge_soc_range: f64,
+    energy_out_kwh: f64,
+    energy_in_kwh: f64,
+    last_sample: Option<(DateTime<Utc>, f64)>,
+}
+
+impl CapacityTest {
+    /// Start a test between `min_soc` and `max_soc`
+    pub fn start(config: CapacityTestConfig, min_soc: f64, max_soc: f64, now: DateTime<Utc>) -> Self {
+        info!(
+            "Starting capacity test at {:.0}W between {:.0}% and {:.0}%",
+            config.power_w, min_soc, max_soc
+        );
+        Self {
+            config,
+            min_soc,
+            max_soc,
+            started_at: now,
+            phase: Phase::Filling,
+            phase_soc: 0.0,
+            discharge_soc_range: 0.0,
+            energy_out_kwh: 0.0,
+            energy_in_kwh: 0.0,
+            last_sample: None,
+        }
+    }
+
+    /// SoC the test discharges down to
+    pub fn min_soc(&self) -> f64 {
+        self.min_soc
+    }
+
+    /// Advance the test with this cycle's SoC, AC battery power (positive =
+    /// charging) and house load
+    pub fn update(&mut self, now: DateTime<Utc>, soc: f64, battery_power_w: Option<f64>, house_load_w: f64) -> Step {
+        if now.signed_duration_since(self.started_at) > Duration::seconds((self.config.max_hours * 3600.0) as i64) {
+            return Step::Aborted(format!("not finished within {:.0}h", self.config.max_hours));
+        }
+        let Some(power_w) = battery_power_w else {
+            return Step::Aborted("no battery power reading".to_string());
+        };
+
+        // Integrate the power since the last sample into the measuring phase
+        if let Some((last_time, last_power_w)) = self.last_sample {
+            let hours = now.signed_duration_since(last_time).num_seconds() as f64 / 3600.0;
+            if hours > 0.0 && hours <= MAX_SAMPLE_GAP_HOURS {
+                let kwh = last_power_w / 1000.0 * hours;
+                match self.phase {
+                    Phase::Discharging => self.energy_out_kwh += (-kwh).max(0.0),
+                    Phase::Recharging => self.energy_in_kwh += kwh.max(0.0),
+                    Phase::Filling => {}
+                }
+            }
+        }
+        self.last_sample = Some((now, power_w));
+
+        match self.phase {
+            Phase::Filling | Phase::Recharging if soc >= self.max_soc => {
+                if self.phase == Phase::Recharging {
+                    return self.finish(now, soc);
+                }
+                info!("Capacity test: full at {:.1}%, discharging", soc);
+                self.phase = Phase::Discharging;
+                self.phase_soc = soc;
+            }
+            Phase::Discharging if soc <= self.min_soc => {
+                info!(
+                    "Capacity test: empty at {:.1}% after {:.2} kWh, recharging",
+                    soc, self.energy_out_kwh
+                );
+                self.discharge_soc_range = self.phase_soc - soc;
+                self.phase = Phase::Recharging;
+                self.phase_soc = soc;
+            }
+            _ => {}
+        }
+
+        let (mode, grid_setpoint_w) = match self.phase {
+            Phase::Filling | Phase::Recharging => (BatteryMode::ChargeReduced, house_load_w + self.config.power_w),
+            Phase::Discharging => (BatteryMode::DischargeToGrid, house_load_w - self.config.power_w),
+        };
+        Step::Control(OptimizationResult {
+            mode,
+            grid_setpoint_w,
+            reason: format!("Capacity test: {}", self.describe(soc)),
+            alternative: None,
+        })
+    }
+
+    fn finish(&self, now: DateTime<Utc>, soc: f64) -> Step {
+        let recharge_soc_range = soc - self.phase_soc;
+        if self.discharge_soc_range < MIN_SOC_RANGE || recharge_soc_range < MIN_SOC_RANGE {
+            return Step::Aborted(format!(
+                "SoC range too small ({:.0}/{:.0} points)",
+                self.discharge_soc_range, recharge_soc_range
+            ));
+        }
+        if self.energy_in_kwh <= 0.0 || self.energy_out_kwh <= 0.0 {
+            return Step::Aborted("no energy measured".to_string());
+        }
+        let out_per_point = self.energy_out_kwh / self.discharge_soc_range;
+        let in_per_point = self.energy_in_kwh / recharge_soc_range;
+        Step::Done(CapacityTestReport {
+            started_at: self.started_at.to_rfc3339(),
+            finished_at: now.to_rfc3339(),
+            usable_capacity_kwh: out_per_point * 100.0,
+            round_trip_efficiency: out_per_point / in_per_point,
+            energy_out_kwh: self.energy_out_kwh,
+            energy_in_kwh: self.energy_in_kwh,
+            discharge_soc_range: self.discharge_soc_range,
+            recharge_soc_range,
+        })
+    }
+
+    /// Phase and progress for the status, e.g. "discharging, 3.2 kWh out, SoC 54%"
+    pub fn describe(&self, soc: f64) -> String {
+        match self.phase {
+            Phase::Filling => format!("filling to {:.0}%, SoC {:.0}%", self.max_soc, soc),
+            Phase::Discharging => format!(
+                "discharging to {:.0}%, {:.2} kWh out, SoC {:.0}%",
+                self.min_soc, self.energy_out_kwh, soc
+            ),
+            Phase::Recharging => format!(
+                "recharging to {:.0}%, {:.2} kWh in, SoC {:.0}%",
+                self.max_soc, self.energy_in_kwh, soc
+            ),
+        }
+    }
+}
+
+fn report_path(data_dir: &str) -> PathBuf {
+    Path::new(data_dir).join("capacity_test.json")
+}
+
+/// The last completed test, if one was saved
+pub fn load_report(data_dir: &str) -> Option<CapacityTestReport> {
+    persist::load_json::<Option<CapacityTestReport>>(&report_path(data_dir))
+}
+
+pub fn save_report(data_dir: &str, report: &CapacityTestReport) {
+    if let Err(e) = persist::save_json(&report_path(data_dir), report) {
+        warn!("Failed to save capacity test result: {}", e);
+    }
+}
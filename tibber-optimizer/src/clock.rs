@@ -0,0 +1,142 @@
+use chrono::{DateTime, TimeZone, Utc};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tracing::warn;
+
+/// Wall-clock times before this are treated as "not yet synced" (GX devices
+/// boot with a 1970 clock until NTP catches up)
+const MIN_PLAUSIBLE_YEAR: i32 = 2024;
+
+/// Maximum allowed difference (seconds) between elapsed wall-clock time and
+/// elapsed monotonic time between two checks before we call it a jump
+const MAX_DRIFT_SECS: i64 = 120;
+
+/// Where the optimizer reads the current time from: the wall clock, or the
+/// recording's time during a replay
+#[derive(Debug, Clone, Default)]
+pub struct Clock {
+    simulated: Option<Arc<Mutex<DateTime<Utc>>>>,
+}
+
+impl Clock {
+    /// A clock that stands at `now` until it is `set`
+    pub fn simulated(now: DateTime<Utc>) -> Self {
+        Self {
+            simulated: Some(Arc::new(Mutex::new(now))),
+        }
+    }
+
+    pub fn now(&self) -> DateTime<Utc> {
+        self.simulated.as_ref().map_or_else(Utc::now, |now| *now.lock().unwrap())
+    }
+
+    /// Move a simulated clock and every clone of it to `now`; the wall clock
+    /// is left alone
+    pub fn set(&self, now: DateTime<Utc>) {
+        if let Some(simulated) = &self.simulated {
+            *simulated.lock().unwrap() = now;
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ClockStatus {
+    /// Wall clock looks sane
+    Ok,
+    /// Wall clock is before any date this software could run at
+    Unsynced(DateTime<Utc>),
+    /// Wall clock jumped relative to the monotonic clock since the last check
+    Jumped { seconds: i64 },
+}
+
+impl ClockStatus {
+    pub fn is_ok(&self) -> bool {
+        *self == ClockStatus::Ok
+    }
+}
+
+impl std::fmt::Display for ClockStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ClockStatus::Ok => write!(f, "ok"),
+            ClockStatus::Unsynced(now) => write!(f, "clock not synced ({})", now.to_rfc3339()),
+            ClockStatus::Jumped { seconds } => write!(f, "clock jumped by {}s", seconds),
+        }
+    }
+}
+
+/// Detects implausible wall-clock times and sudden jumps (e.g. NTP sync after boot)
+/// by comparing wall-clock progress against the monotonic clock
+#[derive(Debug, Default)]
+pub struct ClockMonitor {
+    last: Option<(Instant, DateTime<Utc>)>,
+}
+
+impl ClockMonitor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Check the clock; call once per control cycle
+    pub fn check(&mut self) -> ClockStatus {
+        self.check_at(Instant::now(), Utc::now())
+    }
+
+    fn check_at(&mut self, mono: Instant, wall: DateTime<Utc>) -> ClockStatus {
+        let previous = self.last.replace((mono, wall));
+
+        let min_plausible = Utc.with_ymd_and_hms(MIN_PLAUSIBLE_YEAR, 1, 1, 0, 0, 0).unwrap();
+        if wall < min_plausible {
+            return ClockStatus::Unsynced(wall);
+        }
+
+        if let Some((last_mono, last_wall)) = previous {
+            let mono_elapsed = mono.duration_since(last_mono).as_secs() as i64;
+            let wall_elapsed = wall.signed_duration_since(last_wall).num_seconds();
+            let drift = wall_elapsed - mono_elapsed;
+
+            if drift.abs() > MAX_DRIFT_SECS {
+                warn!(
+                    "System clock jumped by {}s (wall {}s vs monotonic {}s)",
+                    drift, wall_elapsed, mono_elapsed
+                );
+                return ClockStatus::Jumped { seconds: drift };
+            }
+        }
+
+        ClockStatus::Ok
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn reports_unsynced_clocks_and_skew_against_the_monotonic_clock() {
+        let mut monitor = ClockMonitor::new();
+        let start = Instant::now();
+        let wall = Utc.with_ymd_and_hms(2025, 3, 1, 12, 0, 0).unwrap();
+
+        let boot = Utc.with_ymd_and_hms(1970, 1, 1, 0, 0, 5).unwrap();
+        assert_eq!(monitor.check_at(start, boot), ClockStatus::Unsynced(boot));
+        // NTP catching up is a jump as well
+        assert!(matches!(
+            monitor.check_at(start + Duration::from_secs(60), wall),
+            ClockStatus::Jumped { .. }
+        ));
+
+        // A minute of wall time per minute of monotonic time, with drift inside the margin
+        assert!(monitor.check_at(start + Duration::from_secs(120), wall + chrono::Duration::seconds(90)).is_ok());
+        assert_eq!(
+            monitor.check_at(start + Duration::from_secs(180), wall + chrono::Duration::seconds(450)),
+            ClockStatus::Jumped { seconds: 300 }
+        );
+        assert_eq!(
+            monitor.check_at(start + Duration::from_secs(240), wall),
+            ClockStatus::Jumped { seconds: -510 }
+        );
+        assert!(monitor.check_at(start + Duration::from_secs(300), wall + chrono::Duration::seconds(60)).is_ok());
+    }
+}
@@ -0,0 +1,110 @@
+use chrono::{DateTime, Duration, FixedOffset, Local, NaiveDateTime, NaiveTime, TimeZone};
+use serde::{Deserialize, Deserializer};
+
+use crate::rules::ScheduleRule;
+
+/// Runtime commands accepted on the MQTT command topic (and `POST /command`), e.g.
+/// `{"action":"hold","start":"2025-12-01T10:00:00+01:00","end":"2025-12-01T12:00:00+01:00"}`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum Command {
+    /// Hold the battery idle between `start` (default: now) and `end`
+    Hold {
+        #[serde(default)]
+        start: Option<DateTime<FixedOffset>>,
+        end: DateTime<FixedOffset>,
+        #[serde(default)]
+        reason: Option<String>,
+    },
+    /// Cancel all hold windows
+    CancelHold,
+    /// Plan for `energy_kwh` of extra consumption between `start` (default: now) and `end`
+    ConsumptionEvent {
+        name: String,
+        #[serde(default)]
+        start: Option<DateTime<FixedOffset>>,
+        end: DateTime<FixedOffset>,
+        energy_kwh: f64,
+    },
+    /// Cancel the named consumption event, or all of them
+    CancelConsumptionEvent {
+        #[serde(default)]
+        name: Option<String>,
+    },
+    /// Add a recurring rule (see `schedule` in the config); replaces a rule with the same name
+    ScheduleRule(ScheduleRule),
+    /// Remove the named schedule rule, or all of them
+    CancelScheduleRule {
+        #[serde(default)]
+        name: Option<String>,
+    },
+    /// Don't discharge below `min_soc`% until `until`; replaces a floor with the same id
+    SocFloor {
+        id: String,
+        min_soc: f64,
+        #[serde(deserialize_with = "local_time")]
+        until: DateTime<FixedOffset>,
+        #[serde(default)]
+        reason: Option<String>,
+    },
+    /// Cancel the SoC floor with this id, or all of them
+    CancelSocFloor {
+        #[serde(default)]
+        id: Option<String>,
+    },
+    /// Switch dry-run mode (decisions without control writes) on or off
+    DryRun { enabled: bool },
+    /// Charge from the grid at full power until `until`, regardless of price
+    ForceCharge {
+        #[serde(deserialize_with = "local_time")]
+        until: DateTime<FixedOffset>,
+    },
+    /// Stop writing setpoints until `until` (default: until cancelled)
+    Pause {
+        #[serde(default, deserialize_with = "optional_local_time")]
+        until: Option<DateTime<FixedOffset>>,
+    },
+    /// Don't charge beyond `value`% until `until` (default: until cancelled)
+    SetMaxSoc {
+        value: f64,
+        #[serde(default, deserialize_with = "optional_local_time")]
+        until: Option<DateTime<FixedOffset>>,
+    },
+    /// Charge to `target_soc`% by `by` in the cheapest slots before then
+    Boost {
+        target_soc: f64,
+        #[serde(deserialize_with = "local_time")]
+        by: DateTime<FixedOffset>,
+    },
+    /// Cancel force_charge, pause, set_max_soc and boost
+    CancelOverride,
+    /// Run the capacity test: fill, empty and refill the battery at `capacity_test.power_w`
+    CapacityTest,
+    /// Abort a running capacity test
+    CancelCapacityTest,
+}
+
+/// RFC 3339, or a date and time without offset in the system's local time zone
+/// (e.g. `2025-12-01T06:00`), or just a local time (`06:00`) for its next occurrence
+fn local_time<'de, D: Deserializer<'de>>(deserializer: D) -> Result<DateTime<FixedOffset>, D::Error> {
+    let text = String::deserialize(deserializer)?;
+    if let Ok(time) = DateTime::parse_from_rfc3339(&text) {
+        return Ok(time);
+    }
+    let next_occurrence = |time: NaiveTime| {
+        let now = Local::now().naive_local();
+        let today = now.date().and_time(time);
+        if today > now { today } else { today + Duration::days(1) }
+    };
+    ["%Y-%m-%dT%H:%M:%S", "%Y-%m-%dT%H:%M"]
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(&text, format).ok())
+        .or_else(|| NaiveTime::parse_from_str(&text, "%H:%M").ok().map(next_occurrence))
+        .and_then(|naive| Local.from_local_datetime(&naive).earliest())
+        .map(|time| time.fixed_offset())
+        .ok_or_else(|| serde::de::Error::custom(format!("invalid time '{}'", text)))
+}
+
+fn optional_local_time<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<DateTime<FixedOffset>>, D::Error> {
+    local_time(deserializer).map(Some)
+}
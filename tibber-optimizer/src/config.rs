@@ -1,15 +1,301 @@
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use std::path::Path;
-use anyhow::Result;
+use chrono::{DateTime, Datelike, FixedOffset, NaiveDate, NaiveTime, Weekday};
+
+use crate::error::{Error, Result};
+use crate::events::ConsumptionEvent;
+use crate::rules::{window_covers, ScheduleRule};
+use crate::hold::HoldWindow;
+use crate::maintenance::MaintenanceWindow;
+use crate::presets::OptimizerPreset;
+use crate::prices::PricePoint;
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct Config {
-    pub tibber: TibberConfig,
+    /// Battery optimizer, or price publisher only
+    #[serde(default)]
+    pub mode: RunMode,
+    /// Which day-ahead price source to use
+    #[serde(default)]
+    pub price_provider: PriceProviderKind,
+    #[cfg(feature = "tibber")]
+    pub tibber: Option<TibberConfig>,
+    #[cfg(feature = "entsoe")]
+    pub entsoe: Option<EntsoeConfig>,
     pub mqtt: MqttConfig,
+    /// How the grid setpoint is written to the ESS
+    #[serde(default)]
+    pub controller: ControllerKind,
+    pub modbus: Option<ModbusConfig>,
+    pub mqtt_json: Option<MqttJsonControllerConfig>,
+    pub sma: Option<SmaConfig>,
+    #[cfg(feature = "powerwall")]
+    pub powerwall: Option<PowerwallConfig>,
+    /// Required in optimizer mode, see [`Config::validate`]
+    #[serde(default)]
     pub battery: BatteryConfig,
+    #[serde(default)]
     pub optimizer: OptimizerConfig,
+    /// Optimizer overrides for specific weekdays or dates
+    #[serde(default)]
+    pub presets: Vec<OptimizerPreset>,
+    #[serde(default)]
+    pub surplus: SurplusConfig,
+    #[serde(default)]
+    pub fleet_report: FleetReportConfig,
+    /// SQLite history of cycles and prices
+    #[serde(default)]
+    pub storage: StorageConfig,
+    /// Longer control interval through long flat-price periods
+    #[serde(default)]
+    pub economy_sleep: EconomySleepConfig,
+    /// Optional PV production forecast (Forecast.Solar)
+    #[cfg(feature = "forecast-solar")]
+    pub pv_forecast: Option<PvForecastConfig>,
+    /// Only read to warn that this build can't use it
+    #[cfg(not(feature = "forecast-solar"))]
+    pub pv_forecast: Option<serde::de::IgnoredAny>,
+    /// Optional PV production forecast (Solcast rooftop site)
+    #[cfg(feature = "solcast")]
+    pub solcast: Option<SolcastConfig>,
+    /// Only read to warn that this build can't use it
+    #[cfg(not(feature = "solcast"))]
+    pub solcast: Option<serde::de::IgnoredAny>,
+    /// How forecasts from several PV providers are combined
+    #[serde(default)]
+    pub pv_blend: PvBlend,
+    /// Windows during which the battery is held idle
+    #[serde(default)]
+    pub hold_windows: Vec<HoldWindow>,
+    /// Known periods of extra consumption to keep battery energy for
+    #[serde(default)]
+    pub consumption_events: Vec<ConsumptionEvent>,
+    /// Recurring constraints, e.g. no grid discharge on weekday mornings
+    #[serde(default)]
+    pub schedule: Vec<ScheduleRule>,
+    #[serde(default)]
+    pub grid_outage: GridOutageConfig,
+    #[serde(default)]
+    pub manual_override: ManualOverrideConfig,
+    #[serde(default)]
+    pub failsafe: FailsafeConfig,
+    /// What to do when tomorrow's prices are still missing in the evening
+    #[serde(default)]
+    pub missing_tomorrow_prices: MissingPricesConfig,
+    #[serde(default)]
+    pub capacity_test: CapacityTestConfig,
+    #[serde(default)]
+    pub anti_windup: AntiWindupConfig,
+    #[serde(default)]
+    pub plan_divergence: PlanDivergenceConfig,
+    /// Correct the planned house load from the realized SoC
+    #[serde(default)]
+    pub soc_feedback: SocFeedbackConfig,
+    /// Keep grid import below a cap for capacity-based grid tariffs
+    #[serde(default)]
+    pub peak_shaving: PeakShavingConfig,
+    /// Plan for a lower house load while nobody is home (see `mqtt.occupancy_topic`)
+    #[serde(default)]
+    pub occupancy: OccupancyConfig,
+    /// Power derating and grid-charge blocking on a cold battery (see `mqtt.battery_temperature_topic`)
+    #[serde(default)]
+    pub battery_temperature: BatteryTemperatureConfig,
+    /// Embedded HTTP server (plan calendar feed)
+    #[serde(default)]
+    pub http_server: HttpServerConfig,
+    /// Home Assistant MQTT discovery of the optimizer's sensors and controls
+    #[serde(default)]
+    pub mqtt_discovery: MqttDiscoveryConfig,
+    /// Hybrid contracts with fixed-price months
+    #[serde(default)]
+    pub contract: ContractConfig,
+    /// How energy fed into the grid is paid
+    #[serde(default)]
+    pub export: ExportConfig,
+    /// Grid fees and VAT not included in the price provider's prices
+    #[serde(default)]
+    pub tariff: TariffConfig,
+    /// Optional fast realtime/intraday price overriding the current slot
+    pub realtime_price: Option<RealtimePriceConfig>,
+    /// Optional telemetry from Home Assistant entities, overriding the MQTT topics
+    pub home_assistant: Option<HomeAssistantConfig>,
+    /// Optional Shelly Pro 3EM grid meter polled over HTTP (see also
+    /// `mqtt.shelly_3em_prefix`), overriding the grid power and meter topics
+    pub shelly_3em: Option<ShellyConfig>,
+    /// Optional PV curtailment during negative prices with a full battery
+    pub curtailment: Option<CurtailmentConfig>,
+    /// Optional charger AC input current limit raised during full-power charging
+    pub ac_input_limit: Option<AcInputLimitConfig>,
+    /// Optional limit on how fast the written grid setpoint may change
+    pub setpoint_slew: Option<SetpointSlewConfig>,
+    /// Non-critical loads to switch off (first = first shed) when the reserve is threatened
+    #[serde(default)]
+    pub load_shedding: Vec<SheddableLoad>,
+    /// Appliances to publish start-time recommendations for
+    #[serde(default)]
+    pub appliances: Vec<ApplianceProfile>,
+    /// Optional EV charger, charged in the cheapest slots before departure
+    pub ev: Option<EvConfig>,
+    /// Optional hot water heater relay, run in the cheapest slots of the day
+    pub water_heater: Option<WaterHeaterConfig>,
+    /// Optional main fuse shared by the EV charger, the water heater and the
+    /// battery
+    pub main_fuse: Option<MainFuseConfig>,
+    /// Import limits set by the grid operator (see `mqtt.grid_limit_topic`)
+    #[serde(default)]
+    pub grid_limit: GridLimitConfig,
+    /// Directory for persistent state (warranty counters)
+    #[serde(default = "default_data_dir")]
+    pub data_dir: String,
+    /// Run and publish everything except setpoint and other control writes
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+/// The addon's persistent `/data` volume when present, else the working directory
+fn default_data_dir() -> String {
+    if Path::new("/data").is_dir() {
+        "/data".to_string()
+    } else {
+        ".".to_string()
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum RunMode {
+    /// Control the battery
+    #[default]
+    Optimizer,
+    /// Only fetch prices and publish them with their stats and tiers, as a
+    /// price service for other consumers; needs no battery settings
+    PricePublisher,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum PriceProviderKind {
+    #[default]
+    Tibber,
+    Entsoe,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ControllerKind {
+    /// Publish `{"value": x}` to `mqtt.grid_setpoint_write_topic` (Victron)
+    #[default]
+    Mqtt,
+    /// Write the GX's setpoint register over Modbus TCP
+    Modbus,
+    /// Publish a templated payload to a templated topic, for other inverters
+    MqttJson,
+    /// Write the battery power setpoint of an SMA Sunny Boy Storage or Sunny
+    /// Island over Modbus TCP
+    Sma,
+    /// Map modes onto a Tesla Powerwall's backup reserve and export rule over
+    /// the Gateway's local API (needs the `powerwall` feature)
+    Powerwall,
+    /// Call a Home Assistant service on `home_assistant.setpoint_entity`
+    HomeAssistant,
+}
+
+/// Topic and payload templates for the `mqtt_json` controller. `{setpoint}`
+/// (W), `{setpoint_kw}`, `{power}` (W, unsigned) and `{direction}` (`import`
+/// or `export`) are replaced in both.
+#[derive(Debug, Deserialize, Clone)]
+pub struct MqttJsonControllerConfig {
+    pub topic: String,
+    #[serde(default = "default_mqtt_json_payload")]
+    pub payload: String,
+    #[serde(default)]
+    pub retain: bool,
+}
+
+fn default_mqtt_json_payload() -> String {
+    r#"{"value": {setpoint}}"#.to_string()
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct ModbusConfig {
+    /// GX device address
+    pub host: String,
+    #[serde(default = "default_modbus_port")]
+    pub port: u16,
+    /// Unit ID of the register (100 = com.victronenergy.system/settings on a GX)
+    #[serde(default = "default_modbus_unit_id")]
+    pub unit_id: u8,
+    /// ESS grid setpoint register (2700, signed 16-bit W)
+    #[serde(default = "default_modbus_setpoint_register")]
+    pub setpoint_register: u16,
+    #[serde(default = "default_modbus_timeout")]
+    pub timeout_secs: u64,
+}
+
+fn default_modbus_port() -> u16 {
+    502
+}
+
+fn default_modbus_unit_id() -> u8 {
+    100
+}
+
+fn default_modbus_setpoint_register() -> u16 {
+    2700
+}
+
+fn default_modbus_timeout() -> u64 {
+    5
+}
+
+/// SMA battery inverter controlled over Modbus TCP (registers 40149/40151)
+#[derive(Debug, Deserialize, Clone)]
+pub struct SmaConfig {
+    /// Inverter address
+    pub host: String,
+    #[serde(default = "default_modbus_port")]
+    pub port: u16,
+    #[serde(default = "default_sma_unit_id")]
+    pub unit_id: u8,
+    /// Charge power limit (W, default: battery.max_charge_power_w)
+    #[serde(default)]
+    pub max_charge_power_w: Option<f64>,
+    /// Discharge power limit (W, default: battery.max_discharge_power_w)
+    #[serde(default)]
+    pub max_discharge_power_w: Option<f64>,
+    #[serde(default = "default_modbus_timeout")]
+    pub timeout_secs: u64,
+}
+
+fn default_sma_unit_id() -> u8 {
+    3
+}
+
+/// Tesla Powerwall controlled over the Gateway's local API
+#[cfg(feature = "powerwall")]
+#[derive(Debug, Deserialize, Clone)]
+pub struct PowerwallConfig {
+    /// Gateway address; it serves HTTPS with a self-signed certificate
+    pub host: String,
+    /// Customer login email
+    pub email: String,
+    /// Customer login password (by default the last 5 characters of the
+    /// Gateway's serial number)
+    pub password: String,
+    /// Backup reserve outside charge windows (%, default: battery.min_soc_percent)
+    #[serde(default)]
+    pub reserve_percent: Option<f64>,
+    /// Change the grid export rule along with the mode
+    #[serde(default = "default_powerwall_export_rule")]
+    pub set_export_rule: bool,
+}
+
+#[cfg(feature = "powerwall")]
+fn default_powerwall_export_rule() -> bool {
+    true
 }
 
+#[cfg(feature = "tibber")]
 #[derive(Debug, Deserialize, Clone)]
 pub struct TibberConfig {
     pub api_token: String,
@@ -18,33 +304,307 @@ pub struct TibberConfig {
     /// How often to refresh prices (in seconds), default 15 minutes
     #[serde(default = "default_refresh_interval")]
     pub refresh_interval_secs: u64,
+    /// Stream real-time grid power from a Tibber Pulse/Watty (needs the
+    /// `tibber-live` feature)
+    #[serde(default)]
+    pub live_measurement: bool,
+    /// Bidding zone the home is in; prices from another market are refused
+    #[serde(default)]
+    pub bidding_zone: Option<BiddingZone>,
+    /// Home to fetch prices for, for accounts with several (default: the first)
+    #[serde(default)]
+    pub home_id: Option<String>,
+    /// Position of that home in the account's list (0 = first), instead of `home_id`
+    #[serde(default)]
+    pub home_index: Option<usize>,
+}
+
+/// Day-ahead market zones Tibber supplies
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum BiddingZone {
+    #[serde(rename = "NL")]
+    Nl,
+    #[serde(rename = "DE-LU")]
+    DeLu,
+    #[serde(rename = "SE1")]
+    Se1,
+    #[serde(rename = "SE2")]
+    Se2,
+    #[serde(rename = "SE3")]
+    Se3,
+    #[serde(rename = "SE4")]
+    Se4,
+    #[serde(rename = "NO1")]
+    No1,
+    #[serde(rename = "NO2")]
+    No2,
+    #[serde(rename = "NO3")]
+    No3,
+    #[serde(rename = "NO4")]
+    No4,
+    #[serde(rename = "NO5")]
+    No5,
+}
+
+impl BiddingZone {
+    pub fn name(self) -> &'static str {
+        match self {
+            BiddingZone::Nl => "NL",
+            BiddingZone::DeLu => "DE-LU",
+            BiddingZone::Se1 => "SE1",
+            BiddingZone::Se2 => "SE2",
+            BiddingZone::Se3 => "SE3",
+            BiddingZone::Se4 => "SE4",
+            BiddingZone::No1 => "NO1",
+            BiddingZone::No2 => "NO2",
+            BiddingZone::No3 => "NO3",
+            BiddingZone::No4 => "NO4",
+            BiddingZone::No5 => "NO5",
+        }
+    }
+
+    /// Countries (ISO 3166-1 alpha-2) whose homes trade in this zone
+    #[cfg(feature = "tibber")]
+    pub fn countries(self) -> &'static [&'static str] {
+        match self {
+            BiddingZone::Nl => &["NL"],
+            BiddingZone::DeLu => &["DE", "LU"],
+            BiddingZone::Se1 | BiddingZone::Se2 | BiddingZone::Se3 | BiddingZone::Se4 => &["SE"],
+            BiddingZone::No1 | BiddingZone::No2 | BiddingZone::No3 | BiddingZone::No4 | BiddingZone::No5 => &["NO"],
+        }
+    }
+
+    /// Currency retail prices are quoted in
+    #[cfg(feature = "tibber")]
+    pub fn currency(self) -> &'static str {
+        match self {
+            BiddingZone::Nl | BiddingZone::DeLu => "EUR",
+            BiddingZone::Se1 | BiddingZone::Se2 | BiddingZone::Se3 | BiddingZone::Se4 => "SEK",
+            BiddingZone::No1 | BiddingZone::No2 | BiddingZone::No3 | BiddingZone::No4 | BiddingZone::No5 => "NOK",
+        }
+    }
+}
+
+impl std::fmt::Display for BiddingZone {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
 }
 
-fn default_tibber_url() -> String {
+#[cfg(feature = "tibber")]
+pub fn default_tibber_url() -> String {
     "https://api.tibber.com/v1-beta/gql".to_string()
 }
 
+#[cfg(feature = "tibber")]
 fn default_refresh_interval() -> u64 {
     900 // 15 minutes
 }
 
+#[cfg(feature = "entsoe")]
 #[derive(Debug, Deserialize, Clone)]
+pub struct EntsoeConfig {
+    /// ENTSO-E transparency platform security token
+    pub api_token: String,
+    /// Bidding zone EIC code, e.g. `10YNL----------L` (NL) or `10Y1001A1001A82H` (DE-LU)
+    pub area: String,
+    #[serde(default = "default_entsoe_url")]
+    pub api_url: String,
+    /// How often to refresh prices (in seconds), default 1 hour
+    #[serde(default = "default_entsoe_refresh_interval")]
+    pub refresh_interval_secs: u64,
+    /// Supplier markup on the spot price (EUR/kWh, excl. VAT)
+    #[serde(default)]
+    pub markup_per_kwh: f64,
+    /// Energy tax (EUR/kWh, excl. VAT)
+    #[serde(default)]
+    pub energy_tax_per_kwh: f64,
+    /// VAT applied on top of spot price, markup and energy tax
+    #[serde(default)]
+    pub vat_percent: f64,
+}
+
+#[cfg(feature = "entsoe")]
+fn default_entsoe_url() -> String {
+    "https://web-api.tp.entsoe.eu/api".to_string()
+}
+
+#[cfg(feature = "entsoe")]
+fn default_entsoe_refresh_interval() -> u64 {
+    3600 // day-ahead prices change once a day
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct MqttConfig {
     pub host: String,
     #[serde(default = "default_mqtt_port")]
     pub port: u16,
+    // Credentials never end up in recordings
+    #[serde(skip_serializing)]
     pub username: Option<String>,
+    #[serde(skip_serializing)]
     pub password: Option<String>,
     #[serde(default = "default_client_id")]
     pub client_id: String,
     /// Topic to subscribe to for battery State of Charge (0-100)
+    #[serde(default)]
     pub soc_topic: String,
+    /// Optional per-pack SoC topics; when set these replace `soc_topic` and
+    /// are aggregated (capacity-weighted) into one effective SoC
+    #[serde(default)]
+    pub soc_sources: Vec<SocSource>,
     /// Topic to subscribe to for current grid setpoint (N/...for Victron)
+    #[serde(default)]
     pub grid_setpoint_read_topic: String,
     /// Topic to publish the grid setpoint to (W/... for Victron)
+    #[serde(default)]
     pub grid_setpoint_write_topic: String,
     /// Topic to publish current price info
     pub price_topic: String,
+    /// Topic to subscribe to for runtime JSON commands
+    #[serde(default = "default_command_topic")]
+    pub command_topic: String,
+    /// Optional topic with the inverter/charger state (Victron `N/.../vebus/<id>/State`)
+    #[serde(default)]
+    pub inverter_state_topic: Option<String>,
+    /// Optional grid-lost alarm topic (Victron `N/.../vebus/<id>/Alarms/GridLost`)
+    #[serde(default)]
+    pub grid_lost_topic: Option<String>,
+    /// Optional grid power topic in watts (positive = import); a silent meter counts as grid loss
+    #[serde(default)]
+    pub grid_power_topic: Option<String>,
+    /// Optional cumulative grid import meter topic in kWh, used to reconcile
+    /// the energy accounting against the meter
+    #[serde(default)]
+    pub meter_import_topic: Option<String>,
+    /// Optional cumulative grid export meter topic in kWh
+    #[serde(default)]
+    pub meter_export_topic: Option<String>,
+    /// Optional AC battery/inverter power topic in watts (positive = charging),
+    /// used to measure the realized round-trip efficiency
+    #[serde(default)]
+    pub battery_power_topic: Option<String>,
+    /// Optional house consumption topic in watts, learned into the load profile
+    /// (otherwise grid minus battery power is used when both are configured)
+    #[serde(default)]
+    pub house_load_topic: Option<String>,
+    /// Optional prefix of the GX scheduled-charge settings
+    /// (`N/<id>/settings/0/Settings/CGwacs/BatteryLife/Schedule/Charge`); the
+    /// optimizer won't discharge during those windows
+    #[serde(default)]
+    pub charge_schedule_topic: Option<String>,
+    /// Optional GX minimum SoC topic (Victron
+    /// `N/<id>/settings/0/Settings/CGwacs/BatteryLife/MinimumSocLimit`)
+    #[serde(default)]
+    pub min_soc_limit_topic: Option<String>,
+    /// Optional GX active SoC limit topic, the minimum SoC as raised by
+    /// BatteryLife (Victron `N/<id>/system/0/Control/ActiveSocLimit`)
+    #[serde(default)]
+    pub active_soc_limit_topic: Option<String>,
+    /// Power feeds to receive at QoS 0 into lock-free latest-value cells,
+    /// for topics publishing every second or faster
+    #[serde(default)]
+    pub fast_telemetry: Vec<TelemetryFeed>,
+    /// Scheduled broker maintenance: while the broker is down in one of these
+    /// windows, publishes are skipped quietly and the state is republished
+    /// once it is back
+    #[serde(default)]
+    pub maintenance_windows: Vec<MaintenanceWindow>,
+    /// Optional topic prefix of a Shelly Pro 3EM grid meter (its device id,
+    /// e.g. `shellypro3em-0cb815fc1234`), replacing the grid power and meter
+    /// topics; needs generic status updates over MQTT enabled on the meter
+    #[serde(default)]
+    pub shelly_3em_prefix: Option<String>,
+    /// Topic telling whether a car is plugged in (non-zero = connected),
+    /// required for `ev.charger: mqtt`
+    #[serde(default)]
+    pub ev_connected_topic: Option<String>,
+    /// Optional EV charging power topic in watts, for `ev.charger: mqtt`
+    #[serde(default)]
+    pub ev_power_topic: Option<String>,
+    /// Optional topic with the energy charged in the current session in kWh,
+    /// for `ev.charger: mqtt`
+    #[serde(default)]
+    pub ev_energy_topic: Option<String>,
+    /// Optional topic with the import limit set by the grid operator (§14a
+    /// EnWG), e.g. from a ripple-control receiver or an EEBus gateway: the
+    /// allowed import in watts (zero = no limit), or a switch with
+    /// `grid_limit.limit_w`
+    #[serde(default)]
+    pub grid_limit_topic: Option<String>,
+    /// Optional topic telling whether anybody is home (`on`/`off`, `home`/
+    /// `not_home` or a number), e.g. a Home Assistant binary sensor; see `occupancy`
+    #[serde(default)]
+    pub occupancy_topic: Option<String>,
+    /// Optional battery temperature topic in °C; see `battery_temperature`
+    #[serde(default)]
+    pub battery_temperature_topic: Option<String>,
+}
+
+/// A high-frequency power feed
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum TelemetryFeed {
+    GridPower,
+    BatteryPower,
+    HouseLoad,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SocSource {
+    /// Topic publishing this pack's State of Charge (0-100)
+    pub topic: String,
+    /// Usable capacity of this pack in kWh, used as its weight
+    pub capacity_kwh: f64,
+}
+
+impl Config {
+    /// Whether grid power is read from a topic or a Shelly Pro 3EM
+    pub fn measures_grid_power(&self) -> bool {
+        self.mqtt.grid_power_topic.is_some() || self.mqtt.shelly_3em_prefix.is_some() || self.shelly_3em.is_some()
+    }
+
+    /// Plan separately controlled batteries as one: their SoC is aggregated
+    /// like packs, and their capacity and power limits add up
+    pub fn combine_battery_units(&mut self) {
+        let units = &self.battery.units;
+        if units.is_empty() {
+            return;
+        }
+        self.mqtt.soc_sources = units
+            .iter()
+            .map(|unit| SocSource {
+                topic: unit.soc_topic.clone(),
+                capacity_kwh: unit.capacity_kwh,
+            })
+            .collect();
+        self.battery.capacity_kwh = units.iter().map(|u| u.capacity_kwh).sum();
+        self.battery.max_charge_power_w = units.iter().map(|u| u.max_charge_power_w).sum();
+        self.battery.max_discharge_power_w = units.iter().map(|u| u.max_discharge_power_w).sum();
+    }
+}
+
+impl MqttConfig {
+    /// SoC sources to subscribe to; the single `soc_topic` if no packs are configured
+    pub fn effective_soc_sources(&self) -> Vec<SocSource> {
+        if self.soc_sources.is_empty() {
+            vec![SocSource {
+                topic: self.soc_topic.clone(),
+                capacity_kwh: 1.0,
+            }]
+        } else {
+            self.soc_sources.clone()
+        }
+    }
+
+    /// Combined capacity of all configured packs, if any are configured
+    pub fn soc_sources_capacity_kwh(&self) -> Option<f64> {
+        if self.soc_sources.is_empty() {
+            None
+        } else {
+            Some(self.soc_sources.iter().map(|s| s.capacity_kwh).sum())
+        }
+    }
 }
 
 fn default_mqtt_port() -> u16 {
@@ -55,9 +615,14 @@ fn default_client_id() -> String {
     "tibber-optimizer".to_string()
 }
 
-#[derive(Debug, Deserialize, Clone)]
+fn default_command_topic() -> String {
+    "tibber-optimizer/command".to_string()
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct BatteryConfig {
-    /// Battery capacity in kWh
+    /// Battery capacity in kWh (with `units`, their combined capacity)
+    #[serde(default)]
     pub capacity_kwh: f64,
     /// Round-trip efficiency (0.0 - 1.0), e.g., 0.90 for 90%
     pub round_trip_efficiency: f64,
@@ -73,6 +638,96 @@ pub struct BatteryConfig {
     /// Maximum discharge power in watts
     #[serde(default = "default_max_power")]
     pub max_discharge_power_w: f64,
+    /// Plan with the GX's SoC limit when it is stricter than `min_soc_percent`
+    /// (otherwise the conflict is only warned about)
+    #[serde(default)]
+    pub adopt_gx_soc_limit: bool,
+    /// Full cycle equivalents per local day after which only self-consumption
+    /// is done, no grid charging or discharging
+    #[serde(default)]
+    pub max_daily_cycles: Option<f64>,
+    /// Inverter efficiency (0-1) at a few charge powers, e.g. from the
+    /// datasheet; reduced-power charging stays out of the low-power range
+    /// where it collapses
+    #[serde(default)]
+    pub efficiency_curve: Vec<EfficiencyPoint>,
+    /// Separately controlled batteries (e.g. Multiplus clusters on their own
+    /// GX devices), planned as one battery of their combined capacity and power
+    #[serde(default)]
+    pub units: Vec<BatteryUnit>,
+    /// How the grid setpoint is split across `units`
+    #[serde(default)]
+    pub dispatch: Dispatch,
+}
+
+/// One breakpoint of the inverter's efficiency curve
+#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+pub struct EfficiencyPoint {
+    pub power_w: f64,
+    /// Inverter efficiency at `power_w` (0-1)
+    pub efficiency: f64,
+}
+
+impl BatteryConfig {
+    /// Lowest charge power at which the inverter is within `tolerance` of its
+    /// best efficiency on `efficiency_curve`, interpolated between breakpoints
+    pub fn efficient_charge_power_w(&self, tolerance: f64) -> Option<f64> {
+        let mut curve = self.efficiency_curve.clone();
+        curve.sort_by(|a, b| a.power_w.total_cmp(&b.power_w));
+        let best = curve.iter().map(|p| p.efficiency).fold(f64::NAN, f64::max);
+        let threshold = best - tolerance;
+        let index = curve.iter().position(|p| p.efficiency >= threshold)?;
+        let Some(below) = index.checked_sub(1).map(|i| curve[i]) else {
+            return Some(curve[index].power_w);
+        };
+        let above = curve[index];
+        let share = (threshold - below.efficiency) / (above.efficiency - below.efficiency);
+        Some(below.power_w + share * (above.power_w - below.power_w))
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct BatteryUnit {
+    pub name: String,
+    /// Topic publishing this battery's State of Charge (0-100)
+    pub soc_topic: String,
+    pub capacity_kwh: f64,
+    #[serde(default = "default_max_power")]
+    pub max_charge_power_w: f64,
+    #[serde(default = "default_max_power")]
+    pub max_discharge_power_w: f64,
+    /// Topic this battery's share of the grid setpoint is published to (W/... for Victron)
+    pub setpoint_write_topic: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Dispatch {
+    /// In proportion to each battery's power limit in the setpoint's direction
+    #[default]
+    Proportional,
+    /// Up to each battery's power limit in the listed order
+    Priority,
+}
+
+/// Not a usable battery: `capacity_kwh` and `round_trip_efficiency` are zero
+/// until configured
+impl Default for BatteryConfig {
+    fn default() -> Self {
+        Self {
+            capacity_kwh: 0.0,
+            round_trip_efficiency: 0.0,
+            min_soc_percent: default_min_soc(),
+            max_soc_percent: default_max_soc(),
+            max_charge_power_w: default_max_power(),
+            max_discharge_power_w: default_max_power(),
+            adopt_gx_soc_limit: false,
+            max_daily_cycles: None,
+            efficiency_curve: Vec::new(),
+            units: Vec::new(),
+            dispatch: Dispatch::default(),
+        }
+    }
 }
 
 fn default_min_soc() -> f64 {
@@ -87,24 +742,45 @@ fn default_max_power() -> f64 {
     15000.0
 }
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct OptimizerConfig {
-    /// Minimum price spread (EUR) to consider grid discharge worthwhile
-    /// Accounts for round-trip losses
+    /// Extra margin (EUR/kWh) on top of losses, fees and wear before grid
+    /// discharge is considered worthwhile
     #[serde(default = "default_min_spread")]
     pub min_discharge_spread: f64,
+    /// Per-kWh fees for cycling energy through the grid that aren't part of the
+    /// Tibber price (e.g. export fees)
+    #[serde(default)]
+    pub grid_fee_per_kwh: f64,
+    /// Battery wear cost per kWh discharged (pack price / warranted throughput)
+    #[serde(default)]
+    pub wear_cost_per_kwh: f64,
     /// Price percentile for FULL power charging (cheapest X%)
     #[serde(default = "default_cheapest_percentile")]
     pub cheapest_percentile: f64,
     /// Price percentile threshold for reduced charging (cheap X%)
     #[serde(default = "default_charge_percentile")]
     pub charge_percentile: f64,
+    /// Highest SoC to grid-charge to in the cheapest tier (default: max_soc_percent)
+    #[serde(default)]
+    pub cheapest_charge_soc_percent: Option<f64>,
+    /// Highest SoC to grid-charge to in the cheap tier, e.g. 70 to only top up
+    /// partially at mid-tier prices (default: max_soc_percent)
+    #[serde(default)]
+    pub cheap_charge_soc_percent: Option<f64>,
     /// Price percentile threshold for expensive (prevent grid pull)
     #[serde(default = "default_expensive_percentile")]
     pub expensive_percentile: f64,
     /// Price percentile threshold for grid discharge (premium - top X%)
     #[serde(default = "default_discharge_percentile")]
     pub discharge_percentile: f64,
+    /// How tier thresholds are derived from the prices in the horizon
+    #[serde(default)]
+    pub tier_method: TierMethod,
+    /// Standard deviations from the mean for the cheapest and premium tiers
+    /// (half of it for cheap and expensive)
+    #[serde(default = "default_stddev_k")]
+    pub stddev_k: f64,
     /// Base house consumption estimate in watts (used for planning)
     #[serde(default = "default_base_consumption")]
     pub base_consumption_w: f64,
@@ -112,6 +788,42 @@ pub struct OptimizerConfig {
     /// Positive = pull from grid, Negative = feed to grid
     #[serde(default = "default_setpoint_offset")]
     pub setpoint_offset_w: f64,
+    /// How far ahead tiers and the plan look, in hours (default: all cached prices)
+    #[serde(default)]
+    pub horizon_hours: Option<f64>,
+    /// How charge and discharge decisions are made
+    #[serde(default)]
+    pub planner: Planner,
+    /// Plan with the learned house load profile where it has data, instead of
+    /// `base_consumption_w`
+    #[serde(default = "default_use_load_profile")]
+    pub use_load_profile: bool,
+    /// Minutes before a slot boundary to start ramping toward the next planned
+    /// setpoint (0 = switch on the boundary)
+    #[serde(default)]
+    pub ramp_minutes: f64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, schemars::JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Planner {
+    /// Percentile price tiers with forward-looking charge targets
+    #[default]
+    Tiers,
+    /// Cost-minimal schedule over the whole horizon, re-solved every cycle
+    Optimal,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum TierMethod {
+    /// Fixed shares of the slots per tier
+    #[default]
+    Percentile,
+    /// Outliers from the mean price, so flat days label few slots
+    Stddev,
+    /// Slots must qualify under both
+    Combined,
 }
 
 fn default_min_spread() -> f64 {
@@ -134,6 +846,10 @@ fn default_discharge_percentile() -> f64 {
     90.0 // Only discharge to grid when price is in top 10%
 }
 
+fn default_stddev_k() -> f64 {
+    1.0
+}
+
 fn default_base_consumption() -> f64 {
     500.0 // 500W base consumption estimate
 }
@@ -142,30 +858,1471 @@ fn default_setpoint_offset() -> f64 {
     200.0 // 200W offset to account for ESS response lag
 }
 
-impl Config {
-    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
-        let content = std::fs::read_to_string(path)?;
-        let config: Config = serde_yaml::from_str(&content)?;
-        Ok(config)
+fn default_use_load_profile() -> bool {
+    true
+}
+
+impl Default for OptimizerConfig {
+    fn default() -> Self {
+        serde_json::from_value(serde_json::json!({})).expect("every optimizer setting has a default")
     }
+}
 
-    pub fn load_from_env_or_file() -> Result<Self> {
-        // Home Assistant addons typically use /data/options.json
-        let ha_options = Path::new("/data/options.json");
-        if ha_options.exists() {
-            let content = std::fs::read_to_string(ha_options)?;
-            let config: Config = serde_json::from_str(&content)?;
-            return Ok(config);
+#[derive(Debug, Deserialize, Clone)]
+pub struct SurplusConfig {
+    /// Publish the per-slot cheap surplus signal for external heating controllers
+    #[serde(default)]
+    pub enabled: bool,
+    /// Number of upcoming slots to include
+    #[serde(default = "default_surplus_slots")]
+    pub slots: usize,
+    /// Maximum power the grid connection can deliver in watts
+    #[serde(default = "default_grid_limit")]
+    pub grid_limit_w: f64,
+}
+
+impl Default for SurplusConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            slots: default_surplus_slots(),
+            grid_limit_w: default_grid_limit(),
         }
+    }
+}
 
-        // Fall back to config.yaml in current directory or /config
-        let paths = ["config.yaml", "/config/tibber-optimizer.yaml"];
-        for path in paths {
-            if Path::new(path).exists() {
-                return Self::load(path);
-            }
+fn default_surplus_slots() -> usize {
+    8 // next 2 hours
+}
+
+fn default_grid_limit() -> f64 {
+    17250.0 // 3x25A at 230V
+}
+
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct FleetReportConfig {
+    /// Opt in to pushing anonymized daily stats to `endpoint`
+    #[serde(default)]
+    pub enabled: bool,
+    /// HTTP endpoint receiving a JSON POST once per day
+    #[cfg(feature = "fleet-report")]
+    #[serde(default)]
+    pub endpoint: String,
+    /// Optional label to tell your sites apart in the aggregate
+    #[cfg(feature = "fleet-report")]
+    pub site_id: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct EconomySleepConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// How far ahead prices must stay flat (within `min_discharge_spread`) to sleep
+    #[serde(default = "default_sleep_min_flat_hours")]
+    pub min_flat_hours: f64,
+    /// Control interval while asleep
+    #[serde(default = "default_sleep_interval")]
+    pub interval_secs: u64,
+    /// Price change (EUR/kWh) since the last cycle that wakes the loop
+    #[serde(default = "default_sleep_wake_price_change")]
+    pub wake_price_change: f64,
+    /// SoC change (percentage points) since the last cycle that wakes the loop
+    #[serde(default = "default_sleep_wake_soc_change")]
+    pub wake_soc_change_percent: f64,
+}
+
+impl Default for EconomySleepConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            min_flat_hours: default_sleep_min_flat_hours(),
+            interval_secs: default_sleep_interval(),
+            wake_price_change: default_sleep_wake_price_change(),
+            wake_soc_change_percent: default_sleep_wake_soc_change(),
+        }
+    }
+}
+
+fn default_sleep_min_flat_hours() -> f64 {
+    6.0
+}
+
+fn default_sleep_interval() -> u64 {
+    600
+}
+
+fn default_sleep_wake_price_change() -> f64 {
+    0.02
+}
+
+fn default_sleep_wake_soc_change() -> f64 {
+    5.0
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct StorageConfig {
+    /// Record every cycle and all fetched prices to `<data_dir>/history.sqlite`
+    #[serde(default)]
+    pub enabled: bool,
+    /// Days of history to keep (0 = forever)
+    #[cfg(feature = "storage")]
+    #[serde(default = "default_storage_retention_days")]
+    pub retention_days: u32,
+}
+
+#[cfg_attr(not(feature = "storage"), allow(clippy::derivable_impls))]
+impl Default for StorageConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            #[cfg(feature = "storage")]
+            retention_days: default_storage_retention_days(),
+        }
+    }
+}
+
+#[cfg(feature = "storage")]
+fn default_storage_retention_days() -> u32 {
+    365
+}
+
+#[cfg(feature = "forecast-solar")]
+#[derive(Debug, Deserialize, Clone)]
+pub struct PvForecastConfig {
+    pub latitude: f64,
+    pub longitude: f64,
+    /// Panel tilt in degrees (0 = horizontal, 90 = vertical)
+    pub declination: f64,
+    /// Panel azimuth in degrees (-180..180, 0 = south, -90 = east, 90 = west)
+    pub azimuth: f64,
+    /// Installed peak power in kWp
+    pub kwp: f64,
+    /// Optional Forecast.Solar API key (higher rate limits)
+    pub api_key: Option<String>,
+    /// How often to refresh the forecast (in seconds), default 1 hour
+    #[serde(default = "default_pv_refresh_interval")]
+    pub refresh_interval_secs: u64,
+}
+
+#[cfg(feature = "forecast-solar")]
+fn default_pv_refresh_interval() -> u64 {
+    3600 // Forecast.Solar's free tier allows 12 requests per hour
+}
+
+#[cfg(feature = "solcast")]
+#[derive(Debug, Deserialize, Clone)]
+pub struct SolcastConfig {
+    pub api_key: String,
+    /// Rooftop site resource id from the Solcast dashboard
+    pub resource_id: String,
+    #[serde(default = "default_solcast_api_url")]
+    pub api_url: String,
+    /// How often to refresh the forecast (in seconds), default 3 hours
+    #[serde(default = "default_solcast_refresh_interval")]
+    pub refresh_interval_secs: u64,
+}
+
+#[cfg(feature = "solcast")]
+fn default_solcast_api_url() -> String {
+    "https://api.solcast.com.au".to_string()
+}
+
+#[cfg(feature = "solcast")]
+fn default_solcast_refresh_interval() -> u64 {
+    10800 // Solcast's hobbyist tier allows 10 requests per day
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum PvBlend {
+    /// Average the providers' surplus estimates
+    #[default]
+    Average,
+    /// Use the most pessimistic provider
+    Minimum,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct GridOutageConfig {
+    /// Minimum SoC to keep while the grid is down and shortly after it returns
+    #[serde(default = "default_outage_reserve")]
+    pub reserve_soc_percent: f64,
+    /// How long to keep the raised reserve after the grid returns (hours)
+    #[serde(default = "default_outage_reserve_hold")]
+    pub reserve_hold_hours: f64,
+    /// Grid meter silence (seconds) that counts as a grid outage
+    #[serde(default = "default_meter_timeout")]
+    pub meter_timeout_secs: u64,
+}
+
+impl Default for GridOutageConfig {
+    fn default() -> Self {
+        Self {
+            reserve_soc_percent: default_outage_reserve(),
+            reserve_hold_hours: default_outage_reserve_hold(),
+            meter_timeout_secs: default_meter_timeout(),
+        }
+    }
+}
+
+fn default_outage_reserve() -> f64 {
+    50.0 // Outages tend to cluster (storms), keep half a battery
+}
+
+fn default_outage_reserve_hold() -> f64 {
+    6.0
+}
+
+fn default_meter_timeout() -> u64 {
+    120
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct ManualOverrideConfig {
+    /// Pause automatic control when the setpoint is changed outside the optimizer
+    #[serde(default = "default_manual_enabled")]
+    pub enabled: bool,
+    /// How long to leave a manual setpoint alone (seconds)
+    #[serde(default = "default_manual_cooldown")]
+    pub cooldown_secs: u64,
+    /// Difference between the read and written setpoint (W) that counts as a manual change
+    #[serde(default = "default_manual_tolerance")]
+    pub tolerance_w: f64,
+}
+
+impl Default for ManualOverrideConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_manual_enabled(),
+            cooldown_secs: default_manual_cooldown(),
+            tolerance_w: default_manual_tolerance(),
+        }
+    }
+}
+
+fn default_manual_enabled() -> bool {
+    true
+}
+
+fn default_manual_cooldown() -> u64 {
+    1800
+}
+
+fn default_manual_tolerance() -> f64 {
+    100.0
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct FailsafeConfig {
+    /// Setpoint (W) to hold while the inputs are stale (default: `optimizer.setpoint_offset_w`)
+    #[serde(default)]
+    pub setpoint_w: Option<f64>,
+    /// Minutes without a SoC update before the SoC counts as stale
+    #[serde(default = "default_failsafe_soc_timeout")]
+    pub soc_timeout_minutes: u64,
+    /// Hours since the last successful price fetch before prices count as stale
+    #[serde(default = "default_failsafe_price_age")]
+    pub max_price_age_hours: u64,
+    /// Seconds the MQTT connection may be down before entering failsafe
+    #[serde(default = "default_failsafe_mqtt_timeout")]
+    pub mqtt_timeout_secs: u64,
+}
+
+impl Default for FailsafeConfig {
+    fn default() -> Self {
+        Self {
+            setpoint_w: None,
+            soc_timeout_minutes: default_failsafe_soc_timeout(),
+            max_price_age_hours: default_failsafe_price_age(),
+            mqtt_timeout_secs: default_failsafe_mqtt_timeout(),
+        }
+    }
+}
+
+fn default_failsafe_soc_timeout() -> u64 {
+    10
+}
+
+fn default_failsafe_price_age() -> u64 {
+    24
+}
+
+fn default_failsafe_mqtt_timeout() -> u64 {
+    60
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct MissingPricesConfig {
+    /// Local hour from which tomorrow's prices count as missing rather than late
+    #[serde(default = "default_missing_prices_hour")]
+    pub after_hour: u32,
+    #[serde(default)]
+    pub behavior: MissingPricesBehavior,
+    /// Reserve to keep while planning on forecast prices (%)
+    #[serde(default = "default_missing_prices_reserve")]
+    pub reserve_soc_percent: f64,
+}
+
+impl Default for MissingPricesConfig {
+    fn default() -> Self {
+        Self {
+            after_hour: default_missing_prices_hour(),
+            behavior: MissingPricesBehavior::default(),
+            reserve_soc_percent: default_missing_prices_reserve(),
+        }
+    }
+}
+
+impl MissingPricesConfig {
+    /// The constraint the planner keeps to while tomorrow's prices are forecast
+    pub fn reserve_rule(&self) -> ScheduleRule {
+        ScheduleRule {
+            name: "missing_tomorrow_prices".to_string(),
+            days: Vec::new(),
+            start: None,
+            end: None,
+            no_grid_discharge: true,
+            no_grid_charge: false,
+            min_soc: Some(self.reserve_soc_percent),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum MissingPricesBehavior {
+    /// Forecast tomorrow from today's prices, keep a reserve and don't sell to the grid
+    #[default]
+    ReserveFirst,
+    /// Keep optimizing over today's remaining slots
+    TodayOnly,
+}
+
+fn default_missing_prices_hour() -> u32 {
+    20
+}
+
+fn default_missing_prices_reserve() -> f64 {
+    50.0
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct CapacityTestConfig {
+    /// Charge and discharge power during the test (W)
+    #[serde(default = "default_capacity_test_power")]
+    pub power_w: f64,
+    /// Abort a test that hasn't finished after this long
+    #[serde(default = "default_capacity_test_hours")]
+    pub max_hours: f64,
+    /// Plan with the measured capacity and efficiency instead of the configured ones
+    #[serde(default)]
+    pub apply_results: bool,
+}
+
+impl Default for CapacityTestConfig {
+    fn default() -> Self {
+        Self {
+            power_w: default_capacity_test_power(),
+            max_hours: default_capacity_test_hours(),
+            apply_results: false,
+        }
+    }
+}
+
+fn default_capacity_test_power() -> f64 {
+    2000.0
+}
+
+fn default_capacity_test_hours() -> f64 {
+    48.0
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct PlanDivergenceConfig {
+    /// Compare the realized SoC and grid energy against the plan every hour
+    #[serde(default = "default_divergence_enabled")]
+    pub enabled: bool,
+    /// SoC difference (percentage points) from the planned SoC that raises an alert
+    #[serde(default = "default_divergence_soc")]
+    pub soc_tolerance_percent: f64,
+    /// Grid energy difference (kWh per hour) from the plan that raises an alert (needs `mqtt.grid_power_topic`)
+    #[serde(default = "default_divergence_grid")]
+    pub grid_tolerance_kwh: f64,
+}
+
+impl Default for PlanDivergenceConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_divergence_enabled(),
+            soc_tolerance_percent: default_divergence_soc(),
+            grid_tolerance_kwh: default_divergence_grid(),
         }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct SocFeedbackConfig {
+    /// Compare the SoC at the end of each self-consumption slot with the plan
+    /// and correct the expected house load by the difference
+    #[serde(default)]
+    pub enabled: bool,
+    /// Weight of the newest slot in the running mean and variance (0-1)
+    #[serde(default = "default_feedback_smoothing")]
+    pub smoothing: f64,
+    /// Half-width of the published confidence interval, in standard deviations
+    #[serde(default = "default_feedback_sigmas")]
+    pub interval_sigmas: f64,
+}
+
+impl Default for SocFeedbackConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            smoothing: default_feedback_smoothing(),
+            interval_sigmas: default_feedback_sigmas(),
+        }
+    }
+}
+
+fn default_feedback_smoothing() -> f64 {
+    0.1
+}
+
+fn default_feedback_sigmas() -> f64 {
+    2.0
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct PeakShavingConfig {
+    /// Track the month's import peak and plan to keep import below the cap
+    #[serde(default)]
+    pub enabled: bool,
+    /// Average import to stay below over each window (W); the month's peak
+    /// is held to instead once it is higher
+    #[serde(default = "default_peak_threshold")]
+    pub threshold_w: f64,
+    /// Length of the windows import is averaged over (60 for hourly, 15 for
+    /// quarter-hourly peaks)
+    #[serde(default = "default_peak_window")]
+    pub window_minutes: u32,
+    /// Number of highest daily peaks per month the tariff bills the average
+    /// of (3 for the Norwegian kapasitetsledd, 1 for a single monthly peak)
+    #[serde(default = "default_billed_peaks")]
+    pub billed_peaks: u32,
+}
+
+impl Default for PeakShavingConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            threshold_w: default_peak_threshold(),
+            window_minutes: default_peak_window(),
+            billed_peaks: default_billed_peaks(),
+        }
+    }
+}
+
+fn default_peak_threshold() -> f64 {
+    5000.0
+}
+
+fn default_peak_window() -> u32 {
+    60
+}
+
+fn default_billed_peaks() -> u32 {
+    1
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct OccupancyConfig {
+    /// House load while nobody is home, as a fraction of the usual estimate
+    #[serde(default = "default_away_load_factor")]
+    pub away_load_factor: f64,
+    /// How long the house is expected to stay empty once everybody left (hours)
+    #[serde(default = "default_away_hours")]
+    pub away_hours: f64,
+    /// Buffer kept on top of the expected consumption while nobody is home
+    /// (% of capacity; 20 otherwise)
+    #[serde(default = "default_away_buffer")]
+    pub away_buffer_percent: f64,
+}
+
+impl Default for OccupancyConfig {
+    fn default() -> Self {
+        Self {
+            away_load_factor: default_away_load_factor(),
+            away_hours: default_away_hours(),
+            away_buffer_percent: default_away_buffer(),
+        }
+    }
+}
+
+fn default_away_load_factor() -> f64 {
+    0.5
+}
+
+fn default_away_hours() -> f64 {
+    9.0
+}
+
+fn default_away_buffer() -> f64 {
+    10.0
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct BatteryTemperatureConfig {
+    /// Charge power limits below temperature thresholds; the lowest applies
+    #[serde(default)]
+    pub charge_derating: Vec<TemperatureDerating>,
+    /// Discharge power limits below temperature thresholds
+    #[serde(default)]
+    pub discharge_derating: Vec<TemperatureDerating>,
+    /// Don't charge from the grid below this temperature (°C), e.g. 0 for an
+    /// LFP pack in an unheated garage
+    #[serde(default)]
+    pub no_grid_charge_below_c: Option<f64>,
+    /// Minutes after which a reading no longer counts
+    #[serde(default = "default_temperature_max_age")]
+    pub max_age_minutes: u64,
+}
+
+impl Default for BatteryTemperatureConfig {
+    fn default() -> Self {
+        Self {
+            charge_derating: Vec::new(),
+            discharge_derating: Vec::new(),
+            no_grid_charge_below_c: None,
+            max_age_minutes: default_temperature_max_age(),
+        }
+    }
+}
+
+fn default_temperature_max_age() -> u64 {
+    30
+}
+
+/// A power limit below a battery temperature
+#[derive(Debug, Deserialize, Clone, Copy)]
+pub struct TemperatureDerating {
+    /// Applies below this temperature (°C)
+    pub below_c: f64,
+    /// Share of the maximum power allowed (0-1)
+    pub factor: f64,
+}
+
+impl BatteryTemperatureConfig {
+    /// Charge power allowed at `temperature_c` out of `max_power_w`, if derated
+    pub fn charge_limit_w(&self, max_power_w: f64, temperature_c: Option<f64>) -> Option<f64> {
+        derated_power_w(&self.charge_derating, max_power_w, temperature_c)
+    }
+
+    /// Discharge power allowed at `temperature_c` out of `max_power_w`, if derated
+    pub fn discharge_limit_w(&self, max_power_w: f64, temperature_c: Option<f64>) -> Option<f64> {
+        derated_power_w(&self.discharge_derating, max_power_w, temperature_c)
+    }
+
+    /// Whether the battery is too cold to charge from the grid
+    pub fn too_cold(&self, temperature_c: Option<f64>) -> bool {
+        matches!((self.no_grid_charge_below_c, temperature_c), (Some(below_c), Some(t)) if t < below_c)
+    }
+
+    /// The constraint the planner keeps to while the battery is too cold to
+    /// charge from the grid
+    pub fn cold_rule(&self, temperature_c: Option<f64>) -> Option<ScheduleRule> {
+        self.too_cold(temperature_c).then(|| ScheduleRule {
+            name: "cold_battery".to_string(),
+            days: Vec::new(),
+            start: None,
+            end: None,
+            no_grid_discharge: false,
+            no_grid_charge: true,
+            min_soc: None,
+        })
+    }
+}
+
+fn derated_power_w(steps: &[TemperatureDerating], max_power_w: f64, temperature_c: Option<f64>) -> Option<f64> {
+    let temperature_c = temperature_c?;
+    steps
+        .iter()
+        .filter(|step| temperature_c < step.below_c)
+        .map(|step| max_power_w * step.factor)
+        .reduce(f64::min)
+}
+
+fn default_divergence_enabled() -> bool {
+    true
+}
+
+fn default_divergence_soc() -> f64 {
+    10.0
+}
+
+fn default_divergence_grid() -> f64 {
+    2.0
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct HttpServerConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Address to listen on (default: local connections only)
+    #[serde(default = "default_http_bind")]
+    pub bind: String,
+    /// Bearer token `POST /command` must carry; without one, commands over
+    /// HTTP are refused
+    #[serde(default)]
+    pub token: Option<String>,
+}
+
+impl Default for HttpServerConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            bind: default_http_bind(),
+            token: None,
+        }
+    }
+}
+
+fn default_http_bind() -> String {
+    "127.0.0.1:8099".to_string()
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct MqttDiscoveryConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Home Assistant's discovery prefix
+    #[serde(default = "default_discovery_prefix")]
+    pub prefix: String,
+    /// Device id, unique per optimizer instance on the broker
+    #[serde(default = "default_discovery_node_id")]
+    pub node_id: String,
+}
+
+impl Default for MqttDiscoveryConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            prefix: default_discovery_prefix(),
+            node_id: default_discovery_node_id(),
+        }
+    }
+}
+
+fn default_discovery_prefix() -> String {
+    "homeassistant".to_string()
+}
+
+fn default_discovery_node_id() -> String {
+    "tibber_optimizer".to_string()
+}
+
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct ContractConfig {
+    /// Months (1-12) billed at a fixed price instead of the spot price
+    #[serde(default)]
+    pub fixed_months: Vec<u32>,
+    /// Fixed price (EUR/kWh incl. taxes) used for accounting in those months
+    pub fixed_price: Option<f64>,
+}
+
+impl ContractConfig {
+    /// Whether the contract bills `date` (tariff-local) at a fixed price
+    pub fn is_fixed(&self, date: NaiveDate) -> bool {
+        self.fixed_months.contains(&date.month())
+    }
+
+    /// Price actually paid on `date`: the fixed price in fixed months, else the spot price
+    pub fn price_on(&self, date: NaiveDate, spot_price: f64) -> f64 {
+        match self.fixed_price {
+            Some(fixed) if self.is_fixed(date) => fixed,
+            _ => spot_price,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportModel {
+    /// Export is paid the full buy price (salderen)
+    #[default]
+    NetMetering,
+    /// Export is paid the spot price minus `fee_per_kwh`
+    Spot,
+    /// Export is paid a fixed `feed_in_tariff`
+    Fixed,
+}
+
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct ExportConfig {
+    #[serde(default)]
+    pub model: ExportModel,
+    /// Supplier fee per exported kWh, subtracted from the spot price (`spot` model)
+    #[serde(default)]
+    pub fee_per_kwh: f64,
+    /// Price per exported kWh (`fixed` model)
+    #[serde(default)]
+    pub feed_in_tariff: f64,
+}
+
+impl ExportConfig {
+    /// What a kWh fed into the grid during `price`'s slot earns
+    pub fn sell_price(&self, price: &PricePoint) -> f64 {
+        match self.model {
+            ExportModel::NetMetering => price.total,
+            ExportModel::Spot => price.energy - self.fee_per_kwh,
+            ExportModel::Fixed => self.feed_in_tariff,
+        }
+    }
+}
+
+/// A distribution fee charged on some days and hours
+#[derive(Debug, Deserialize, Clone)]
+pub struct TimeOfUseFee {
+    /// Weekdays the fee applies on (`mon`, `tue`, ...; default: every day)
+    #[serde(default)]
+    pub days: Vec<Weekday>,
+    /// Local start and end time (default: the whole day); a window ending
+    /// before it starts runs past midnight into the next day
+    #[serde(default)]
+    pub start: Option<NaiveTime>,
+    #[serde(default)]
+    pub end: Option<NaiveTime>,
+    pub fee_per_kwh: f64,
+}
+
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct TariffConfig {
+    /// Grid fee per imported kWh the provider's price doesn't include
+    #[serde(default)]
+    pub grid_fee_per_kwh: f64,
+    /// Distribution fees by time of day, on top of `grid_fee_per_kwh`; matching windows add up
+    #[serde(default)]
+    pub time_of_use: Vec<TimeOfUseFee>,
+    /// VAT (%) charged on the fees above
+    #[serde(default)]
+    pub vat_percent: f64,
+    /// Compensation per exported kWh from the grid operator, on top of the export model's price (not with `net_metering`)
+    #[serde(default)]
+    pub feed_in_compensation_per_kwh: f64,
+}
+
+impl TariffConfig {
+    /// Fees and VAT on a kWh imported in the slot starting at `starts_at`
+    pub fn fees_at(&self, starts_at: DateTime<FixedOffset>) -> f64 {
+        let time_of_use: f64 = self
+            .time_of_use
+            .iter()
+            .filter(|fee| window_covers(&fee.days, fee.start, fee.end, starts_at))
+            .map(|fee| fee.fee_per_kwh)
+            .sum();
+        (self.grid_fee_per_kwh + time_of_use) * (1.0 + self.vat_percent / 100.0)
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct AntiWindupConfig {
+    /// Lower the commanded power when the ESS persistently delivers less (needs `mqtt.grid_power_topic`)
+    #[serde(default = "default_anti_windup_enabled")]
+    pub enabled: bool,
+    /// Shortfall between commanded setpoint and measured grid power (W) that counts as not delivered
+    #[serde(default = "default_anti_windup_tolerance")]
+    pub tolerance_w: f64,
+    /// Consecutive cycles with a shortfall before the setpoint is limited
+    #[serde(default = "default_anti_windup_cycles")]
+    pub cycles: u32,
+    /// Per-cycle increase (%) of a limit the ESS keeps up with, to notice when it is lifted
+    #[serde(default = "default_anti_windup_recovery")]
+    pub recovery_percent: f64,
+}
+
+impl Default for AntiWindupConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_anti_windup_enabled(),
+            tolerance_w: default_anti_windup_tolerance(),
+            cycles: default_anti_windup_cycles(),
+            recovery_percent: default_anti_windup_recovery(),
+        }
+    }
+}
+
+fn default_anti_windup_enabled() -> bool {
+    true
+}
+
+fn default_anti_windup_tolerance() -> f64 {
+    500.0
+}
+
+fn default_anti_windup_cycles() -> u32 {
+    5
+}
+
+fn default_anti_windup_recovery() -> f64 {
+    2.0
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct HomeAssistantConfig {
+    /// Home Assistant base URL, e.g. `http://homeassistant.local:8123`
+    pub url: String,
+    /// Long-lived access token
+    pub token: String,
+    /// Battery SoC entity (%)
+    #[serde(default)]
+    pub soc_entity: Option<String>,
+    /// Grid power entity (W or kW, positive = import)
+    #[serde(default)]
+    pub grid_power_entity: Option<String>,
+    /// Battery power entity (W or kW, positive = charging)
+    #[serde(default)]
+    pub battery_power_entity: Option<String>,
+    /// House consumption entity (W or kW)
+    #[serde(default)]
+    pub house_load_entity: Option<String>,
+    /// PV production entity (W or kW)
+    #[serde(default)]
+    pub pv_power_entity: Option<String>,
+    /// Grid setpoint entity (W), written by `controller: home_assistant` and
+    /// read back as the actual setpoint
+    #[serde(default)]
+    pub setpoint_entity: Option<String>,
+    /// Service called with `entity_id` and `value` to write the setpoint
+    #[serde(default = "default_ha_setpoint_service")]
+    pub setpoint_service: String,
+}
+
+fn default_ha_setpoint_service() -> String {
+    "number.set_value".to_string()
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct ShellyConfig {
+    /// Address of the meter, e.g. `192.168.1.40`
+    pub host: String,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct RealtimePriceConfig {
+    /// HTTP endpoint returning JSON with the current price (EUR/kWh, incl. fees like Tibber's total)
+    pub url: String,
+    /// JSON pointer to the price in the response, e.g. `/price`
+    #[serde(default = "default_realtime_pointer")]
+    pub json_pointer: String,
+    #[serde(default = "default_realtime_poll_interval")]
+    pub poll_interval_secs: u64,
+    /// Prices older than this are ignored
+    #[serde(default = "default_realtime_max_age")]
+    pub max_age_secs: u64,
+    /// Deviation from the day-ahead price (EUR/kWh) before the realtime price takes over
+    #[serde(default = "default_realtime_min_deviation")]
+    pub min_deviation: f64,
+    /// Minimum time a realtime-driven mode is kept within a slot
+    #[serde(default = "default_realtime_min_hold")]
+    pub min_hold_secs: u64,
+}
+
+fn default_realtime_pointer() -> String {
+    "/price".to_string()
+}
+
+fn default_realtime_poll_interval() -> u64 {
+    300
+}
+
+fn default_realtime_max_age() -> u64 {
+    900
+}
+
+fn default_realtime_min_deviation() -> f64 {
+    0.03
+}
+
+fn default_realtime_min_hold() -> u64 {
+    600
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct ApplianceProfile {
+    pub name: String,
+    /// Average power draw over a run in watts
+    pub power_w: f64,
+    /// Length of a run in minutes
+    pub duration_minutes: u32,
+    /// Only consider runs that finish within this many hours, e.g. 12 for a
+    /// dishwasher that should be done by morning
+    pub finish_within_hours: Option<f64>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct SheddableLoad {
+    pub name: String,
+    /// Topic switching the load (e.g. a smart plug command topic)
+    pub topic: String,
+    /// Typical power draw in watts, used to decide how many loads to shed
+    pub power_w: f64,
+    #[serde(default = "default_shed_payload")]
+    pub shed_payload: String,
+    #[serde(default = "default_restore_payload")]
+    pub restore_payload: String,
+}
+
+fn default_shed_payload() -> String {
+    "off".to_string()
+}
+
+fn default_restore_payload() -> String {
+    "on".to_string()
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct CurtailmentConfig {
+    /// Feed-in limit topic (Victron `W/.../settings/0/Settings/CGwacs/MaxFeedInPower`)
+    pub topic: String,
+    /// Value published while curtailing (0 = no feed-in)
+    #[serde(default)]
+    pub curtailed_value: f64,
+    /// Value published to lift curtailment (-1 = unlimited on Victron)
+    #[serde(default = "default_curtailment_normal")]
+    pub normal_value: f64,
+    /// SoC at which the battery counts as full
+    #[serde(default = "default_curtailment_full_soc")]
+    pub full_soc_percent: f64,
+    /// Curtail when the total price drops below this, or at full SoC the sell
+    /// or spot price (EUR/kWh)
+    #[serde(default)]
+    pub price_threshold: f64,
+}
+
+fn default_curtailment_normal() -> f64 {
+    -1.0
+}
+
+fn default_curtailment_full_soc() -> f64 {
+    98.0
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct AcInputLimitConfig {
+    /// Current limit topic (Victron `W/.../vebus/276/Ac/In/1/CurrentLimit`)
+    pub topic: String,
+    /// Limit published while charging at full power (A)
+    pub charge_current_a: f64,
+    /// Limit restored afterwards, normally the one configured in the GX (A)
+    pub normal_current_a: f64,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct SetpointSlewConfig {
+    /// Largest change per step (W)
+    pub max_step_w: f64,
+    /// Seconds between steps
+    #[serde(default = "default_slew_step_secs")]
+    pub step_secs: u64,
+}
+
+fn default_slew_step_secs() -> u64 {
+    10
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct EvConfig {
+    /// How the charge current is set
+    #[serde(default)]
+    pub charger: EvChargerKind,
+    /// OCPP central system the charger connects to
+    #[cfg(feature = "ocpp")]
+    #[serde(default)]
+    pub ocpp: OcppConfig,
+    /// Easee cloud API account and charger, for `charger: easee`
+    #[serde(default)]
+    pub easee: Option<EaseeConfig>,
+    /// go-e charger on the LAN, for `charger: go_e`
+    #[serde(default)]
+    pub go_e: Option<GoEConfig>,
+    /// myWallbox cloud API account and charger, for `charger: wallbox`
+    #[serde(default)]
+    pub wallbox: Option<WallboxConfig>,
+    /// Current-limit topic, for `charger: mqtt`
+    #[serde(default)]
+    pub mqtt: Option<EvMqttConfig>,
+    /// Phases the car charges on (1 or 3)
+    #[serde(default = "default_ev_phases")]
+    pub phases: u32,
+    /// Lowest current the charger can deliver; below it charging pauses (A)
+    #[serde(default = "default_ev_min_current")]
+    pub min_current_a: f64,
+    /// Highest current per phase (A)
+    #[serde(default = "default_ev_max_current")]
+    pub max_current_a: f64,
+    /// Energy to deliver in a charging session (kWh)
+    #[serde(default = "default_ev_session_kwh")]
+    pub session_kwh: f64,
+    /// Local time the car must be charged by
+    #[serde(default = "default_ev_ready_by")]
+    pub ready_by: NaiveTime,
+    /// Also charge in slots at or below these prices, even when the session
+    /// doesn't need them to be ready in time
+    #[serde(default)]
+    pub price_tiers: Vec<EvPriceTier>,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum EvChargerKind {
+    /// Run an OCPP 1.6J central system the charger connects to
+    #[default]
+    Ocpp,
+    /// Set the dynamic charger current over the Easee cloud API
+    Easee,
+    /// Set the current over a go-e charger's local HTTP API (v2)
+    GoE,
+    /// Set the maximum charging current over the myWallbox cloud API
+    Wallbox,
+    /// Publish the current to `ev.mqtt.current_topic`
+    Mqtt,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct EvPriceTier {
+    /// Highest total price of the tier (per kWh)
+    pub max_price: f64,
+    /// Charge current per phase in the tier's slots (A)
+    pub current_a: f64,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct EaseeConfig {
+    pub username: String,
+    pub password: String,
+    /// Charger serial, e.g. EH123456
+    pub charger_id: String,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct GoEConfig {
+    /// Host name or IP address of the charger
+    pub host: String,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct WallboxConfig {
+    pub username: String,
+    pub password: String,
+    /// Charger serial number as shown in the myWallbox app
+    pub charger_id: String,
+}
+
+/// Topic and payload template for `charger: mqtt`; `{current}` (A) is
+/// replaced in the payload. The car's state comes from
+/// `mqtt.ev_connected_topic`, `mqtt.ev_power_topic` and `mqtt.ev_energy_topic`.
+#[derive(Debug, Deserialize, Clone)]
+pub struct EvMqttConfig {
+    pub current_topic: String,
+    #[serde(default = "default_ev_mqtt_payload")]
+    pub payload: String,
+}
+
+impl EvConfig {
+    /// Name of the section `charger` needs but that is missing
+    pub fn missing_section(&self) -> Option<&'static str> {
+        match self.charger {
+            EvChargerKind::Easee if self.easee.is_none() => Some("easee"),
+            EvChargerKind::GoE if self.go_e.is_none() => Some("go_e"),
+            EvChargerKind::Wallbox if self.wallbox.is_none() => Some("wallbox"),
+            EvChargerKind::Mqtt if self.mqtt.is_none() => Some("mqtt"),
+            _ => None,
+        }
+    }
+}
+
+fn default_ev_mqtt_payload() -> String {
+    r#"{"value": {current}}"#.to_string()
+}
+
+fn default_ev_phases() -> u32 {
+    3
+}
+
+fn default_ev_min_current() -> f64 {
+    6.0
+}
+
+fn default_ev_max_current() -> f64 {
+    16.0
+}
+
+fn default_ev_session_kwh() -> f64 {
+    20.0
+}
+
+fn default_ev_ready_by() -> NaiveTime {
+    NaiveTime::from_hms_opt(7, 0, 0).expect("valid time")
+}
+
+#[cfg(feature = "ocpp")]
+#[derive(Debug, Deserialize, Clone)]
+pub struct OcppConfig {
+    /// Address the websocket server listens on; the charger connects to
+    /// `ws://<host>:<port>/<charge point id>`
+    #[serde(default = "default_ocpp_bind")]
+    pub bind: String,
+    /// Only accept this charge point (default: the first to connect)
+    #[serde(default)]
+    pub charge_point_id: Option<String>,
+    /// Authorization key the charge point must present with HTTP basic auth
+    /// (OCPP security profile 1), its id being the user name
+    #[serde(default)]
+    pub password: Option<String>,
+    /// Connector the car is plugged into
+    #[serde(default = "default_ocpp_connector")]
+    pub connector_id: u32,
+}
+
+#[cfg(feature = "ocpp")]
+impl Default for OcppConfig {
+    fn default() -> Self {
+        Self {
+            bind: default_ocpp_bind(),
+            charge_point_id: None,
+            password: None,
+            connector_id: default_ocpp_connector(),
+        }
+    }
+}
+
+#[cfg(feature = "ocpp")]
+fn default_ocpp_bind() -> String {
+    "127.0.0.1:8887".to_string()
+}
+
+#[cfg(feature = "ocpp")]
+fn default_ocpp_connector() -> u32 {
+    1
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct WaterHeaterConfig {
+    /// Topic switching the heater's relay
+    pub topic: String,
+    #[serde(default = "default_relay_on")]
+    pub on_payload: String,
+    #[serde(default = "default_relay_off")]
+    pub off_payload: String,
+    /// Power of the heating element (W)
+    pub power_w: f64,
+    /// Phases the element is connected to
+    #[serde(default = "default_water_heater_phases")]
+    pub phases: u32,
+    /// Cheapest slots of the (tariff-local) day to heat in
+    #[serde(default = "default_water_heater_slots")]
+    pub slots_per_day: usize,
+    /// Least time to heat per day; slots missed (e.g. for the main fuse) are
+    /// caught up in the cheapest slots left
+    #[serde(default)]
+    pub min_runtime_minutes: f64,
+}
+
+fn default_relay_on() -> String {
+    "on".to_string()
+}
+
+fn default_relay_off() -> String {
+    "off".to_string()
+}
+
+fn default_water_heater_phases() -> u32 {
+    1
+}
+
+fn default_water_heater_slots() -> usize {
+    8
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct GridLimitConfig {
+    /// Allowed import while the signal is non-zero (W), for signals that only
+    /// switch the limit on and off; without it the signal is the limit itself
+    #[serde(default)]
+    pub limit_w: Option<f64>,
+    /// Minutes without a signal after which it counts as lost, for receivers
+    /// that repeat their state; unset for signals only sent on a change
+    #[serde(default)]
+    pub signal_timeout_minutes: Option<u64>,
+    /// Allowed import while the signal is lost (W)
+    #[serde(default = "default_grid_limit_fallback")]
+    pub fallback_limit_w: f64,
+}
+
+impl Default for GridLimitConfig {
+    fn default() -> Self {
+        Self {
+            limit_w: None,
+            signal_timeout_minutes: None,
+            fallback_limit_w: default_grid_limit_fallback(),
+        }
+    }
+}
+
+/// The least import §14a EnWG leaves a controllable load
+fn default_grid_limit_fallback() -> f64 {
+    4200.0
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct MainFuseConfig {
+    /// Rating of the main fuse per phase (A)
+    pub current_a: f64,
+    /// Phases of the grid connection
+    #[serde(default = "default_fuse_phases")]
+    pub phases: u32,
+    /// Nominal phase voltage (V)
+    #[serde(default = "default_fuse_voltage")]
+    pub voltage_v: f64,
+    /// Contracted grid power (W), if below what the fuse allows
+    #[serde(default)]
+    pub max_grid_power_w: Option<f64>,
+}
+
+fn default_fuse_phases() -> u32 {
+    3
+}
+
+fn default_fuse_voltage() -> f64 {
+    230.0
+}
+
+impl Config {
+    /// Check the settings only the price publisher can do without
+    pub fn validate(&self) -> Result<()> {
+        if self.mode == RunMode::PricePublisher {
+            return Ok(());
+        }
+        let capacity_kwh = self.battery.capacity_kwh + self.battery.units.iter().map(|u| u.capacity_kwh).sum::<f64>();
+        if capacity_kwh <= 0.0 || self.battery.round_trip_efficiency <= 0.0 {
+            return Err(Error::validation("battery.capacity_kwh and battery.round_trip_efficiency are required"));
+        }
+        if self.battery.max_daily_cycles.is_some_and(|cycles| cycles <= 0.0) {
+            return Err(Error::validation("battery.max_daily_cycles must be positive"));
+        }
+        if self
+            .battery
+            .efficiency_curve
+            .iter()
+            .any(|p| p.power_w < 0.0 || p.efficiency <= 0.0 || p.efficiency > 1.0)
+        {
+            return Err(Error::validation(
+                "battery.efficiency_curve needs non-negative power_w and efficiency between 0 and 1",
+            ));
+        }
+        let mqtt = &self.mqtt;
+        let home_assistant = self.home_assistant.as_ref();
+        let ha_soc = home_assistant.is_some_and(|ha| ha.soc_entity.is_some());
+        let units = &self.battery.units;
+        if mqtt.soc_topic.is_empty() && mqtt.soc_sources.is_empty() && units.is_empty() && !ha_soc {
+            return Err(Error::validation("mqtt.soc_topic is required"));
+        }
+        if !units.is_empty() {
+            if self.controller != ControllerKind::Mqtt {
+                return Err(Error::validation("battery.units need the mqtt controller"));
+            }
+            if !mqtt.soc_sources.is_empty() {
+                return Err(Error::validation("battery.units replace mqtt.soc_sources, set only one of them"));
+            }
+            if let Some(unit) = units.iter().find(|u| u.soc_topic.is_empty() || u.setpoint_write_topic.is_empty()) {
+                return Err(Error::validation(format!("battery unit {} needs soc_topic and setpoint_write_topic", unit.name)));
+            }
+            return Ok(());
+        }
+        // SMA inverters take a battery power setpoint and Powerwalls a reserve;
+        // neither has a grid setpoint to read back. Home Assistant reads it
+        // back from the setpoint entity.
+        let reads_setpoint = !matches!(
+            self.controller,
+            ControllerKind::Sma | ControllerKind::Powerwall | ControllerKind::HomeAssistant
+        );
+        if mqtt.grid_setpoint_read_topic.is_empty() && reads_setpoint {
+            return Err(Error::validation("mqtt.grid_setpoint_read_topic is required"));
+        }
+        match self.controller {
+            ControllerKind::Mqtt if mqtt.grid_setpoint_write_topic.is_empty() => {
+                return Err(Error::validation("mqtt.grid_setpoint_write_topic is required"))
+            }
+            ControllerKind::Modbus if self.modbus.is_none() => {
+                return Err(Error::validation("controller is modbus but the modbus section is missing"))
+            }
+            ControllerKind::MqttJson if self.mqtt_json.is_none() => {
+                return Err(Error::validation("controller is mqtt_json but the mqtt_json section is missing"))
+            }
+            ControllerKind::Sma if self.sma.is_none() => {
+                return Err(Error::validation("controller is sma but the sma section is missing"))
+            }
+            ControllerKind::Sma if mqtt.house_load_topic.is_none() && !self.measures_grid_power() => {
+                return Err(Error::validation("controller is sma but neither mqtt.house_load_topic nor mqtt.grid_power_topic is set"))
+            }
+            #[cfg(feature = "powerwall")]
+            ControllerKind::Powerwall if self.powerwall.is_none() => {
+                return Err(Error::validation("controller is powerwall but the powerwall section is missing"))
+            }
+            ControllerKind::HomeAssistant if home_assistant.and_then(|ha| ha.setpoint_entity.as_ref()).is_none() => {
+                return Err(Error::validation("controller is home_assistant but home_assistant.setpoint_entity is missing"))
+            }
+            _ => {}
+        }
+        if let Some(ev) = &self.ev {
+            if let Some(section) = ev.missing_section() {
+                return Err(Error::validation(format!(
+                    "ev.charger is {} but the ev.{} section is missing",
+                    section, section
+                )))
+            }
+            if ev.charger == EvChargerKind::Mqtt && self.mqtt.ev_connected_topic.is_none() {
+                return Err(Error::validation("ev.charger is mqtt but mqtt.ev_connected_topic is missing"))
+            }
+        }
+        let window_minutes = self.peak_shaving.window_minutes;
+        if self.peak_shaving.enabled && (window_minutes == 0 || 60 % window_minutes != 0) {
+            return Err(Error::validation("peak_shaving.window_minutes must divide an hour (e.g. 60 or 15)"))
+        }
+        if self.peak_shaving.enabled && self.peak_shaving.billed_peaks == 0 {
+            return Err(Error::validation("peak_shaving.billed_peaks must be at least 1"))
+        }
+        if self.grid_limit.limit_w.is_some() && self.mqtt.grid_limit_topic.is_none() {
+            return Err(Error::validation("grid_limit.limit_w is set but mqtt.grid_limit_topic is missing"))
+        }
+        if self.grid_limit.signal_timeout_minutes == Some(0) {
+            return Err(Error::validation("grid_limit.signal_timeout_minutes must be positive"))
+        }
+        if !(0.0..=1.0).contains(&self.occupancy.away_load_factor) {
+            return Err(Error::validation("occupancy.away_load_factor must be between 0 and 1"))
+        }
+        let temperature = &self.battery_temperature;
+        let derating = temperature.charge_derating.iter().chain(&temperature.discharge_derating);
+        if derating.clone().any(|step| !(0.0..=1.0).contains(&step.factor)) {
+            return Err(Error::validation("battery_temperature derating factors must be between 0 and 1"));
+        }
+        let configured = derating.count() > 0 || temperature.no_grid_charge_below_c.is_some();
+        if configured && self.mqtt.battery_temperature_topic.is_none() {
+            return Err(Error::validation("battery_temperature is set but mqtt.battery_temperature_topic is missing"));
+        }
+        if let Some(slew) = &self.setpoint_slew {
+            if slew.max_step_w <= 0.0 || slew.step_secs == 0 {
+                return Err(Error::validation("setpoint_slew.max_step_w and step_secs must be positive"))
+            }
+        }
+        if self.tariff.vat_percent < 0.0 {
+            return Err(Error::validation("tariff.vat_percent must not be negative"))
+        }
+        Ok(())
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let content = read(path.as_ref())?;
+        let config: Config = serde_yaml::from_str(&content)?;
+        Ok(config)
+    }
+
+    pub fn load_from_env_or_file() -> Result<Self> {
+        // Home Assistant addons typically use /data/options.json
+        let ha_options = Path::new("/data/options.json");
+        if ha_options.exists() {
+            let content = read(ha_options)?;
+            let config: Config = serde_json::from_str(&content)?;
+            return Ok(config);
+        }
+
+        // Fall back to config.yaml in current directory or /config
+        let paths = ["config.yaml", "/config/tibber-optimizer.yaml"];
+        for path in paths {
+            if Path::new(path).exists() {
+                return Self::load(path);
+            }
+        }
+
+        Err(Error::validation("No configuration file found"))
+    }
+}
+
+fn read(path: &Path) -> Result<String> {
+    std::fs::read_to_string(path).map_err(|e| Error::Validation(format!("Failed to read {}: {}", path.display(), e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temperature_config() -> BatteryTemperatureConfig {
+        BatteryTemperatureConfig {
+            charge_derating: vec![
+                TemperatureDerating { below_c: 10.0, factor: 0.5 },
+                TemperatureDerating { below_c: 5.0, factor: 0.2 },
+            ],
+            no_grid_charge_below_c: Some(0.0),
+            ..BatteryTemperatureConfig::default()
+        }
+    }
+
+    #[test]
+    fn derates_by_the_lowest_step_below_the_temperature() {
+        let config = temperature_config();
+        assert_eq!(config.charge_limit_w(5000.0, Some(12.0)), None);
+        assert_eq!(config.charge_limit_w(5000.0, Some(8.0)), Some(2500.0));
+        assert_eq!(config.charge_limit_w(5000.0, Some(-3.0)), Some(1000.0));
+        assert_eq!(config.charge_limit_w(5000.0, None), None);
+        assert_eq!(config.discharge_limit_w(5000.0, Some(-3.0)), None);
+    }
+
+    #[test]
+    fn forbids_grid_charging_below_the_threshold() {
+        let config = temperature_config();
+        let rule = config.cold_rule(Some(-0.5)).unwrap();
+        assert!(rule.no_grid_charge && !rule.no_grid_discharge);
+        assert!(config.cold_rule(Some(0.0)).is_none());
+        assert!(config.cold_rule(None).is_none());
+        assert!(BatteryTemperatureConfig::default().cold_rule(Some(-20.0)).is_none());
+    }
+
+    #[test]
+    fn names_the_missing_charger_section() {
+        let ev: EvConfig = serde_yaml::from_str("charger: wallbox").unwrap();
+        assert_eq!(ev.missing_section(), Some("wallbox"));
+        let ev: EvConfig = serde_yaml::from_str("charger: go_e\ngo_e:\n  host: 192.168.1.60").unwrap();
+        assert_eq!(ev.missing_section(), None);
+        let ev: EvConfig = serde_yaml::from_str("{}").unwrap();
+        assert_eq!(ev.missing_section(), None);
+    }
 
-        anyhow::bail!("No configuration file found")
+    #[test]
+    fn adds_up_the_time_of_use_fees_in_force_with_vat() {
+        let tariff = TariffConfig {
+            grid_fee_per_kwh: 0.05,
+            time_of_use: vec![
+                TimeOfUseFee {
+                    days: vec![Weekday::Mon, Weekday::Tue, Weekday::Wed, Weekday::Thu, Weekday::Fri],
+                    start: NaiveTime::from_hms_opt(6, 0, 0),
+                    end: NaiveTime::from_hms_opt(22, 0, 0),
+                    fee_per_kwh: 0.08,
+                },
+                TimeOfUseFee {
+                    days: Vec::new(),
+                    start: NaiveTime::from_hms_opt(17, 0, 0),
+                    end: NaiveTime::from_hms_opt(20, 0, 0),
+                    fee_per_kwh: 0.02,
+                },
+            ],
+            vat_percent: 25.0,
+            ..TariffConfig::default()
+        };
+        let fees_at = |at: &str| tariff.fees_at(DateTime::parse_from_rfc3339(at).unwrap());
+        // Monday 2025-12-01
+        assert!((fees_at("2025-12-01T03:00:00+01:00") - 0.0625).abs() < 1e-9);
+        assert!((fees_at("2025-12-01T12:00:00+01:00") - 0.1625).abs() < 1e-9);
+        assert!((fees_at("2025-12-01T18:00:00+01:00") - 0.1875).abs() < 1e-9);
+        // Saturday: only the daily evening fee
+        assert!((fees_at("2025-12-06T18:00:00+01:00") - 0.0875).abs() < 1e-9);
     }
 }
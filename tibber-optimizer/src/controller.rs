@@ -0,0 +1,151 @@
+use anyhow::Result;
+use std::future::Future;
+use std::pin::Pin;
+
+use crate::config::{Config, ControllerKind};
+use crate::dispatch::MultiBatteryController;
+use crate::home_assistant::HomeAssistantController;
+use crate::modbus::ModbusController;
+use crate::mqtt::{BatteryState, MqttClient};
+use crate::optimizer::BatteryMode;
+#[cfg(feature = "powerwall")]
+use crate::powerwall::PowerwallController;
+use crate::slew::SlewLimitedController;
+use crate::sma::SmaController;
+use crate::supervisor::Supervisor;
+
+pub type WriteFuture<'a> = Pin<Box<dyn Future<Output = Result<()>> + Send + 'a>>;
+
+/// Writes the grid setpoint to the ESS
+pub trait BatteryController: Send + Sync {
+    /// Name used in logs
+    fn name(&self) -> &'static str;
+
+    /// Write the grid setpoint (W, positive = import)
+    fn write_setpoint(&self, setpoint_w: f64) -> WriteFuture<'_>;
+
+    /// Write the optimizer's decision, for controllers that act on the mode
+    /// rather than the setpoint
+    fn write_mode(&self, _mode: BatteryMode, setpoint_w: f64) -> WriteFuture<'_> {
+        self.write_setpoint(setpoint_w)
+    }
+
+    /// Take in this cycle's readings, for controllers that translate the grid
+    /// setpoint into battery power
+    fn observe(&self, _state: &BatteryState) {}
+
+    /// Whether every cycle's decision must be passed on, even with an
+    /// unchanged setpoint: what is written depends on the load or the mode
+    fn writes_every_cycle(&self) -> bool {
+        false
+    }
+
+    /// Setpoint the last write left on the ESS, for controllers that don't
+    /// write the requested one straight away
+    fn written_setpoint_w(&self) -> Option<f64> {
+        None
+    }
+
+    /// Hand control back (pause, manual override, dry run, outage): stop
+    /// writes still under way
+    fn release(&self) -> WriteFuture<'_> {
+        Box::pin(async { Ok(()) })
+    }
+}
+
+/// The controller selected by `controller`, stepping through `setpoint_slew`
+/// if configured
+pub fn from_config(
+    config: &Config,
+    mqtt_client: &MqttClient,
+    supervisor: &Supervisor,
+) -> Result<Box<dyn BatteryController>> {
+    let controller = writer_from_config(config, mqtt_client)?;
+    Ok(match &config.setpoint_slew {
+        Some(slew) => Box::new(SlewLimitedController::new(controller, slew, supervisor)),
+        None => controller,
+    })
+}
+
+fn writer_from_config(config: &Config, mqtt_client: &MqttClient) -> Result<Box<dyn BatteryController>> {
+    Ok(match config.controller {
+        ControllerKind::Mqtt if !config.battery.units.is_empty() => {
+            let units = config.battery.units.clone();
+            let writers = units
+                .iter()
+                .map(|unit| mqtt_client.setpoint_writer(&unit.setpoint_write_topic, r#"{"value": {setpoint}}"#, false))
+                .collect();
+            Box::new(MultiBatteryController::new(
+                units,
+                writers,
+                config.battery.dispatch,
+                config.battery.min_soc_percent,
+                config.battery.max_soc_percent,
+            ))
+        }
+        ControllerKind::Mqtt => Box::new(mqtt_client.setpoint_writer(
+            &config.mqtt.grid_setpoint_write_topic,
+            r#"{"value": {setpoint}}"#,
+            false,
+        )),
+        ControllerKind::MqttJson => {
+            let json = config
+                .mqtt_json
+                .as_ref()
+                .ok_or_else(|| anyhow::anyhow!("controller is mqtt_json but the mqtt_json section is missing"))?;
+            Box::new(mqtt_client.setpoint_writer(&json.topic, &json.payload, json.retain))
+        }
+        ControllerKind::Modbus => {
+            let modbus = config
+                .modbus
+                .clone()
+                .ok_or_else(|| anyhow::anyhow!("controller is modbus but the modbus section is missing"))?;
+            Box::new(ModbusController::new(modbus))
+        }
+        ControllerKind::Sma => {
+            let sma = config
+                .sma
+                .as_ref()
+                .ok_or_else(|| anyhow::anyhow!("controller is sma but the sma section is missing"))?;
+            Box::new(SmaController::new(
+                sma,
+                config.battery.max_charge_power_w,
+                config.battery.max_discharge_power_w,
+                config.optimizer.base_consumption_w,
+            ))
+        }
+        #[cfg(feature = "powerwall")]
+        ControllerKind::Powerwall => {
+            let powerwall = config
+                .powerwall
+                .clone()
+                .ok_or_else(|| anyhow::anyhow!("controller is powerwall but the powerwall section is missing"))?;
+            Box::new(PowerwallController::new(
+                powerwall,
+                config.battery.min_soc_percent,
+                config.battery.max_soc_percent,
+            )?)
+        }
+        #[cfg(not(feature = "powerwall"))]
+        ControllerKind::Powerwall => anyhow::bail!("controller powerwall is not included in this build"),
+        ControllerKind::HomeAssistant => {
+            let home_assistant = config
+                .home_assistant
+                .clone()
+                .ok_or_else(|| anyhow::anyhow!("controller is home_assistant but the home_assistant section is missing"))?;
+            Box::new(HomeAssistantController::new(home_assistant))
+        }
+    })
+}
+
+/// Fill the setpoint placeholders of a topic or payload template
+pub fn render(template: &str, setpoint_w: f64) -> String {
+    // Adding zero turns -0 into 0
+    let setpoint_w = setpoint_w.round() + 0.0;
+    let direction = if setpoint_w < 0.0 { "export" } else { "import" };
+    template
+        .replace("{setpoint}", &format!("{}", setpoint_w))
+        .replace("{setpoint_kw}", &format!("{}", setpoint_w / 1000.0))
+        .replace("{power}", &format!("{}", setpoint_w.abs()))
+        .replace("{direction}", direction)
+}
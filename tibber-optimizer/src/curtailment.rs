@@ -0,0 +1,101 @@
+use tracing::info;
+
+use crate::config::CurtailmentConfig;
+use crate::prices::PricePoint;
+
+/// Decides when to curtail PV production: importing pays, or the battery
+/// can't absorb more and exporting would cost money
+#[derive(Debug)]
+pub struct CurtailmentController {
+    config: CurtailmentConfig,
+    /// Last commanded state; None until the first decision is published
+    active: Option<bool>,
+}
+
+impl CurtailmentController {
+    pub fn new(config: CurtailmentConfig) -> Self {
+        Self { config, active: None }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active == Some(true)
+    }
+
+    /// Whether curtailment should be active for this SoC and price
+    pub fn should_curtail(&self, soc: f64, price: &PricePoint) -> bool {
+        let threshold = self.config.price_threshold;
+        // While importing pays, any PV beyond what the battery takes at full
+        // power only displaces paid import
+        if price.total < threshold {
+            return true;
+        }
+        // Export is paid at the sell price or (roughly) the spot price, so a
+        // negative energy component means feed-in costs money even if the
+        // total is positive
+        let export_costs = price.sell_price() < threshold || price.energy < threshold;
+        soc >= self.config.full_soc_percent && export_costs
+    }
+
+    /// Returns the feed-in limit value to publish when the state changes
+    pub fn update(&mut self, soc: f64, price: &PricePoint) -> Option<f64> {
+        let curtail = self.should_curtail(soc, price);
+        if self.active == Some(curtail) {
+            return None;
+        }
+
+        self.active = Some(curtail);
+        if curtail {
+            info!(
+                "Negative price {:.4} (sell {:.4}) at SoC {:.1}%, curtailing PV feed-in",
+                price.total,
+                price.sell_price(),
+                soc
+            );
+            Some(self.config.curtailed_value)
+        } else {
+            info!("Lifting PV curtailment");
+            Some(self.config.normal_value)
+        }
+    }
+
+    /// Forget the last published state so it is re-sent on the next update
+    pub fn reset(&mut self) {
+        self.active = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::DateTime;
+
+    fn price(total: f64, energy: f64, sell: Option<f64>) -> PricePoint {
+        let starts_at = DateTime::parse_from_rfc3339("2025-06-01T13:00:00+02:00").unwrap();
+        PricePoint {
+            total,
+            energy,
+            tax: total - energy,
+            starts_at,
+            ends_at: starts_at + chrono::Duration::minutes(15),
+            level: None,
+            currency: None,
+            sell,
+            source: Default::default(),
+        }
+    }
+
+    #[test]
+    fn curtails_at_a_negative_price_whatever_the_soc_and_at_costly_export_once_full() {
+        let config: CurtailmentConfig = serde_yaml::from_str("topic: feed_in").unwrap();
+        let mut controller = CurtailmentController::new(config);
+
+        // Importing pays: curtail even with room in the battery
+        assert_eq!(controller.update(40.0, &price(-0.02, -0.12, None)), Some(0.0));
+        assert!(controller.is_active());
+        // Positive buy price but export costs money: only once full
+        assert_eq!(controller.update(50.0, &price(0.12, 0.02, Some(-0.01))), Some(-1.0));
+        assert!(controller.should_curtail(99.0, &price(0.12, 0.02, Some(-0.01))));
+        assert!(controller.should_curtail(99.0, &price(0.08, -0.02, None)));
+        assert!(!controller.should_curtail(99.0, &price(0.12, 0.02, Some(0.05))));
+    }
+}
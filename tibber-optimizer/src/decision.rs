@@ -0,0 +1,1655 @@
+//! The optimizer's decision logic as pure functions over an explicit
+//! [`OptimizerInput`]: no clock, locks or I/O, so the same input always yields
+//! the same decision. `BatteryOptimizer` gathers the live state into an input.
+
+use chrono::{DateTime, Duration, FixedOffset, Utc};
+use tracing::debug;
+
+use crate::config::{BatteryConfig, OptimizerConfig, Planner, TierMethod};
+use crate::events::ConsumptionEvent;
+use crate::floors::{self, SocFloor};
+use crate::load_profile::LoadProfile;
+use crate::occupancy::{Away, HOME_BUFFER_PERCENT};
+use crate::optimal;
+use crate::overrides::Boost;
+use crate::peak::PeakLimit;
+use crate::optimizer::{Alternative, BatteryMode, ForecastInfo, OptimizationResult, PlannedSlot};
+use crate::prices::{PriceCache, PricePoint};
+use crate::pv_forecast::PvForecast;
+use crate::rules::{self, Constraints, ScheduleRule};
+use crate::soc_feedback::LoadCorrection;
+
+/// How far below the best inverter efficiency a charge power still counts as efficient
+const EFFICIENCY_TOLERANCE: f64 = 0.02;
+
+/// Everything a decision depends on
+#[derive(Debug, Clone)]
+pub struct OptimizerInput<'a> {
+    pub battery: &'a BatteryConfig,
+    pub optimizer: &'a OptimizerConfig,
+    /// Time the decision is made at; slots starting before it are past
+    pub now: DateTime<Utc>,
+    pub soc: f64,
+    /// Price of the slot being decided (may be a realtime override)
+    pub current_price: &'a PricePoint,
+    pub prices: &'a PriceCache,
+    /// Price tiers over the future slots, see [`PriceTiers::compute`]
+    pub tiers: PriceTiers,
+    /// Configured minimum SoC, raised by any active override
+    pub min_soc: f64,
+    /// Measured round-trip efficiency, or the configured one
+    pub round_trip_efficiency: f64,
+    /// Charge and discharge power the ESS actually delivers
+    pub max_charge_power_w: f64,
+    pub max_discharge_power_w: f64,
+    pub pv_forecast: Option<&'a PvForecast>,
+    pub consumption_events: &'a [ConsumptionEvent],
+    /// Recurring constraints, applied to each slot as it is decided
+    pub schedule_rules: &'a [ScheduleRule],
+    /// Commanded SoC floors, each until its end
+    pub soc_floors: &'a [SocFloor],
+    /// Commanded charge target and deadline
+    pub boost: Option<Boost>,
+    /// Learned house load, if enabled; `base_consumption_w` fills its gaps
+    pub load_profile: Option<&'a LoadProfile>,
+    /// Correction of the house load from the SoC feedback, if learned
+    pub load_correction: Option<LoadCorrection>,
+    /// Import cap for capacity tariffs, if peak shaving is enabled
+    pub peak_limit: Option<PeakLimit>,
+    /// Lower house load and reserve while nobody is home
+    pub away: Option<Away>,
+}
+
+/// Prices of the slots from `now` up to the configured horizon
+pub fn horizon_prices<'a>(
+    config: &OptimizerConfig,
+    prices: &'a PriceCache,
+    now: DateTime<Utc>,
+) -> impl Iterator<Item = &'a PricePoint> + Clone {
+    let end = config.horizon_hours.map(|hours| now + Duration::seconds((hours * 3600.0) as i64));
+    prices
+        .prices_from(now)
+        .take_while(move |p| end.is_none_or(|end| p.starts_at < end))
+}
+
+impl OptimizerInput<'_> {
+    fn future_prices(&self) -> impl Iterator<Item = &PricePoint> + Clone {
+        horizon_prices(self.optimizer, self.prices, self.now)
+    }
+
+    /// Length of the current price slot (hours)
+    fn slot_hours(&self) -> f64 {
+        self.current_price.duration().num_seconds() as f64 / 3600.0
+    }
+
+    fn count_slots_below_threshold(&self, threshold: f64) -> usize {
+        self.future_prices().filter(|p| p.total <= threshold).count()
+    }
+
+    /// Expected house load at `at` (W), without announced events
+    pub fn house_load_w(&self, at: DateTime<FixedOffset>) -> f64 {
+        let estimate_w = self
+            .load_profile
+            .and_then(|profile| profile.load_w(at))
+            .unwrap_or(self.optimizer.base_consumption_w);
+        let load_factor = self.away.map_or(1.0, |away| away.load_factor_at(at));
+        (estimate_w + self.load_correction.map_or(0.0, |c| c.bias_w)).max(0.0) * load_factor
+    }
+
+    /// Expected house load between `from` and `to` (kWh), without announced events
+    fn house_load_kwh(&self, from: DateTime<FixedOffset>, to: DateTime<FixedOffset>) -> f64 {
+        let mut kwh = 0.0;
+        let step = self.current_price.duration();
+        let mut at = from;
+        while at < to {
+            let next = (at + step).min(to);
+            let hours = next.signed_duration_since(at).num_seconds() as f64 / 3600.0;
+            kwh += self.house_load_w(at) / 1000.0 * hours;
+            at = next;
+        }
+        kwh
+    }
+
+    /// Announced extra consumption between `from` and `to` (kWh)
+    pub fn event_energy_kwh(&self, from: DateTime<FixedOffset>, to: DateTime<FixedOffset>) -> f64 {
+        self.consumption_events.iter().map(|e| e.energy_between(from, to)).sum()
+    }
+
+    /// Constraints of the schedule rules in effect in the slot being decided
+    fn constraints(&self) -> Constraints<'_> {
+        rules::constraints_at(self.schedule_rules, self.current_price.starts_at)
+    }
+
+    /// Highest reserve the schedule rules or SoC floors require in slots
+    /// between `from` and `to` (%)
+    fn scheduled_reserve(&self, from: DateTime<FixedOffset>, to: DateTime<FixedOffset>) -> f64 {
+        self.future_prices()
+            .filter(|p| p.starts_at >= from && p.starts_at < to)
+            .map(|p| {
+                let rule = rules::constraints_at(self.schedule_rules, p.starts_at).min_soc;
+                let floor = floors::floor_at(self.soc_floors, p.starts_at);
+                rule.map_or(0.0, |(min_soc, _)| min_soc).max(floor.map_or(0.0, |floor| floor.min_soc))
+            })
+            .fold(0.0, f64::max)
+    }
+
+    /// The boost still to be reached by charging before its deadline, if any
+    fn pending_boost(&self) -> Option<Boost> {
+        self.boost.filter(|boost| {
+            self.current_price.starts_at < boost.by && self.soc < boost.target_soc.min(self.battery.max_soc_percent)
+        })
+    }
+
+    /// SoC to keep for holding import at the peak cap in the slots after the
+    /// current one, until cheap prices return (%)
+    fn peak_reserve_soc(&self, limit: &PeakLimit) -> f64 {
+        let current_time = self.current_price.starts_at;
+        let recharge_at = end_of_next_expensive_period(self).unwrap_or(current_time + Duration::hours(24));
+        let excess_kwh: f64 = self
+            .future_prices()
+            .filter(|p| p.starts_at > current_time && p.starts_at < recharge_at)
+            .map(|p| {
+                let hours = p.duration().num_seconds() as f64 / 3600.0;
+                let load_w = self.house_load_w(p.starts_at) + self.event_energy_kwh(p.starts_at, p.ends_at) / hours * 1000.0;
+                (load_w - limit.cap_at(p.starts_at)).max(0.0) / 1000.0 * hours
+            })
+            .sum();
+        let reserve_kwh = excess_kwh / self.round_trip_efficiency.sqrt();
+        (self.min_soc + reserve_kwh / self.battery.capacity_kwh * 100.0).min(self.battery.max_soc_percent)
+    }
+
+    /// Spread over `buy_price` a discharge to grid must earn: what cycling
+    /// costs, plus grid fees and the configured margin
+    pub fn required_discharge_spread(&self, buy_price: f64) -> f64 {
+        self.required_cycle_spread(buy_price) + self.optimizer.grid_fee_per_kwh + self.optimizer.min_discharge_spread
+    }
+
+    /// Spread over `buy_price` any discharge must save to be worth cycling
+    /// the battery: efficiency losses on the charged energy and battery wear
+    fn required_cycle_spread(&self, buy_price: f64) -> f64 {
+        let losses = buy_price / self.round_trip_efficiency - buy_price;
+        losses + self.optimizer.wear_cost_per_kwh
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PriceTiers {
+    /// Bottom 10% - full power charging
+    pub cheapest_threshold: f64,
+    /// Bottom 25% - reduced charging
+    pub cheap_threshold: f64,
+    /// Top 25% - prevent grid pull
+    pub expensive_threshold: f64,
+    /// Top 10% of sell prices - discharge to grid
+    pub premium_threshold: f64,
+}
+
+/// Standard deviation assumed at least, so flat prices yield no outliers (EUR/kWh)
+const MIN_TIER_STDDEV: f64 = 0.001;
+
+fn mean_stddev(values: &[f64]) -> (f64, f64) {
+    let mean = values.iter().sum::<f64>() / values.len() as f64;
+    let variance = values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / values.len() as f64;
+    (mean, variance.sqrt().max(MIN_TIER_STDDEV))
+}
+
+impl PriceTiers {
+    /// Thresholds over the prices within the horizon (buy prices for charging
+    /// and grid pull, sell prices for discharge), by the configured method
+    pub fn compute(config: &OptimizerConfig, prices: &PriceCache, now: DateTime<Utc>) -> Self {
+        let percentile = Self::percentile(config, prices, now);
+        if config.tier_method == TierMethod::Percentile || percentile == Self::default() {
+            return percentile;
+        }
+        let stddev = Self::stddev(config, prices, now);
+        match config.tier_method {
+            TierMethod::Stddev => stddev,
+            // Both must agree: the narrower threshold of each tier wins
+            _ => Self {
+                cheapest_threshold: percentile.cheapest_threshold.min(stddev.cheapest_threshold),
+                cheap_threshold: percentile.cheap_threshold.min(stddev.cheap_threshold),
+                expensive_threshold: percentile.expensive_threshold.max(stddev.expensive_threshold),
+                premium_threshold: percentile.premium_threshold.max(stddev.premium_threshold),
+            },
+        }
+    }
+
+    /// Outlier thresholds at `stddev_k` standard deviations from the mean for
+    /// the cheapest and premium tiers, and half that for cheap and expensive
+    fn stddev(config: &OptimizerConfig, prices: &PriceCache, now: DateTime<Utc>) -> Self {
+        let buy: Vec<f64> = horizon_prices(config, prices, now).map(|p| p.total).collect();
+        let sell: Vec<f64> = horizon_prices(config, prices, now).map(|p| p.sell_price()).collect();
+        let (mean, stddev) = mean_stddev(&buy);
+        let (sell_mean, sell_stddev) = mean_stddev(&sell);
+        let k = config.stddev_k;
+
+        Self {
+            cheapest_threshold: mean - k * stddev,
+            cheap_threshold: mean - k * stddev / 2.0,
+            expensive_threshold: mean + k * stddev / 2.0,
+            premium_threshold: sell_mean + k * sell_stddev,
+        }
+    }
+
+    /// Percentile thresholds: the configured shares of slots land in each tier
+    fn percentile(config: &OptimizerConfig, prices: &PriceCache, now: DateTime<Utc>) -> Self {
+        let mut sorted: Vec<f64> = horizon_prices(config, prices, now).map(|p| p.total).collect();
+        if sorted.is_empty() {
+            return Self::default();
+        }
+        sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let mut sorted_sell: Vec<f64> = horizon_prices(config, prices, now).map(|p| p.sell_price()).collect();
+        sorted_sell.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+        let len = sorted.len();
+
+        // Cheapest tier: bottom 10% of prices (full power charging)
+        let cheapest_idx = ((len as f64 * config.cheapest_percentile / 100.0) as usize).max(1).min(len - 1);
+        // Cheap tier: bottom 25% of prices (reduced charging)
+        let cheap_idx = ((len as f64 * config.charge_percentile / 100.0) as usize).min(len - 1);
+        // Expensive tier: top 25% (prevent grid pull)
+        let expensive_idx = ((len as f64 * (100.0 - config.expensive_percentile) / 100.0) as usize).min(len - 1);
+        // Premium tier: top 10% (discharge to grid)
+        let premium_idx = ((len as f64 * config.discharge_percentile / 100.0) as usize).min(len - 1);
+
+        Self {
+            cheapest_threshold: sorted[cheapest_idx],
+            cheap_threshold: sorted[cheap_idx],
+            expensive_threshold: sorted[expensive_idx],
+            premium_threshold: sorted_sell[premium_idx],
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct ChargePlan {
+    /// Target SoC to reach during cheap period
+    target_soc: f64,
+    /// Energy needed to reach target (kWh)
+    energy_needed_kwh: f64,
+    /// Number of cheap price slots available
+    cheap_slots_available: usize,
+    /// Number of cheapest price slots available
+    cheapest_slots_available: usize,
+    /// Slots needed at full power to reach target
+    slots_needed_full_power: usize,
+}
+
+/// Main optimization function - determines what the battery should do
+pub fn optimize(input: &OptimizerInput) -> OptimizationResult {
+    if input.future_prices().next().is_none() {
+        return OptimizationResult {
+            mode: BatteryMode::SelfConsumption,
+            grid_setpoint_w: input.optimizer.setpoint_offset_w,
+            reason: "No price data available, defaulting to self-consumption".to_string(),
+            alternative: None,
+        };
+    }
+
+    if input.optimizer.planner == Planner::Optimal {
+        // Paid import overrides the schedule too, within the peak cap
+        return match check_negative_price(input) {
+            Some(mut result) => {
+                if let Some(limit) = input.peak_limit {
+                    result.cap_setpoint(limit.cap_at(input.current_price.starts_at), "peak shaving");
+                }
+                result
+            }
+            None => optimal::optimize(input),
+        };
+    }
+
+    // Keep what the coming peaks need, and hold import at the cap now
+    if let Some(limit) = input.peak_limit {
+        let reserve = input.peak_reserve_soc(&limit);
+        let mut result = optimize(&OptimizerInput {
+            min_soc: input.min_soc.max(reserve),
+            peak_limit: None,
+            ..input.clone()
+        });
+        result.cap_setpoint(limit.cap_at(input.current_price.starts_at), "peak shaving");
+        return result;
+    }
+
+    // A schedule rule's reserve raises the minimum SoC for this slot
+    if let Some((reserve, rule)) = input.constraints().min_soc.filter(|&(reserve, _)| reserve > input.min_soc) {
+        debug!("Schedule rule '{}' holds a {:.0}% reserve", rule, reserve);
+        return optimize(&OptimizerInput {
+            min_soc: reserve,
+            ..input.clone()
+        });
+    }
+
+    // So does a commanded SoC floor, until its end
+    if let Some(floor) = floors::floor_at(input.soc_floors, input.current_price.starts_at)
+        .filter(|floor| floor.min_soc > input.min_soc)
+    {
+        debug!("SoC floor '{}' holds {:.0}% until {}", floor.id, floor.min_soc, floor.until.to_rfc3339());
+        return optimize(&OptimizerInput {
+            min_soc: floor.min_soc,
+            ..input.clone()
+        });
+    }
+
+    if let Some(result) = check_boost(input) {
+        return result;
+    }
+
+    if let Some(result) = check_negative_price(input) {
+        return result;
+    }
+
+    let price = input.current_price.total;
+    let tiers = &input.tiers;
+
+    debug!(
+        "Price: {:.4}, Tiers - Cheapest: {:.4}, Cheap: {:.4}, Expensive: {:.4}, Premium: {:.4}",
+        price, tiers.cheapest_threshold, tiers.cheap_threshold,
+        tiers.expensive_threshold, tiers.premium_threshold
+    );
+
+    // Check if we should discharge to grid (sell power) - HIGHEST PRIORITY when profitable
+    let sell_price = input.current_price.sell_price();
+    let discharge = match check_grid_discharge(input, sell_price) {
+        Ok(result) => return result,
+        Err(reason) => Alternative {
+            mode: BatteryMode::DischargeToGrid,
+            reason,
+        },
+    };
+
+    // Check charging modes with forward-looking planning
+    let charge = match check_charging(input, price) {
+        Ok(result) => {
+            return OptimizationResult {
+                alternative: Some(discharge),
+                ..result
+            }
+        }
+        Err(reason) => Alternative {
+            mode: BatteryMode::ChargeFull,
+            reason,
+        },
+    };
+
+    // The runner-up is whichever price tier was closer to being reached
+    let discharge_gap = (tiers.premium_threshold - sell_price).max(0.0);
+    let charge_gap = (price - tiers.cheap_threshold).max(0.0);
+    let alternative = Some(if discharge_gap <= charge_gap { discharge } else { charge });
+
+    // Don't let self-consumption eat into the reserve
+    if input.soc <= input.min_soc {
+        return OptimizationResult {
+            mode: BatteryMode::Idle,
+            grid_setpoint_w: input.house_load_w(input.current_price.starts_at),
+            reason: format!(
+                "SoC {:.1}% at reserve {:.1}%, holding battery (grid covers house load)",
+                input.soc, input.min_soc
+            ),
+            alternative,
+        };
+    }
+
+    // Covering the house from the battery cycles it too: with a wear cost,
+    // only do so below the expensive tier when the import avoided beats
+    // recharging later. The peaks are what the stored energy is kept for.
+    if input.optimizer.wear_cost_per_kwh > 0.0 && price < tiers.expensive_threshold {
+        let spread = price - tiers.cheapest_threshold;
+        let required_spread = input.required_cycle_spread(tiers.cheapest_threshold);
+        if spread < required_spread {
+            return OptimizationResult {
+                mode: BatteryMode::Idle,
+                grid_setpoint_w: input.house_load_w(input.current_price.starts_at),
+                reason: format!(
+                    "Price {:.4} EUR saves {:.4} over recharging, less than losses and wear {:.4}, holding battery (grid covers house load)",
+                    price, spread, required_spread
+                ),
+                alternative,
+            };
+        }
+    }
+
+    // Determine self-consumption mode based on price level
+    OptimizationResult {
+        alternative,
+        ..determine_self_consumption_mode(input, price)
+    }
+}
+
+/// Discharge to the grid when `sell_price` is premium and beats recharging
+/// later, else why not
+fn check_grid_discharge(input: &OptimizerInput, sell_price: f64) -> Result<OptimizationResult, String> {
+    let tiers = &input.tiers;
+
+    if let Some(rule) = input.constraints().no_grid_discharge {
+        return Err(format!("schedule rule '{}' forbids grid discharge", rule));
+    }
+    if let Some(boost) = input.pending_boost() {
+        return Err(format!("{} pending", boost.describe()));
+    }
+
+    // Need sufficient SoC to discharge
+    if input.soc <= input.min_soc + 15.0 {
+        return Err(format!("SoC {:.1}% <= required {:.1}%", input.soc, input.min_soc + 15.0));
+    }
+
+    // Feed-in that costs money is never worth it
+    if sell_price <= 0.0 {
+        return Err(format!("sell price {:.4} not positive", sell_price));
+    }
+
+    // Only discharge at premium prices
+    if sell_price < tiers.premium_threshold {
+        return Err(format!(
+            "sell price {:.4} < premium threshold {:.4}",
+            sell_price, tiers.premium_threshold
+        ));
+    }
+
+    // Calculate if discharging is profitable considering losses, fees and wear
+    let required_spread = input.required_discharge_spread(tiers.cheapest_threshold);
+    let spread = sell_price - tiers.cheapest_threshold;
+
+    if spread < required_spread {
+        return Err(format!(
+            "spread {:.4} < required {:.4} (losses, fees and wear)",
+            spread, required_spread
+        ));
+    }
+
+    // Check if there are enough cheap hours coming to recharge
+    let energy_available = (input.soc - input.min_soc) / 100.0 * input.battery.capacity_kwh;
+    let hours_to_recharge = energy_available / (input.max_charge_power_w / 1000.0 * input.round_trip_efficiency);
+    let slots_needed = (hours_to_recharge / input.slot_hours()).ceil() as usize;
+
+    let cheap_slots = input.count_slots_below_threshold(tiers.cheap_threshold);
+
+    if cheap_slots < slots_needed / 2 {
+        return Err(format!(
+            "only {} cheap slots to recharge, need at least {}",
+            cheap_slots,
+            slots_needed / 2
+        ));
+    }
+
+    Ok(OptimizationResult {
+        mode: BatteryMode::DischargeToGrid,
+        grid_setpoint_w: -input.max_discharge_power_w,
+        reason: format!(
+            "Premium sell price {:.4} EUR (threshold {:.4}), discharging to grid. {} cheap slots available for recharge.",
+            sell_price, tiers.premium_threshold, cheap_slots
+        ),
+        alternative: None,
+    })
+}
+
+/// Charge at full power if this slot is among the cheapest ones left before
+/// a pending boost's deadline that reach its target
+fn check_boost(input: &OptimizerInput) -> Option<OptimizationResult> {
+    let boost = input.pending_boost()?;
+    if input.constraints().no_grid_charge.is_some() {
+        return None;
+    }
+    let current = input.current_price;
+    let target_soc = boost.target_soc.min(input.battery.max_soc_percent);
+    let kwh_per_slot = input.max_charge_power_w / 1000.0 * input.slot_hours() * input.round_trip_efficiency.sqrt();
+    let slots_needed = ((target_soc - input.soc) / 100.0 * input.battery.capacity_kwh / kwh_per_slot).ceil() as usize;
+
+    // Slots left before the deadline that the schedule rules let charge
+    let mut window: Vec<f64> = std::iter::once(current)
+        .chain(input.future_prices().filter(|p| p.starts_at > current.starts_at))
+        .filter(|p| p.starts_at < boost.by)
+        .filter(|p| rules::constraints_at(input.schedule_rules, p.starts_at).no_grid_charge.is_none())
+        .map(|p| p.total)
+        .collect();
+    window.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let threshold = window[slots_needed.clamp(1, window.len()) - 1];
+    if current.total > threshold {
+        debug!("Boost: {} slots needed, price {:.4} above {:.4}", slots_needed, current.total, threshold);
+        return None;
+    }
+
+    Some(OptimizationResult {
+        mode: BatteryMode::ChargeFull,
+        grid_setpoint_w: input.max_charge_power_w,
+        reason: format!(
+            "Boost to {:.0}% by {}: price {:.4} EUR is among the {} cheapest slots left, charging at full power. SoC: {:.1}%",
+            target_soc,
+            boost.by.format("%H:%M"),
+            current.total,
+            slots_needed,
+            input.soc
+        ),
+        alternative: None,
+    })
+}
+
+/// While importing pays, charge at full power regardless of the charge plan,
+/// and once full let the grid cover the house so no PV is fed in
+fn check_negative_price(input: &OptimizerInput) -> Option<OptimizationResult> {
+    let price = input.current_price.total;
+    if price >= 0.0 || input.constraints().no_grid_charge.is_some() {
+        return None;
+    }
+
+    if input.soc < input.battery.max_soc_percent {
+        return Some(OptimizationResult {
+            mode: BatteryMode::ChargeFull,
+            grid_setpoint_w: input.max_charge_power_w,
+            reason: format!(
+                "Negative price {:.4} EUR, charging at full power. SoC: {:.1}% -> max {:.1}%",
+                price, input.soc, input.battery.max_soc_percent
+            ),
+            alternative: None,
+        });
+    }
+
+    Some(OptimizationResult {
+        mode: BatteryMode::Idle,
+        grid_setpoint_w: input.house_load_w(input.current_price.starts_at),
+        reason: format!(
+            "Negative price {:.4} EUR, battery full at {:.1}%: grid covers house load, no feed-in",
+            price, input.soc
+        ),
+        alternative: None,
+    })
+}
+
+/// Grid charging at this price and SoC, else why not
+fn check_charging(input: &OptimizerInput, price: f64) -> Result<OptimizationResult, String> {
+    let soc = input.soc;
+    let tiers = &input.tiers;
+
+    if let Some(rule) = input.constraints().no_grid_charge {
+        return Err(format!("schedule rule '{}' forbids grid charging", rule));
+    }
+
+    // Don't charge if already at max SoC
+    if soc >= input.battery.max_soc_percent {
+        return Err(format!("SoC {:.1}% at max {:.1}%", soc, input.battery.max_soc_percent));
+    }
+
+    // Calculate charge planning parameters
+    let plan = calculate_charge_plan(input, price);
+
+    debug!(
+        "Charge plan: need {:.1}kWh, {} cheap slots available, {} cheapest slots, target SoC: {:.1}%",
+        plan.energy_needed_kwh, plan.cheap_slots_available, plan.cheapest_slots_available, plan.target_soc
+    );
+
+    // Mid-tier prices only top the battery up partially
+    let target_soc = plan.target_soc.min(charge_soc_cap(input, price));
+
+    // FULL POWER charging during the absolute cheapest slots
+    if price <= tiers.cheapest_threshold && soc < target_soc {
+        return Ok(OptimizationResult {
+            mode: BatteryMode::ChargeFull,
+            grid_setpoint_w: input.max_charge_power_w,
+            reason: format!(
+                "Cheapest price tier {:.4} EUR, charging at full power. SoC: {:.1}% -> target {:.1}%",
+                price, soc, target_soc
+            ),
+            alternative: None,
+        });
+    }
+
+    // Charging during cheap (but not cheapest) slots
+    // Always charge if we're in a cheap slot and haven't reached target
+    if price <= tiers.cheap_threshold && soc < target_soc {
+        // Calculate how aggressively we need to charge based on available slots
+        // Below the inverter's efficient range losses climb, so the same
+        // energy goes in over fewer slots at a higher power instead
+        let efficient_factor = input
+            .battery
+            .efficient_charge_power_w(EFFICIENCY_TOLERANCE)
+            .map_or(0.0, |power_w| (power_w / input.max_charge_power_w).min(1.0));
+        let power_factor = calculate_charge_power_factor(&plan, price, tiers).max(efficient_factor);
+        let charge_power = input.max_charge_power_w * power_factor;
+
+        return Ok(OptimizationResult {
+            mode: if power_factor >= 0.9 { BatteryMode::ChargeFull } else { BatteryMode::ChargeReduced },
+            grid_setpoint_w: charge_power,
+            reason: format!(
+                "Cheap price tier {:.4} EUR, charging at {:.0}% power ({:.0}W). SoC: {:.1}% -> target {:.1}%, {} slots remaining",
+                price, power_factor * 100.0, charge_power, soc, target_soc, plan.cheap_slots_available
+            ),
+            alternative: None,
+        });
+    }
+
+    // Emergency charging if SoC is critically low
+    if soc < input.min_soc + 5.0 && price < tiers.expensive_threshold {
+        return Ok(OptimizationResult {
+            mode: BatteryMode::ChargeReduced,
+            grid_setpoint_w: input.max_charge_power_w * 0.5,
+            reason: format!(
+                "Critical SoC {:.1}%, emergency charging at 50% power despite moderate price {:.4} EUR",
+                soc, price
+            ),
+            alternative: None,
+        });
+    }
+
+    if price > tiers.cheap_threshold {
+        Err(format!("price {:.4} > cheap threshold {:.4}", price, tiers.cheap_threshold))
+    } else {
+        Err(format!("SoC {:.1}% already at target {:.1}%", soc, target_soc))
+    }
+}
+
+/// Calculate a forward-looking charge plan
+fn calculate_charge_plan(input: &OptimizerInput, price: f64) -> ChargePlan {
+    let tiers = &input.tiers;
+    let current_time = input.current_price.starts_at;
+    let capacity = input.battery.capacity_kwh;
+
+    // Count cheap and cheapest slots
+    let cheap_slots_available = input.count_slots_below_threshold(tiers.cheap_threshold);
+    let cheapest_slots_available = input.count_slots_below_threshold(tiers.cheapest_threshold);
+
+    // Calculate hours until next cheap period (for planning reserves)
+    let hours_until_cheap = hours_until_next_cheap_period(input);
+
+    // Estimate energy consumption during expensive period, plus announced
+    // events before cheap prices return
+    let recharge_at = end_of_next_expensive_period(input).unwrap_or(current_time + Duration::hours(24));
+    let consumption_kwh = input.house_load_kwh(
+        current_time,
+        current_time + Duration::seconds((hours_until_cheap * 3600.0) as i64),
+    ) + input.event_energy_kwh(current_time, recharge_at);
+
+    // Size the reserve for the upper end of the SoC feedback's interval
+    let uncertainty_kwh = input
+        .load_correction
+        .map_or(0.0, |c| (c.upper_w - c.bias_w) / 1000.0 * hours_until_cheap);
+
+    // Target SoC: enough to cover consumption until next cheap period + buffer
+    // Minimum target is to always have reserves for one expensive cycle
+    let buffer_percent = input.away.map_or(HOME_BUFFER_PERCENT, |away| away.buffer_percent);
+    let min_reserve_kwh = consumption_kwh + uncertainty_kwh + capacity * buffer_percent / 100.0;
+    // Reserves that schedule rules hold before then have to be charged now too
+    let min_reserve_soc = (min_reserve_kwh / capacity * 100.0)
+        .max(input.scheduled_reserve(current_time, recharge_at))
+        .min(input.battery.max_soc_percent);
+
+    // Only fill up completely if now is the cheapest chance before the battery
+    // is needed again. If cheaper slots follow the next expensive period
+    // (e.g. tomorrow night), just cover the reserve until then.
+    let mut target_soc = match cheapest_price_after_next_expensive_period(input) {
+        Some(later_price) if later_price < price => {
+            debug!(
+                "Cheaper price {:.4} follows the next expensive period, charging to reserve only",
+                later_price
+            );
+            min_reserve_soc
+        }
+        _ => input.battery.max_soc_percent,
+    };
+
+    // Leave room for forecast PV surplus that would fill the battery for free
+    if let Some(pv) = input.pv_forecast {
+        let pv_kwh = pv.surplus_kwh(
+            current_time,
+            current_time + Duration::hours(24),
+            input.optimizer.base_consumption_w,
+        );
+        let pv_soc = pv_kwh / capacity * 100.0;
+        target_soc = target_soc.min(input.battery.max_soc_percent - pv_soc);
+    }
+    let target_soc = target_soc.max(min_reserve_soc).max(input.min_soc);
+
+    // Energy needed to reach target
+    let energy_needed_kwh = (target_soc - input.soc) / 100.0 * capacity;
+
+    // Effective charge rate per slot
+    let kwh_per_slot = (input.max_charge_power_w / 1000.0) * input.slot_hours() * input.round_trip_efficiency;
+
+    // Slots needed at full power
+    let slots_needed_full_power = (energy_needed_kwh / kwh_per_slot).ceil() as usize;
+
+    ChargePlan {
+        target_soc,
+        energy_needed_kwh,
+        cheap_slots_available,
+        cheapest_slots_available,
+        slots_needed_full_power,
+    }
+}
+
+/// Highest SoC to grid-charge to at this price's tier
+fn charge_soc_cap(input: &OptimizerInput, price: f64) -> f64 {
+    let cap = if price <= input.tiers.cheapest_threshold {
+        input.optimizer.cheapest_charge_soc_percent
+    } else {
+        input.optimizer.cheap_charge_soc_percent
+    };
+    cap.unwrap_or(input.battery.max_soc_percent)
+}
+
+/// Calculate how aggressively we should charge based on available slots and energy needed
+fn calculate_charge_power_factor(plan: &ChargePlan, price: f64, tiers: &PriceTiers) -> f64 {
+    // If we have more cheap slots than needed, we can charge at a lower rate
+    // If we have fewer, we need to charge more aggressively
+
+    if plan.cheap_slots_available == 0 {
+        return 1.0; // Full power if this is our only chance
+    }
+
+    // Calculate the ratio of needed slots to available slots
+    let slot_ratio = plan.slots_needed_full_power as f64 / plan.cheap_slots_available as f64;
+
+    // If we need more slots than available, charge at full power
+    if slot_ratio >= 1.0 {
+        return 1.0;
+    }
+
+    // If we have plenty of slots, scale power based on how cheap this slot is
+    // Cheapest slots: 100% power
+    // Less cheap slots: proportionally less, but minimum 40%
+    let price_range = tiers.cheap_threshold - tiers.cheapest_threshold;
+    if price_range <= 0.0 {
+        return 1.0;
+    }
+
+    let price_position = ((price - tiers.cheapest_threshold) / price_range).clamp(0.0, 1.0);
+
+    // Scale from 100% at cheapest to 40% at cheap threshold
+    // But increase if we're running low on slots
+    let base_factor = 1.0 - (price_position * 0.6);
+
+    // Adjust based on slot availability - if running low, charge harder
+    let urgency_factor = slot_ratio.max(0.4);
+
+    (base_factor * urgency_factor).clamp(0.4, 1.0)
+}
+
+/// Calculate hours until the next cheap price period
+fn hours_until_next_cheap_period(input: &OptimizerInput) -> f64 {
+    // Find the first expensive slot, then find how long until cheap prices return
+    let mut expensive_start: Option<DateTime<FixedOffset>> = None;
+
+    for price in input.future_prices() {
+        if price.total > input.tiers.cheap_threshold {
+            expensive_start.get_or_insert(price.starts_at);
+        } else if let Some(start) = expensive_start {
+            // Found cheap price after expensive period
+            let duration = price.starts_at.signed_duration_since(start);
+            return duration.num_minutes() as f64 / 60.0;
+        }
+    }
+
+    // If we didn't find a transition, estimate based on typical daily cycle
+    // Assume ~8 hours of expensive period
+    8.0
+}
+
+/// Start of the first cheap slot after the upcoming expensive period
+fn end_of_next_expensive_period(input: &OptimizerInput) -> Option<DateTime<FixedOffset>> {
+    let mut seen_expensive = false;
+    for price in input.future_prices() {
+        if price.total > input.tiers.cheap_threshold {
+            seen_expensive = true;
+        } else if seen_expensive {
+            return Some(price.starts_at);
+        }
+    }
+    None
+}
+
+/// Lowest price after the upcoming expensive period has ended
+fn cheapest_price_after_next_expensive_period(input: &OptimizerInput) -> Option<f64> {
+    let mut seen_expensive = false;
+    let mut after_expensive = false;
+    let mut cheapest: Option<f64> = None;
+
+    for price in input.future_prices() {
+        if !after_expensive {
+            if price.total > input.tiers.cheap_threshold {
+                seen_expensive = true;
+            } else if seen_expensive {
+                after_expensive = true;
+            }
+        }
+        if after_expensive {
+            cheapest = Some(cheapest.map_or(price.total, |c: f64| c.min(price.total)));
+        }
+    }
+
+    cheapest
+}
+
+fn determine_self_consumption_mode(input: &OptimizerInput, price: f64) -> OptimizationResult {
+    let tiers = &input.tiers;
+    let offset = input.optimizer.setpoint_offset_w;
+    let sell_price = input.current_price.sell_price();
+
+    if sell_price < 0.0 {
+        // Feed-in costs money - keep drawing a little so PV surplus goes to the battery
+        OptimizationResult {
+            mode: BatteryMode::SelfConsumptionPreventFeedIn,
+            grid_setpoint_w: offset,
+            reason: format!(
+                "Negative sell price {:.4} EUR, setpoint +{:.0}W to prevent feed-in",
+                sell_price, offset
+            ),
+            alternative: None,
+        }
+    } else if price >= tiers.expensive_threshold {
+        // High price - prevent pulling from grid, prefer battery
+        // Negative setpoint means "try to feed X watts to grid" which forces battery use
+        OptimizationResult {
+            mode: BatteryMode::SelfConsumptionPreventGridPull,
+            grid_setpoint_w: -offset,
+            reason: format!(
+                "Expensive price {:.4} EUR (>= {:.4}), setpoint -{:.0}W to prevent grid pull",
+                price, tiers.expensive_threshold, offset
+            ),
+            alternative: None,
+        }
+    } else if price <= tiers.cheap_threshold {
+        // Low price but not charging (already full?) - prevent feeding back to grid
+        OptimizationResult {
+            mode: BatteryMode::SelfConsumptionPreventFeedIn,
+            grid_setpoint_w: offset,
+            reason: format!(
+                "Low price {:.4} EUR but not charging, setpoint +{:.0}W to prevent feed-in",
+                price, offset
+            ),
+            alternative: None,
+        }
+    } else {
+        // Moderate price - slight positive offset to prefer grid over battery discharge
+        OptimizationResult {
+            mode: BatteryMode::SelfConsumption,
+            grid_setpoint_w: offset,
+            reason: format!(
+                "Moderate price {:.4} EUR, setpoint +{:.0}W (preserve battery for expensive periods)",
+                price, offset
+            ),
+            alternative: None,
+        }
+    }
+}
+
+/// Project the optimizer's decisions over the current and all future slots,
+/// simulating the SoC trajectory that results from them
+pub fn plan(input: &OptimizerInput) -> Vec<PlannedSlot> {
+    if input.optimizer.planner == Planner::Optimal {
+        return optimal::solve(input).slots;
+    }
+
+    let capacity = input.battery.capacity_kwh;
+    let one_way_efficiency = input.round_trip_efficiency.sqrt();
+
+    let mut soc = input.soc;
+    std::iter::once(input.current_price)
+        .chain(input.future_prices())
+        .map(|slot| {
+            let result = optimize(&OptimizerInput {
+                soc,
+                current_price: slot,
+                ..input.clone()
+            });
+
+            let slot_hours = slot.duration().num_seconds() as f64 / 3600.0;
+
+            // Grid = house load + battery power, so the battery covers the difference
+            let event_w = input.event_energy_kwh(slot.starts_at, slot.ends_at) / slot_hours * 1000.0;
+            let house_w = input.house_load_w(slot.starts_at) + event_w;
+            let requested_w = (result.grid_setpoint_w - house_w)
+                .clamp(-input.max_discharge_power_w, input.max_charge_power_w);
+            let delta_kwh = if requested_w >= 0.0 {
+                requested_w / 1000.0 * slot_hours * one_way_efficiency
+            } else {
+                requested_w / 1000.0 * slot_hours / one_way_efficiency
+            };
+
+            let start_soc = soc;
+            let floor = input.min_soc.min(start_soc);
+            let ceiling = input.battery.max_soc_percent.max(start_soc);
+            soc = (start_soc + delta_kwh / capacity * 100.0).clamp(floor, ceiling);
+
+            // Battery power actually realized once SoC limits are applied
+            let realized_kwh = (soc - start_soc) / 100.0 * capacity;
+            let battery_power_w = if realized_kwh >= 0.0 {
+                realized_kwh / one_way_efficiency / slot_hours * 1000.0
+            } else {
+                realized_kwh * one_way_efficiency / slot_hours * 1000.0
+            };
+
+            PlannedSlot {
+                starts_at: slot.starts_at,
+                ends_at: slot.ends_at,
+                price: slot.total,
+                sell_price: slot.sell_price(),
+                grid_power_w: house_w + battery_power_w,
+                grid_setpoint_w: result.grid_setpoint_w,
+                cheap: slot.total <= input.tiers.cheap_threshold,
+                mode: result.mode,
+                battery_power_w,
+                soc_end: soc,
+            }
+        })
+        .collect()
+}
+
+/// Information about upcoming price conditions
+pub fn forecast_info(input: &OptimizerInput) -> ForecastInfo {
+    let tiers = &input.tiers;
+    let mut future = input.future_prices();
+
+    let next_cheap = future
+        .clone()
+        .find(|p| p.total <= tiers.cheapest_threshold)
+        .map(|p| p.starts_at.to_rfc3339());
+
+    let next_expensive = future
+        .find(|p| p.sell_price() >= tiers.premium_threshold)
+        .map(|p| p.starts_at.to_rfc3339());
+
+    ForecastInfo {
+        next_cheap_slot: next_cheap,
+        next_expensive_slot: next_expensive,
+        cheap_slots_remaining: input.count_slots_below_threshold(tiers.cheap_threshold),
+        cheapest_slots_remaining: input.count_slots_below_threshold(tiers.cheapest_threshold),
+        required_discharge_spread: input.required_discharge_spread(tiers.cheapest_threshold),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    //! Golden cases for the documented decisions. A change to the decision
+    //! logic that alters one of these outputs should be deliberate and update
+    //! the table (and the README) alongside.
+
+    use super::*;
+    use crate::config::{ExportConfig, ExportModel, TariffConfig};
+    use crate::prices::SlotOrigin;
+    use chrono::{TimeZone, Timelike};
+
+    /// Hourly prices of the test day (EUR/kWh): cheap night, morning and
+    /// evening peaks, moderate midday
+    const HOURLY: [f64; 24] = [
+        0.12, 0.11, 0.10, 0.10, 0.11, 0.13, // 00-05
+        0.25, 0.35, 0.38, 0.30, // 06-09
+        0.24, 0.22, 0.20, 0.21, 0.23, 0.26, // 10-15
+        0.35, 0.42, 0.48, 0.45, 0.38, // 16-20
+        0.28, 0.22, 0.18, // 21-23
+    ];
+
+    fn day_start() -> DateTime<FixedOffset> {
+        FixedOffset::east_opt(3600).unwrap().with_ymd_and_hms(2025, 12, 1, 0, 0, 0).unwrap()
+    }
+
+    /// The test day's prices starting `day` days after it
+    fn day(day: i64) -> Vec<PricePoint> {
+        (0..96)
+            .map(|slot| PricePoint {
+                total: HOURLY[slot / 4],
+                energy: HOURLY[slot / 4],
+                tax: 0.0,
+                starts_at: day_start() + Duration::days(day) + Duration::minutes(15 * slot as i64),
+                ends_at: day_start() + Duration::days(day) + Duration::minutes(15 * (slot as i64 + 1)),
+                level: None,
+                currency: None,
+                sell: None,
+                source: SlotOrigin::Published,
+            })
+            .collect()
+    }
+
+    /// Today and tomorrow repeat the same day
+    fn prices() -> PriceCache {
+        PriceCache::new(day(0).into_iter().chain(day(1)).collect(), None).with_generation(1)
+    }
+
+    /// The test day and the next in hourly slots
+    fn hourly_prices() -> PriceCache {
+        let slots = prices()
+            .slots()
+            .chunks(4)
+            .map(|hour| PricePoint {
+                ends_at: hour[3].ends_at,
+                ..hour[0].clone()
+            })
+            .collect();
+        PriceCache::new(slots, None).with_generation(1)
+    }
+
+    /// `prices` keeping only the slots `keep` accepts by index
+    fn prices_where(keep: impl Fn(usize) -> bool) -> PriceCache {
+        let slots = prices().slots().iter().enumerate().filter(|(i, _)| keep(*i)).map(|(_, p)| p.clone()).collect();
+        PriceCache::new(slots, None).with_generation(1)
+    }
+
+    /// Settings a case can adjust before deciding
+    struct Fixture {
+        battery: BatteryConfig,
+        optimizer: OptimizerConfig,
+        min_soc: f64,
+        max_charge_power_w: f64,
+        events: Vec<ConsumptionEvent>,
+        rules: Vec<ScheduleRule>,
+        floors: Vec<SocFloor>,
+        boost: Option<Boost>,
+        export: ExportConfig,
+        /// Added to every slot's price, e.g. to push the night below zero
+        price_offset: f64,
+    }
+
+    impl Fixture {
+        fn new() -> Self {
+            let battery: BatteryConfig = serde_yaml::from_str("capacity_kwh: 10\nround_trip_efficiency: 0.9").unwrap();
+            let optimizer: OptimizerConfig = serde_yaml::from_str("{}").unwrap();
+            Self {
+                min_soc: battery.min_soc_percent,
+                max_charge_power_w: battery.max_charge_power_w,
+                battery,
+                optimizer,
+                events: Vec::new(),
+                rules: Vec::new(),
+                floors: Vec::new(),
+                boost: None,
+                export: ExportConfig::default(),
+                price_offset: 0.0,
+            }
+        }
+
+        /// Run `decide` a minute into `hour` with `soc`, like a control cycle would
+        fn run<R>(&self, hour: i64, soc: f64, prices: &PriceCache, decide: impl FnOnce(&OptimizerInput) -> R) -> R {
+            let now = (day_start() + Duration::hours(hour) + Duration::minutes(1)).with_timezone(&Utc);
+            let current_price = prices.price_at(now).unwrap().clone();
+            let input = OptimizerInput {
+                battery: &self.battery,
+                optimizer: &self.optimizer,
+                now,
+                soc,
+                current_price: &current_price,
+                prices,
+                tiers: PriceTiers::compute(&self.optimizer, prices, now),
+                min_soc: self.min_soc,
+                round_trip_efficiency: self.battery.round_trip_efficiency,
+                max_charge_power_w: self.max_charge_power_w,
+                max_discharge_power_w: self.battery.max_discharge_power_w,
+                pv_forecast: None,
+                consumption_events: &self.events,
+                schedule_rules: &self.rules,
+                soc_floors: &self.floors,
+                boost: self.boost,
+                load_profile: None,
+                load_correction: None,
+                peak_limit: None,
+                away: None,
+            };
+            decide(&input)
+        }
+    }
+
+    struct Case {
+        name: &'static str,
+        hour: i64,
+        soc: f64,
+        setup: fn(&mut Fixture),
+        mode: BatteryMode,
+        setpoint_w: f64,
+    }
+
+    fn defaults(_: &mut Fixture) {}
+
+    const CASES: &[Case] = &[
+        Case {
+            name: "cheapest slot charges at full power",
+            hour: 2,
+            soc: 30.0,
+            setup: defaults,
+            mode: BatteryMode::ChargeFull,
+            setpoint_w: 15000.0,
+        },
+        Case {
+            name: "full battery at a low price prevents feed-in",
+            hour: 2,
+            soc: 100.0,
+            setup: defaults,
+            mode: BatteryMode::SelfConsumptionPreventFeedIn,
+            setpoint_w: 200.0,
+        },
+        Case {
+            name: "moderate price preserves the battery",
+            hour: 11,
+            soc: 60.0,
+            setup: defaults,
+            mode: BatteryMode::SelfConsumption,
+            setpoint_w: 200.0,
+        },
+        Case {
+            name: "expensive price prevents grid pull",
+            hour: 7,
+            soc: 60.0,
+            setup: defaults,
+            mode: BatteryMode::SelfConsumptionPreventGridPull,
+            setpoint_w: -200.0,
+        },
+        Case {
+            name: "premium price discharges to grid",
+            hour: 18,
+            soc: 90.0,
+            setup: defaults,
+            mode: BatteryMode::DischargeToGrid,
+            setpoint_w: -15000.0,
+        },
+        Case {
+            name: "premium price without spare SoC only prevents grid pull",
+            hour: 18,
+            soc: 20.0,
+            setup: defaults,
+            mode: BatteryMode::SelfConsumptionPreventGridPull,
+            setpoint_w: -200.0,
+        },
+        Case {
+            name: "wear cost makes discharge unprofitable",
+            hour: 18,
+            soc: 90.0,
+            setup: |f| f.optimizer.wear_cost_per_kwh = 0.5,
+            mode: BatteryMode::SelfConsumptionPreventGridPull,
+            setpoint_w: -200.0,
+        },
+        Case {
+            name: "wear cost above the spread holds the battery",
+            hour: 11,
+            soc: 60.0,
+            setup: |f| f.optimizer.wear_cost_per_kwh = 0.15,
+            mode: BatteryMode::Idle,
+            setpoint_w: 500.0,
+        },
+        Case {
+            name: "low feed-in tariff makes discharge unprofitable",
+            hour: 18,
+            soc: 90.0,
+            setup: |f| {
+                f.export.model = ExportModel::Fixed;
+                f.export.feed_in_tariff = 0.07;
+            },
+            mode: BatteryMode::SelfConsumptionPreventGridPull,
+            setpoint_w: -200.0,
+        },
+        Case {
+            name: "reserve reached at an expensive price holds the battery",
+            hour: 17,
+            soc: 10.0,
+            setup: defaults,
+            mode: BatteryMode::Idle,
+            setpoint_w: 500.0,
+        },
+        Case {
+            name: "raised minimum SoC is respected",
+            hour: 17,
+            soc: 30.0,
+            setup: |f| f.min_soc = 40.0,
+            mode: BatteryMode::Idle,
+            setpoint_w: 500.0,
+        },
+        Case {
+            name: "commanded SoC floor holds the battery before its end",
+            hour: 17,
+            soc: 55.0,
+            setup: |f| {
+                f.floors.push(SocFloor {
+                    id: "backup".to_string(),
+                    min_soc: 60.0,
+                    until: day_start() + Duration::hours(20),
+                    reason: None,
+                })
+            },
+            mode: BatteryMode::Idle,
+            setpoint_w: 500.0,
+        },
+        Case {
+            name: "critical SoC charges at half power",
+            hour: 11,
+            soc: 12.0,
+            setup: defaults,
+            mode: BatteryMode::ChargeReduced,
+            setpoint_w: 7500.0,
+        },
+        Case {
+            name: "observed power limit caps charging",
+            hour: 2,
+            soc: 30.0,
+            setup: |f| f.max_charge_power_w = 8000.0,
+            mode: BatteryMode::ChargeFull,
+            setpoint_w: 8000.0,
+        },
+        Case {
+            name: "cheapest-tier SoC cap stops charging",
+            hour: 2,
+            soc: 75.0,
+            setup: |f| f.optimizer.cheapest_charge_soc_percent = Some(70.0),
+            mode: BatteryMode::SelfConsumptionPreventFeedIn,
+            setpoint_w: 200.0,
+        },
+        Case {
+            name: "cheaper slots after the next peak limit charging to the reserve",
+            hour: 5,
+            soc: 60.0,
+            setup: defaults,
+            mode: BatteryMode::SelfConsumptionPreventFeedIn,
+            setpoint_w: 200.0,
+        },
+        Case {
+            name: "announced consumption raises the reserve",
+            hour: 5,
+            soc: 60.0,
+            setup: |f| {
+                f.events.push(ConsumptionEvent {
+                    name: "ev".to_string(),
+                    start: day_start() + Duration::hours(7),
+                    end: day_start() + Duration::hours(10),
+                    energy_kwh: 6.0,
+                })
+            },
+            mode: BatteryMode::ChargeReduced,
+            setpoint_w: 6000.0,
+        },
+        Case {
+            name: "reduced charging stays in the inverter's efficient range",
+            hour: 5,
+            soc: 60.0,
+            setup: |f| {
+                f.events.push(ConsumptionEvent {
+                    name: "ev".to_string(),
+                    start: day_start() + Duration::hours(7),
+                    end: day_start() + Duration::hours(10),
+                    energy_kwh: 6.0,
+                });
+                f.battery.efficiency_curve = serde_yaml::from_str(
+                    "[{power_w: 1000, efficiency: 0.5}, {power_w: 9000, efficiency: 0.9}, {power_w: 15000, efficiency: 0.92}]",
+                )
+                .unwrap();
+            },
+            mode: BatteryMode::ChargeReduced,
+            setpoint_w: 9000.0,
+        },
+        Case {
+            name: "negative price charges at full power past the charge plan",
+            hour: 2,
+            soc: 80.0,
+            setup: |f| f.price_offset = -0.15,
+            mode: BatteryMode::ChargeFull,
+            setpoint_w: 15000.0,
+        },
+        Case {
+            name: "negative price with a full battery lets the grid cover the house",
+            hour: 2,
+            soc: 100.0,
+            setup: |f| f.price_offset = -0.15,
+            mode: BatteryMode::Idle,
+            setpoint_w: 500.0,
+        },
+        Case {
+            name: "optimal planner charges at full power at a negative price",
+            hour: 2,
+            soc: 80.0,
+            setup: |f| {
+                f.price_offset = -0.15;
+                f.optimizer.planner = Planner::Optimal;
+            },
+            mode: BatteryMode::ChargeFull,
+            setpoint_w: 15000.0,
+        },
+        Case {
+            name: "optimal planner with a full battery lets the grid cover the house at a negative price",
+            hour: 2,
+            soc: 100.0,
+            setup: |f| {
+                f.price_offset = -0.15;
+                f.optimizer.planner = Planner::Optimal;
+            },
+            mode: BatteryMode::Idle,
+            setpoint_w: 500.0,
+        },
+        Case {
+            name: "negative sell price prevents feed-in",
+            hour: 11,
+            soc: 60.0,
+            setup: |f| {
+                f.export.model = ExportModel::Fixed;
+                f.export.feed_in_tariff = -0.02;
+            },
+            mode: BatteryMode::SelfConsumptionPreventFeedIn,
+            setpoint_w: 200.0,
+        },
+    ];
+
+    #[test]
+    fn golden_decisions() {
+        let mut failures = Vec::new();
+        for case in CASES {
+            let mut fixture = Fixture::new();
+            (case.setup)(&mut fixture);
+            let slots = prices()
+                .slots()
+                .iter()
+                .map(|p| PricePoint {
+                    total: p.total + fixture.price_offset,
+                    energy: p.energy + fixture.price_offset,
+                    ..p.clone()
+                })
+                .collect();
+            let mut prices = PriceCache::new(slots, None).with_generation(1);
+            prices.apply_tariff(&TariffConfig::default(), &fixture.export);
+            let result = fixture.run(case.hour, case.soc, &prices, optimize);
+            if result.mode != case.mode || (result.grid_setpoint_w - case.setpoint_w).abs() > 0.5 {
+                failures.push(format!(
+                    "{}: expected {} at {:.0}W, got {} at {:.0}W ({})",
+                    case.name, case.mode, case.setpoint_w, result.mode, result.grid_setpoint_w, result.reason
+                ));
+            }
+        }
+        assert!(failures.is_empty(), "golden cases failed:\n{}", failures.join("\n"));
+    }
+
+    #[test]
+    fn horizon_limits_tiers_and_plan() {
+        let mut fixture = Fixture::new();
+        fixture.optimizer.horizon_hours = Some(12.0);
+        let prices = prices();
+        let plan = fixture.run(0, 30.0, &prices, plan);
+        // The current slot plus the 48 slots starting before 12:01
+        assert_eq!(plan.len(), 49);
+
+        // Looking six hours ahead the morning peak is premium; over the whole
+        // cache only the evening peak is
+        fixture.optimizer.horizon_hours = Some(6.0);
+        let tiers = fixture.run(2, 60.0, &prices, |input| input.tiers.clone());
+        assert_eq!(tiers.premium_threshold, 0.35);
+        fixture.optimizer.horizon_hours = None;
+        let tiers = fixture.run(2, 60.0, &prices, |input| input.tiers.clone());
+        assert_eq!(tiers.premium_threshold, 0.42);
+    }
+
+    #[test]
+    fn stddev_tiers_label_no_outliers_on_a_flat_day() {
+        let mut fixture = Fixture::new();
+        let slots = day(0).into_iter().chain(day(1)).map(|slot| PricePoint {
+            total: 0.25 + if slot.starts_at.hour() == 3 { 0.0001 } else { 0.0 },
+            ..slot
+        });
+        let flat = PriceCache::new(slots.collect(), None).with_generation(1);
+        // Percentiles still find a cheapest tier a hundredth of a cent lower
+        let result = fixture.run(2, 30.0, &flat, optimize);
+        assert_eq!(result.mode, BatteryMode::ChargeFull);
+
+        fixture.optimizer.tier_method = TierMethod::Stddev;
+        let tiers = fixture.run(2, 30.0, &flat, |input| input.tiers.clone());
+        assert!(tiers.cheapest_threshold < 0.25 && tiers.premium_threshold > 0.2501);
+        let result = fixture.run(2, 30.0, &flat, optimize);
+        assert_eq!(result.mode, BatteryMode::SelfConsumption);
+
+        // On the test day the night valley is still an outlier
+        fixture.optimizer.tier_method = TierMethod::Combined;
+        let result = fixture.run(2, 30.0, &prices(), optimize);
+        assert_eq!(result.mode, BatteryMode::ChargeFull);
+    }
+
+    #[test]
+    fn explanation_names_the_runner_up() {
+        let mut fixture = Fixture::new();
+        // Wear eats the whole spread at the evening peak
+        fixture.optimizer.wear_cost_per_kwh = 1.0;
+        let result = fixture.run(18, 80.0, &prices(), optimize);
+        assert_eq!(result.mode, BatteryMode::SelfConsumptionPreventGridPull);
+        let alternative = result.alternative.unwrap();
+        assert_eq!(alternative.mode, BatteryMode::DischargeToGrid);
+        assert!(alternative.reason.starts_with("spread "), "{}", alternative.reason);
+
+        // Charging at the cheapest price beat discharging
+        let result = fixture.run(2, 30.0, &prices(), optimize);
+        assert_eq!(result.alternative.unwrap().mode, BatteryMode::DischargeToGrid);
+    }
+
+    #[test]
+    fn schedule_rules_constrain_decisions_and_plan() {
+        let mut fixture = Fixture::new();
+        // The test day is a Monday
+        fixture.rules = serde_yaml::from_str(
+            "- {name: evening, days: [mon], start: '17:00', end: '20:00', no_grid_discharge: true}\n\
+             - {name: night, start: '22:00', end: '03:00', no_grid_charge: true}\n\
+             - {name: reserve, days: [tue], min_soc: 60}",
+        )
+        .unwrap();
+        let prices = prices();
+
+        let result = fixture.run(18, 90.0, &prices, optimize);
+        assert_eq!(result.mode, BatteryMode::SelfConsumptionPreventGridPull);
+        assert!(result.alternative.unwrap().reason.contains("'evening'"));
+        // Tuesday's peak still sells, down to the reserve only
+        assert_eq!(fixture.run(42, 90.0, &prices, optimize).mode, BatteryMode::DischargeToGrid);
+        assert_ne!(fixture.run(42, 70.0, &prices, optimize).mode, BatteryMode::DischargeToGrid);
+        // The night window runs past midnight
+        let night = fixture.run(2, 30.0, &prices, optimize).mode;
+        assert!(!matches!(night, BatteryMode::ChargeFull | BatteryMode::ChargeReduced), "{}", night);
+        assert_eq!(fixture.run(3, 30.0, &prices, optimize).mode, BatteryMode::ChargeFull);
+
+        // The optimal plan charges ahead of Tuesday's reserve and sells nothing Monday evening
+        fixture.optimizer.planner = Planner::Optimal;
+        let plan = fixture.run(0, 30.0, &prices, plan);
+        assert!(plan[68..80].iter().all(|s| s.mode != BatteryMode::DischargeToGrid));
+        assert!(plan[96..].iter().all(|s| s.soc_end >= 59.5), "{:?}", plan[96..].iter().map(|s| s.soc_end).fold(f64::MAX, f64::min));
+        assert!(plan[88..96].iter().all(|s| s.battery_power_w <= 0.0));
+    }
+
+    #[test]
+    fn boost_charges_in_the_cheapest_slots_before_its_deadline() {
+        let mut fixture = Fixture::new();
+        fixture.boost = Some(Boost {
+            target_soc: 80.0,
+            by: day_start() + Duration::hours(15),
+        });
+        let prices = prices();
+
+        // 5 kWh takes two slots at full power; 12:00 is the cheapest hour before 15:00
+        assert_ne!(fixture.run(11, 30.0, &prices, optimize).mode, BatteryMode::ChargeFull);
+        let result = fixture.run(12, 30.0, &prices, optimize);
+        assert_eq!(result.mode, BatteryMode::ChargeFull, "{}", result.reason);
+        assert_ne!(fixture.run(12, 85.0, &prices, optimize).mode, BatteryMode::ChargeFull);
+        // Past the deadline the boost no longer applies
+        assert_ne!(fixture.run(16, 30.0, &prices, optimize).mode, BatteryMode::ChargeFull);
+
+        for planner in [Planner::Tiers, Planner::Optimal] {
+            fixture.optimizer.planner = planner;
+            let plan = fixture.run(11, 30.0, &prices, plan);
+            // Slot 15 ends at 15:00
+            assert!(plan[15].soc_end >= 79.5, "{:?}: SoC at 15:00 was {:.1}", planner, plan[15].soc_end);
+            assert!(plan[..4].iter().all(|s| s.battery_power_w <= 0.0), "{:?} charged at 11:00", planner);
+        }
+    }
+
+    #[test]
+    fn missing_slots_are_interpolated() {
+        let fixture = Fixture::new();
+        let complete = prices();
+        // 02:15-02:45 and the first hour of tomorrow are missing
+        let mut gappy = prices_where(|i| !(9..12).contains(&i) && !(96..100).contains(&i));
+        assert_eq!(gappy.fill_gaps(), 7);
+        assert_eq!(gappy.slots().len(), 192);
+        assert!(gappy.slots()[10].is_estimated() && !gappy.slots()[12].is_estimated());
+        assert_eq!(gappy.slots()[10].starts_at, complete.slots()[10].starts_at);
+        assert_eq!(gappy.slots()[96].ends_at, complete.slots()[96].ends_at);
+
+        // The night valley keeps its full length for slot counting
+        let count = |prices: &PriceCache| fixture.run(0, 30.0, prices, |input| input.count_slots_below_threshold(0.11));
+        assert_eq!(count(&gappy), count(&complete));
+    }
+
+    #[test]
+    fn repeated_cycles_leave_the_heap_flat() {
+        let fixture = Fixture::new();
+        let prices = prices();
+        let cycle = |hour| {
+            let result = fixture.run(hour, 60.0, &prices, optimize);
+            let plan = fixture.run(hour, 60.0, &prices, plan);
+            (result, plan)
+        };
+        // Warm up lazily initialized state first
+        drop(cycle(0));
+
+        let before = crate::memory::thread_heap_bytes();
+        for hour in (0..24).cycle().take(500) {
+            drop(cycle(hour));
+        }
+        let leaked = crate::memory::thread_heap_bytes() - before;
+        assert_eq!(leaked, 0, "500 cycles left {} bytes allocated", leaked);
+    }
+
+    #[test]
+    fn no_prices_falls_back_to_self_consumption() {
+        let fixture = Fixture::new();
+        // Nothing left after the current slot
+        let prices = prices_where(|i| i == 0);
+        let result = fixture.run(0, 50.0, &prices, optimize);
+        assert_eq!(result.mode, BatteryMode::SelfConsumption);
+        assert_eq!(result.grid_setpoint_w, 200.0);
+    }
+
+    /// The test day's prices pulled towards (below 1) or pushed away from
+    /// (above 1) their mean by `factor`
+    fn around_mean(factor: f64) -> PriceCache {
+        let mean = HOURLY.iter().sum::<f64>() / HOURLY.len() as f64;
+        let slots = prices()
+            .slots()
+            .iter()
+            .map(|p| PricePoint {
+                total: mean + (p.total - mean) * factor,
+                energy: mean + (p.energy - mean) * factor,
+                ..p.clone()
+            })
+            .collect();
+        PriceCache::new(slots, None).with_generation(1)
+    }
+
+    #[test]
+    fn discharge_spread_adapts_to_volatility() {
+        let fixture = Fixture::new();
+        let spreads = |prices: &PriceCache| {
+            fixture.run(18, 90.0, prices, |input| {
+                let tiers = &input.tiers;
+                let required = input.required_discharge_spread(tiers.cheapest_threshold);
+                (tiers.premium_threshold - tiers.cheapest_threshold, required)
+            })
+        };
+
+        // A calm day doesn't earn back what cycling costs. A volatile one
+        // charges cheaper, so less of its wider spread is lost to efficiency.
+        let (calm, calm_required) = spreads(&around_mean(0.2));
+        let (volatile, volatile_required) = spreads(&around_mean(1.5));
+        assert!(calm < calm_required, "calm spread {:.4} >= required {:.4}", calm, calm_required);
+        assert!(volatile > volatile_required, "volatile spread {:.4} <= required {:.4}", volatile, volatile_required);
+        assert!(volatile_required < calm_required);
+
+        let result = fixture.run(18, 90.0, &around_mean(0.2), optimize);
+        assert_eq!(result.mode, BatteryMode::SelfConsumptionPreventGridPull);
+        let result = fixture.run(18, 90.0, &around_mean(1.5), optimize);
+        assert_eq!(result.mode, BatteryMode::DischargeToGrid);
+    }
+
+    #[test]
+    fn discharge_spread_without_a_price_range() {
+        let mut fixture = Fixture::new();
+        fixture.optimizer.wear_cost_per_kwh = 0.03;
+
+        // Without prices there are no tiers, and nothing is lost charging at
+        // zero: only the margin and wear remain
+        let required = fixture.run(18, 90.0, &prices(), |input| {
+            let tiers = PriceTiers::compute(input.optimizer, &PriceCache::default(), input.now);
+            assert_eq!(tiers, PriceTiers::default());
+            input.required_discharge_spread(tiers.cheapest_threshold)
+        });
+        assert!((required - 0.08).abs() < 1e-9, "required spread {:.4}", required);
+
+        // A single slot ahead is every tier at once, leaving no spread to earn
+        let single = prices_where(|i| i == 73);
+        let (tiers, required) = fixture.run(18, 90.0, &prices(), |input| {
+            let tiers = PriceTiers::compute(input.optimizer, &single, input.now);
+            let required = input.required_discharge_spread(tiers.cheapest_threshold);
+            (tiers, required)
+        });
+        assert_eq!(tiers.cheapest_threshold, 0.48);
+        assert_eq!(tiers.premium_threshold, 0.48);
+        assert!((required - (0.48 / 0.9 - 0.48 + 0.08)).abs() < 1e-9, "required spread {:.4}", required);
+    }
+
+    #[test]
+    fn golden_plan() {
+        let fixture = Fixture::new();
+        let plan = fixture.run(0, 30.0, &prices(), plan);
+        assert_eq!(plan.len(), 192);
+
+        // Hourly through the night: full power in the cheapest hours only
+        let modes: Vec<BatteryMode> = plan.iter().take(24).step_by(4).map(|s| s.mode).collect();
+        assert_eq!(
+            modes,
+            [
+                BatteryMode::ChargeReduced,
+                BatteryMode::ChargeFull,
+                BatteryMode::ChargeFull,
+                BatteryMode::ChargeFull,
+                BatteryMode::ChargeFull,
+                BatteryMode::ChargeReduced,
+            ]
+        );
+        assert!(plan.iter().all(|s| (10.0..=100.0).contains(&s.soc_end)));
+        let soc_at_six = plan[23].soc_end;
+        assert!((soc_at_six - 99.21).abs() < 0.05, "SoC at 06:00 was {:.2}", soc_at_six);
+    }
+
+    #[test]
+    fn optimal_planner_buys_low_and_sells_high() {
+        let mut fixture = Fixture::new();
+        fixture.optimizer.planner = Planner::Optimal;
+        let prices = prices();
+
+        let plan = fixture.run(0, 30.0, &prices, plan);
+        assert_eq!(plan.len(), 192);
+        assert!(plan.iter().all(|s| (10.0..=100.0).contains(&s.soc_end)));
+        // Filled in the cheap night, emptied into the evening peak
+        assert!((plan[23].soc_end - 100.0).abs() < 0.01, "SoC at 06:00 was {:.2}", plan[23].soc_end);
+        assert!(plan[64..84].iter().any(|s| s.mode == BatteryMode::DischargeToGrid));
+
+        let result = fixture.run(18, 90.0, &prices, optimize);
+        assert_eq!(result.mode, BatteryMode::DischargeToGrid);
+        // Moderate midday prices: cheaper to buy now than to spend stored energy
+        let result = fixture.run(11, 60.0, &prices, optimize);
+        assert_eq!(result.mode, BatteryMode::Idle);
+        assert_eq!(result.grid_setpoint_w, 500.0);
+    }
+
+    #[test]
+    fn hourly_slots_plan_hours_of_energy() {
+        let mut fixture = Fixture::new();
+        fixture.optimizer.planner = Planner::Optimal;
+        let quarterly = fixture.run(0, 30.0, &prices(), optimal::solve);
+        let hourly = fixture.run(0, 30.0, &hourly_prices(), optimal::solve);
+
+        assert_eq!(hourly.slots.len(), 48);
+        assert!(hourly.slots.iter().all(|s| s.hours() == 1.0));
+        // The same prices in longer slots cost the same
+        assert!((hourly.cost - quarterly.cost).abs() < 0.01, "{:.2} vs {:.2}", hourly.cost, quarterly.cost);
+    }
+
+    #[test]
+    fn rolling_horizon_extends_past_published_prices() {
+        let mut fixture = Fixture::new();
+        fixture.optimizer.planner = Planner::Optimal;
+        fixture.optimizer.horizon_hours = Some(24.0);
+        let prices = prices_where(|i| i < 96);
+
+        // Planned on the day before's prices up to 20:01 tomorrow, reported up to midnight
+        let schedule = fixture.run(20, 90.0, &prices, optimal::solve);
+        assert_eq!(schedule.slots.len(), 16);
+        assert_eq!(schedule.estimated_slots, 81);
+
+        fixture.optimizer.horizon_hours = None;
+        let schedule = fixture.run(20, 90.0, &prices, optimal::solve);
+        assert_eq!(schedule.estimated_slots, 0);
+    }
+}
@@ -0,0 +1,192 @@
+use chrono::{DateTime, Duration, Timelike, Utc};
+use schemars::JsonSchema;
+use serde::Serialize;
+use tracing::{info, warn};
+
+use crate::config::{FailsafeConfig, MissingPricesBehavior, MissingPricesConfig};
+use crate::optimizer::{Alternative, BatteryMode, OptimizationResult};
+use crate::prices::PriceCache;
+
+/// Local hour by which tomorrow's day-ahead prices are normally published
+const TOMORROW_PRICES_DUE_HOUR: u32 = 14;
+
+/// Refresh intervals without a successful fetch before prices count as stale
+const STALE_AFTER_REFRESHES: i64 = 3;
+
+/// Prices count as stale no earlier than this after the last fetch
+const MIN_STALE_HOURS: i64 = 2;
+
+/// How much of its inputs the control loop has, from full operation down to a
+/// failsafe setpoint. Levels are ordered: a higher level is a worse one, and
+/// each defines what the loop does while in it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Degradation {
+    /// All inputs present: optimize over today's and tomorrow's prices
+    #[default]
+    Full,
+    /// Tomorrow's prices are overdue: optimize over the rest of today only
+    NoTomorrowPrices,
+    /// Tomorrow's prices are still missing in the evening: plan a day ahead on
+    /// forecast prices, keeping a reserve and not selling to the grid
+    MissingTomorrowPrices,
+    /// Prices weren't refreshed for several intervals: optimize on the cached
+    /// prices, but don't sell to the grid on possibly outdated ones
+    StalePrices,
+    /// No price for the current slot: self-consumption at the setpoint offset
+    NoPrices,
+    /// No SoC received yet: self-consumption at the setpoint offset
+    NoSoc,
+    /// Implausible clock, or inputs gone stale (SoC updates stopped, very old
+    /// prices, MQTT down): hold the failsafe setpoint, skip everything else
+    Failsafe,
+}
+
+impl Degradation {
+    /// Whether the optimizer decides the setpoint at this level
+    pub fn optimizes(self) -> bool {
+        self <= Degradation::StalePrices
+    }
+
+    /// What the control loop does at this level
+    pub fn behavior(self) -> &'static str {
+        match self {
+            Degradation::Full => "optimizing over all known prices",
+            Degradation::NoTomorrowPrices => "optimizing over today's remaining prices",
+            Degradation::MissingTomorrowPrices => "planning on forecast prices, reserve first",
+            Degradation::StalePrices => "optimizing on cached prices without grid discharge",
+            Degradation::NoPrices | Degradation::NoSoc => "self-consumption at the setpoint offset",
+            Degradation::Failsafe => "holding the failsafe setpoint",
+        }
+    }
+
+    /// Why the inputs are too stale to act on at `now`, if they are. Unlike a
+    /// missing input, a stale one would keep the last decision in force
+    /// indefinitely, so it trips the failsafe.
+    pub fn stale_inputs(
+        config: &FailsafeConfig,
+        last_soc_update: Option<DateTime<Utc>>,
+        prices: &PriceCache,
+        mqtt_down_since: Option<DateTime<Utc>>,
+        now: DateTime<Utc>,
+    ) -> Option<String> {
+        if let Some(since) = mqtt_down_since {
+            let down = now.signed_duration_since(since);
+            if down > Duration::seconds(config.mqtt_timeout_secs as i64) {
+                return Some(format!("MQTT connection down for {}s", down.num_seconds()));
+            }
+        }
+        if let Some(last_update) = last_soc_update {
+            let age = now.signed_duration_since(last_update);
+            if age > Duration::minutes(config.soc_timeout_minutes as i64) {
+                return Some(format!("no SoC update for {} min", age.num_minutes()));
+            }
+        }
+        if let Some(last_fetch) = prices.last_fetch {
+            let age = now.signed_duration_since(last_fetch);
+            if age > Duration::hours(config.max_price_age_hours as i64) {
+                return Some(format!("prices last fetched {}h ago", age.num_hours()));
+            }
+        }
+        None
+    }
+
+    /// The optimizing level the price data allows at `now`
+    pub fn for_prices(
+        prices: &PriceCache,
+        refresh_interval_secs: u64,
+        missing: &MissingPricesConfig,
+        now: DateTime<Utc>,
+    ) -> (Self, Option<String>) {
+        let stale_after = Duration::seconds(refresh_interval_secs as i64 * STALE_AFTER_REFRESHES)
+            .max(Duration::hours(MIN_STALE_HOURS));
+        if let Some(last_fetch) = prices.last_fetch {
+            let age = now.signed_duration_since(last_fetch);
+            if age > stale_after {
+                let reason = format!("prices last fetched {:.1}h ago", age.num_minutes() as f64 / 60.0);
+                return (Degradation::StalePrices, Some(reason));
+            }
+        }
+
+        let local_now = prices
+            .slots()
+            .first()
+            .map_or(now.fixed_offset(), |p| now.with_timezone(p.starts_at.offset()));
+        let has_tomorrow = prices.has_tomorrow(now);
+        if !has_tomorrow
+            && local_now.hour() >= missing.after_hour
+            && missing.behavior == MissingPricesBehavior::ReserveFirst
+        {
+            let reason = format!("tomorrow's prices still missing at {}:00", missing.after_hour);
+            return (Degradation::MissingTomorrowPrices, Some(reason));
+        }
+        if !has_tomorrow && local_now.hour() >= TOMORROW_PRICES_DUE_HOUR {
+            let reason = format!("tomorrow's prices not published by {}:00", TOMORROW_PRICES_DUE_HOUR);
+            return (Degradation::NoTomorrowPrices, Some(reason));
+        }
+
+        (Degradation::Full, None)
+    }
+}
+
+/// Published on every level change
+#[derive(Debug, Clone, Serialize)]
+pub struct DegradationStatus {
+    pub level: Degradation,
+    pub behavior: &'static str,
+    /// Why the loop is at this level, unless fully operational
+    pub reason: Option<String>,
+}
+
+/// Tracks the current level of the degradation ladder
+#[derive(Debug, Default)]
+pub struct DegradationLadder {
+    level: Degradation,
+    reason: Option<String>,
+}
+
+impl DegradationLadder {
+    pub fn level(&self) -> Degradation {
+        self.level
+    }
+
+    pub fn status(&self) -> DegradationStatus {
+        DegradationStatus {
+            level: self.level,
+            behavior: self.level.behavior(),
+            reason: self.reason.clone(),
+        }
+    }
+
+    /// Enter `level` for this cycle; returns the new status when the level changed
+    pub fn enter(&mut self, level: Degradation, reason: Option<String>) -> Option<DegradationStatus> {
+        let changed = level != self.level;
+        self.level = level;
+        self.reason = reason;
+        if !changed {
+            return None;
+        }
+        let status = self.status();
+        match &status.reason {
+            Some(reason) => warn!("Degraded to {:?} ({}): {}", level, reason, status.behavior),
+            None => info!("Back to {:?}: {}", level, status.behavior),
+        }
+        Some(status)
+    }
+
+    /// Apply the current level's restrictions to an optimizer decision
+    pub fn constrain(&self, result: OptimizationResult, setpoint_offset_w: f64) -> OptimizationResult {
+        if self.level == Degradation::StalePrices && result.mode == BatteryMode::DischargeToGrid {
+            return OptimizationResult {
+                mode: BatteryMode::SelfConsumptionPreventGridPull,
+                grid_setpoint_w: -setpoint_offset_w,
+                reason: format!("Prices are stale, not discharging to grid ({})", result.reason),
+                alternative: Some(Alternative {
+                    mode: result.mode,
+                    reason: "prices are stale".to_string(),
+                }),
+            };
+        }
+        result
+    }
+}
@@ -0,0 +1,118 @@
+use std::time::Duration;
+
+use anyhow::Result;
+
+use crate::config::Config;
+use crate::mqtt::{parse_mqtt_value, parse_victron_soc};
+use crate::scan::{self, BrokerScan, BrokerSettings};
+
+const SCAN_DURATION: Duration = Duration::from_secs(10);
+const WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// `tibber-optimizer diagnose`: check the broker against the configuration
+pub async fn run() -> Result<()> {
+    let mut config = Config::load_from_env_or_file()?;
+    config.combine_battery_units();
+    let settings = BrokerSettings::from(&config.mqtt);
+
+    println!("Scanning {}:{} for {}s...", settings.host, settings.port, SCAN_DURATION.as_secs());
+    let found = scan::scan(&settings, SCAN_DURATION).await?;
+    match &found.portal_id {
+        Some(id) => println!("Victron device {} found, {} topics seen", id, found.topics.len()),
+        None => println!("No Victron device found, {} topics seen", found.topics.len()),
+    }
+
+    println!("\nCandidate topics:");
+    for pattern in scan::SOC_PATTERNS {
+        print_candidates(&found, "SoC", pattern, parse_victron_soc);
+    }
+    print_candidates(&found, "Setpoint", scan::SETPOINT_PATTERN, parse_mqtt_value);
+    print_candidates(&found, "Grid power", scan::GRID_POWER_PATTERN, parse_mqtt_value);
+    print_candidates(&found, "Battery power", scan::BATTERY_POWER_PATTERN, parse_mqtt_value);
+    print_candidates(&found, "Inverter state", scan::INVERTER_STATE_PATTERN, parse_mqtt_value);
+    print_candidates(&found, "Grid lost", scan::GRID_LOST_PATTERN, parse_mqtt_value);
+
+    println!("\nConfigured topics:");
+    for source in config.mqtt.effective_soc_sources() {
+        check_configured(&found, "SoC", &source.topic, parse_victron_soc);
+    }
+    check_configured(&found, "Setpoint read", &config.mqtt.grid_setpoint_read_topic, parse_mqtt_value);
+    let optional_topics = [
+        ("Inverter state", &config.mqtt.inverter_state_topic),
+        ("Grid lost", &config.mqtt.grid_lost_topic),
+        ("Grid power", &config.mqtt.grid_power_topic),
+        ("Battery power", &config.mqtt.battery_power_topic),
+        ("Import meter", &config.mqtt.meter_import_topic),
+        ("Export meter", &config.mqtt.meter_export_topic),
+    ];
+    for (name, topic) in optional_topics {
+        if let Some(topic) = topic {
+            check_configured(&found, name, topic, parse_mqtt_value);
+        }
+    }
+
+    // Nudge the setpoint by a watt and wait for the device to echo that value
+    // (a keepalive republishes the old one), then put the old one back
+    println!("\nSetpoint write check:");
+    let current = found
+        .topics
+        .get(&config.mqtt.grid_setpoint_read_topic)
+        .and_then(|payload| parse_mqtt_value(payload));
+    match current {
+        Some(value) => {
+            let write = |value: f64| {
+                scan::write_echoed(
+                    &settings,
+                    found.portal_id.as_deref(),
+                    &config.mqtt.grid_setpoint_write_topic,
+                    &config.mqtt.grid_setpoint_read_topic,
+                    value,
+                    WRITE_TIMEOUT,
+                )
+            };
+            let probe = value + 1.0;
+            let echoed = write(probe).await?;
+            let restored = write(value).await?;
+            if echoed {
+                println!("  OK   wrote {} to {}, device confirmed", probe, config.mqtt.grid_setpoint_write_topic);
+                if !restored {
+                    println!("  WARN restoring {} wasn't confirmed, check the setpoint", value);
+                }
+            } else {
+                println!(
+                    "  FAIL no confirmation within {}s after writing to {} (check the topic and broker ACLs)",
+                    WRITE_TIMEOUT.as_secs(),
+                    config.mqtt.grid_setpoint_write_topic
+                );
+            }
+        }
+        None => println!("  SKIP current setpoint unknown, not writing blind"),
+    }
+
+    Ok(())
+}
+
+fn print_candidates(found: &BrokerScan, name: &str, pattern: &str, parse: fn(&str) -> Option<f64>) {
+    for (topic, payload) in found.matching(pattern) {
+        println!("  {:<15} {} = {} -> {}", name, topic, truncate(payload), describe(parse(payload)));
+    }
+}
+
+fn check_configured(found: &BrokerScan, name: &str, topic: &str, parse: fn(&str) -> Option<f64>) {
+    match found.topics.get(topic) {
+        Some(payload) => println!("  OK   {:<15} {} = {} -> {}", name, topic, truncate(payload), describe(parse(payload))),
+        None => println!("  FAIL {:<15} {} (no messages seen)", name, topic),
+    }
+}
+
+fn describe(value: Option<f64>) -> String {
+    value.map_or("not parseable".to_string(), |v| format!("parsed {}", v))
+}
+
+fn truncate(payload: &str) -> String {
+    if payload.chars().count() > 60 {
+        format!("{}...", payload.chars().take(60).collect::<String>())
+    } else {
+        payload.to_string()
+    }
+}
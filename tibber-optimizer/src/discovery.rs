@@ -0,0 +1,138 @@
+use serde_json::{json, Value};
+
+use crate::config::MqttDiscoveryConfig;
+
+/// A sensor read from the status payload
+struct StatusSensor {
+    id: &'static str,
+    name: &'static str,
+    template: &'static str,
+    unit: Option<&'static str>,
+    device_class: Option<&'static str>,
+    numeric: bool,
+}
+
+const fn text(id: &'static str, name: &'static str, template: &'static str) -> StatusSensor {
+    StatusSensor { id, name, template, unit: None, device_class: None, numeric: false }
+}
+
+const fn number(id: &'static str, name: &'static str, template: &'static str, unit: Option<&'static str>) -> StatusSensor {
+    StatusSensor { id, name, template, unit, device_class: None, numeric: true }
+}
+
+const fn timestamp(id: &'static str, name: &'static str, template: &'static str) -> StatusSensor {
+    StatusSensor { id, name, template, unit: None, device_class: Some("timestamp"), numeric: false }
+}
+
+const PRICE_UNIT: Option<&str> = Some("EUR/kWh");
+
+const STATUS_SENSORS: &[StatusSensor] = &[
+    text("mode", "Mode", "{{ value_json.current_mode }}"),
+    text("reason", "Reason", "{{ value_json.explanation.reason[:255] }}"),
+    text("degradation", "Degradation", "{{ value_json.degradation }}"),
+    StatusSensor {
+        device_class: Some("power"),
+        ..number("grid_setpoint", "Grid setpoint", "{{ value_json.grid_setpoint_w }}", Some("W"))
+    },
+    StatusSensor {
+        device_class: Some("battery"),
+        ..number("soc", "Battery SoC", "{{ value_json.battery_soc }}", Some("%"))
+    },
+    number("price", "Current price", "{{ value_json.current_price }}", PRICE_UNIT),
+    number("sell_price", "Current sell price", "{{ value_json.current_sell_price }}", PRICE_UNIT),
+    number("price_min", "Price minimum", "{{ value_json.price_stats.min if value_json.price_stats else None }}", PRICE_UNIT),
+    number("price_max", "Price maximum", "{{ value_json.price_stats.max if value_json.price_stats else None }}", PRICE_UNIT),
+    number("price_avg", "Price average", "{{ value_json.price_stats.avg if value_json.price_stats else None }}", PRICE_UNIT),
+    number("cheap_slots", "Cheap slots remaining", "{{ value_json.cheap_slots_remaining }}", None),
+    number("cheapest_slots", "Cheapest slots remaining", "{{ value_json.cheapest_slots_remaining }}", None),
+    timestamp("next_cheap_slot", "Next cheap slot", "{{ value_json.next_cheap_slot }}"),
+    timestamp("next_expensive_slot", "Next expensive slot", "{{ value_json.next_expensive_slot }}"),
+];
+
+/// First planned slot whose mode differs from the current one
+const NEXT_CHANGE_TEMPLATE: &str = "{% if value_json.slots %}\
+{% set next = value_json.slots | rejectattr('mode', 'eq', value_json.slots[0].mode) | first %}\
+{{ next.starts_at if next else None }}{% else %}None{% endif %}";
+
+/// Home Assistant MQTT discovery messages (topic, retained config) for the
+/// optimizer's sensors, the dry-run switch and the cancel-hold button
+pub fn messages(config: &MqttDiscoveryConfig, base_topic: &str, command_topic: &str) -> Vec<(String, Value)> {
+    let node_id = &config.node_id;
+    let device = json!({
+        "identifiers": [node_id],
+        "name": "Tibber Optimizer",
+        "manufacturer": "tibber-optimizer",
+        "sw_version": env!("CARGO_PKG_VERSION"),
+    });
+    let status_topic = format!("{}/status", base_topic);
+    let topic = |component: &str, id: &str| format!("{}/{}/{}/{}/config", config.prefix, component, node_id, id);
+
+    let mut messages: Vec<(String, Value)> = STATUS_SENSORS
+        .iter()
+        .map(|sensor| {
+            let mut payload = json!({
+                "name": sensor.name,
+                "unique_id": format!("{}_{}", node_id, sensor.id),
+                "device": device,
+                "state_topic": status_topic,
+                "value_template": sensor.template,
+            });
+            if let Some(unit) = sensor.unit {
+                payload["unit_of_measurement"] = json!(unit);
+            }
+            if let Some(device_class) = sensor.device_class {
+                payload["device_class"] = json!(device_class);
+            }
+            if sensor.numeric {
+                payload["state_class"] = json!("measurement");
+            }
+            (topic("sensor", sensor.id), payload)
+        })
+        .collect();
+
+    // The next planned mode change, with the upcoming slots as attributes
+    messages.push((
+        topic("sensor", "next_mode_change"),
+        json!({
+            "name": "Next mode change",
+            "unique_id": format!("{}_next_mode_change", node_id),
+            "device": device,
+            "state_topic": format!("{}/plan", base_topic),
+            "value_template": NEXT_CHANGE_TEMPLATE,
+            "device_class": "timestamp",
+            "json_attributes_topic": format!("{}/plan", base_topic),
+            "json_attributes_template": "{{ {'slots': value_json.slots[:24]} | tojson }}",
+        }),
+    ));
+
+    messages.push((
+        topic("switch", "dry_run"),
+        json!({
+            "name": "Dry run",
+            "unique_id": format!("{}_dry_run", node_id),
+            "device": device,
+            "state_topic": status_topic,
+            "value_template": "{{ 'ON' if value_json.dry_run else 'OFF' }}",
+            "command_topic": command_topic,
+            "payload_on": r#"{"action":"dry_run","enabled":true}"#,
+            "payload_off": r#"{"action":"dry_run","enabled":false}"#,
+            "state_on": "ON",
+            "state_off": "OFF",
+            "icon": "mdi:test-tube",
+        }),
+    ));
+
+    messages.push((
+        topic("button", "cancel_hold"),
+        json!({
+            "name": "Cancel hold",
+            "unique_id": format!("{}_cancel_hold", node_id),
+            "device": device,
+            "command_topic": command_topic,
+            "payload_press": r#"{"action":"cancel_hold"}"#,
+            "icon": "mdi:play-circle-outline",
+        }),
+    ));
+
+    messages
+}
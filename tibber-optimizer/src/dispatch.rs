@@ -0,0 +1,170 @@
+//! Several separately controlled batteries planned as one: the optimizer
+//! decides one grid setpoint, which is split into a share per battery.
+
+use std::sync::Mutex;
+
+use anyhow::Context;
+use tracing::debug;
+
+use crate::config::{BatteryUnit, Dispatch};
+use crate::controller::{BatteryController, WriteFuture};
+use crate::mqtt::{BatteryState, MqttSetpointWriter};
+
+/// Split `setpoint_w` over `units`. Batteries that can't move in the
+/// setpoint's direction (full when charging, at the minimum when discharging)
+/// get nothing while another one can; `soc` lists their latest SoC.
+pub fn split(
+    setpoint_w: f64,
+    units: &[BatteryUnit],
+    soc: &[Option<f64>],
+    dispatch: Dispatch,
+    min_soc: f64,
+    max_soc: f64,
+) -> Vec<f64> {
+    let charging = setpoint_w >= 0.0;
+    let limit = |unit: &BatteryUnit| if charging { unit.max_charge_power_w } else { unit.max_discharge_power_w };
+    let can_move = |index: usize| match soc.get(index).copied().flatten() {
+        Some(soc) if charging => soc < max_soc,
+        Some(soc) => soc > min_soc,
+        None => true,
+    };
+    let mut eligible: Vec<usize> = (0..units.len()).filter(|&i| can_move(i)).collect();
+    if eligible.is_empty() {
+        eligible = (0..units.len()).collect();
+    }
+
+    let mut shares = vec![0.0; units.len()];
+    match dispatch {
+        Dispatch::Proportional => {
+            let total: f64 = eligible.iter().map(|&i| limit(&units[i])).sum();
+            for &i in &eligible {
+                shares[i] = if total > 0.0 {
+                    setpoint_w * limit(&units[i]) / total
+                } else {
+                    setpoint_w / eligible.len() as f64
+                };
+            }
+        }
+        Dispatch::Priority => {
+            let mut remaining = setpoint_w.abs();
+            for &i in &eligible {
+                let share = remaining.min(limit(&units[i]));
+                shares[i] = share.copysign(setpoint_w);
+                remaining -= share;
+            }
+            // More than all limits together: the last battery takes the rest
+            if let Some(&last) = eligible.last() {
+                shares[last] += remaining.copysign(setpoint_w);
+            }
+        }
+    }
+    shares
+}
+
+/// Writes each battery's share of the grid setpoint to its own topic
+pub struct MultiBatteryController {
+    units: Vec<BatteryUnit>,
+    /// One per unit, in the same order
+    writers: Vec<MqttSetpointWriter>,
+    dispatch: Dispatch,
+    min_soc: f64,
+    max_soc: f64,
+    /// Latest SoC per battery, in the order of `units`
+    soc: Mutex<Vec<Option<f64>>>,
+}
+
+impl MultiBatteryController {
+    pub fn new(
+        units: Vec<BatteryUnit>,
+        writers: Vec<MqttSetpointWriter>,
+        dispatch: Dispatch,
+        min_soc: f64,
+        max_soc: f64,
+    ) -> Self {
+        let soc = Mutex::new(vec![None; units.len()]);
+        Self {
+            units,
+            writers,
+            dispatch,
+            min_soc,
+            max_soc,
+            soc,
+        }
+    }
+}
+
+impl BatteryController for MultiBatteryController {
+    fn name(&self) -> &'static str {
+        "MQTT (multiple batteries)"
+    }
+
+    fn write_setpoint(&self, setpoint_w: f64) -> WriteFuture<'_> {
+        Box::pin(async move {
+            let soc = self.soc.lock().unwrap().clone();
+            let shares = split(setpoint_w, &self.units, &soc, self.dispatch, self.min_soc, self.max_soc);
+
+            // Write every share before reporting a failure, so one unreachable
+            // GX doesn't leave the others on an old setpoint
+            let mut result = Ok(());
+            for ((unit, writer), share) in self.units.iter().zip(&self.writers).zip(shares) {
+                debug!("Battery {}: {:.0}W of {:.0}W", unit.name, share, setpoint_w);
+                let written = writer.write_setpoint(share).await.with_context(|| format!("battery {}", unit.name));
+                if result.is_ok() {
+                    result = written;
+                }
+            }
+            result
+        })
+    }
+
+    fn observe(&self, state: &BatteryState) {
+        // The units are the SoC sources, in the same order
+        let mut soc = self.soc.lock().unwrap();
+        for (latest, reported) in soc.iter_mut().zip(&state.pack_soc) {
+            if reported.is_some() {
+                *latest = *reported;
+            }
+        }
+    }
+
+    /// A battery filling up or running empty shifts the split
+    fn writes_every_cycle(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unit(name: &str, charge_w: f64, discharge_w: f64) -> BatteryUnit {
+        BatteryUnit {
+            name: name.to_string(),
+            soc_topic: format!("{}/soc", name),
+            capacity_kwh: 10.0,
+            max_charge_power_w: charge_w,
+            max_discharge_power_w: discharge_w,
+            setpoint_write_topic: format!("{}/setpoint", name),
+        }
+    }
+
+    #[test]
+    fn splits_proportionally_and_by_priority() {
+        let units = [unit("a", 3000.0, 6000.0), unit("b", 1000.0, 2000.0)];
+        let soc = [Some(50.0), Some(50.0)];
+
+        assert_eq!(split(2000.0, &units, &soc, Dispatch::Proportional, 10.0, 100.0), [1500.0, 500.0]);
+        assert_eq!(split(-4000.0, &units, &soc, Dispatch::Proportional, 10.0, 100.0), [-3000.0, -1000.0]);
+        assert_eq!(split(3500.0, &units, &soc, Dispatch::Priority, 10.0, 100.0), [3000.0, 500.0]);
+        assert_eq!(split(-9000.0, &units, &soc, Dispatch::Priority, 10.0, 100.0), [-6000.0, -3000.0]);
+    }
+
+    #[test]
+    fn skips_batteries_at_their_limit() {
+        let units = [unit("a", 3000.0, 3000.0), unit("b", 3000.0, 3000.0)];
+        let soc = [Some(10.0), Some(100.0)];
+
+        assert_eq!(split(-2000.0, &units, &soc, Dispatch::Proportional, 10.0, 100.0), [0.0, -2000.0]);
+        assert_eq!(split(2000.0, &units, &soc, Dispatch::Priority, 10.0, 100.0), [2000.0, 0.0]);
+    }
+}
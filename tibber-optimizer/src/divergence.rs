@@ -0,0 +1,140 @@
+use chrono::{DateTime, Duration, Utc};
+use tracing::{info, warn};
+
+use crate::config::PlanDivergenceConfig;
+use crate::optimizer::PlannedSlot;
+use crate::stats::MAX_SAMPLE_GAP_HOURS;
+
+/// How long realized behavior is followed before it is compared with the plan
+const CHECK_INTERVAL_MINUTES: i64 = 60;
+
+/// The plan at the start of a check period, and the grid energy realized since
+#[derive(Debug)]
+struct Checkpoint {
+    started: DateTime<Utc>,
+    plan: Vec<PlannedSlot>,
+    /// Grid energy since `started` (kWh), while grid power samples keep arriving
+    grid_kwh: Option<f64>,
+    last_sample: DateTime<Utc>,
+}
+
+impl Checkpoint {
+    fn new(now: DateTime<Utc>, plan: &[PlannedSlot], grid_power_w: Option<f64>) -> Self {
+        Self {
+            started: now,
+            plan: plan.to_vec(),
+            grid_kwh: grid_power_w.map(|_| 0.0),
+            last_sample: now,
+        }
+    }
+
+    /// Planned SoC at the end of the last slot that has ended by `now`
+    fn planned_soc(&self, now: DateTime<Utc>) -> Option<f64> {
+        self.plan
+            .iter()
+            .take_while(|slot| slot.ends_at <= now)
+            .last()
+            .map(|slot| slot.soc_end)
+    }
+
+    /// Planned grid energy between the start of the check and `now` (kWh)
+    fn planned_grid_kwh(&self, now: DateTime<Utc>) -> f64 {
+        self.plan
+            .iter()
+            .map(|slot| {
+                let start = slot.starts_at.with_timezone(&Utc).max(self.started);
+                let end = slot.ends_at.with_timezone(&Utc).min(now);
+                let hours = end.signed_duration_since(start).num_seconds().max(0) as f64 / 3600.0;
+                slot.grid_power_w / 1000.0 * hours
+            })
+            .sum()
+    }
+}
+
+/// Compares the realized SoC trajectory and grid energy against the plan every
+/// hour, catching broken sensors, an ESS ignoring setpoints or another
+/// controller driving the battery
+#[derive(Debug)]
+pub struct PlanMonitor {
+    config: PlanDivergenceConfig,
+    checkpoint: Option<Checkpoint>,
+    /// Description of the divergence found by the last check, if any
+    diverging: Option<String>,
+}
+
+impl PlanMonitor {
+    pub fn new(config: PlanDivergenceConfig) -> Self {
+        Self {
+            config,
+            checkpoint: None,
+            diverging: None,
+        }
+    }
+
+    pub fn divergence(&self) -> Option<&str> {
+        self.diverging.as_deref()
+    }
+
+    /// Follow the realized SoC and grid power against `plan`. Returns
+    /// `(active, message)` when the alert is raised or cleared. Pass
+    /// `in_control = false` while something other than the plan decides the
+    /// setpoint (hold, manual override, ...), which restarts the comparison.
+    pub fn check(
+        &mut self,
+        now: DateTime<Utc>,
+        soc: f64,
+        grid_power_w: Option<f64>,
+        plan: &[PlannedSlot],
+        in_control: bool,
+    ) -> Option<(bool, String)> {
+        if !self.config.enabled || !in_control {
+            self.checkpoint = None;
+            return None;
+        }
+        let Some(checkpoint) = self.checkpoint.as_mut() else {
+            self.checkpoint = Some(Checkpoint::new(now, plan, grid_power_w));
+            return None;
+        };
+
+        let hours = now.signed_duration_since(checkpoint.last_sample).num_seconds() as f64 / 3600.0;
+        checkpoint.grid_kwh = match (checkpoint.grid_kwh, grid_power_w) {
+            (Some(kwh), Some(power_w)) if hours <= MAX_SAMPLE_GAP_HOURS => Some(kwh + power_w / 1000.0 * hours),
+            _ => None,
+        };
+        checkpoint.last_sample = now;
+
+        if now.signed_duration_since(checkpoint.started) < Duration::minutes(CHECK_INTERVAL_MINUTES) {
+            return None;
+        }
+
+        let mut findings = Vec::new();
+        if let Some(planned_soc) = checkpoint.planned_soc(now) {
+            if (soc - planned_soc).abs() > self.config.soc_tolerance_percent {
+                findings.push(format!("SoC {:.1}% vs planned {:.1}%", soc, planned_soc));
+            }
+        }
+        if let Some(grid_kwh) = checkpoint.grid_kwh {
+            let planned_kwh = checkpoint.planned_grid_kwh(now);
+            if (grid_kwh - planned_kwh).abs() > self.config.grid_tolerance_kwh {
+                findings.push(format!("grid {:.1} kWh vs planned {:.1} kWh", grid_kwh, planned_kwh));
+            }
+        }
+        self.checkpoint = Some(Checkpoint::new(now, plan, grid_power_w));
+
+        let diverging = (!findings.is_empty())
+            .then(|| format!("Battery diverges from the plan over the last hour: {}", findings.join(", ")));
+        let was_diverging = self.diverging.is_some();
+        self.diverging = diverging.clone();
+        match diverging {
+            Some(message) if !was_diverging => {
+                warn!("{}", message);
+                Some((true, message))
+            }
+            None if was_diverging => {
+                info!("Battery follows the plan again");
+                Some((false, "Battery follows the plan again".to_string()))
+            }
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,178 @@
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, FixedOffset};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+
+use crate::config::{BatteryConfig, OptimizerConfig};
+use crate::mqtt::BatteryState;
+use crate::persist;
+use crate::stats::MAX_SAMPLE_GAP_HOURS;
+
+/// Relative deviation from the configured value that is worth a suggestion
+const SUGGEST_DEVIATION: f64 = 0.15;
+
+/// Share of the configured maximum a setpoint must reach to count as a
+/// full-power command
+const FULL_POWER_SHARE: f64 = 0.9;
+
+/// Measurements collected over one ISO week, persisted across restarts
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct WeekSamples {
+    /// ISO week, e.g. `2025-W48`
+    pub week: Option<String>,
+    /// House load energy (grid minus battery power) and the time it covers
+    pub house_load_wh: f64,
+    pub house_load_hours: f64,
+    /// Highest grid import measured while charging at full power (W)
+    pub max_charge_w: Option<f64>,
+}
+
+impl WeekSamples {
+    /// Average measured house load (W)
+    pub fn house_load_w(&self) -> Option<f64> {
+        (self.house_load_hours >= 1.0).then(|| self.house_load_wh / self.house_load_hours)
+    }
+}
+
+/// One configured assumption next to what was measured
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct ParameterDrift {
+    pub parameter: &'static str,
+    pub configured: f64,
+    pub measured: Option<f64>,
+    /// Concrete config change, if the measurement deviates significantly
+    pub suggestion: Option<String>,
+}
+
+impl ParameterDrift {
+    fn new(parameter: &'static str, configured: f64, measured: Option<f64>, format: impl Fn(f64) -> String) -> Self {
+        let suggestion = measured
+            .filter(|m| configured.abs() > f64::EPSILON && ((m - configured) / configured).abs() > SUGGEST_DEVIATION)
+            .map(|m| format!("set {}: {} (configured {})", parameter, format(m), format(configured)));
+        Self {
+            parameter,
+            configured,
+            measured,
+            suggestion,
+        }
+    }
+}
+
+/// Weekly comparison of configured assumptions against measured reality
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct DriftReport {
+    pub week: String,
+    pub parameters: Vec<ParameterDrift>,
+}
+
+impl DriftReport {
+    pub fn new(
+        samples: &WeekSamples,
+        battery: &BatteryConfig,
+        optimizer: &OptimizerConfig,
+        measured_efficiency: Option<f64>,
+        charge_limit_w: Option<f64>,
+    ) -> Self {
+        Self {
+            week: samples.week.clone().unwrap_or_default(),
+            parameters: vec![
+                ParameterDrift::new(
+                    "base_consumption_w",
+                    optimizer.base_consumption_w,
+                    samples.house_load_w(),
+                    |w| format!("{:.0}", w),
+                ),
+                ParameterDrift::new(
+                    "round_trip_efficiency",
+                    battery.round_trip_efficiency,
+                    measured_efficiency,
+                    |e| format!("{:.2}", e),
+                ),
+                ParameterDrift::new(
+                    "max_charge_power_w",
+                    battery.max_charge_power_w,
+                    samples.max_charge_w.or(charge_limit_w),
+                    |w| format!("{:.0}", w),
+                ),
+            ],
+        }
+    }
+
+    pub fn suggestions(&self) -> Vec<&str> {
+        self.parameters.iter().filter_map(|p| p.suggestion.as_deref()).collect()
+    }
+}
+
+/// Collects the measurements behind the weekly drift report, persisted in the data directory
+#[derive(Debug)]
+pub struct DriftTracker {
+    path: PathBuf,
+    max_charge_power_w: f64,
+    samples: WeekSamples,
+    last_sample: Option<DateTime<FixedOffset>>,
+    last_save: Option<DateTime<FixedOffset>>,
+}
+
+impl DriftTracker {
+    /// Load this week's samples from `<data_dir>/drift.json`, starting fresh if absent
+    pub fn load(data_dir: &str, max_charge_power_w: f64) -> Self {
+        let path = Path::new(data_dir).join("drift.json");
+        Self {
+            samples: persist::load_json(&path),
+            path,
+            max_charge_power_w,
+            last_sample: None,
+            last_save: None,
+        }
+    }
+
+    /// Record telemetry at tariff-local `now`, given the setpoint commanded in
+    /// the previous cycle. Returns the completed week's samples when the week
+    /// rolls over.
+    pub fn record(
+        &mut self,
+        now: DateTime<FixedOffset>,
+        state: &BatteryState,
+        commanded_w: Option<f64>,
+    ) -> Option<WeekSamples> {
+        let week = now.format("%G-W%V").to_string();
+        let finished = match &self.samples.week {
+            Some(current) if *current != week => Some(std::mem::take(&mut self.samples)),
+            _ => None,
+        };
+        self.samples.week = Some(week);
+
+        if let (Some(last), Some(grid_w)) = (self.last_sample, state.grid_power_w) {
+            let hours = now.signed_duration_since(last).num_seconds() as f64 / 3600.0;
+            if hours > 0.0 && hours <= MAX_SAMPLE_GAP_HOURS {
+                if let Some(battery_w) = state.battery_power_w {
+                    self.samples.house_load_wh += (grid_w - battery_w) * hours;
+                    self.samples.house_load_hours += hours;
+                }
+                if commanded_w.is_some_and(|w| w >= self.max_charge_power_w * FULL_POWER_SHARE) {
+                    let max = self.samples.max_charge_w.get_or_insert(grid_w);
+                    *max = max.max(grid_w);
+                }
+            }
+        }
+        self.last_sample = Some(now);
+
+        let save_due = finished.is_some()
+            || self
+                .last_save
+                .is_none_or(|last| now.signed_duration_since(last).num_seconds() >= persist::SAVE_INTERVAL_SECS);
+        if save_due {
+            match persist::save_json(&self.path, &self.samples) {
+                Ok(()) => {
+                    self.last_save = Some(now);
+                    debug!("Saved drift samples to {}", self.path.display());
+                }
+                Err(e) => warn!("Failed to save drift samples: {}", e),
+            }
+        }
+
+        finished
+    }
+}
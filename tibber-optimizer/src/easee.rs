@@ -0,0 +1,160 @@
+//! Easee chargers over the Easee cloud API: the charge current is set as the
+//! charger's dynamic current (0 A pauses), and the car's state and the session
+//! energy come from the charger state. The API is HTTPS only, so this needs
+//! the default `reqwest` feature. The state is read at most once a minute,
+//! as the cloud API rate-limits its clients.
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use tracing::info;
+
+use crate::config::EaseeConfig;
+use crate::controller::WriteFuture;
+use crate::ev::{CachedStatus, ChargerStatus, EvCharger, StatusFuture};
+use crate::http::{HttpClient, HttpResponse};
+
+const API_URL: &str = "https://api.easee.com/api";
+
+/// How long a state read from the cloud is used for
+const STATE_MAX_AGE: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// The fields used from `GET /chargers/{id}/state`
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ChargerState {
+    /// 1 = disconnected, 2 = awaiting start, 3 = charging, 4 = completed,
+    /// 5 = error, 6 = ready to charge
+    charger_op_mode: u8,
+    /// Charging power (kW)
+    total_power: f64,
+    /// Energy delivered in the current session (kWh)
+    session_energy: f64,
+    /// Current per phase (A)
+    #[serde(default)]
+    in_current_t3: Option<f64>,
+    #[serde(default)]
+    in_current_t4: Option<f64>,
+    #[serde(default)]
+    in_current_t5: Option<f64>,
+}
+
+impl ChargerState {
+    fn status(&self) -> ChargerStatus {
+        let current_a = [self.in_current_t3, self.in_current_t4, self.in_current_t5]
+            .into_iter()
+            .flatten()
+            .reduce(f64::max);
+        ChargerStatus {
+            connected: matches!(self.charger_op_mode, 2 | 3 | 4 | 6),
+            session_kwh: self.session_energy,
+            power_w: Some(self.total_power * 1000.0),
+            current_a,
+        }
+    }
+}
+
+pub struct EaseeCharger {
+    config: EaseeConfig,
+    http_client: HttpClient,
+    token: tokio::sync::Mutex<Option<String>>,
+    state: CachedStatus,
+}
+
+impl EaseeCharger {
+    pub fn new(config: EaseeConfig) -> Self {
+        Self {
+            config,
+            http_client: HttpClient::new(),
+            token: tokio::sync::Mutex::new(None),
+            state: CachedStatus::new(STATE_MAX_AGE),
+        }
+    }
+
+    async fn login(&self) -> Result<String> {
+        let body = serde_json::json!({
+            "userName": self.config.username,
+            "password": self.config.password,
+        });
+        let url = format!("{}/accounts/login", API_URL);
+        let response = self.http_client.post_json(&url, &[], &body).await?;
+        if !response.is_success() {
+            anyhow::bail!("Easee login failed: {} - {}", response.status, response.text());
+        }
+        let body: serde_json::Value = serde_json::from_slice(&response.body)?;
+        let token = body["accessToken"].as_str().context("Easee login returned no access token")?;
+        info!("Logged in to the Easee cloud for charger {}", self.config.charger_id);
+        Ok(token.to_string())
+    }
+
+    /// Send a request with the access token, logging in first when there is
+    /// none; a rejected token is dropped so the next request logs in again
+    async fn request(&self, path: &str, body: Option<&serde_json::Value>) -> Result<HttpResponse> {
+        let mut token = self.token.lock().await;
+        if token.is_none() {
+            *token = Some(self.login().await?);
+        }
+        let auth = format!("Bearer {}", token.as_deref().expect("logged in above"));
+        let headers = [("Authorization", auth.as_str())];
+        let url = format!("{}/chargers/{}{}", API_URL, self.config.charger_id, path);
+        let response = match body {
+            Some(body) => self.http_client.post_json(&url, &headers, body).await?,
+            None => self.http_client.get(&url, &headers).await?,
+        };
+        if matches!(response.status, 401 | 403) {
+            *token = None;
+        }
+        if !response.is_success() {
+            anyhow::bail!("Easee {} failed: {} - {}", path, response.status, response.text());
+        }
+        Ok(response)
+    }
+}
+
+impl EvCharger for EaseeCharger {
+    fn name(&self) -> &'static str {
+        "Easee cloud API"
+    }
+
+    fn status(&self) -> StatusFuture<'_> {
+        Box::pin(self.state.get(async move {
+            let response = self.request("/state", None).await?;
+            let state: ChargerState = serde_json::from_slice(&response.body)?;
+            Ok(state.status())
+        }))
+    }
+
+    fn set_current(&self, current_a: f64) -> WriteFuture<'_> {
+        Box::pin(async move {
+            let settings = serde_json::json!({ "dynamicChargerCurrent": current_a });
+            self.request("/settings", Some(&settings)).await?;
+            self.state.invalidate();
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_the_car_and_session_from_the_charger_state() {
+        let body = r#"{"chargerOpMode": 3, "totalPower": 7.2, "sessionEnergy": 4.5,
+            "inCurrentT3": 10.1, "inCurrentT4": 10.4, "inCurrentT5": 9.8, "smartCharging": false}"#;
+        let state: ChargerState = serde_json::from_str(body).unwrap();
+        assert_eq!(
+            state.status(),
+            ChargerStatus {
+                connected: true,
+                session_kwh: 4.5,
+                power_w: Some(7200.0),
+                current_a: Some(10.4),
+            }
+        );
+
+        let body = r#"{"chargerOpMode": 1, "totalPower": 0.0, "sessionEnergy": 0.0}"#;
+        let state: ChargerState = serde_json::from_str(body).unwrap();
+        assert!(!state.status().connected);
+        assert_eq!(state.status().current_a, None);
+    }
+}
@@ -0,0 +1,104 @@
+use chrono::{DateTime, Duration, Utc};
+use tracing::info;
+
+use crate::config::EconomySleepConfig;
+use crate::prices::{PriceCache, PricePoint};
+
+/// What the control loop last acted on, to notice material changes while asleep
+#[derive(Debug, Clone, Copy)]
+struct Snapshot {
+    at: DateTime<Utc>,
+    soc: f64,
+    price: f64,
+    generation: u64,
+}
+
+/// Sleeps through long flat-price periods: control cycles run at a longer
+/// interval, holding the self-consumption setpoint, and wake as soon as prices
+/// or SoC change materially
+#[derive(Debug)]
+pub struct EconomySleep {
+    config: EconomySleepConfig,
+    asleep: Option<Snapshot>,
+}
+
+impl EconomySleep {
+    pub fn new(config: EconomySleepConfig) -> Self {
+        Self { config, asleep: None }
+    }
+
+    pub fn is_asleep(&self) -> bool {
+        self.asleep.is_some()
+    }
+
+    /// Whether to hold the last decision this cycle. Wakes on new prices, a
+    /// price or SoC change beyond the thresholds, or `woken` (e.g. a command
+    /// arrived or the grid operator limit changed).
+    pub fn skip(&mut self, now: DateTime<Utc>, soc: f64, prices: &PriceCache, current: &PricePoint, woken: bool) -> bool {
+        let Some(snapshot) = self.asleep else {
+            return false;
+        };
+
+        let wake_reason = if woken {
+            Some("command, hold, capacity test or grid limit".to_string())
+        } else if prices.generation != snapshot.generation {
+            Some("new prices".to_string())
+        } else if (current.total - snapshot.price).abs() > self.config.wake_price_change {
+            Some(format!("price {:.4} -> {:.4} EUR", snapshot.price, current.total))
+        } else if (soc - snapshot.soc).abs() > self.config.wake_soc_change_percent {
+            Some(format!("SoC {:.1}% -> {:.1}%", snapshot.soc, soc))
+        } else {
+            None
+        };
+        if let Some(reason) = wake_reason {
+            info!("Waking from economy sleep: {}", reason);
+            self.asleep = None;
+            return false;
+        }
+
+        now.signed_duration_since(snapshot.at) < Duration::seconds(self.config.interval_secs as i64)
+    }
+
+    /// After a full cycle: fall (or stay) asleep when prices stay within
+    /// `min_spread` for the next `min_flat_hours`, else wake
+    pub fn settle(
+        &mut self,
+        now: DateTime<Utc>,
+        soc: f64,
+        prices: &PriceCache,
+        current: &PricePoint,
+        min_spread: f64,
+        eligible: bool,
+    ) {
+        let end = current.starts_at + Duration::seconds((self.config.min_flat_hours * 3600.0) as i64);
+        let window: Vec<f64> = prices
+            .all_prices()
+            .filter(|p| p.ends_at > current.starts_at && p.starts_at < end)
+            .map(|p| p.total)
+            .collect();
+        let covered = prices.all_prices().last().is_some_and(|p| p.ends_at >= end);
+        let min = window.iter().copied().fold(f64::INFINITY, f64::min);
+        let max = window.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+
+        if !eligible || !covered || max - min >= min_spread {
+            if self.asleep.take().is_some() {
+                info!("Leaving economy sleep");
+            }
+            return;
+        }
+
+        if self.asleep.is_none() {
+            info!(
+                "Entering economy sleep: prices within {:.4} EUR for the next {:.0}h",
+                max - min,
+                self.config.min_flat_hours
+            );
+        }
+        self.asleep = Some(Snapshot {
+            at: now,
+            soc,
+            price: current.total,
+            generation: prices.generation,
+        });
+    }
+}
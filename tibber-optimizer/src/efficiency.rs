@@ -0,0 +1,109 @@
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+
+use crate::persist;
+use crate::stats::MAX_SAMPLE_GAP_HOURS;
+
+/// Full discharges worth of energy needed before the measurement is trusted
+const MIN_CYCLES: f64 = 1.0;
+
+/// Once this many cycles are accumulated the sums are halved, so the estimate
+/// follows the battery as it ages
+const DECAY_CYCLES: f64 = 30.0;
+
+/// Plausible round-trip efficiency range; anything outside is a measurement problem
+const MIN_EFFICIENCY: f64 = 0.5;
+const MAX_EFFICIENCY: f64 = 1.0;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct EfficiencyState {
+    /// AC energy into the battery (kWh)
+    ac_in_kwh: f64,
+    /// AC energy out of the battery (kWh)
+    ac_out_kwh: f64,
+    /// Net change of stored energy from SoC (kWh, battery side)
+    stored_kwh: f64,
+}
+
+/// Measures the realized round-trip efficiency from AC battery power and SoC
+#[derive(Debug)]
+pub struct EfficiencyTracker {
+    path: PathBuf,
+    capacity_kwh: f64,
+    state: EfficiencyState,
+    last_sample: Option<(DateTime<Utc>, f64, f64)>,
+    last_save: Option<DateTime<Utc>>,
+}
+
+impl EfficiencyTracker {
+    /// Load sums from `<data_dir>/efficiency.json`, starting fresh if absent
+    pub fn load(data_dir: &str, capacity_kwh: f64) -> Self {
+        let path = Path::new(data_dir).join("efficiency.json");
+        Self {
+            state: persist::load_json(&path),
+            path,
+            capacity_kwh,
+            last_sample: None,
+            last_save: None,
+        }
+    }
+
+    /// Record an SoC and AC battery power sample (positive = charging)
+    pub fn record(&mut self, now: DateTime<Utc>, soc: f64, battery_power_w: f64) {
+        if let Some((last_time, last_soc, last_power_w)) = self.last_sample {
+            let hours = now.signed_duration_since(last_time).num_seconds() as f64 / 3600.0;
+            if hours > 0.0 && hours <= MAX_SAMPLE_GAP_HOURS {
+                let ac_kwh = last_power_w / 1000.0 * hours;
+                if ac_kwh >= 0.0 {
+                    self.state.ac_in_kwh += ac_kwh;
+                } else {
+                    self.state.ac_out_kwh += -ac_kwh;
+                }
+                self.state.stored_kwh += (soc - last_soc) / 100.0 * self.capacity_kwh;
+
+                if self.state.ac_out_kwh >= DECAY_CYCLES * self.capacity_kwh {
+                    self.state.ac_in_kwh /= 2.0;
+                    self.state.ac_out_kwh /= 2.0;
+                    self.state.stored_kwh /= 2.0;
+                }
+            }
+        }
+        self.last_sample = Some((now, soc, battery_power_w));
+
+        let save_due = self
+            .last_save
+            .is_none_or(|last| now.signed_duration_since(last).num_seconds() >= persist::SAVE_INTERVAL_SECS);
+        if save_due {
+            match persist::save_json(&self.path, &self.state) {
+                Ok(()) => {
+                    self.last_save = Some(now);
+                    debug!("Saved efficiency measurement to {}", self.path.display());
+                }
+                Err(e) => warn!("Failed to save efficiency measurement: {}", e),
+            }
+        }
+    }
+
+    /// Measured round-trip efficiency, once enough energy has been cycled.
+    ///
+    /// With one-way efficiency `e`, the stored energy changes by
+    /// `in * e - out / e`; solving that for `e` and squaring gives the round trip.
+    pub fn round_trip_efficiency(&self) -> Option<f64> {
+        let EfficiencyState { ac_in_kwh, ac_out_kwh, stored_kwh } = self.state;
+        if ac_out_kwh < MIN_CYCLES * self.capacity_kwh || ac_in_kwh <= 0.0 {
+            return None;
+        }
+
+        let one_way = (stored_kwh + (stored_kwh * stored_kwh + 4.0 * ac_in_kwh * ac_out_kwh).sqrt()) / (2.0 * ac_in_kwh);
+        let round_trip = one_way * one_way;
+        if (MIN_EFFICIENCY..=MAX_EFFICIENCY).contains(&round_trip) {
+            Some(round_trip)
+        } else {
+            debug!("Ignoring implausible measured round-trip efficiency {:.2}", round_trip);
+            None
+        }
+    }
+}
@@ -0,0 +1,295 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Datelike, Duration, FixedOffset, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use std::collections::BTreeMap;
+
+use crate::config::EntsoeConfig;
+use crate::http::HttpClient;
+use crate::price_source::{FetchFuture, PriceProvider};
+use crate::prices::{PriceCache, PricePoint, SlotOrigin};
+
+const SLOT_MINUTES: i64 = 15;
+
+/// Inner text of every `<tag>...</tag>` element in `xml`, in document order.
+/// The day-ahead document is flat and attribute-free where we read it, so a
+/// scanner is enough and keeps the GX build free of an XML dependency.
+fn elements<'a>(xml: &'a str, tag: &str) -> Vec<&'a str> {
+    let open = format!("<{}>", tag);
+    let close = format!("</{}>", tag);
+    let mut found = Vec::new();
+    let mut rest = xml;
+    while let Some(start) = rest.find(&open) {
+        let inner = &rest[start + open.len()..];
+        let Some(end) = inner.find(&close) else { break };
+        found.push(&inner[..end]);
+        rest = &inner[end + close.len()..];
+    }
+    found
+}
+
+fn element<'a>(xml: &'a str, tag: &str) -> Option<&'a str> {
+    elements(xml, tag).into_iter().next()
+}
+
+fn parse_time(value: &str) -> Result<DateTime<Utc>> {
+    let naive = NaiveDateTime::parse_from_str(value.trim(), "%Y-%m-%dT%H:%MZ")
+        .with_context(|| format!("Invalid ENTSO-E time '{}'", value))?;
+    Ok(Utc.from_utc_datetime(&naive))
+}
+
+/// `PT15M` / `PT30M` / `PT60M` in minutes
+fn parse_resolution(value: &str) -> Result<i64> {
+    value
+        .trim()
+        .strip_prefix("PT")
+        .and_then(|v| v.strip_suffix('M'))
+        .and_then(|v| v.parse().ok())
+        .filter(|minutes: &i64| *minutes > 0 && minutes % SLOT_MINUTES == 0)
+        .with_context(|| format!("Unsupported ENTSO-E resolution '{}'", value))
+}
+
+/// Bidding zones (EIC codes) whose market day runs on Eastern or Western
+/// European time; every other zone trades in Central European time
+const EET_AREAS: &[&str] = &[
+    "10YFI-1--------U", // Finland
+    "10Y1001A1001A39I", // Estonia
+    "10YLV-1001A00074", // Latvia
+    "10YLT-1001A0008Q", // Lithuania
+    "10YGR-HTSO-----Y", // Greece
+    "10YCA-BULGARIA-R", // Bulgaria
+    "10YRO-TEL------P", // Romania
+];
+const WET_AREAS: &[&str] = &[
+    "10YPT-REN------W", // Portugal
+    "10Y1001A1001A59C", // Ireland (SEM)
+];
+
+/// UTC offset of the bidding zone's local time at `at`. All zones follow the
+/// EU summer time rule: an hour ahead from 01:00 UTC on the last Sunday of
+/// March until 01:00 UTC on the last Sunday of October.
+fn market_offset(area: &str, at: DateTime<Utc>) -> FixedOffset {
+    let standard_hours = if EET_AREAS.contains(&area) {
+        2
+    } else if WET_AREAS.contains(&area) {
+        0
+    } else {
+        1
+    };
+    let switch = |month: u32| {
+        let last_day = NaiveDate::from_ymd_opt(at.year(), month, 31).expect("March and October have 31 days");
+        let last_sunday = last_day - Duration::days(last_day.weekday().num_days_from_sunday() as i64);
+        Utc.from_utc_datetime(&last_sunday.and_hms_opt(1, 0, 0).expect("valid time"))
+    };
+    let summer = switch(3) <= at && at < switch(10);
+    FixedOffset::east_opt((standard_hours + summer as i32) * 3600).expect("offset within a day")
+}
+
+/// Day-ahead spot prices (EUR/MWh) per quarter-hour from a
+/// `Publication_MarketDocument`. Hourly points are split into quarters, and
+/// positions the curve omits repeat the previous price.
+pub fn parse_spot_prices(xml: &str) -> Result<BTreeMap<DateTime<Utc>, f64>> {
+    if xml.contains("Acknowledgement_MarketDocument") {
+        let reason = element(xml, "text").unwrap_or("unknown reason");
+        anyhow::bail!("ENTSO-E returned no prices: {}", reason);
+    }
+
+    let mut prices = BTreeMap::new();
+    for period in elements(xml, "Period") {
+        let start = parse_time(element(period, "start").context("Period without start")?)?;
+        let end = parse_time(element(period, "end").context("Period without end")?)?;
+        let resolution = parse_resolution(element(period, "resolution").context("Period without resolution")?)?;
+
+        let mut points = BTreeMap::new();
+        for point in elements(period, "Point") {
+            let position: i64 = element(point, "position").context("Point without position")?.trim().parse()?;
+            let amount: f64 = element(point, "price.amount").context("Point without price")?.trim().parse()?;
+            points.insert(position, amount);
+        }
+
+        let count = end.signed_duration_since(start).num_minutes() / resolution;
+        let mut last = None;
+        for position in 1..=count {
+            let Some(amount) = points.get(&position).copied().or(last) else { continue };
+            last = Some(amount);
+            let point_start = start + Duration::minutes((position - 1) * resolution);
+            for quarter in 0..resolution / SLOT_MINUTES {
+                // Overlapping time series: keep the first price seen for a slot
+                prices
+                    .entry(point_start + Duration::minutes(quarter * SLOT_MINUTES))
+                    .or_insert(amount);
+            }
+        }
+    }
+    Ok(prices)
+}
+
+/// Day-ahead prices from the ENTSO-E transparency platform, for dynamic
+/// contracts other than Tibber. Spot prices get the configured markup,
+/// energy tax and VAT added so `total` matches what the supplier charges.
+pub struct EntsoeClient {
+    config: EntsoeConfig,
+    http_client: HttpClient,
+}
+
+impl EntsoeClient {
+    pub fn new(config: EntsoeConfig) -> Self {
+        Self {
+            config,
+            http_client: HttpClient::new(),
+        }
+    }
+
+    fn price_point(&self, starts_at: DateTime<Utc>, spot_eur_mwh: f64) -> PricePoint {
+        let energy = spot_eur_mwh / 1000.0;
+        let total = (energy + self.config.markup_per_kwh + self.config.energy_tax_per_kwh)
+            * (1.0 + self.config.vat_percent / 100.0);
+        let starts_at = starts_at.with_timezone(&market_offset(&self.config.area, starts_at));
+        PricePoint {
+            total,
+            energy,
+            tax: total - energy,
+            starts_at,
+            ends_at: starts_at + Duration::minutes(SLOT_MINUTES),
+            level: None,
+            currency: Some("EUR".to_string()),
+            sell: None,
+            source: SlotOrigin::Published,
+        }
+    }
+
+    async fn fetch_prices(&self, generation: u64) -> Result<PriceCache> {
+        let now = Utc::now();
+        // Days as the market counts them, not the host's
+        let today = now.with_timezone(&market_offset(&self.config.area, now)).date_naive();
+        let period_start = now - Duration::days(1);
+        let period_end = now + Duration::days(2);
+        let url = format!(
+            "{}?securityToken={}&documentType=A44&in_Domain={}&out_Domain={}&periodStart={}&periodEnd={}",
+            self.config.api_url,
+            self.config.api_token,
+            self.config.area,
+            self.config.area,
+            period_start.format("%Y%m%d%H00"),
+            period_end.format("%Y%m%d%H00"),
+        );
+
+        let response = self.http_client.get(&url, &[]).await?;
+        if !response.is_success() {
+            anyhow::bail!("ENTSO-E API error: {} - {}", response.status, response.text());
+        }
+
+        let spot = parse_spot_prices(&response.text())?;
+        let slots = spot
+            .into_iter()
+            .map(|(starts_at, amount)| self.price_point(starts_at, amount))
+            .filter(|point| point.starts_at.date_naive() >= today)
+            .collect();
+        Ok(PriceCache::new(slots, None).with_generation(generation).fetched_at(now.fixed_offset()))
+    }
+}
+
+impl PriceProvider for EntsoeClient {
+    fn name(&self) -> &'static str {
+        "ENTSO-E"
+    }
+
+    fn refresh_interval_secs(&self) -> u64 {
+        self.config.refresh_interval_secs
+    }
+
+    fn fetch(&self, generation: u64) -> FetchFuture<'_> {
+        Box::pin(self.fetch_prices(generation))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A trimmed day-ahead document for NL on the night summer time ends: an
+    /// hourly series omitting a repeated price, and a quarter-hourly one
+    const DAY_AHEAD: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<Publication_MarketDocument xmlns="urn:iec62325.351:tc57wg16:451-3:publicationdocument:7:3">
+  <mRID>2bc8e6c1b2a44d6e9b0a1f3c</mRID>
+  <type>A44</type>
+  <TimeSeries>
+    <mRID>1</mRID>
+    <in_Domain.mRID codingScheme="A01">10YNL----------L</in_Domain.mRID>
+    <currency_Unit.name>EUR</currency_Unit.name>
+    <price_Measure_Unit.name>MWH</price_Measure_Unit.name>
+    <curveType>A03</curveType>
+    <Period>
+      <timeInterval>
+        <start>2025-10-25T22:00Z</start>
+        <end>2025-10-26T01:00Z</end>
+      </timeInterval>
+      <resolution>PT60M</resolution>
+      <Point>
+        <position>1</position>
+        <price.amount>85.12</price.amount>
+      </Point>
+      <Point>
+        <position>3</position>
+        <price.amount>-1.5</price.amount>
+      </Point>
+    </Period>
+  </TimeSeries>
+  <TimeSeries>
+    <mRID>2</mRID>
+    <curveType>A03</curveType>
+    <Period>
+      <timeInterval>
+        <start>2025-10-26T01:00Z</start>
+        <end>2025-10-26T01:30Z</end>
+      </timeInterval>
+      <resolution>PT15M</resolution>
+      <Point>
+        <position>1</position>
+        <price.amount>70</price.amount>
+      </Point>
+      <Point>
+        <position>2</position>
+        <price.amount>71.25</price.amount>
+      </Point>
+    </Period>
+  </TimeSeries>
+</Publication_MarketDocument>"#;
+
+    #[test]
+    fn parses_a_day_ahead_document_into_quarter_hours() {
+        let prices = parse_spot_prices(DAY_AHEAD).unwrap();
+        let at = |time: &str| parse_time(time).unwrap();
+
+        assert_eq!(prices.len(), 14);
+        assert_eq!(prices[&at("2025-10-25T22:00Z")], 85.12);
+        assert_eq!(prices[&at("2025-10-25T22:45Z")], 85.12);
+        // Position 2 is omitted and repeats position 1
+        assert_eq!(prices[&at("2025-10-25T23:30Z")], 85.12);
+        assert_eq!(prices[&at("2025-10-26T00:15Z")], -1.5);
+        assert_eq!(prices[&at("2025-10-26T01:15Z")], 71.25);
+    }
+
+    #[test]
+    fn reports_why_no_prices_were_returned() {
+        let xml = "<Acknowledgement_MarketDocument><Reason><code>999</code>\
+            <text>No matching data found</text></Reason></Acknowledgement_MarketDocument>";
+        let e = parse_spot_prices(xml).unwrap_err();
+        assert!(e.to_string().contains("No matching data found"), "{}", e);
+    }
+
+    #[test]
+    fn counts_days_in_the_bidding_zone_time() {
+        let at = |time: &str| parse_time(time).unwrap();
+        let hours = |offset: FixedOffset| offset.local_minus_utc() / 3600;
+
+        assert_eq!(hours(market_offset("10YNL----------L", at("2025-10-26T00:59Z"))), 2);
+        assert_eq!(hours(market_offset("10YNL----------L", at("2025-10-26T01:00Z"))), 1);
+        assert_eq!(hours(market_offset("10YNL----------L", at("2025-03-30T01:00Z"))), 2);
+        assert_eq!(hours(market_offset("10YFI-1--------U", at("2025-01-15T12:00Z"))), 2);
+        assert_eq!(hours(market_offset("10YPT-REN------W", at("2025-07-01T12:00Z"))), 1);
+
+        // The NL day-ahead day starting at 22:00 UTC in winter begins at midnight
+        let starts_at = at("2025-11-30T23:00Z");
+        let local = starts_at.with_timezone(&market_offset("10YNL----------L", starts_at));
+        assert_eq!(local.to_rfc3339(), "2025-12-01T00:00:00+01:00");
+    }
+}
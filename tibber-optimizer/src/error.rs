@@ -0,0 +1,110 @@
+//! Classes of failure the retry, alerting and health handling react to
+//! differently. The Tibber client, the MQTT client and config loading return
+//! an [`Error`]; where it travels on as `anyhow::Error`, callers find the
+//! class again with `downcast_ref::<Error>()` instead of matching messages.
+
+/// A failure talking to the outside world, by what can be done about it
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// Credentials were rejected, e.g. an expired API token; retrying won't
+    /// help until the user acts
+    #[error("{0}")]
+    Auth(String),
+    /// A service was unreachable, timed out or failed server-side; likely to pass
+    #[error("{0}")]
+    Network(String),
+    /// A response or file couldn't be understood
+    #[error("{0}")]
+    Parse(String),
+    /// Settings or an account setup that can't work
+    #[error("{0}")]
+    Validation(String),
+}
+
+impl Error {
+    pub fn validation(message: impl Into<String>) -> Self {
+        Self::Validation(message.into())
+    }
+
+    /// Short name of the class, for logs and alerts
+    pub fn class(&self) -> &'static str {
+        match self {
+            Self::Auth(_) => "auth",
+            Self::Network(_) => "network",
+            Self::Parse(_) => "parse",
+            Self::Validation(_) => "validation",
+        }
+    }
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Self {
+        Self::Parse(e.to_string())
+    }
+}
+
+impl From<serde_yaml::Error> for Error {
+    fn from(e: serde_yaml::Error) -> Self {
+        Self::Parse(e.to_string())
+    }
+}
+
+impl From<rumqttc::ClientError> for Error {
+    fn from(e: rumqttc::ClientError) -> Self {
+        Self::Network(format!("MQTT request failed: {}", e))
+    }
+}
+
+impl From<rumqttc::ConnectionError> for Error {
+    fn from(e: rumqttc::ConnectionError) -> Self {
+        use rumqttc::{ConnectReturnCode, ConnectionError};
+        match e {
+            ConnectionError::ConnectionRefused(
+                code @ (ConnectReturnCode::BadUserNamePassword | ConnectReturnCode::NotAuthorized),
+            ) => Self::Auth(format!("MQTT broker refused the connection: {:?}", code)),
+            e => Self::Network(format!("MQTT connection error: {}", e)),
+        }
+    }
+}
+
+#[cfg(feature = "tibber")]
+impl From<tibber_client::Error> for Error {
+    fn from(e: tibber_client::Error) -> Self {
+        use tibber_client::Error as Tibber;
+        let message = e.to_string();
+        match e {
+            Tibber::Status { status: 401 | 403, .. } | Tibber::Unauthenticated(_) => Self::Auth(message),
+            Tibber::GraphQl(_) | Tibber::MissingData | Tibber::Decode(_) => Self::Parse(message),
+            Tibber::NoHomes | Tibber::NoSubscription => Self::Validation(message),
+            _ => Self::Network(message),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classifies_mqtt_errors() {
+        use rumqttc::{ConnectReturnCode, ConnectionError};
+        let refused = Error::from(ConnectionError::ConnectionRefused(ConnectReturnCode::NotAuthorized));
+        assert_eq!(refused.class(), "auth");
+        let unavailable = Error::from(ConnectionError::ConnectionRefused(ConnectReturnCode::ServiceUnavailable));
+        assert_eq!(unavailable.class(), "network");
+    }
+
+    #[cfg(feature = "tibber")]
+    #[test]
+    fn classifies_tibber_errors() {
+        use tibber_client::Error as Tibber;
+        let class = |e: Tibber| Error::from(e).class();
+        assert_eq!(class(Tibber::Unauthenticated(vec!["invalid token".to_string()])), "auth");
+        assert_eq!(class(Tibber::Status { status: 401, body: String::new() }), "auth");
+        assert_eq!(class(Tibber::Status { status: 503, body: String::new() }), "network");
+        assert_eq!(class(Tibber::GraphQl(vec!["syntax error".to_string()])), "parse");
+        assert_eq!(class(Tibber::NoSubscription), "validation");
+    }
+}
@@ -0,0 +1,407 @@
+//! EV charging scheduled into the cheapest slots before the car must be ready,
+//! next to the battery: the charge current follows the plan, is held under
+//! the main fuse together with the house and the battery, and the grid
+//! setpoint is raised by what the car draws so the battery doesn't feed it.
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Mutex;
+
+use anyhow::Result;
+use chrono::{DateTime, Duration, Local, NaiveTime, TimeZone, Utc};
+use tracing::{info, warn};
+
+use crate::config::{EvChargerKind, EvConfig, EvMqttConfig, MainFuseConfig};
+use crate::controller::{BatteryController, WriteFuture};
+use crate::easee::EaseeCharger;
+use crate::fuse;
+use crate::go_e::GoECharger;
+use crate::mqtt::{BatteryState, MqttClient};
+use crate::prices::PriceCache;
+use crate::supervisor::Supervisor;
+use crate::wallbox::WallboxCharger;
+
+/// Nominal phase voltage, for converting between current and power
+const PHASE_VOLTAGE_V: f64 = 230.0;
+
+pub type StatusFuture<'a> = Pin<Box<dyn Future<Output = Result<ChargerStatus>> + Send + 'a>>;
+
+/// What the charger reports about the car and the session
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ChargerStatus {
+    /// A car is plugged in
+    pub connected: bool,
+    /// Energy delivered since the car was plugged in (kWh)
+    pub session_kwh: f64,
+    /// Power the car draws right now (W)
+    pub power_w: Option<f64>,
+    /// Current on the busiest phase right now (A)
+    pub current_a: Option<f64>,
+}
+
+/// The status last read from a cloud API, reused for a while so the API
+/// isn't polled every control cycle
+pub struct CachedStatus {
+    max_age: std::time::Duration,
+    last: Mutex<Option<(tokio::time::Instant, ChargerStatus)>>,
+}
+
+impl CachedStatus {
+    pub fn new(max_age: std::time::Duration) -> Self {
+        Self {
+            max_age,
+            last: Mutex::new(None),
+        }
+    }
+
+    /// The cached status while it is fresh, else the one `read` returns
+    pub async fn get(&self, read: impl Future<Output = Result<ChargerStatus>>) -> Result<ChargerStatus> {
+        if let Some((_, status)) = self.last.lock().unwrap().as_ref().filter(|(at, _)| at.elapsed() < self.max_age) {
+            return Ok(status.clone());
+        }
+        let status = read.await?;
+        *self.last.lock().unwrap() = Some((tokio::time::Instant::now(), status.clone()));
+        Ok(status)
+    }
+
+    /// Read the status anew next time, e.g. after the current was changed
+    pub fn invalidate(&self) {
+        *self.last.lock().unwrap() = None;
+    }
+}
+
+/// Sets the charge current of an EV charger
+pub trait EvCharger: Send + Sync {
+    /// Name used in logs
+    fn name(&self) -> &'static str;
+
+    /// Latest state of the charger; fails while it can't be reached
+    fn status(&self) -> StatusFuture<'_>;
+
+    /// Limit the charge current per phase (A, 0 = pause)
+    fn set_current(&self, current_a: f64) -> WriteFuture<'_>;
+
+    /// Latest MQTT state, for chargers reporting through it
+    fn observe(&self, _state: &BatteryState) {}
+}
+
+/// The charger configured under `ev`
+pub async fn from_config(
+    config: &EvConfig,
+    mqtt_client: &MqttClient,
+    supervisor: &Supervisor,
+) -> Result<Box<dyn EvCharger>> {
+    const CHECKED: &str = "Config::validate checks the charger's section";
+    match config.charger {
+        #[cfg(feature = "ocpp")]
+        EvChargerKind::Ocpp => Ok(Box::new(crate::ocpp::OcppCharger::start(config.ocpp.clone(), supervisor).await?)),
+        #[cfg(not(feature = "ocpp"))]
+        EvChargerKind::Ocpp => {
+            let _ = supervisor;
+            anyhow::bail!("ev.charger ocpp is not included in this build")
+        }
+        EvChargerKind::Easee => Ok(Box::new(EaseeCharger::new(config.easee.clone().expect(CHECKED)))),
+        EvChargerKind::GoE => Ok(Box::new(GoECharger::new(config.go_e.clone().expect(CHECKED)))),
+        EvChargerKind::Wallbox => Ok(Box::new(WallboxCharger::new(config.wallbox.clone().expect(CHECKED)))),
+        EvChargerKind::Mqtt => Ok(Box::new(MqttCharger::new(config.mqtt.as_ref().expect(CHECKED), mqtt_client))),
+    }
+}
+
+/// Publishes the current to a topic; the car's state comes from the EV topics
+/// of the MQTT state. Until the connected topic reports a car none counts as
+/// plugged in, and without an energy topic every session starts from zero.
+struct MqttCharger {
+    writer: Box<dyn BatteryController>,
+    status: Mutex<ChargerStatus>,
+}
+
+impl MqttCharger {
+    fn new(config: &EvMqttConfig, mqtt_client: &MqttClient) -> Self {
+        // The setpoint writer fills `{setpoint}` with the value it is given
+        let payload = config.payload.replace("{current}", "{setpoint}");
+        Self {
+            writer: Box::new(mqtt_client.setpoint_writer(&config.current_topic, &payload, false)),
+            status: Mutex::new(ChargerStatus::default()),
+        }
+    }
+}
+
+impl EvCharger for MqttCharger {
+    fn name(&self) -> &'static str {
+        "MQTT"
+    }
+
+    fn status(&self) -> StatusFuture<'_> {
+        let status = self.status.lock().unwrap().clone();
+        Box::pin(async move { Ok(status) })
+    }
+
+    fn set_current(&self, current_a: f64) -> WriteFuture<'_> {
+        self.writer.write_setpoint(current_a)
+    }
+
+    fn observe(&self, state: &BatteryState) {
+        *self.status.lock().unwrap() = ChargerStatus {
+            connected: state.ev_connected.unwrap_or(false),
+            session_kwh: state.ev_session_kwh.unwrap_or(0.0),
+            power_w: state.ev_power_w,
+            current_a: None,
+        };
+    }
+}
+
+/// This cycle's charging decision
+#[derive(Debug, Clone, PartialEq)]
+pub struct EvDecision {
+    /// Charge current per phase (A, 0 = paused)
+    pub current_a: f64,
+    /// Human-readable reason, e.g. "cheapest slot, 12.4 kWh to go by 07:00"
+    pub reason: String,
+}
+
+/// Picks the slots to charge in per session and the current for this cycle
+#[derive(Debug)]
+pub struct EvScheduler {
+    config: EvConfig,
+    /// When the car plugged into the current session must be charged
+    deadline: Option<DateTime<Utc>>,
+    /// Current last written to the charger
+    written: Option<f64>,
+}
+
+impl EvScheduler {
+    pub fn new(config: EvConfig) -> Self {
+        Self {
+            config,
+            deadline: None,
+            written: None,
+        }
+    }
+
+    /// Charging power at the full current (kW)
+    fn max_power_kw(&self) -> f64 {
+        self.config.max_current_a * self.config.phases as f64 * PHASE_VOLTAGE_V / 1000.0
+    }
+
+    /// Next `ready_by` after `now`, in local time
+    fn next_ready_by(ready_by: NaiveTime, now: DateTime<Utc>) -> DateTime<Utc> {
+        let local_now = now.with_timezone(&Local).naive_local();
+        let today = local_now.date().and_time(ready_by);
+        let naive = if today > local_now { today } else { today + Duration::days(1) };
+        Local
+            .from_local_datetime(&naive)
+            .earliest()
+            .map_or(now + Duration::days(1), |time| time.with_timezone(&Utc))
+    }
+
+    /// Whether the slot containing `now` is among the cheapest ones needed to
+    /// deliver `remaining_kwh` by `deadline`. Slots past the known prices
+    /// don't count; without enough slots every one charges.
+    pub fn charges_now(
+        prices: &PriceCache,
+        now: DateTime<Utc>,
+        remaining_kwh: f64,
+        power_kw: f64,
+        deadline: DateTime<Utc>,
+    ) -> bool {
+        let mut candidates: Vec<_> = prices
+            .all_prices()
+            .skip_while(|p| p.ends_at <= now)
+            .take_while(|p| p.starts_at < deadline)
+            .collect();
+        let Some(current) = candidates.first().filter(|p| p.starts_at <= now).map(|p| p.starts_at) else {
+            // No price for the current slot: charge rather than miss the deadline
+            return true;
+        };
+        candidates.sort_by(|a, b| a.total.total_cmp(&b.total));
+        let mut planned_kwh = 0.0;
+        for slot in candidates {
+            if planned_kwh >= remaining_kwh {
+                return false;
+            }
+            if slot.starts_at == current {
+                return true;
+            }
+            planned_kwh += power_kw * slot.duration().num_seconds() as f64 / 3600.0;
+        }
+        false
+    }
+
+    /// Highest current of the price tiers the slot containing `now` falls in,
+    /// within the charger's current range
+    fn tier_current_a(&self, prices: &PriceCache, now: DateTime<Utc>) -> Option<f64> {
+        let price = prices.price_at(now)?.total;
+        self.config
+            .price_tiers
+            .iter()
+            .filter(|tier| price <= tier.max_price && tier.current_a > 0.0)
+            .map(|tier| tier.current_a.min(self.config.max_current_a).max(self.config.min_current_a))
+            .reduce(f64::max)
+    }
+
+    /// Decide the current for this cycle from the charger's status, the
+    /// prices and the headroom on the main fuse
+    pub fn decide(
+        &mut self,
+        status: &ChargerStatus,
+        prices: &PriceCache,
+        fuse: Option<&MainFuseConfig>,
+        state: &BatteryState,
+        now: DateTime<Utc>,
+    ) -> EvDecision {
+        if !status.connected {
+            if self.deadline.take().is_some() {
+                info!("EV unplugged after {:.1} kWh", status.session_kwh);
+            }
+            return EvDecision {
+                current_a: 0.0,
+                reason: "no car plugged in".to_string(),
+            };
+        }
+        // A car left plugged in past its deadline (or a charger that can't
+        // tell) is planned for the next one
+        if self.deadline.is_some_and(|deadline| deadline <= now) {
+            self.deadline = Some(Self::next_ready_by(self.config.ready_by, now));
+        }
+        let deadline = *self.deadline.get_or_insert_with(|| {
+            let deadline = Self::next_ready_by(self.config.ready_by, now);
+            info!("EV plugged in, charging {:.1} kWh by {}", self.config.session_kwh, deadline.with_timezone(&Local));
+            deadline
+        });
+
+        let remaining_kwh = self.config.session_kwh - status.session_kwh;
+        let ready_by = deadline.with_timezone(&Local).format("%H:%M");
+        let (current_a, reason) = if remaining_kwh <= 0.0 {
+            (0.0, format!("{:.1} kWh charged", status.session_kwh))
+        } else if Self::charges_now(prices, now, remaining_kwh, self.max_power_kw(), deadline) {
+            let reason = format!("cheapest slot, {:.1} kWh to go by {}", remaining_kwh, ready_by);
+            (self.config.max_current_a, reason)
+        } else if let Some(tier_a) = self.tier_current_a(prices, now) {
+            (tier_a, format!("price tier, {:.1} kWh to go by {}", remaining_kwh, ready_by))
+        } else {
+            (0.0, format!("waiting for cheaper slots, {:.1} kWh to go by {}", remaining_kwh, ready_by))
+        };
+
+        // Share the fuse: take what the house and the battery leave, and pause
+        // below the lowest current the car accepts. Without a grid reading,
+        // only the lowest current is safe.
+        let Some(fuse) = fuse.filter(|_| current_a > 0.0) else {
+            return EvDecision { current_a, reason };
+        };
+        let own_current_a = status.current_a.or(self.written).unwrap_or(0.0);
+        let headroom_a = fuse::headroom_a(fuse, state, own_current_a, now);
+        match headroom_a {
+            Some(headroom_a) if headroom_a < current_a && headroom_a < self.config.min_current_a => EvDecision {
+                current_a: 0.0,
+                reason: format!("{}, paused for the main fuse ({:.1}A left)", reason, headroom_a),
+            },
+            Some(headroom_a) if headroom_a < current_a => EvDecision {
+                current_a: headroom_a.floor(),
+                reason: format!("{}, limited by the main fuse", reason),
+            },
+            Some(_) => EvDecision { current_a, reason },
+            None => EvDecision {
+                current_a: self.config.min_current_a,
+                reason: format!("{}, no grid reading for the main fuse", reason),
+            },
+        }
+    }
+
+    /// Write the decision when it changed; returns the power the car is
+    /// expected to draw (W) for the grid setpoint
+    pub async fn apply(&mut self, charger: &dyn EvCharger, decision: &EvDecision, status: &ChargerStatus) -> f64 {
+        if self.written != Some(decision.current_a) {
+            match charger.set_current(decision.current_a).await {
+                Ok(()) => {
+                    info!("EV charge current {:.0}A: {}", decision.current_a, decision.reason);
+                    self.written = Some(decision.current_a);
+                }
+                Err(e) => {
+                    warn!("Failed to set EV charge current on {}: {}", charger.name(), e);
+                    self.written = None;
+                }
+            }
+        }
+        if decision.current_a <= 0.0 {
+            return 0.0;
+        }
+        status
+            .power_w
+            .unwrap_or(decision.current_a * self.config.phases as f64 * PHASE_VOLTAGE_V)
+    }
+
+    /// Write again on the next cycle, e.g. after the charger reconnected
+    pub fn reset(&mut self) {
+        self.written = None;
+    }
+
+    /// Hand the charger back at full current when control is given up, so a
+    /// paused or reduced session doesn't stay that way
+    pub async fn release(&mut self, charger: &dyn EvCharger) {
+        let max_current_a = self.config.max_current_a;
+        if self.written.take().is_some_and(|current_a| current_a != max_current_a) {
+            match charger.set_current(max_current_a).await {
+                Ok(()) => info!("EV charge current {:.0}A: control handed back", max_current_a),
+                Err(e) => warn!("Failed to release EV charger {}: {}", charger.name(), e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prices::test_support::quarter_hours;
+
+    #[test]
+    fn charges_in_the_cheapest_slots_before_the_deadline() {
+        let start = DateTime::parse_from_rfc3339("2025-12-01T22:00:00+01:00").unwrap();
+        let prices = quarter_hours(&[0.30, 0.20, 0.25, 0.10, 0.05], start);
+        let now = start.with_timezone(&Utc);
+        let deadline = now + Duration::minutes(60);
+
+        // 11 kW for 2.75 kWh is one slot: the cheapest before the deadline is the fourth
+        assert!(!EvScheduler::charges_now(&prices, now, 2.75, 11.0, deadline));
+        assert!(EvScheduler::charges_now(&prices, now + Duration::minutes(45), 2.75, 11.0, deadline));
+        // More than fits before the deadline: charge right away
+        assert!(EvScheduler::charges_now(&prices, now, 20.0, 11.0, deadline));
+    }
+
+    #[test]
+    fn charges_at_the_tier_current_in_cheap_slots_it_doesnt_need() {
+        let config: EvConfig = serde_yaml::from_str(
+            "session_kwh: 2.75\nprice_tiers:\n  - max_price: 0.12\n    current_a: 8\n",
+        )
+        .unwrap();
+        let mut scheduler = EvScheduler::new(config);
+        let start = DateTime::parse_from_rfc3339("2025-12-01T22:00:00+01:00").unwrap();
+        let prices = quarter_hours(&[0.30, 0.10, 0.25, 0.05], start);
+        let now = start.with_timezone(&Utc);
+        let status = ChargerStatus {
+            connected: true,
+            ..Default::default()
+        };
+        let state = BatteryState::default();
+
+        // One slot covers the session; the cheapest is the last
+        assert_eq!(scheduler.decide(&status, &prices, None, &state, now).current_a, 0.0);
+        let tier = scheduler.decide(&status, &prices, None, &state, now + Duration::minutes(15));
+        assert_eq!(tier.current_a, 8.0);
+        let needed = scheduler.decide(&status, &prices, None, &state, now + Duration::minutes(45));
+        assert_eq!(needed.current_a, 16.0);
+    }
+
+    #[tokio::test]
+    async fn reads_a_cached_status_again_once_invalidated() {
+        let cache = CachedStatus::new(std::time::Duration::from_secs(3600));
+        let plugged_in = ChargerStatus {
+            connected: true,
+            ..ChargerStatus::default()
+        };
+        assert!(cache.get(async { Ok(plugged_in.clone()) }).await.unwrap().connected);
+        // Fresh: the API isn't asked again
+        assert!(cache.get(async { Ok(ChargerStatus::default()) }).await.unwrap().connected);
+        cache.invalidate();
+        assert!(!cache.get(async { Ok(ChargerStatus::default()) }).await.unwrap().connected);
+    }
+}
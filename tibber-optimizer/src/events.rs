@@ -0,0 +1,79 @@
+use chrono::{DateTime, FixedOffset, Utc};
+use serde::Deserialize;
+use tracing::info;
+
+/// An announced period of extra consumption on top of the base load, e.g.
+/// an EV arriving at 18:00 that needs 20 kWh, or guests over the weekend
+#[derive(Debug, Clone, Deserialize)]
+pub struct ConsumptionEvent {
+    pub name: String,
+    pub start: DateTime<FixedOffset>,
+    pub end: DateTime<FixedOffset>,
+    /// Extra energy consumed between `start` and `end` (kWh)
+    pub energy_kwh: f64,
+}
+
+impl ConsumptionEvent {
+    /// Extra load spread evenly over the event (W)
+    pub fn average_power_w(&self) -> f64 {
+        let hours = self.end.signed_duration_since(self.start).num_seconds() as f64 / 3600.0;
+        if hours > 0.0 {
+            self.energy_kwh / hours * 1000.0
+        } else {
+            0.0
+        }
+    }
+
+    /// Extra energy falling between `from` and `to` (kWh)
+    pub fn energy_between(&self, from: DateTime<FixedOffset>, to: DateTime<FixedOffset>) -> f64 {
+        let overlap = to.min(self.end).signed_duration_since(from.max(self.start)).num_seconds();
+        if overlap <= 0 {
+            return 0.0;
+        }
+        self.average_power_w() / 1000.0 * overlap as f64 / 3600.0
+    }
+
+    pub fn describe(&self) -> String {
+        format!(
+            "{}: {:.1} kWh {} - {}",
+            self.name,
+            self.energy_kwh,
+            self.start.to_rfc3339(),
+            self.end.to_rfc3339()
+        )
+    }
+}
+
+/// Configured and announced consumption events; ended events expire
+#[derive(Debug, Default)]
+pub struct EventSchedule {
+    events: Vec<ConsumptionEvent>,
+}
+
+impl EventSchedule {
+    pub fn new(events: Vec<ConsumptionEvent>) -> Self {
+        Self { events }
+    }
+
+    /// Add an event, replacing an announced event with the same name
+    pub fn add(&mut self, event: ConsumptionEvent) {
+        info!("Planning for consumption event {}", event.describe());
+        self.events.retain(|e| e.name != event.name);
+        self.events.push(event);
+    }
+
+    /// Cancel the named event, or all events
+    pub fn cancel(&mut self, name: Option<&str>) {
+        let before = self.events.len();
+        self.events.retain(|e| name.is_some_and(|name| e.name != name));
+        if self.events.len() < before {
+            info!("Cancelled {} consumption event(s)", before - self.events.len());
+        }
+    }
+
+    /// Current and upcoming events, dropping events that have ended
+    pub fn upcoming(&mut self, now: DateTime<Utc>) -> &[ConsumptionEvent] {
+        self.events.retain(|e| e.end > now);
+        &self.events
+    }
+}
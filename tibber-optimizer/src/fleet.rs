@@ -0,0 +1,80 @@
+use anyhow::Result;
+use serde::Serialize;
+use tracing::info;
+
+use crate::config::{Config, FleetReportConfig};
+use crate::http::HttpClient;
+use crate::stats::DailyStats;
+
+/// Anonymized daily performance report. Contains no tokens, hostnames or topics.
+#[derive(Debug, Clone, Serialize)]
+pub struct FleetReport {
+    /// Optional user-chosen label to tell sites apart
+    pub site_id: Option<String>,
+    pub version: &'static str,
+    pub date: String,
+    pub savings_eur: f64,
+    pub savings_percent: f64,
+    pub cycles: f64,
+    pub charged_kwh: f64,
+    pub discharged_kwh: f64,
+    /// Hash of the battery and optimizer settings, to group identical setups
+    pub config_hash: String,
+}
+
+/// Opt-in reporter pushing daily stats to a user-configured HTTP endpoint
+#[derive(Clone)]
+pub struct FleetReporter {
+    config: FleetReportConfig,
+    config_hash: String,
+    capacity_kwh: f64,
+    http_client: HttpClient,
+}
+
+impl FleetReporter {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            config: config.fleet_report.clone(),
+            config_hash: config_hash(config),
+            capacity_kwh: config.battery.capacity_kwh,
+            http_client: HttpClient::new(),
+        }
+    }
+
+    pub async fn send(&self, day: &DailyStats) -> Result<()> {
+        let report = FleetReport {
+            site_id: self.config.site_id.clone(),
+            version: env!("CARGO_PKG_VERSION"),
+            date: day.date.to_string(),
+            savings_eur: day.savings(),
+            savings_percent: day.savings_percent(),
+            cycles: day.cycles(self.capacity_kwh),
+            charged_kwh: day.charged_kwh,
+            discharged_kwh: day.discharged_kwh,
+            config_hash: self.config_hash.clone(),
+        };
+
+        let response = self
+            .http_client
+            .post_json(&self.config.endpoint, &[], &serde_json::to_value(&report)?)
+            .await?;
+
+        if !response.is_success() {
+            anyhow::bail!("Fleet endpoint error: {} - {}", response.status, response.text());
+        }
+
+        info!("Sent fleet report for {} ({:.1}% savings)", report.date, report.savings_percent);
+        Ok(())
+    }
+}
+
+/// FNV-1a hash over the battery and optimizer settings only (no secrets)
+fn config_hash(config: &Config) -> String {
+    let input = format!("{:?}{:?}", config.battery, config.optimizer);
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in input.bytes() {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    format!("{:016x}", hash)
+}
@@ -0,0 +1,101 @@
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, FixedOffset, Utc};
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::persist;
+
+/// A temporary SoC floor posted by an external automation, e.g. keep 60%
+/// until a medical device's backup window ends. The planners don't discharge
+/// below it before `until`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SocFloor {
+    pub id: String,
+    pub min_soc: f64,
+    pub until: DateTime<FixedOffset>,
+    pub reason: Option<String>,
+}
+
+impl SocFloor {
+    /// Whether the floor applies in the slot starting at `starts_at`
+    pub fn covers(&self, starts_at: DateTime<FixedOffset>) -> bool {
+        starts_at < self.until
+    }
+
+    pub fn describe(&self) -> String {
+        format!(
+            "{}: {:.0}% until {}{}",
+            self.id,
+            self.min_soc,
+            self.until.to_rfc3339(),
+            self.reason.as_deref().map(|r| format!(" ({})", r)).unwrap_or_default()
+        )
+    }
+}
+
+/// Highest floor in effect in the slot starting at `starts_at`
+pub fn floor_at(floors: &[SocFloor], starts_at: DateTime<FixedOffset>) -> Option<&SocFloor> {
+    floors
+        .iter()
+        .filter(|f| f.covers(starts_at))
+        .max_by(|a, b| a.min_soc.total_cmp(&b.min_soc))
+}
+
+/// Commanded SoC floors by id, kept across restarts; expired floors are dropped
+#[derive(Debug)]
+pub struct FloorSchedule {
+    path: PathBuf,
+    floors: Vec<SocFloor>,
+}
+
+impl FloorSchedule {
+    /// Load floors from `<data_dir>/soc_floors.json`, starting empty if absent
+    pub fn load(data_dir: &str) -> Self {
+        let path = Path::new(data_dir).join("soc_floors.json");
+        Self {
+            floors: persist::load_json(&path),
+            path,
+        }
+    }
+
+    /// Add a floor, replacing one with the same id
+    pub fn add(&mut self, floor: SocFloor) {
+        info!("Holding SoC floor {}", floor.describe());
+        self.floors.retain(|f| f.id != floor.id);
+        self.floors.push(floor);
+        self.save();
+    }
+
+    /// Cancel the floor with this id, or all floors
+    pub fn cancel(&mut self, id: Option<&str>) {
+        let before = self.floors.len();
+        self.floors.retain(|f| id.is_some_and(|id| f.id != id));
+        if self.floors.len() < before {
+            info!("Cancelled {} SoC floor(s)", before - self.floors.len());
+            self.save();
+        }
+    }
+
+    /// Floors still in effect, dropping those that have expired
+    pub fn active(&mut self, now: DateTime<Utc>) -> &[SocFloor] {
+        let before = self.floors.len();
+        self.floors.retain(|f| {
+            let expired = f.until <= now;
+            if expired {
+                info!("SoC floor {} expired", f.id);
+            }
+            !expired
+        });
+        if self.floors.len() < before {
+            self.save();
+        }
+        &self.floors
+    }
+
+    fn save(&self) {
+        if let Err(e) = persist::save_json(&self.path, &self.floors) {
+            warn!("Failed to save SoC floors to {}: {}", self.path.display(), e);
+        }
+    }
+}
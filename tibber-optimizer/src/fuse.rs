@@ -0,0 +1,84 @@
+//! Headroom on the main fuse for controllable loads, from the measured grid
+//! power per phase (or the total spread evenly over the phases), and the
+//! highest grid setpoint the fuse and the contracted power allow.
+
+use chrono::{DateTime, Utc};
+
+use crate::config::MainFuseConfig;
+use crate::mqtt::BatteryState;
+
+/// Grid readings older than this don't count as a measurement
+const MAX_AGE_SECS: i64 = 120;
+
+/// Current per phase (A, positive = import), from the per-phase grid power
+/// where the meter reports it
+pub fn phase_currents(config: &MainFuseConfig, state: &BatteryState, now: DateTime<Utc>) -> Option<Vec<f64>> {
+    let fresh = state
+        .last_grid_power_update
+        .is_some_and(|at| (now - at).num_seconds() <= MAX_AGE_SECS);
+    if !fresh {
+        return None;
+    }
+    if let Some(phases) = state.grid_phase_power_w {
+        return Some(phases.iter().map(|power_w| power_w / config.voltage_v).collect());
+    }
+    let phases = config.phases.max(1);
+    let current_a = state.grid_power_w? / phases as f64 / config.voltage_v;
+    Some(vec![current_a; phases as usize])
+}
+
+/// Current a load now drawing `own_current_a` per phase may draw without the
+/// busiest phase exceeding the fuse; `None` without a fresh grid reading. The
+/// load's own draw is taken off every phase, as the meter doesn't say which
+/// phase a single-phase load is on.
+pub fn headroom_a(
+    config: &MainFuseConfig,
+    state: &BatteryState,
+    own_current_a: f64,
+    now: DateTime<Utc>,
+) -> Option<f64> {
+    let busiest = phase_currents(config, state, now)?
+        .into_iter()
+        .map(|current_a| current_a - own_current_a)
+        .fold(f64::MIN, f64::max);
+    Some((config.current_a - busiest).max(0.0))
+}
+
+/// Highest grid setpoint (W) keeping the total within the fuse and the
+/// contracted power and, from a fresh grid reading, the busiest phase within
+/// the fuse. The battery's change from the measured grid power is taken to
+/// spread evenly over the phases.
+pub fn max_setpoint_w(config: &MainFuseConfig, state: &BatteryState, now: DateTime<Utc>) -> f64 {
+    let phases = config.phases.max(1) as f64;
+    let mut max_w = config.current_a * config.voltage_v * phases;
+    if let Some(contracted_w) = config.max_grid_power_w {
+        max_w = max_w.min(contracted_w);
+    }
+    if let Some(currents) = phase_currents(config, state, now) {
+        let grid_w = currents.iter().sum::<f64>() * config.voltage_v;
+        let busiest = currents.into_iter().fold(f64::MIN, f64::max);
+        max_w = max_w.min(grid_w + (config.current_a - busiest) * config.voltage_v * phases);
+    }
+    max_w
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn setpoint_leaves_the_busiest_phase_within_the_fuse() {
+        let config: MainFuseConfig = serde_yaml::from_str("current_a: 25
+max_grid_power_w: 15000
+").unwrap();
+        let now = Utc::now();
+        let mut state = BatteryState::default();
+        assert_eq!(max_setpoint_w(&config, &state, now), 15000.0);
+
+        // 20A on L1 (4600W) and 2A on the others: 5A more per phase
+        state.grid_phase_power_w = Some([4600.0, 460.0, 460.0]);
+        state.last_grid_power_update = Some(now);
+        let max_w = max_setpoint_w(&config, &state, now);
+        assert!((max_w - (5520.0 + 5.0 * 230.0 * 3.0)).abs() < 1e-6, "{}", max_w);
+    }
+}
@@ -0,0 +1,158 @@
+use chrono::{DateTime, Utc};
+use tracing::{info, warn};
+
+use crate::config::GridOutageConfig;
+use crate::mqtt::BatteryState;
+
+/// Grid connection transitions worth alerting on
+#[derive(Debug, Clone, PartialEq)]
+pub enum GridEvent {
+    Lost(String),
+    Restored,
+}
+
+/// Why the grid looks down: the Victron grid-lost alarm is raised, or the grid
+/// meter has been silent for over `meter_timeout_secs`
+pub fn outage_reason(state: &BatteryState, now: DateTime<Utc>, meter_timeout_secs: u64) -> Option<String> {
+    let meter_silent = state
+        .last_grid_power_update
+        .is_some_and(|last| now.signed_duration_since(last).num_seconds() as u64 > meter_timeout_secs);
+
+    if state.grid_lost == Some(true) {
+        Some("grid-lost alarm raised".to_string())
+    } else if meter_silent {
+        Some(format!("no grid meter data for over {}s", meter_timeout_secs))
+    } else {
+        None
+    }
+}
+
+/// Detects grid loss from the Victron grid-lost alarm or a silent grid meter,
+/// and keeps the reserve raised for a while after the grid returns
+#[derive(Debug)]
+pub struct GridMonitor {
+    config: GridOutageConfig,
+    lost: bool,
+    restored_at: Option<DateTime<Utc>>,
+}
+
+impl GridMonitor {
+    pub fn new(config: GridOutageConfig) -> Self {
+        Self {
+            config,
+            lost: false,
+            restored_at: None,
+        }
+    }
+
+    pub fn is_lost(&self) -> bool {
+        self.lost
+    }
+
+    /// Update from the latest telemetry; returns an event on transitions
+    pub fn update(&mut self, state: &BatteryState, now: DateTime<Utc>) -> Option<GridEvent> {
+        match (self.lost, outage_reason(state, now, self.config.meter_timeout_secs)) {
+            (false, Some(reason)) => {
+                warn!("Grid outage detected: {}", reason);
+                self.lost = true;
+                self.restored_at = None;
+                Some(GridEvent::Lost(reason))
+            }
+            (true, None) => {
+                info!("Grid restored, keeping raised reserve for {}h", self.config.reserve_hold_hours);
+                self.lost = false;
+                self.restored_at = Some(now);
+                Some(GridEvent::Restored)
+            }
+            _ => None,
+        }
+    }
+
+    /// Raised minimum SoC while the grid is down and for a while after it returns
+    pub fn reserve_override(&self, now: DateTime<Utc>) -> Option<f64> {
+        let in_hold = self.restored_at.is_some_and(|restored| {
+            now.signed_duration_since(restored).num_seconds() < (self.config.reserve_hold_hours * 3600.0) as i64
+        });
+        (self.lost || in_hold).then_some(self.config.reserve_soc_percent)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, TimeZone};
+
+    fn reading(grid_lost: bool, metered_at: DateTime<Utc>) -> BatteryState {
+        BatteryState {
+            grid_lost: Some(grid_lost),
+            last_grid_power_update: Some(metered_at),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn tells_an_outage_from_the_alarm_or_a_silent_meter() {
+        let now = Utc.with_ymd_and_hms(2025, 1, 15, 18, 0, 0).unwrap();
+
+        assert_eq!(outage_reason(&reading(false, now), now, 120), None);
+        assert_eq!(outage_reason(&BatteryState::default(), now, 120), None);
+        assert_eq!(
+            outage_reason(&reading(true, now), now, 120).as_deref(),
+            Some("grid-lost alarm raised")
+        );
+        assert_eq!(outage_reason(&reading(false, now - Duration::seconds(120)), now, 120), None);
+        assert_eq!(
+            outage_reason(&reading(false, now - Duration::seconds(121)), now, 120).as_deref(),
+            Some("no grid meter data for over 120s")
+        );
+    }
+
+    #[test]
+    fn raises_the_reserve_through_an_outage_and_after_recovery() {
+        let config = GridOutageConfig::default();
+        let mut grid = GridMonitor::new(config.clone());
+        let start = Utc.with_ymd_and_hms(2025, 1, 15, 18, 0, 0).unwrap();
+
+        assert_eq!(grid.update(&reading(false, start), start), None);
+        assert_eq!(grid.reserve_override(start), None);
+
+        let lost_at = start + Duration::minutes(1);
+        assert_eq!(
+            grid.update(&reading(true, lost_at), lost_at),
+            Some(GridEvent::Lost("grid-lost alarm raised".to_string()))
+        );
+        assert!(grid.is_lost());
+        assert_eq!(grid.reserve_override(lost_at), Some(config.reserve_soc_percent));
+        assert_eq!(grid.update(&reading(true, lost_at), lost_at + Duration::minutes(1)), None);
+
+        let restored_at = start + Duration::hours(1);
+        assert_eq!(grid.update(&reading(false, restored_at), restored_at), Some(GridEvent::Restored));
+        assert!(!grid.is_lost());
+        let hold = Duration::seconds((config.reserve_hold_hours * 3600.0) as i64);
+        assert_eq!(grid.reserve_override(restored_at + hold - Duration::minutes(1)), Some(config.reserve_soc_percent));
+        assert_eq!(grid.reserve_override(restored_at + hold), None);
+    }
+
+    #[test]
+    fn flapping_readings_report_each_transition_and_restart_the_hold() {
+        let config = GridOutageConfig::default();
+        let mut grid = GridMonitor::new(config.clone());
+        let start = Utc.with_ymd_and_hms(2025, 1, 15, 18, 0, 0).unwrap();
+
+        let mut events = Vec::new();
+        for (i, lost) in [true, false, true, false].into_iter().enumerate() {
+            let now = start + Duration::seconds(10 * i as i64);
+            events.push(grid.update(&reading(lost, now), now));
+        }
+        assert!(matches!(
+            events[..],
+            [Some(GridEvent::Lost(_)), Some(GridEvent::Restored), Some(GridEvent::Lost(_)), Some(GridEvent::Restored)]
+        ));
+
+        // The hold runs from the last recovery, not the first
+        let hold = Duration::seconds((config.reserve_hold_hours * 3600.0) as i64);
+        let last_restore = start + Duration::seconds(30);
+        assert_eq!(grid.reserve_override(last_restore + hold - Duration::seconds(5)), Some(config.reserve_soc_percent));
+        assert_eq!(grid.reserve_override(last_restore + hold), None);
+    }
+}
@@ -0,0 +1,261 @@
+//! Dynamic import limits set by the grid operator (§14a EnWG): a
+//! ripple-control receiver or EEBus gateway reports the limit over MQTT, and
+//! while it is active the grid setpoint is hard-capped at it, whatever mode
+//! was chosen. A receiver that repeats its state and falls silent counts as
+//! lost, and the fallback limit applies until it is heard from again. Every
+//! start, change and end of a limit is appended to a log in the data directory
+//! as proof of compliance.
+
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tracing::{info, warn};
+
+use crate::config::GridLimitConfig;
+use crate::mqtt::BatteryState;
+use crate::optimizer::OptimizationResult;
+
+/// A change of the limit in force
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GridLimitEvent {
+    Started(f64),
+    Changed(f64),
+    Ended,
+}
+
+/// The limit in force and what it cut off
+#[derive(Debug, Clone, Copy)]
+struct ActiveLimit {
+    since: DateTime<Utc>,
+    limit_w: f64,
+    /// Cycles in which the setpoint was capped
+    capped_cycles: u32,
+    /// Highest setpoint asked for while the limit was active (W)
+    max_requested_w: Option<f64>,
+}
+
+/// One line of the compliance log
+#[derive(Debug, Serialize)]
+struct LogEntry {
+    at: DateTime<Utc>,
+    event: &'static str,
+    limit_w: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    duration_minutes: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    capped_cycles: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_requested_w: Option<f64>,
+}
+
+#[derive(Debug)]
+pub struct GridLimit {
+    config: GridLimitConfig,
+    path: PathBuf,
+    active: Option<ActiveLimit>,
+    /// Whether the signal was lost at the last update
+    lost: bool,
+}
+
+impl GridLimit {
+    /// Log to `<data_dir>/grid_limit_log.jsonl`
+    pub fn new(config: GridLimitConfig, data_dir: &str) -> Self {
+        Self {
+            config,
+            path: Path::new(data_dir).join("grid_limit_log.jsonl"),
+            active: None,
+            lost: false,
+        }
+    }
+
+    /// The limit a signal stands for: the allowed import itself (W, zero or
+    /// below = lifted), or with `limit_w` configured a switch (non-zero =
+    /// limited)
+    fn limit_from(&self, signal: f64) -> Option<f64> {
+        match self.config.limit_w {
+            Some(limit_w) => (signal != 0.0).then_some(limit_w),
+            None => (signal > 0.0).then_some(signal),
+        }
+    }
+
+    /// Whether a repeating signal hasn't been heard from within the timeout
+    fn signal_lost(&self, state: &BatteryState, now: DateTime<Utc>) -> bool {
+        let Some(timeout) = self.config.signal_timeout_minutes else {
+            return false;
+        };
+        state
+            .last_grid_limit_update
+            .is_some_and(|at| (now - at).num_minutes() >= timeout as i64)
+    }
+
+    /// Follow the latest signal, logging every change of the limit
+    pub fn update(&mut self, state: &BatteryState, now: DateTime<Utc>) -> Option<GridLimitEvent> {
+        let lost = self.signal_lost(state, now);
+        if lost != self.lost {
+            if lost {
+                warn!(
+                    "Grid limit signal lost, import capped at {:.0}W until it returns",
+                    self.config.fallback_limit_w
+                );
+            } else {
+                info!("Grid limit signal back");
+            }
+            self.lost = lost;
+        }
+        let limit_w = if lost {
+            Some(self.config.fallback_limit_w)
+        } else {
+            state.grid_limit_signal.and_then(|signal| self.limit_from(signal))
+        };
+        match (self.active, limit_w) {
+            (None, Some(limit_w)) => {
+                warn!("Grid operator limit active: import capped at {:.0}W", limit_w);
+                self.active = Some(ActiveLimit {
+                    since: now,
+                    limit_w,
+                    capped_cycles: 0,
+                    max_requested_w: None,
+                });
+                self.log(&LogEntry::new(now, "start", limit_w));
+                Some(GridLimitEvent::Started(limit_w))
+            }
+            (Some(active), Some(limit_w)) if active.limit_w != limit_w => {
+                warn!("Grid operator limit changed: import capped at {:.0}W", limit_w);
+                self.active = Some(ActiveLimit { limit_w, ..active });
+                self.log(&LogEntry::new(now, "change", limit_w));
+                Some(GridLimitEvent::Changed(limit_w))
+            }
+            (Some(active), None) => {
+                let minutes = (now - active.since).num_seconds() as f64 / 60.0;
+                info!(
+                    "Grid operator limit lifted after {:.0} min ({} cycles capped)",
+                    minutes, active.capped_cycles
+                );
+                self.active = None;
+                self.log(&LogEntry {
+                    duration_minutes: Some(minutes),
+                    capped_cycles: Some(active.capped_cycles),
+                    max_requested_w: active.max_requested_w,
+                    ..LogEntry::new(now, "end", active.limit_w)
+                });
+                Some(GridLimitEvent::Ended)
+            }
+            _ => None,
+        }
+    }
+
+    /// Hard-cap the grid setpoint at the limit in force
+    pub fn cap(&mut self, result: &mut OptimizationResult) {
+        let Some(active) = self.active.as_mut() else {
+            return;
+        };
+        let requested_w = result.grid_setpoint_w;
+        active.max_requested_w = Some(active.max_requested_w.map_or(requested_w, |max| max.max(requested_w)));
+        if requested_w > active.limit_w {
+            active.capped_cycles += 1;
+            result.cap_setpoint(active.limit_w, "the grid operator");
+        }
+    }
+
+    /// The allowed import while a limit is active (W)
+    pub fn limit_w(&self) -> Option<f64> {
+        self.active.map(|active| active.limit_w)
+    }
+
+    fn log(&self, entry: &LogEntry) {
+        if let Err(e) = self.append(entry) {
+            warn!("Failed to write grid limit log {}: {}", self.path.display(), e);
+        }
+    }
+
+    fn append(&self, entry: &LogEntry) -> Result<()> {
+        let mut file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        writeln!(file, "{}", serde_json::to_string(entry)?)?;
+        Ok(())
+    }
+}
+
+impl LogEntry {
+    fn new(at: DateTime<Utc>, event: &'static str, limit_w: f64) -> Self {
+        Self {
+            at,
+            event,
+            limit_w,
+            duration_minutes: None,
+            capped_cycles: None,
+            max_requested_w: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::optimizer::BatteryMode;
+    use chrono::Duration;
+
+    #[test]
+    fn caps_the_setpoint_while_switched_on_and_logs_the_period() {
+        let dir = std::env::temp_dir().join(format!("grid_limit_test_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let config = GridLimitConfig {
+            limit_w: Some(4200.0),
+            signal_timeout_minutes: Some(5),
+            ..GridLimitConfig::default()
+        };
+        let mut limit = GridLimit::new(config, dir.to_str().unwrap());
+        let now = DateTime::parse_from_rfc3339("2025-12-01T18:00:00+00:00").unwrap().with_timezone(&Utc);
+        let signal = |value: f64, at: DateTime<Utc>| BatteryState {
+            grid_limit_signal: Some(value),
+            last_grid_limit_update: Some(at),
+            ..BatteryState::default()
+        };
+        let mut result = OptimizationResult {
+            mode: BatteryMode::ChargeFull,
+            grid_setpoint_w: 9000.0,
+            reason: "cheap slot".to_string(),
+            alternative: None,
+        };
+
+        assert_eq!(limit.update(&signal(0.0, now), now), None);
+        assert_eq!(limit.update(&signal(1.0, now), now), Some(GridLimitEvent::Started(4200.0)));
+        limit.cap(&mut result);
+        assert_eq!(result.grid_setpoint_w, 4200.0);
+        assert_eq!(result.mode, BatteryMode::ChargeReduced);
+
+        let later = now + Duration::minutes(30);
+        assert_eq!(limit.update(&signal(0.0, later), later), Some(GridLimitEvent::Ended));
+        let log = std::fs::read_to_string(&limit.path).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        let lines: Vec<serde_json::Value> = log.lines().map(|l| serde_json::from_str(l).unwrap()).collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[1]["event"], "end");
+        assert_eq!(lines[1]["capped_cycles"], 1);
+        assert_eq!(lines[1]["max_requested_w"], 9000.0);
+    }
+
+    #[test]
+    fn applies_the_fallback_limit_while_the_signal_is_lost() {
+        let config = GridLimitConfig {
+            signal_timeout_minutes: Some(5),
+            ..GridLimitConfig::default()
+        };
+        let dir = std::env::temp_dir().join(format!("grid_limit_lost_test_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut limit = GridLimit::new(config, dir.to_str().unwrap());
+        let now = DateTime::parse_from_rfc3339("2025-12-01T18:00:00+00:00").unwrap().with_timezone(&Utc);
+        let state = BatteryState {
+            grid_limit_signal: Some(0.0),
+            last_grid_limit_update: Some(now),
+            ..BatteryState::default()
+        };
+
+        assert_eq!(limit.update(&state, now + Duration::minutes(4)), None);
+        assert_eq!(limit.update(&state, now + Duration::minutes(5)), Some(GridLimitEvent::Started(4200.0)));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,57 @@
+use chrono::{DateTime, FixedOffset, Utc};
+use serde::Deserialize;
+use tracing::info;
+
+/// A window during which the battery must be held idle (e.g. firmware updates)
+#[derive(Debug, Clone, Deserialize)]
+pub struct HoldWindow {
+    pub start: DateTime<FixedOffset>,
+    pub end: DateTime<FixedOffset>,
+    #[serde(default)]
+    pub reason: Option<String>,
+}
+
+impl HoldWindow {
+    pub fn contains(&self, now: DateTime<Utc>) -> bool {
+        now >= self.start && now < self.end
+    }
+
+    pub fn describe(&self) -> String {
+        format!(
+            "hold until {}{}",
+            self.end.to_rfc3339(),
+            self.reason.as_deref().map(|r| format!(" ({})", r)).unwrap_or_default()
+        )
+    }
+}
+
+/// Configured and commanded hold windows
+#[derive(Debug, Default)]
+pub struct HoldSchedule {
+    windows: Vec<HoldWindow>,
+}
+
+impl HoldSchedule {
+    pub fn new(windows: Vec<HoldWindow>) -> Self {
+        Self { windows }
+    }
+
+    pub fn add(&mut self, window: HoldWindow) {
+        info!("Scheduled battery {} from {}", window.describe(), window.start.to_rfc3339());
+        self.windows.push(window);
+    }
+
+    /// Cancel all active and upcoming holds
+    pub fn clear(&mut self) {
+        if !self.windows.is_empty() {
+            info!("Cancelled {} battery hold window(s)", self.windows.len());
+        }
+        self.windows.clear();
+    }
+
+    /// The hold window active at `now`, dropping windows that have ended
+    pub fn active(&mut self, now: DateTime<Utc>) -> Option<&HoldWindow> {
+        self.windows.retain(|w| w.end > now);
+        self.windows.iter().find(|w| w.contains(now))
+    }
+}
@@ -0,0 +1,161 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use tracing::{debug, warn};
+
+use crate::config::HomeAssistantConfig;
+use crate::controller::{BatteryController, WriteFuture};
+use crate::http::HttpClient;
+use crate::mqtt::BatteryState;
+
+/// Reads battery telemetry from Home Assistant entities over its REST API,
+/// for inverters integrated in HA but not exposed on the MQTT broker
+pub struct HomeAssistantTelemetry {
+    config: HomeAssistantConfig,
+    http_client: HttpClient,
+    soc: Option<(f64, DateTime<Utc>)>,
+    grid_power_w: Option<f64>,
+    battery_power_w: Option<f64>,
+    house_load_w: Option<f64>,
+    pv_power_w: Option<f64>,
+    setpoint_w: Option<(f64, DateTime<Utc>)>,
+}
+
+impl HomeAssistantTelemetry {
+    pub fn new(config: HomeAssistantConfig) -> Self {
+        Self {
+            config,
+            http_client: HttpClient::new(),
+            soc: None,
+            grid_power_w: None,
+            battery_power_w: None,
+            house_load_w: None,
+            pv_power_w: None,
+            setpoint_w: None,
+        }
+    }
+
+    /// Numeric state of `entity_id`, or None while it is unavailable. Power in
+    /// kW is converted to W.
+    async fn state(&self, entity_id: &str) -> Result<Option<f64>> {
+        let url = format!("{}/api/states/{}", self.config.url.trim_end_matches('/'), entity_id);
+        let auth = format!("Bearer {}", self.config.token);
+        let response = self
+            .http_client
+            .get(&url, &[("Authorization", auth.as_str()), ("Accept", "application/json")])
+            .await?;
+        if !response.is_success() {
+            anyhow::bail!("Home Assistant API error: {} - {}", response.status, response.text());
+        }
+
+        let body: serde_json::Value = serde_json::from_slice(&response.body)?;
+        let Some(value) = body["state"].as_str().and_then(|s| s.parse::<f64>().ok()) else {
+            debug!("Home Assistant entity {} has no numeric state: {}", entity_id, body["state"]);
+            return Ok(None);
+        };
+        let scale = match body["attributes"]["unit_of_measurement"].as_str() {
+            Some("kW") => 1000.0,
+            _ => 1.0,
+        };
+        Ok(Some(value * scale))
+    }
+
+    /// Read one entity, keeping the last good value when the read fails
+    async fn read(&self, entity_id: &Option<String>, last: Option<f64>) -> Option<f64> {
+        let entity_id = entity_id.as_deref()?;
+        match self.state(entity_id).await {
+            Ok(value) => value,
+            Err(e) => {
+                warn!("Failed to read Home Assistant entity {}: {}", entity_id, e);
+                last
+            }
+        }
+    }
+
+    /// Refresh all configured entities
+    pub async fn poll(&mut self, now: DateTime<Utc>) {
+        if let Some(soc) = self.read(&self.config.soc_entity, None).await {
+            self.soc = Some((soc.clamp(0.0, 100.0), now));
+        }
+        self.grid_power_w = self.read(&self.config.grid_power_entity, self.grid_power_w).await;
+        self.battery_power_w = self.read(&self.config.battery_power_entity, self.battery_power_w).await;
+        self.house_load_w = self.read(&self.config.house_load_entity, self.house_load_w).await;
+        self.pv_power_w = self.read(&self.config.pv_power_entity, self.pv_power_w).await;
+        if let Some(setpoint_w) = self.read(&self.config.setpoint_entity, None).await {
+            self.setpoint_w = Some((setpoint_w, now));
+        }
+    }
+
+    pub fn pv_power_w(&self) -> Option<f64> {
+        self.pv_power_w
+    }
+
+    /// The MQTT state with the Home Assistant readings taking precedence
+    pub fn apply(&self, state: BatteryState) -> BatteryState {
+        let (soc, last_soc_update) = match self.soc {
+            Some((soc, at)) => (soc, Some(at)),
+            None => (state.soc, state.last_soc_update),
+        };
+        let (current_setpoint_w, last_setpoint_update) = match self.setpoint_w {
+            Some((setpoint_w, at)) => (Some(setpoint_w), Some(at)),
+            None => (state.current_setpoint_w, state.last_setpoint_update),
+        };
+        BatteryState {
+            soc,
+            last_soc_update,
+            current_setpoint_w,
+            last_setpoint_update,
+            grid_power_w: self.grid_power_w.or(state.grid_power_w),
+            battery_power_w: self.battery_power_w.or(state.battery_power_w),
+            house_load_w: self.house_load_w.or(state.house_load_w),
+            ..state
+        }
+    }
+}
+
+/// Writes the grid setpoint by calling a Home Assistant service, for
+/// inverters only reachable through an HA integration
+pub struct HomeAssistantController {
+    config: HomeAssistantConfig,
+    http_client: HttpClient,
+}
+
+impl HomeAssistantController {
+    pub fn new(config: HomeAssistantConfig) -> Self {
+        Self {
+            config,
+            http_client: HttpClient::new(),
+        }
+    }
+}
+
+impl BatteryController for HomeAssistantController {
+    fn name(&self) -> &'static str {
+        "Home Assistant service"
+    }
+
+    fn write_setpoint(&self, setpoint_w: f64) -> WriteFuture<'_> {
+        Box::pin(async move {
+            let entity_id = self
+                .config
+                .setpoint_entity
+                .as_deref()
+                .ok_or_else(|| anyhow::anyhow!("home_assistant.setpoint_entity is missing"))?;
+            let (domain, service) = self
+                .config
+                .setpoint_service
+                .split_once('.')
+                .ok_or_else(|| anyhow::anyhow!("Invalid service '{}', expected domain.service", self.config.setpoint_service))?;
+            let url = format!("{}/api/services/{}/{}", self.config.url.trim_end_matches('/'), domain, service);
+            let auth = format!("Bearer {}", self.config.token);
+            // Adding zero turns -0 into 0
+            let value = setpoint_w.round() + 0.0;
+            let body = serde_json::json!({ "entity_id": entity_id, "value": value });
+            let response = self.http_client.post_json(&url, &[("Authorization", auth.as_str())], &body).await?;
+            if !response.is_success() {
+                anyhow::bail!("Home Assistant API error: {} - {}", response.status, response.text());
+            }
+            debug!("Called {} on {} with {} W", self.config.setpoint_service, entity_id, value);
+            Ok(())
+        })
+    }
+}
@@ -0,0 +1,261 @@
+use anyhow::Result;
+
+/// A fully buffered HTTP response
+#[derive(Debug, Clone)]
+pub struct HttpResponse {
+    pub status: u16,
+    pub body: Vec<u8>,
+}
+
+impl HttpResponse {
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+
+    /// Body as text, for error messages
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).into_owned()
+    }
+}
+
+/// Small HTTP client facade.
+///
+/// With the default `reqwest` feature this wraps `reqwest::Client` (with TLS).
+/// Without it, a minimal plain-HTTP/1.1 client over a tokio `TcpStream` is used,
+/// which keeps the binary small enough for GX devices. HTTPS endpoints then
+/// need to be reached through a local TLS-terminating proxy.
+#[derive(Clone, Default)]
+pub struct HttpClient {
+    #[cfg(feature = "reqwest")]
+    inner: reqwest::Client,
+}
+
+impl HttpClient {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A client for devices on the LAN that serve HTTPS with a self-signed
+    /// certificate
+    #[cfg(all(feature = "reqwest", feature = "powerwall"))]
+    pub fn accepting_invalid_certs() -> Result<Self> {
+        let inner = reqwest::Client::builder().danger_accept_invalid_certs(true).build()?;
+        Ok(Self { inner })
+    }
+
+    pub async fn get(&self, url: &str, headers: &[(&str, &str)]) -> Result<HttpResponse> {
+        self.request("GET", url, headers, None).await
+    }
+
+    pub async fn post_json(
+        &self,
+        url: &str,
+        headers: &[(&str, &str)],
+        body: &serde_json::Value,
+    ) -> Result<HttpResponse> {
+        self.request("POST", url, headers, Some(serde_json::to_vec(body)?))
+            .await
+    }
+
+    pub async fn put_json(
+        &self,
+        url: &str,
+        headers: &[(&str, &str)],
+        body: &serde_json::Value,
+    ) -> Result<HttpResponse> {
+        self.request("PUT", url, headers, Some(serde_json::to_vec(body)?))
+            .await
+    }
+
+    #[cfg(feature = "reqwest")]
+    async fn request(
+        &self,
+        method: &str,
+        url: &str,
+        headers: &[(&str, &str)],
+        body: Option<Vec<u8>>,
+    ) -> Result<HttpResponse> {
+        let method = reqwest::Method::from_bytes(method.as_bytes())?;
+        let mut request = self.inner.request(method, url);
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+        if let Some(body) = body {
+            request = request.header("Content-Type", "application/json").body(body);
+        }
+
+        let mut response = request.send().await?;
+        let status = response.status().as_u16();
+
+        // Read the body chunk by chunk into a single pre-sized buffer
+        let mut body = Vec::with_capacity(response.content_length().unwrap_or(0) as usize);
+        while let Some(chunk) = response.chunk().await? {
+            body.extend_from_slice(&chunk);
+        }
+
+        Ok(HttpResponse { status, body })
+    }
+
+    #[cfg(not(feature = "reqwest"))]
+    async fn request(
+        &self,
+        method: &str,
+        url: &str,
+        headers: &[(&str, &str)],
+        body: Option<Vec<u8>>,
+    ) -> Result<HttpResponse> {
+        minimal::request(method, url, headers, body).await
+    }
+}
+
+/// `Authorization` header value for HTTP basic auth
+pub fn basic_auth(user: &str, password: &str) -> String {
+    format!("Basic {}", base64(format!("{}:{}", user, password).as_bytes()))
+}
+
+/// Standard base64 with padding
+fn base64(input: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut output = String::new();
+    for chunk in input.chunks(3) {
+        let bytes = [chunk[0], chunk.get(1).copied().unwrap_or(0), chunk.get(2).copied().unwrap_or(0)];
+        let bits = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                output.push(ALPHABET[((bits >> (18 - 6 * i)) & 63) as usize] as char);
+            } else {
+                output.push('=');
+            }
+        }
+    }
+    output
+}
+
+/// Lets the Tibber client go through the same stack, so the minimal build
+/// reaches it over plain HTTP too
+#[cfg(feature = "tibber")]
+impl tibber_client::Transport for HttpClient {
+    fn post<'a>(&'a self, url: &'a str, headers: &'a [(&'a str, &'a str)], body: Vec<u8>) -> tibber_client::TransportFuture<'a> {
+        Box::pin(async move {
+            let response = self.request("POST", url, headers, Some(body)).await?;
+            Ok(tibber_client::HttpResponse {
+                status: response.status,
+                body: response.body,
+            })
+        })
+    }
+}
+
+#[cfg(not(feature = "reqwest"))]
+mod minimal {
+    use anyhow::{Context, Result};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpStream;
+
+    use super::HttpResponse;
+
+    pub async fn request(
+        method: &str,
+        url: &str,
+        headers: &[(&str, &str)],
+        body: Option<Vec<u8>>,
+    ) -> Result<HttpResponse> {
+        let (host, port, path) = parse_url(url)?;
+
+        let mut request = format!(
+            "{} {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\nUser-Agent: tibber-optimizer\r\n",
+            method, path, host
+        );
+        for (name, value) in headers {
+            request.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        if let Some(body) = &body {
+            request.push_str("Content-Type: application/json\r\n");
+            request.push_str(&format!("Content-Length: {}\r\n", body.len()));
+        }
+        request.push_str("\r\n");
+
+        let mut stream = TcpStream::connect((host.as_str(), port))
+            .await
+            .with_context(|| format!("Failed to connect to {}:{}", host, port))?;
+        stream.write_all(request.as_bytes()).await?;
+        if let Some(body) = &body {
+            stream.write_all(body).await?;
+        }
+
+        // Connection: close, so the response ends at EOF
+        let mut raw = Vec::new();
+        stream.read_to_end(&mut raw).await?;
+
+        parse_response(&raw)
+    }
+
+    /// Split `http://host[:port]/path` into its parts
+    fn parse_url(url: &str) -> Result<(String, u16, String)> {
+        let rest = match url.strip_prefix("http://") {
+            Some(rest) => rest,
+            None if url.starts_with("https://") => anyhow::bail!(
+                "HTTPS URL {} requires the `reqwest` feature (or a local TLS proxy)",
+                url
+            ),
+            None => anyhow::bail!("Unsupported URL: {}", url),
+        };
+
+        let (authority, path) = match rest.find('/') {
+            Some(idx) => (&rest[..idx], &rest[idx..]),
+            None => (rest, "/"),
+        };
+
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) => (host, port.parse().context("Invalid port in URL")?),
+            None => (authority, 80),
+        };
+
+        Ok((host.to_string(), port, path.to_string()))
+    }
+
+    fn parse_response(raw: &[u8]) -> Result<HttpResponse> {
+        let header_end = raw
+            .windows(4)
+            .position(|w| w == b"\r\n\r\n")
+            .context("Malformed HTTP response: no header terminator")?;
+        let head = std::str::from_utf8(&raw[..header_end])?;
+        let body = &raw[header_end + 4..];
+
+        let mut lines = head.split("\r\n");
+        let status = lines
+            .next()
+            .and_then(|line| line.split_whitespace().nth(1))
+            .and_then(|code| code.parse().ok())
+            .context("Malformed HTTP status line")?;
+
+        let chunked = lines.any(|line| {
+            let lower = line.to_ascii_lowercase();
+            lower.starts_with("transfer-encoding:") && lower.contains("chunked")
+        });
+
+        let body = if chunked { decode_chunked(body)? } else { body.to_vec() };
+
+        Ok(HttpResponse { status, body })
+    }
+
+    fn decode_chunked(mut data: &[u8]) -> Result<Vec<u8>> {
+        let mut out = Vec::with_capacity(data.len());
+        loop {
+            let line_end = data
+                .windows(2)
+                .position(|w| w == b"\r\n")
+                .context("Malformed chunked body")?;
+            let size_str = std::str::from_utf8(&data[..line_end])?;
+            let size_str = size_str.split(';').next().unwrap_or("").trim();
+            let size = usize::from_str_radix(size_str, 16).context("Invalid chunk size")?;
+            data = &data[line_end + 2..];
+            if size == 0 {
+                return Ok(out);
+            }
+            anyhow::ensure!(data.len() >= size, "Truncated chunked body");
+            out.extend_from_slice(&data[..size]);
+            data = data.get(size + 2..).unwrap_or(&[]);
+        }
+    }
+}
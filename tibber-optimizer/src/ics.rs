@@ -0,0 +1,259 @@
+use chrono::{DateTime, FixedOffset, Utc};
+
+use crate::optimizer::{BatteryMode, PlannedSlot};
+
+/// A run of consecutive plan slots sharing one calendar event
+struct Window<'a> {
+    slots: &'a [PlannedSlot],
+}
+
+impl Window<'_> {
+    fn start(&self) -> DateTime<FixedOffset> {
+        self.slots[0].starts_at
+    }
+
+    fn end(&self) -> DateTime<FixedOffset> {
+        self.slots[self.slots.len() - 1].ends_at
+    }
+
+    fn avg_price(&self) -> f64 {
+        self.slots.iter().map(|s| s.price).sum::<f64>() / self.slots.len() as f64
+    }
+
+    fn avg_sell_price(&self) -> f64 {
+        self.slots.iter().map(|s| s.sell_price).sum::<f64>() / self.slots.len() as f64
+    }
+
+    /// Battery energy over the window (kWh, positive = charged)
+    fn energy_kwh(&self) -> f64 {
+        self.slots.iter().map(|s| s.battery_power_w / 1000.0 * s.hours()).sum()
+    }
+}
+
+/// Calendar event types derived from the plan
+#[derive(Debug, Clone, Copy)]
+enum EventKind {
+    Charge,
+    Discharge,
+    Cheap,
+}
+
+impl EventKind {
+    const ALL: [EventKind; 3] = [EventKind::Charge, EventKind::Discharge, EventKind::Cheap];
+
+    fn matches(self, slot: &PlannedSlot) -> bool {
+        match self {
+            EventKind::Charge => matches!(slot.mode, BatteryMode::ChargeFull | BatteryMode::ChargeReduced),
+            EventKind::Discharge => slot.mode == BatteryMode::DischargeToGrid,
+            EventKind::Cheap => slot.cheap,
+        }
+    }
+
+    fn id(self) -> &'static str {
+        match self {
+            EventKind::Charge => "charge",
+            EventKind::Discharge => "discharge",
+            EventKind::Cheap => "cheap",
+        }
+    }
+
+    fn summary(self) -> &'static str {
+        match self {
+            EventKind::Charge => "Battery charging",
+            EventKind::Discharge => "Battery discharging to grid",
+            EventKind::Cheap => "Cheap electricity",
+        }
+    }
+}
+
+/// Maximal runs of consecutive slots matching `predicate`
+fn windows(plan: &[PlannedSlot], predicate: impl Fn(&PlannedSlot) -> bool) -> Vec<Window<'_>> {
+    let mut result = Vec::new();
+    let mut start = None;
+    for (i, slot) in plan.iter().enumerate() {
+        match (predicate(slot), start) {
+            (true, None) => start = Some(i),
+            (false, Some(s)) => {
+                result.push(Window { slots: &plan[s..i] });
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    if let Some(s) = start {
+        result.push(Window { slots: &plan[s..] });
+    }
+    result
+}
+
+fn format_utc(time: DateTime<FixedOffset>) -> String {
+    time.with_timezone(&Utc).format("%Y%m%dT%H%M%SZ").to_string()
+}
+
+/// The projected plan as an iCalendar feed: battery charge and discharge
+/// windows plus cheap-price windows for flexible household loads
+pub fn plan_calendar(plan: &[PlannedSlot], now: DateTime<Utc>) -> String {
+    let mut lines = vec![
+        "BEGIN:VCALENDAR".to_string(),
+        "VERSION:2.0".to_string(),
+        "PRODID:-//tibber-optimizer//plan//EN".to_string(),
+        "CALSCALE:GREGORIAN".to_string(),
+        "X-WR-CALNAME:Battery plan".to_string(),
+    ];
+    for kind in EventKind::ALL {
+        for window in windows(plan, |slot| kind.matches(slot)) {
+            let description = match kind {
+                EventKind::Cheap => format!("Average price {:.4} EUR/kWh", window.avg_price()),
+                EventKind::Charge => format!(
+                    "{:.1} kWh at an average price of {:.4} EUR/kWh",
+                    window.energy_kwh(),
+                    window.avg_price()
+                ),
+                EventKind::Discharge => format!(
+                    "{:.1} kWh at an average sell price of {:.4} EUR/kWh",
+                    -window.energy_kwh(),
+                    window.avg_sell_price()
+                ),
+            };
+            lines.extend([
+                "BEGIN:VEVENT".to_string(),
+                format!("UID:{}-{}@tibber-optimizer", kind.id(), format_utc(window.start())),
+                format!("DTSTAMP:{}", now.format("%Y%m%dT%H%M%SZ")),
+                format!("DTSTART:{}", format_utc(window.start())),
+                format!("DTEND:{}", format_utc(window.end())),
+                format!("SUMMARY:{}", kind.summary()),
+                format!("DESCRIPTION:{}", description),
+                "TRANSP:TRANSPARENT".to_string(),
+                "END:VEVENT".to_string(),
+            ]);
+        }
+    }
+    lines.push("END:VCALENDAR".to_string());
+
+    // iCalendar requires CRLF line endings
+    let mut calendar = lines.join("\r\n");
+    calendar.push_str("\r\n");
+    calendar
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    fn slot(starts_at: &str, minutes: i64, mode: BatteryMode) -> PlannedSlot {
+        let starts_at = DateTime::parse_from_rfc3339(starts_at).unwrap();
+        PlannedSlot {
+            starts_at,
+            ends_at: starts_at + Duration::minutes(minutes),
+            price: 0.20,
+            sell_price: 0.10,
+            cheap: false,
+            mode,
+            battery_power_w: if mode == BatteryMode::ChargeFull { 4000.0 } else { 0.0 },
+            grid_power_w: 0.0,
+            grid_setpoint_w: 0.0,
+            soc_end: 50.0,
+        }
+    }
+
+    /// The feed read back as one list of (property with parameters, value) per event
+    fn events(calendar: &str) -> Vec<Vec<(&str, &str)>> {
+        assert!(calendar.ends_with("\r\n") && !calendar.replace("\r\n", "").contains('\n'));
+        let mut events = Vec::new();
+        for line in calendar.split("\r\n") {
+            match line {
+                "BEGIN:VEVENT" => events.push(Vec::new()),
+                "END:VEVENT" | "" => {}
+                _ => {
+                    if let Some(event) = events.last_mut() {
+                        event.push(line.split_once(':').unwrap());
+                    }
+                }
+            }
+        }
+        events
+    }
+
+    fn property<'a>(event: &[(&str, &'a str)], name: &str) -> &'a str {
+        event.iter().find(|(key, _)| *key == name).map(|(_, value)| *value).unwrap()
+    }
+
+    fn now() -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339("2025-10-25T12:00:00Z").unwrap().with_timezone(&Utc)
+    }
+
+    #[test]
+    fn a_window_spanning_a_whole_day_is_not_an_all_day_event() {
+        let start = DateTime::parse_from_rfc3339("2025-12-01T00:00:00+01:00").unwrap();
+        let plan: Vec<PlannedSlot> = (0..96)
+            .map(|i| slot(&(start + Duration::minutes(15 * i)).to_rfc3339(), 15, BatteryMode::ChargeFull))
+            .collect();
+        let calendar = plan_calendar(&plan, now());
+        let events = events(&calendar);
+
+        assert_eq!(events.len(), 1);
+        // Date-times rather than VALUE=DATE, which calendars show as all-day
+        assert_eq!(property(&events[0], "DTSTART"), "20251130T230000Z");
+        assert_eq!(property(&events[0], "DTEND"), "20251201T230000Z");
+        assert!(!calendar.contains("VALUE=DATE"));
+        assert_eq!(property(&events[0], "DESCRIPTION"), "96.0 kWh at an average price of 0.2000 EUR/kWh");
+    }
+
+    #[test]
+    fn local_times_across_a_dst_change_are_written_in_utc() {
+        // The night the clocks go back: 02:00 local comes twice
+        let plan = [
+            slot("2025-10-26T01:00:00+02:00", 60, BatteryMode::ChargeFull),
+            slot("2025-10-26T02:00:00+02:00", 60, BatteryMode::ChargeFull),
+            slot("2025-10-26T02:00:00+01:00", 60, BatteryMode::ChargeFull),
+            slot("2025-10-26T03:00:00+01:00", 60, BatteryMode::SelfConsumption),
+        ];
+        let calendar = plan_calendar(&plan, now());
+        let events = events(&calendar);
+
+        // UTC times need no TZID or VTIMEZONE for the client to resolve
+        assert!(!calendar.contains("TZID") && !calendar.contains("VTIMEZONE"));
+        assert_eq!(events.len(), 1);
+        assert_eq!(property(&events[0], "DTSTART"), "20251025T230000Z");
+        assert_eq!(property(&events[0], "DTEND"), "20251026T020000Z");
+    }
+
+    #[test]
+    fn daily_windows_are_separate_events_instead_of_a_recurrence() {
+        let mut plan = Vec::new();
+        for day in ["2025-12-01", "2025-12-02"] {
+            for hour in 0..24 {
+                let mode = if (2..5).contains(&hour) {
+                    BatteryMode::ChargeFull
+                } else if hour == 18 {
+                    BatteryMode::DischargeToGrid
+                } else {
+                    BatteryMode::SelfConsumption
+                };
+                plan.push(slot(&format!("{}T{:02}:00:00+01:00", day, hour), 60, mode));
+            }
+        }
+        let calendar = plan_calendar(&plan, now());
+        let events = events(&calendar);
+
+        assert!(!calendar.contains("RRULE") && !calendar.contains("RECURRENCE-ID"));
+        let charges: Vec<_> = events
+            .iter()
+            .filter(|event| property(event, "SUMMARY") == "Battery charging")
+            .map(|event| (property(event, "UID"), property(event, "DTSTART")))
+            .collect();
+        assert_eq!(
+            charges,
+            [
+                ("charge-20251201T010000Z@tibber-optimizer", "20251201T010000Z"),
+                ("charge-20251202T010000Z@tibber-optimizer", "20251202T010000Z"),
+            ]
+        );
+        assert_eq!(events.len(), 4);
+        let mut uids: Vec<_> = events.iter().map(|event| property(event, "UID")).collect();
+        uids.sort();
+        uids.dedup();
+        assert_eq!(uids.len(), 4);
+    }
+}
@@ -0,0 +1,220 @@
+use std::io::Write;
+use std::path::Path;
+use std::time::Duration;
+
+use anyhow::Result;
+
+use crate::config::{default_tibber_url, TibberConfig};
+use crate::error::Error;
+use crate::scan::{self, BrokerScan, BrokerSettings};
+use crate::tibber::{describe_home, TibberClient};
+
+const SCAN_DURATION: Duration = Duration::from_secs(10);
+
+/// `tibber-optimizer init [path]`: interactively build a config file
+pub async fn run(path: Option<String>) -> Result<()> {
+    let path = path.unwrap_or_else(|| "config.yaml".to_string());
+    println!("Tibber Battery Optimizer setup - press enter to accept [defaults]\n");
+
+    if Path::new(&path).exists() && !confirm(&format!("{} exists, overwrite?", path), false)? {
+        return Ok(());
+    }
+
+    let (api_token, viewer) = loop {
+        let token = prompt("Tibber API token (https://developer.tibber.com/)", None)?;
+        let client = TibberClient::new(TibberConfig {
+            api_token: token.clone(),
+            api_url: default_tibber_url(),
+            refresh_interval_secs: 900,
+            live_measurement: false,
+            bidding_zone: None,
+            home_id: None,
+            home_index: None,
+        }, None);
+        match client.validate_token().await {
+            Ok(viewer) => {
+                println!("  Token valid (account: {})", viewer.name.as_deref().unwrap_or("unknown"));
+                break (token, viewer);
+            }
+            // A rejected token is worth asking for again, and so is a token
+            // that couldn't be checked for a passing network failure
+            Err(Error::Auth(message)) => println!("  {}", message),
+            Err(Error::Network(message)) => println!("  {} - check the connection and try again", message),
+            Err(e) => return Err(e.into()),
+        }
+    };
+
+    // Accounts with a cabin next to the house need to say which home the battery is in
+    let mut home_id = String::new();
+    if viewer.homes.len() > 1 {
+        for (index, home) in viewer.homes.iter().enumerate() {
+            println!("  {}: {}", index, describe_home(home));
+        }
+        let index: usize = prompt("Home with the battery", Some("0"))?.parse()?;
+        let home = viewer
+            .homes
+            .get(index)
+            .ok_or_else(|| anyhow::anyhow!("No home {}", index))?;
+        home_id = format!("  home_id: {}\n", quote(&home.id));
+    }
+
+    let settings = BrokerSettings {
+        host: prompt("MQTT broker host", Some("localhost"))?,
+        port: prompt("MQTT broker port", Some("1883"))?.parse()?,
+        username: optional(prompt("MQTT username (empty for none)", Some(""))?),
+        password: optional(prompt("MQTT password (empty for none)", Some(""))?),
+    };
+
+    println!("\nScanning the broker for {}s...", SCAN_DURATION.as_secs());
+    let found = match scan::scan(&settings, SCAN_DURATION).await {
+        Ok(found) => found,
+        Err(e) => {
+            println!("  Scan failed ({}), topics need to be entered manually", e);
+            BrokerScan::default()
+        }
+    };
+    let portal_id = found.portal_id.clone().unwrap_or_else(|| "YOUR_PORTAL_ID".to_string());
+    match &found.portal_id {
+        Some(id) => println!("  Found Victron device {} ({} topics)", id, found.topics.len()),
+        None => println!("  No Victron device found ({} topics)", found.topics.len()),
+    }
+
+    let soc_default = scan::SOC_PATTERNS
+        .iter()
+        .find_map(|pattern| found.first(pattern))
+        .unwrap_or_else(|| format!("N/{}/system/0/Batteries", portal_id));
+    let soc_topic = prompt("SoC topic", Some(&soc_default))?;
+
+    let setpoint_default = found
+        .first(scan::SETPOINT_PATTERN)
+        .unwrap_or_else(|| format!("N/{}/settings/0/Settings/CGwacs/AcPowerSetPoint", portal_id));
+    let setpoint_read_topic = prompt("Grid setpoint read topic", Some(&setpoint_default))?;
+    let setpoint_write_default = setpoint_read_topic.replacen("N/", "W/", 1);
+    let setpoint_write_topic = prompt("Grid setpoint write topic", Some(&setpoint_write_default))?;
+
+    let mut optional_topics = String::new();
+    for (key, pattern) in [
+        ("inverter_state_topic", scan::INVERTER_STATE_PATTERN),
+        ("grid_lost_topic", scan::GRID_LOST_PATTERN),
+        ("grid_power_topic", scan::GRID_POWER_PATTERN),
+        ("battery_power_topic", scan::BATTERY_POWER_PATTERN),
+        ("min_soc_limit_topic", scan::MIN_SOC_LIMIT_PATTERN),
+        ("active_soc_limit_topic", scan::ACTIVE_SOC_LIMIT_PATTERN),
+    ] {
+        if let Some(topic) = found.first(pattern) {
+            if confirm(&format!("Use {} for {}?", topic, key), true)? {
+                optional_topics.push_str(&format!("  {}: {}\n", key, quote(&topic)));
+            }
+        }
+    }
+    if let Some(prefix) = found
+        .first(scan::CHARGE_SCHEDULE_PATTERN)
+        .and_then(|topic| topic.rsplitn(3, '/').nth(2).map(str::to_string))
+    {
+        if confirm(&format!("Respect charge windows scheduled in the GX UI ({})?", prefix), true)? {
+            optional_topics.push_str(&format!("  charge_schedule_topic: {}\n", quote(&prefix)));
+        }
+    }
+
+    println!();
+    let capacity_default = proposed_capacity_kwh(&found).map_or("10.0".to_string(), |kwh| format!("{:.1}", kwh));
+    let capacity_kwh: f64 = prompt("Usable battery capacity (kWh)", Some(&capacity_default))?.parse()?;
+    let round_trip_efficiency: f64 = prompt("Round-trip efficiency", Some("0.90"))?.parse()?;
+    let min_soc_percent: f64 = prompt("Minimum SoC (%)", Some("10"))?.parse()?;
+    let max_power_w: f64 = prompt("Maximum charge/discharge grid setpoint (W)", Some("15000"))?.parse()?;
+
+    let config = format!(
+        r#"# Generated by `tibber-optimizer init`, see config.example.yaml for all options
+
+tibber:
+  api_token: {api_token}
+{home_id}
+mqtt:
+  host: {host}
+  port: {port}
+{credentials}  soc_topic: {soc_topic}
+  grid_setpoint_read_topic: {setpoint_read_topic}
+  grid_setpoint_write_topic: {setpoint_write_topic}
+  price_topic: "tibber/price/current"
+{optional_topics}
+battery:
+  capacity_kwh: {capacity_kwh:.1}
+  round_trip_efficiency: {round_trip_efficiency:.2}
+  min_soc_percent: {min_soc_percent:.1}
+  max_soc_percent: 100.0
+  max_charge_power_w: {max_power_w:.1}
+  max_discharge_power_w: {max_power_w:.1}
+
+optimizer:
+  base_consumption_w: 500.0
+"#,
+        api_token = quote(&api_token),
+        host = quote(&settings.host),
+        port = settings.port,
+        credentials = match (&settings.username, &settings.password) {
+            (Some(user), Some(pass)) => format!("  username: {}\n  password: {}\n", quote(user), quote(pass)),
+            _ => String::new(),
+        },
+        soc_topic = quote(&soc_topic),
+        setpoint_read_topic = quote(&setpoint_read_topic),
+        setpoint_write_topic = quote(&setpoint_write_topic),
+    );
+
+    std::fs::write(&path, config)?;
+    println!("\nWrote {}. Start the optimizer from this directory to use it.", path);
+    Ok(())
+}
+
+/// Sum of installed capacity (Ah) times voltage over all batteries seen
+fn proposed_capacity_kwh(found: &BrokerScan) -> Option<f64> {
+    let kwh: f64 = found
+        .matching(scan::BATTERY_CAPACITY_PATTERN)
+        .filter_map(|(topic, payload)| {
+            let ah = value(payload)?;
+            let voltage_topic = topic.replace("InstalledCapacity", "Dc/0/Voltage");
+            let voltage = found.topics.get(&voltage_topic).and_then(|p| value(p))?;
+            Some(ah * voltage / 1000.0)
+        })
+        .sum();
+    (kwh > 0.0).then_some(kwh)
+}
+
+/// Numeric value of a Victron `{"value": x}` payload
+fn value(payload: &str) -> Option<f64> {
+    serde_json::from_str::<serde_json::Value>(payload).ok()?.get("value")?.as_f64()
+}
+
+/// YAML double-quoted scalar (JSON string syntax is valid YAML)
+fn quote(value: &str) -> String {
+    serde_json::to_string(value).expect("strings always serialize")
+}
+
+fn optional(value: String) -> Option<String> {
+    (!value.is_empty()).then_some(value)
+}
+
+fn prompt(question: &str, default: Option<&str>) -> Result<String> {
+    loop {
+        match default {
+            Some(default) if !default.is_empty() => print!("{} [{}]: ", question, default),
+            _ => print!("{}: ", question),
+        }
+        std::io::stdout().flush()?;
+
+        let mut answer = String::new();
+        if std::io::stdin().read_line(&mut answer)? == 0 {
+            anyhow::bail!("Setup aborted");
+        }
+        let answer = answer.trim();
+        match (answer.is_empty(), default) {
+            (false, _) => return Ok(answer.to_string()),
+            (true, Some(default)) => return Ok(default.to_string()),
+            (true, None) => continue,
+        }
+    }
+}
+
+fn confirm(question: &str, default: bool) -> Result<bool> {
+    let answer = prompt(&format!("{} (y/n)", question), Some(if default { "y" } else { "n" }))?;
+    Ok(answer.eq_ignore_ascii_case("y") || answer.eq_ignore_ascii_case("yes"))
+}
@@ -0,0 +1,238 @@
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Datelike, Duration, DurationRound, FixedOffset, Timelike};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+
+use crate::mqtt::BatteryState;
+use crate::persist;
+use crate::stats::MAX_SAMPLE_GAP_HOURS;
+
+/// Number of past observations a bucket roughly averages over
+const WINDOW: u32 = 8;
+
+/// Observations a weekday bucket needs before it is trusted over the all-days one
+const MIN_WEEKDAY_SAMPLES: u32 = 3;
+
+/// Share of an hour that must be measured for it to count
+const MIN_HOUR_COVERAGE: f64 = 0.75;
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct Bucket {
+    /// Average house load (W) over the recent observations
+    pub avg_w: f64,
+    /// Hours folded into the average so far
+    pub samples: u32,
+}
+
+impl Bucket {
+    fn fold(&mut self, load_w: f64) {
+        self.samples += 1;
+        self.avg_w += (load_w - self.avg_w) / self.samples.min(WINDOW) as f64;
+    }
+}
+
+/// Learned house load per hour of day, over all days and per weekday
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LoadProfile {
+    /// By tariff-local hour of day
+    pub by_hour: Vec<Bucket>,
+    /// By weekday (Monday first) and hour, `weekday * 24 + hour`
+    pub by_weekday_hour: Vec<Bucket>,
+}
+
+impl Default for LoadProfile {
+    fn default() -> Self {
+        Self {
+            by_hour: vec![Bucket::default(); 24],
+            by_weekday_hour: vec![Bucket::default(); 7 * 24],
+        }
+    }
+}
+
+impl LoadProfile {
+    fn weekday_index(at: DateTime<FixedOffset>) -> usize {
+        at.weekday().num_days_from_monday() as usize * 24 + at.hour() as usize
+    }
+
+    /// Expected house load at `at` (W): the weekday's hour once it has been
+    /// seen a few times, else the hour over all days, if seen at all
+    pub fn load_w(&self, at: DateTime<FixedOffset>) -> Option<f64> {
+        let weekday = self.by_weekday_hour[Self::weekday_index(at)];
+        let hour = self.by_hour[at.hour() as usize];
+        if weekday.samples >= MIN_WEEKDAY_SAMPLES {
+            Some(weekday.avg_w)
+        } else {
+            (hour.samples > 0).then_some(hour.avg_w)
+        }
+    }
+
+    fn fold(&mut self, hour_start: DateTime<FixedOffset>, load_w: f64) {
+        self.by_hour[hour_start.hour() as usize].fold(load_w);
+        self.by_weekday_hour[Self::weekday_index(hour_start)].fold(load_w);
+    }
+}
+
+/// Energy measured in the hour being collected
+#[derive(Debug)]
+struct OpenHour {
+    start: DateTime<FixedOffset>,
+    wh: f64,
+    hours: f64,
+}
+
+/// Builds the load profile from measured house load, persisted in the data directory
+#[derive(Debug)]
+pub struct LoadProfiler {
+    path: PathBuf,
+    profile: LoadProfile,
+    open_hour: Option<OpenHour>,
+    last_sample: Option<DateTime<FixedOffset>>,
+}
+
+impl LoadProfiler {
+    /// Load the profile from `<data_dir>/load_profile.json`, starting fresh if absent
+    pub fn load(data_dir: &str) -> Self {
+        let path = Path::new(data_dir).join("load_profile.json");
+        Self {
+            profile: persist::load_json(&path),
+            path,
+            open_hour: None,
+            last_sample: None,
+        }
+    }
+
+    pub fn profile(&self) -> &LoadProfile {
+        &self.profile
+    }
+
+    /// Record the house load at tariff-local `now`: the house load topic if
+    /// configured, else grid minus battery power. Returns true when a completed
+    /// hour was folded into the profile.
+    pub fn record(&mut self, now: DateTime<FixedOffset>, state: &BatteryState) -> bool {
+        let load_w = state
+            .house_load_w
+            .or_else(|| Some(state.grid_power_w? - state.battery_power_w?));
+        let hour_start = now.duration_trunc(Duration::hours(1)).unwrap_or(now);
+
+        let mut folded = false;
+        if self.open_hour.as_ref().is_some_and(|open| open.start != hour_start) {
+            let done = self.open_hour.take().expect("checked above");
+            if done.hours >= MIN_HOUR_COVERAGE {
+                self.profile.fold(done.start, done.wh / done.hours);
+                folded = true;
+                match persist::save_json(&self.path, &self.profile) {
+                    Ok(()) => debug!("Saved load profile to {}", self.path.display()),
+                    Err(e) => warn!("Failed to save load profile: {}", e),
+                }
+            }
+        }
+        let open = self.open_hour.get_or_insert(OpenHour {
+            start: hour_start,
+            wh: 0.0,
+            hours: 0.0,
+        });
+
+        if let (Some(last), Some(load_w)) = (self.last_sample, load_w) {
+            // Only the part of the interval inside this hour counts towards it
+            let hours = now.signed_duration_since(last.max(hour_start)).num_seconds() as f64 / 3600.0;
+            if hours > 0.0 && hours <= MAX_SAMPLE_GAP_HOURS {
+                open.wh += load_w.max(0.0) * hours;
+                open.hours += hours;
+            }
+        }
+        self.last_sample = load_w.map(|_| now);
+
+        folded
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(time: &str) -> DateTime<FixedOffset> {
+        DateTime::parse_from_rfc3339(time).unwrap()
+    }
+
+    fn load(house_load_w: f64) -> BatteryState {
+        BatteryState {
+            house_load_w: Some(house_load_w),
+            ..Default::default()
+        }
+    }
+
+    /// Feed `load_w` every five minutes from `from` until before `to`; returns
+    /// the times a completed hour was folded in
+    fn measure(
+        profiler: &mut LoadProfiler,
+        from: DateTime<FixedOffset>,
+        to: DateTime<FixedOffset>,
+        load_w: f64,
+    ) -> Vec<DateTime<FixedOffset>> {
+        let mut folded = Vec::new();
+        let mut now = from;
+        while now < to {
+            if profiler.record(now, &load(load_w)) {
+                folded.push(now);
+            }
+            now += Duration::minutes(5);
+        }
+        folded
+    }
+
+    #[test]
+    fn buckets_measured_load_by_local_hour_of_day() {
+        let dir = std::env::temp_dir().join(format!("load_profile_test_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut profiler = LoadProfiler::load(dir.to_str().unwrap());
+        profiler.profile = LoadProfile::default();
+
+        // Monday morning, tariff-local (06:00 and 07:00 UTC)
+        let morning = at("2025-12-01T07:00:00+01:00");
+        assert!(measure(&mut profiler, morning, morning + Duration::hours(1), 500.0).is_empty());
+        let folded = measure(&mut profiler, morning + Duration::hours(1), morning + Duration::hours(2), 2000.0);
+        assert_eq!(folded, [morning + Duration::hours(1)]);
+        // Only 20 minutes of the next hour are measured, too little to count
+        measure(&mut profiler, morning + Duration::hours(2), morning + Duration::minutes(140), 800.0);
+        measure(&mut profiler, morning + Duration::hours(4), morning + Duration::minutes(245), 800.0);
+
+        let profile = profiler.profile();
+        assert_eq!(profile.by_hour.iter().filter(|b| b.samples > 0).count(), 2);
+        assert!((profile.by_hour[7].avg_w - 500.0).abs() < 1.0);
+        assert_eq!(profile.by_hour[9].samples, 0);
+        // Any day falls back to the hour over all days
+        assert!((profile.load_w(at("2025-12-04T07:30:00+01:00")).unwrap() - 500.0).abs() < 1.0);
+        assert!((profile.load_w(at("2025-12-04T08:30:00+01:00")).unwrap() - 2000.0).abs() < 1.0);
+        assert_eq!(profile.load_w(at("2025-12-04T09:30:00+01:00")), None);
+
+        // Once a weekday hour has been seen often enough it wins over the others
+        let mut profile = profile.clone();
+        for _ in 0..MIN_WEEKDAY_SAMPLES {
+            profile.fold(at("2025-12-06T07:00:00+01:00"), 1500.0);
+        }
+        assert!((profile.load_w(at("2025-12-13T07:15:00+01:00")).unwrap() - 1500.0).abs() < 1.0);
+        let weekday = profile.load_w(at("2025-12-08T07:15:00+01:00")).unwrap();
+        assert!(weekday > 500.0 && weekday < 1500.0, "Monday 07:00 should blend all days, got {:.0}W", weekday);
+
+        // Saved for the next start
+        let reloaded = LoadProfiler::load(dir.to_str().unwrap());
+        assert_eq!(reloaded.profile().by_hour[7].samples, 1);
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn without_history_the_profile_has_no_estimate() {
+        let dir = std::env::temp_dir().join(format!("load_profile_empty_test_{}", std::process::id()));
+        let mut profiler = LoadProfiler::load(dir.to_str().unwrap());
+        let start = at("2025-12-01T00:00:00+01:00");
+        // The planner then uses the configured base consumption
+        assert!((0..7 * 24).all(|hour| profiler.profile().load_w(start + Duration::hours(hour)).is_none()));
+
+        // Samples without any load reading don't start one either
+        for minutes in (0..180).step_by(5) {
+            assert!(!profiler.record(start + Duration::minutes(minutes), &BatteryState::default()));
+        }
+        assert_eq!(profiler.profile().load_w(start), None);
+    }
+}
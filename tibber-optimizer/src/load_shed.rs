@@ -0,0 +1,80 @@
+use tracing::{info, warn};
+
+use crate::config::SheddableLoad;
+use crate::optimizer::{BatteryMode, PlannedSlot};
+
+/// Sheds non-critical loads (in priority order) when the plan runs the battery
+/// into its reserve before the next cheap window, so the house isn't left
+/// pulling from the grid at expensive prices
+#[derive(Debug)]
+pub struct LoadShedder {
+    loads: Vec<SheddableLoad>,
+    /// Number of loads (from the front of `loads`) currently shed
+    shed: usize,
+}
+
+impl LoadShedder {
+    pub fn new(loads: Vec<SheddableLoad>) -> Self {
+        Self { loads, shed: 0 }
+    }
+
+    /// Names of the loads currently shed
+    pub fn shed_loads(&self) -> Vec<String> {
+        self.loads[..self.shed].iter().map(|l| l.name.clone()).collect()
+    }
+
+    /// Energy the grid would have to supply at non-cheap prices because the
+    /// battery sits at its reserve before the next cheap slot (kWh)
+    fn reserve_deficit(plan: &[PlannedSlot], base_consumption_w: f64) -> (f64, f64) {
+        let until_cheap: Vec<&PlannedSlot> = plan.iter().take_while(|s| !s.cheap).collect();
+        let hours: f64 = until_cheap.iter().map(|s| s.hours()).sum();
+        let deficit_kwh = until_cheap
+            .iter()
+            .filter(|s| s.mode == BatteryMode::Idle)
+            .map(|s| s.hours())
+            .sum::<f64>()
+            * base_consumption_w
+            / 1000.0;
+        (deficit_kwh, hours)
+    }
+
+    /// Recompute from the latest plan; returns loads whose state changed
+    /// (`true` = shed, `false` = restored)
+    pub fn update(&mut self, plan: &[PlannedSlot], base_consumption_w: f64) -> Vec<(SheddableLoad, bool)> {
+        let (deficit_kwh, hours) = Self::reserve_deficit(plan, base_consumption_w);
+
+        let required = if deficit_kwh <= 0.0 {
+            0
+        } else {
+            // Shed the fewest loads that save the deficit before the cheap window
+            let mut saved_kwh = 0.0;
+            self.loads
+                .iter()
+                .position(|load| {
+                    saved_kwh += load.power_w / 1000.0 * hours;
+                    saved_kwh >= deficit_kwh
+                })
+                .map_or(self.loads.len(), |idx| idx + 1)
+        };
+
+        // Shed more as soon as needed, but only restore once the reserve is no
+        // longer threatened: the plan doesn't know about shed loads
+        let target = if required == 0 { 0 } else { required.max(self.shed) };
+        let changes: Vec<(SheddableLoad, bool)> = if target > self.shed {
+            warn!(
+                "Reserve reached before next cheap window ({:.2} kWh short), shedding {} load(s)",
+                deficit_kwh,
+                target - self.shed
+            );
+            self.loads[self.shed..target].iter().map(|l| (l.clone(), true)).collect()
+        } else if target < self.shed {
+            info!("Reserve no longer threatened, restoring {} load(s)", self.shed - target);
+            self.loads[target..self.shed].iter().rev().map(|l| (l.clone(), false)).collect()
+        } else {
+            Vec::new()
+        };
+
+        self.shed = target;
+        changes
+    }
+}
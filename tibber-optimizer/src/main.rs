@@ -1,16 +1,149 @@
+mod ac_input;
+mod appliances;
+mod capacity_test;
+mod clock;
+mod commands;
 mod config;
+mod controller;
+mod curtailment;
+mod decision;
+mod degradation;
+mod diagnose;
+mod discovery;
+mod dispatch;
+mod divergence;
+mod drift;
+mod easee;
+mod economy;
+mod efficiency;
+#[cfg(feature = "entsoe")]
+mod entsoe;
+mod error;
+mod ev;
+mod events;
+mod floors;
+#[cfg(feature = "fleet-report")]
+mod fleet;
+mod fuse;
+mod go_e;
+mod grid;
+mod grid_limit;
+mod hold;
+mod home_assistant;
+mod http;
+mod ics;
+#[cfg(feature = "tibber")]
+mod init;
+mod load_profile;
+mod load_shed;
+mod maintenance;
+mod manual;
+mod memory;
+mod metrics;
+mod modbus;
 mod mqtt;
+mod occupancy;
+#[cfg(feature = "ocpp")]
+mod ocpp;
+mod optimal;
 mod optimizer;
+mod overrides;
+mod peak;
+mod persist;
+#[cfg(feature = "powerwall")]
+mod powerwall;
+mod presets;
+mod price_source;
+mod scan;
+mod soc_feedback;
+mod soc_limits;
+mod schema;
+mod server;
+mod schedule;
+mod prices;
+mod publisher;
+mod pv_forecast;
+mod ramp;
+mod realtime;
+mod record;
+mod replay;
+mod rpc;
+mod rules;
+mod shelly;
+mod slew;
+mod sma;
+mod stats;
+#[cfg(feature = "storage")]
+mod storage;
+mod surplus;
+mod supervisor;
+mod telemetry;
+#[cfg(feature = "tibber")]
 mod tibber;
+mod wallbox;
+mod warranty;
+mod water_heater;
+mod windup;
+
+#[cfg(not(any(feature = "tibber", feature = "entsoe")))]
+compile_error!("At least one price source feature must be enabled (e.g. `tibber`)");
+
+#[cfg(feature = "mimalloc")]
+#[global_allocator]
+static ALLOCATOR: CountingAllocator<mimalloc::MiMalloc> = CountingAllocator(mimalloc::MiMalloc);
+#[cfg(all(feature = "jemalloc", not(feature = "mimalloc")))]
+#[global_allocator]
+static ALLOCATOR: CountingAllocator<tikv_jemallocator::Jemalloc> = CountingAllocator(tikv_jemallocator::Jemalloc);
+#[cfg(not(any(feature = "mimalloc", feature = "jemalloc")))]
+#[global_allocator]
+static ALLOCATOR: CountingAllocator<std::alloc::System> = CountingAllocator(std::alloc::System);
 
 use anyhow::Result;
+use std::sync::Arc;
 use std::time::Duration;
-use tracing::{error, info, warn};
+use tracing::{debug, error, info, warn};
 
-use config::Config;
-use mqtt::{MqttClient, OptimizerStatus, PriceStatsJson};
-use optimizer::BatteryOptimizer;
-use tibber::TibberClient;
+use ac_input::AcInputLimitController;
+use clock::ClockMonitor;
+use commands::Command;
+use config::{Config, RunMode};
+use curtailment::CurtailmentController;
+use degradation::{Degradation, DegradationLadder};
+use divergence::PlanMonitor;
+use drift::{DriftReport, DriftTracker};
+use economy::EconomySleep;
+use ev::EvScheduler;
+use home_assistant::HomeAssistantTelemetry;
+use shelly::ShellyPoller;
+use load_profile::LoadProfiler;
+use capacity_test::CapacityTest;
+use efficiency::EfficiencyTracker;
+use soc_feedback::SocFeedback;
+use events::{ConsumptionEvent, EventSchedule};
+use floors::{FloorSchedule, SocFloor};
+use load_shed::LoadShedder;
+use manual::{ManualEvent, ManualOverrideDetector};
+use memory::{CountingAllocator, MemoryLog};
+use metrics::CycleTimer;
+use realtime::RealtimePriceLayer;
+use record::Recorder;
+use rules::RuleSchedule;
+use server::ServerState;
+use grid::{GridEvent, GridMonitor};
+use grid_limit::{GridLimit, GridLimitEvent};
+use hold::{HoldSchedule, HoldWindow};
+use mqtt::{ExplanationJson, MqttClient, OptimizerStatus, PriceStatsJson, WarrantyJson};
+use optimizer::{BatteryMode, BatteryOptimizer, OptimizationResult, PlannedSlot};
+use occupancy::Away;
+use peak::PeakTracker;
+use pv_forecast::PvForecastSource;
+use soc_limits::SocLimitCheck;
+use stats::EnergyAccounting;
+use supervisor::Supervisor;
+use warranty::WarrantyTracker;
+use water_heater::WaterHeaterScheduler;
+use windup::SetpointLimiter;
+use price_source::{PriceSource, ZoneMismatch};
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -23,100 +156,1150 @@ async fn main() -> Result<()> {
         )
         .init();
 
+    // Subcommands
+    let mut args = std::env::args().skip(1);
+    let mut recorder = None;
+    if let Some(command) = args.next() {
+        match command.as_str() {
+            #[cfg(feature = "tibber")]
+            "init" => return init::run(args.next()).await,
+            "diagnose" => return diagnose::run().await,
+            "replay" => {
+                let path = args.next().ok_or_else(|| anyhow::anyhow!("Usage: replay <bundle>"))?;
+                return replay::run(&path);
+            }
+            "record" => {
+                let path = args.next().ok_or_else(|| anyhow::anyhow!("Usage: record <bundle>"))?;
+                recorder = Some(Recorder::create(&path)?);
+            }
+            other => anyhow::bail!("Unknown command '{}' (available: init, diagnose, record, replay)", other),
+        }
+    }
+
     info!("Tibber Battery Optimizer starting up");
 
     // Load configuration
-    let config = Config::load_from_env_or_file()?;
+    let mut config = Config::load_from_env_or_file()?;
+    config.validate()?;
     info!("Configuration loaded successfully");
 
+    if config.mode == RunMode::PricePublisher {
+        return publisher::run(config).await;
+    }
+
+    // With multiple battery packs, plan against their combined capacity;
+    // separately controlled batteries are planned as one as well
+    config.combine_battery_units();
+    if let Some(capacity) = config.mqtt.soc_sources_capacity_kwh() {
+        info!(
+            "Aggregating SoC over {} packs, effective capacity {:.1} kWh",
+            config.mqtt.soc_sources.len(),
+            capacity
+        );
+        config.battery.capacity_kwh = capacity;
+    }
+
+    if let Some(recorder) = &recorder {
+        recorder.config(&config.mqtt, &config.battery, &config.optimizer);
+    }
+
     // Initialize components
-    let tibber_client = TibberClient::new(config.tibber.clone());
-    let mqtt_client = MqttClient::new(config.mqtt.clone()).await?;
-    let optimizer = BatteryOptimizer::new(config.battery.clone(), config.optimizer.clone());
+    let price_source = PriceSource::from_config(&config, recorder.clone())?;
+    let supervisor = Supervisor::new();
+    let mqtt_client = MqttClient::new(config.mqtt.clone(), recorder.clone(), &supervisor).await?;
+    let controller = controller::from_config(&config, &mqtt_client, &supervisor)?;
+    info!("Writing grid setpoints over {}", controller.name());
+    if let Err(e) = mqtt_client.publish_schemas().await {
+        error!("Failed to publish payload schemas: {}", e);
+    }
+    if config.mqtt_discovery.enabled {
+        if let Err(e) = mqtt_client.publish_discovery(&config.mqtt_discovery).await {
+            error!("Failed to publish Home Assistant discovery: {}", e);
+        }
+    }
+    let mut optimizer = BatteryOptimizer::new(config.battery.clone(), config.optimizer.clone());
+    let pv_source = PvForecastSource::from_config(&config);
+
+    let server_state = Arc::new(ServerState::default());
+    if config.http_server.enabled {
+        server::start(&config.http_server, server_state.clone(), &supervisor).await?;
+    }
 
     // Initial price fetch
-    info!("Fetching initial price data from Tibber...");
-    if let Err(e) = tibber_client.fetch_prices().await {
+    info!("Fetching initial price data from {}...", price_source.name());
+    if let Err(e) = price_source.fetch_prices().await {
         error!("Failed to fetch initial prices: {}", e);
         // Continue anyway, will retry later
     }
 
     // Main loop - run every minute
-    let mut interval = tokio::time::interval(Duration::from_secs(60));
+    let cycle_period = Duration::from_secs(60);
+    let mut interval = tokio::time::interval(cycle_period);
     let mut last_setpoint: Option<f64> = None;
+    let mut controlling = false;
+    let mut active_preset: Option<String> = None;
+    let mut clock = ClockMonitor::new();
+    let mut ladder = DegradationLadder::default();
+    let mut economy = config.economy_sleep.enabled.then(|| EconomySleep::new(config.economy_sleep.clone()));
+    // Decision of the last full cycle, held while asleep
+    let mut held_result: Option<OptimizationResult> = None;
+    // Last published plan, for ramping toward the next slot's setpoint
+    let mut published_plan: Vec<PlannedSlot> = Vec::new();
+    let mut inverter_was_available = true;
+    let mut zone_mismatch = false;
+    let mut price_auth_failed = false;
+    let mut tomorrow_missing = false;
+    let mut holds = HoldSchedule::new(config.hold_windows.clone());
+    let mut dry_run = config.dry_run;
+    if dry_run {
+        warn!("Dry run: setpoints are computed and published in the status, but not written");
+    }
+    let mut events = EventSchedule::new(config.consumption_events.clone());
+    let mut soc_floors = FloorSchedule::load(&config.data_dir);
+    let mut rules = RuleSchedule::new(config.schedule.clone());
+    let mut overrides = overrides::Overrides::default();
+    let mut grid = GridMonitor::new(config.grid_outage.clone());
+    let mut soc_limits = SocLimitCheck::new(config.battery.min_soc_percent, config.battery.adopt_gx_soc_limit);
+    let mut manual = ManualOverrideDetector::new(config.manual_override.clone());
+    let mut limiter = SetpointLimiter::new(
+        config.anti_windup.clone(),
+        config.battery.max_charge_power_w,
+        config.battery.max_discharge_power_w,
+    );
+    let mut realtime = config.realtime_price.clone().map(RealtimePriceLayer::new);
+    let mut home_assistant = config.home_assistant.clone().map(HomeAssistantTelemetry::new);
+    let mut shelly = config.shelly_3em.as_ref().map(|shelly| ShellyPoller::new(shelly.host.clone()));
+    #[cfg(feature = "tibber-live")]
+    let live_measurements = config
+        .tibber
+        .clone()
+        .filter(|tibber| tibber.live_measurement)
+        .map(|tibber| tibber::live::LiveMeasurements::spawn(tibber, &supervisor));
+    #[cfg(all(feature = "tibber", not(feature = "tibber-live")))]
+    if config.tibber.as_ref().is_some_and(|tibber| tibber.live_measurement) {
+        warn!("tibber.live_measurement is enabled but this build lacks the `tibber-live` feature");
+    }
+    let mut load_shedder = LoadShedder::new(config.load_shedding.clone());
+    let mut curtailment = config.curtailment.clone().map(CurtailmentController::new);
+    let mut ac_input_limit = config.ac_input_limit.clone().map(AcInputLimitController::new);
+    let mut ev_charging = match &config.ev {
+        Some(ev) => Some((ev::from_config(ev, &mqtt_client, &supervisor).await?, EvScheduler::new(ev.clone()))),
+        None => None,
+    };
+    let mut water_heater = config.water_heater.clone().map(WaterHeaterScheduler::new);
+    let mut grid_limit = config
+        .mqtt
+        .grid_limit_topic
+        .as_ref()
+        .map(|_| GridLimit::new(config.grid_limit.clone(), &config.data_dir));
+    let mut accounting = EnergyAccounting::new(
+        config.battery.capacity_kwh,
+        config.battery.round_trip_efficiency,
+        config.optimizer.base_consumption_w,
+    );
+    let mut warranty = WarrantyTracker::load(&config.data_dir, config.battery.capacity_kwh);
+    let mut cycle_limit_reached = false;
+    let mut plan_monitor = PlanMonitor::new(config.plan_divergence.clone());
+    let mut soc_feedback = config.soc_feedback.enabled.then(|| {
+        SocFeedback::load(config.soc_feedback.clone(), &config.data_dir, config.battery.capacity_kwh)
+    });
+    if let Some(feedback) = &soc_feedback {
+        optimizer.set_load_correction(feedback.correction());
+    }
+    let mut peak_tracker = config
+        .peak_shaving
+        .enabled
+        .then(|| PeakTracker::load(config.peak_shaving.clone(), &config.data_dir));
+    let mut drift = DriftTracker::load(&config.data_dir, config.battery.max_charge_power_w);
+    let mut load_profiler = LoadProfiler::load(&config.data_dir);
+    optimizer.set_load_profile(load_profiler.profile().clone());
+    let battery_power_measured = config.mqtt.battery_power_topic.is_some()
+        || config.home_assistant.as_ref().is_some_and(|ha| ha.battery_power_entity.is_some());
+    let mut efficiency = battery_power_measured
+        .then(|| EfficiencyTracker::load(&config.data_dir, config.battery.capacity_kwh));
+    let mut capacity_test: Option<CapacityTest> = None;
+    if config.capacity_test.apply_results {
+        if let Some(report) = capacity_test::load_report(&config.data_dir) {
+            info!(
+                "Planning with the tested capacity {:.2} kWh and efficiency {:.2}",
+                report.usable_capacity_kwh, report.round_trip_efficiency
+            );
+            optimizer.set_tested(Some(&report));
+        }
+    }
+    #[cfg(feature = "storage")]
+    let mut storage = if config.storage.enabled {
+        storage::Storage::open(&config.data_dir, config.storage.retention_days)
+            .map_err(|e| error!("Failed to open history database: {}", e))
+            .ok()
+    } else {
+        None
+    };
+    #[cfg(not(feature = "storage"))]
+    if config.storage.enabled {
+        warn!("storage is enabled but this build lacks the `storage` feature");
+    }
+    #[cfg(feature = "fleet-report")]
+    let fleet_reporter = config.fleet_report.enabled.then(|| fleet::FleetReporter::new(&config));
+    #[cfg(not(feature = "fleet-report"))]
+    if config.fleet_report.enabled {
+        warn!("fleet_report is enabled but this build lacks the `fleet-report` feature");
+    }
+
+    let mut cycle_timer: Option<CycleTimer> = None;
+    let mut overrunning = false;
+    let mut tasks_failing = false;
+    let mut memory_log = MemoryLog::default();
+
+    // Stopping the add-on sends SIGTERM: hand control back before exiting
+    let shutdown = supervisor::shutdown_signal();
+    tokio::pin!(shutdown);
 
     loop {
-        interval.tick().await;
+        let scheduled = tokio::select! {
+            scheduled = interval.tick() => scheduled,
+            () = &mut shutdown => break,
+        };
+        if let Some(recorder) = &recorder {
+            recorder.tick();
+        }
 
-        // Refresh prices if needed
-        if let Err(e) = tibber_client.refresh_if_needed().await {
-            warn!("Failed to refresh prices: {}", e);
+        // Publishes were skipped while the broker was down for maintenance:
+        // restore its retained state and rewrite the current setpoint now
+        if mqtt_client.back_from_maintenance() {
+            info!("MQTT broker back from maintenance, republishing state");
+            last_setpoint = None;
+            if let Err(e) = mqtt_client.publish_schemas().await {
+                error!("Failed to publish payload schemas: {}", e);
+            }
+            if config.mqtt_discovery.enabled {
+                if let Err(e) = mqtt_client.publish_discovery(&config.mqtt_discovery).await {
+                    error!("Failed to publish Home Assistant discovery: {}", e);
+                }
+            }
         }
 
-        // Get current state
-        let price_cache = tibber_client.get_cache().await;
-        let current_price = match tibber_client.get_current_price().await {
-            Some(p) => p,
-            None => {
-                warn!("No current price available, skipping optimization cycle");
-                continue;
+        // Report the previous cycle's timing, whichever path it ended on
+        if let Some(previous) = cycle_timer.replace(CycleTimer::start(scheduled)).map(CycleTimer::finish) {
+            let overran = previous.overran(cycle_period);
+            if overran {
+                warn!(
+                    "Control cycle overran: {:.0}ms (tick {:.0}ms late), phases {:?}",
+                    previous.cycle_ms, previous.tick_drift_ms, previous.phases_ms
+                );
             }
-        };
+            if overran != overrunning {
+                let message = if overran {
+                    format!("Control cycle took {:.0}ms, slot boundaries may be missed", previous.cycle_ms)
+                } else {
+                    "Control cycle timing back to normal".to_string()
+                };
+                if let Err(e) = mqtt_client.publish_alert("cycle_overrun", &message, overran).await {
+                    error!("Failed to publish alert: {}", e);
+                }
+                overrunning = overran;
+            }
+            memory_log.report(&previous.memory);
+            if let Err(e) = mqtt_client.publish_metrics(&previous).await {
+                error!("Failed to publish metrics: {}", e);
+            }
+        }
+        let timer = cycle_timer.as_mut().expect("started above");
+
+        // Background tasks that panicked or exited are restarted by the supervisor
+        let failed_tasks = supervisor.unhealthy(chrono::Utc::now());
+        if failed_tasks.is_empty() == tasks_failing {
+            tasks_failing = !failed_tasks.is_empty();
+            let message = if tasks_failing {
+                format!("Background tasks restarted: {}", failed_tasks.join("; "))
+            } else {
+                "Background tasks running normally".to_string()
+            };
+            if let Err(e) = mqtt_client.publish_alert("task_failure", &message, tasks_failing).await {
+                error!("Failed to publish alert: {}", e);
+            }
+        }
+
+        // Apply runtime commands
+        let mut commands = mqtt_client.take_commands().await;
+        commands.extend(server_state.take_commands().await);
+        let commands_received = !commands.is_empty();
+        for command in commands {
+            match command {
+                Command::Hold { start, end, reason } => holds.add(HoldWindow {
+                    start: start.unwrap_or_else(|| chrono::Utc::now().fixed_offset()),
+                    end,
+                    reason,
+                }),
+                Command::CancelHold => holds.clear(),
+                Command::ConsumptionEvent { name, start, end, energy_kwh } => events.add(ConsumptionEvent {
+                    name,
+                    start: start.unwrap_or_else(|| chrono::Utc::now().fixed_offset()),
+                    end,
+                    energy_kwh,
+                }),
+                Command::CancelConsumptionEvent { name } => events.cancel(name.as_deref()),
+                Command::ScheduleRule(rule) => rules.add(rule),
+                Command::CancelScheduleRule { name } => rules.cancel(name.as_deref()),
+                Command::SocFloor { id, min_soc, until, reason } => soc_floors.add(SocFloor {
+                    id,
+                    min_soc: min_soc.clamp(0.0, config.battery.max_soc_percent),
+                    until,
+                    reason,
+                }),
+                Command::CancelSocFloor { id } => soc_floors.cancel(id.as_deref()),
+                Command::DryRun { enabled } => {
+                    if enabled != dry_run {
+                        info!("Dry run {}", if enabled { "enabled" } else { "disabled, writing setpoints" });
+                        dry_run = enabled;
+                    }
+                }
+                Command::ForceCharge { until } => overrides.force_charge(until),
+                Command::Pause { until } => overrides.pause(until),
+                Command::SetMaxSoc { value, until } => overrides.set_max_soc(value, until),
+                Command::Boost { target_soc, by } => overrides.boost(target_soc, by),
+                Command::CapacityTest if !battery_power_measured => {
+                    warn!("Ignoring capacity test: it needs a battery power topic or entity")
+                }
+                Command::CapacityTest => {
+                    capacity_test = Some(CapacityTest::start(
+                        config.capacity_test.clone(),
+                        optimizer.min_soc_now(),
+                        config.battery.max_soc_percent,
+                        chrono::Utc::now(),
+                    ))
+                }
+                Command::CancelCapacityTest => {
+                    if capacity_test.take().is_some() {
+                        info!("Capacity test cancelled");
+                    }
+                }
+                Command::CancelOverride => overrides.clear(),
+            }
+        }
+        optimizer.set_consumption_events(events.upcoming(chrono::Utc::now()).to_vec());
+        optimizer.set_schedule_rules(rules.rules().to_vec());
+        optimizer.set_soc_floors(soc_floors.active(chrono::Utc::now()).to_vec());
+        overrides.expire(chrono::Utc::now());
+        optimizer.set_max_soc_override(overrides.max_soc());
+        optimizer.set_boost(overrides.boost_target());
+
+        timer.mark("commands");
+
+        // Refresh prices if needed; another market's prices are dropped and
+        // alerted on, as are rejected credentials
+        match price_source.refresh_if_needed().await {
+            Ok(true) => {
+                if price_auth_failed {
+                    price_auth_failed = false;
+                    let message = "Price provider accepts the credentials again";
+                    if let Err(e) = mqtt_client.publish_alert("price_auth", message, false).await {
+                        error!("Failed to publish alert: {}", e);
+                    }
+                }
+                if zone_mismatch {
+                    zone_mismatch = false;
+                    let message = "Prices match the configured bidding zone again";
+                    if let Err(e) = mqtt_client.publish_alert("price_zone_mismatch", message, false).await {
+                        error!("Failed to publish alert: {}", e);
+                    }
+                }
+            }
+            Ok(false) => {}
+            Err(e) => {
+                match e.downcast_ref::<error::Error>() {
+                    Some(error::Error::Auth(_)) => {
+                        let message = format!("Price provider rejected the credentials: {}", e);
+                        error!("{}", message);
+                        if !price_auth_failed {
+                            price_auth_failed = true;
+                            if let Err(e) = mqtt_client.publish_alert("price_auth", &message, true).await {
+                                error!("Failed to publish alert: {}", e);
+                            }
+                        }
+                    }
+                    Some(error) => warn!("Failed to refresh prices ({} error): {}", error.class(), e),
+                    None => warn!("Failed to refresh prices: {}", e),
+                }
+                if let (Some(mismatch), false) = (e.downcast_ref::<ZoneMismatch>(), zone_mismatch) {
+                    zone_mismatch = true;
+                    let message = format!("Not optimizing: {}", mismatch);
+                    if let Err(e) = mqtt_client.publish_alert("price_zone_mismatch", &message, true).await {
+                        error!("Failed to publish alert: {}", e);
+                    }
+                }
+            }
+        }
+        if let Some(realtime) = realtime.as_mut() {
+            if let Err(e) = realtime.refresh_if_needed(chrono::Utc::now()).await {
+                warn!("Failed to refresh realtime price: {}", e);
+            }
+        }
+        timer.mark("prices");
+
+        // Refresh PV forecasts if needed
+        if let Some(pv_source) = &pv_source {
+            pv_source.refresh_if_needed().await;
+            optimizer.set_pv_forecast(pv_source.get_forecast().await);
+            timer.mark("pv_forecast");
+        }
 
+        // Get current state
+        let price_cache = price_source.get_cache().await;
+        let current_price = price_source.get_current_price().await;
+        mqtt_client.set_prices(price_cache.clone());
+
+        if let Some(home_assistant) = home_assistant.as_mut() {
+            home_assistant.poll(chrono::Utc::now()).await;
+        }
+        if let Some(shelly) = shelly.as_mut() {
+            shelly.poll(chrono::Utc::now()).await;
+        }
         let battery_state = mqtt_client.get_battery_state().await;
 
-        // Check if we have valid battery state
-        if battery_state.last_soc_update.is_none() {
-            warn!("No battery SoC data received yet, using default self-consumption mode");
-            if let Err(e) = mqtt_client.publish_grid_setpoint(200.0).await {
-                error!("Failed to publish grid setpoint: {}", e);
+        // Home Assistant entities and the Shelly meter take precedence over the MQTT topics
+        let battery_state = match &home_assistant {
+            Some(home_assistant) => home_assistant.apply(battery_state),
+            None => battery_state,
+        };
+        let battery_state = match &shelly {
+            Some(shelly) => shelly.apply(battery_state),
+            None => battery_state,
+        };
+
+        // Without a grid power topic, the Tibber Pulse reports grid power
+        #[cfg(feature = "tibber-live")]
+        let pulse = match &live_measurements {
+            Some(live) => live.latest(chrono::Utc::now()).await,
+            None => None,
+        };
+        #[cfg(feature = "tibber-live")]
+        let battery_state = match pulse.filter(|_| battery_state.grid_power_w.is_none()) {
+            Some(m) => mqtt::BatteryState {
+                grid_power_w: Some(m.grid_power_w()),
+                last_grid_power_update: Some(m.timestamp.with_timezone(&chrono::Utc)),
+                ..battery_state
+            },
+            None => battery_state,
+        };
+        #[cfg(feature = "tibber-live")]
+        let pulse_consumption_kwh = pulse.map(|m| m.accumulated_consumption);
+        #[cfg(not(feature = "tibber-live"))]
+        let pulse_consumption_kwh = None;
+
+        // Controllers that write battery power need the current load
+        controller.observe(&battery_state);
+
+        // Don't publish commands into the void while the inverter is off or faulted
+        let inverter_available = battery_state.inverter_available();
+        let inverter_state = battery_state.inverter_state.map(|s| s.to_string());
+        if inverter_available != inverter_was_available {
+            let message = if inverter_available {
+                format!("Inverter available again ({})", inverter_state.as_deref().unwrap_or("unknown"))
+            } else {
+                format!("Inverter unavailable ({}), suppressing setpoint writes", inverter_state.as_deref().unwrap_or("unknown"))
+            };
+            if let Err(e) = mqtt_client.publish_alert("inverter_unavailable", &message, !inverter_available).await {
+                error!("Failed to publish alert: {}", e);
             }
-            continue;
+            inverter_was_available = inverter_available;
         }
 
-        // Run optimization
-        let result = optimizer.optimize(battery_state.soc, &current_price, &price_cache);
+        // During a grid outage the ESS runs off-grid: stop writing setpoints and
+        // preserve a raised reserve until well after the grid returns
+        match grid.update(&battery_state, chrono::Utc::now()) {
+            Some(GridEvent::Lost(reason)) => {
+                let message = format!("Grid outage ({}), suppressing setpoint writes", reason);
+                if let Err(e) = mqtt_client.publish_alert("grid_lost", &message, true).await {
+                    error!("Failed to publish alert: {}", e);
+                }
+            }
+            Some(GridEvent::Restored) => {
+                if let Err(e) = mqtt_client.publish_alert("grid_lost", "Grid restored, resuming plan", false).await {
+                    error!("Failed to publish alert: {}", e);
+                }
+            }
+            None => {}
+        }
 
-        info!(
-            "Optimization result: mode={}, setpoint={:.0}W, soc={:.1}%, price={:.4} EUR - {}",
-            result.mode, result.grid_setpoint_w, battery_state.soc, current_price.total, result.reason
+        // Don't plan on discharging below a stricter floor the GX enforces itself
+        if let Some((message, active)) = soc_limits.update(battery_state.gx_soc_floor()) {
+            if let Err(e) = mqtt_client.publish_alert("soc_limit_conflict", &message, active).await {
+                error!("Failed to publish alert: {}", e);
+            }
+        }
+        let reserve = grid.reserve_override(chrono::Utc::now());
+        optimizer.set_min_soc_override(match (reserve, soc_limits.adopted()) {
+            (Some(reserve), Some(gx)) => Some(reserve.max(gx)),
+            (reserve, gx) => reserve.or(gx),
+        });
+
+        // Steps the slew rate wrote since the last cycle were ours as well
+        if let (Some(last), Some(written)) = (last_setpoint, controller.written_setpoint_w()) {
+            if last != written {
+                last_setpoint = Some(written);
+                manual.commanded(written, chrono::Utc::now());
+            }
+        }
+
+        // Leave setpoints written by hand alone for a while instead of fighting them
+        match manual.check(&battery_state, chrono::Utc::now()) {
+            Some(ManualEvent::Detected(setpoint)) => {
+                let message = format!(
+                    "Setpoint changed manually to {:.0}W, pausing automatic control for {}s",
+                    setpoint, config.manual_override.cooldown_secs
+                );
+                if let Err(e) = mqtt_client.publish_alert("manual_override", &message, true).await {
+                    error!("Failed to publish alert: {}", e);
+                }
+            }
+            Some(ManualEvent::Resumed) => {
+                let message = "Manual override cooldown ended, resuming automatic control";
+                if let Err(e) = mqtt_client.publish_alert("manual_override", message, false).await {
+                    error!("Failed to publish alert: {}", e);
+                }
+            }
+            None => {}
+        }
+
+        let can_write =
+            inverter_available && !grid.is_lost() && !manual.is_paused() && !overrides.is_paused() && !dry_run;
+        if !can_write {
+            // Force a fresh setpoint once control is possible again
+            last_setpoint = None;
+        }
+        if can_write != controlling {
+            if !can_write {
+                // Paused or switched to dry run by command: leave the ESS on a
+                // neutral setpoint rather than the last decision, e.g. a forced
+                // charge. A dry run from the start never writes at all.
+                if (overrides.is_paused() || dry_run) && inverter_available && !grid.is_lost() {
+                    let neutral = config.optimizer.setpoint_offset_w;
+                    match controller.write_setpoint(neutral).await {
+                        Ok(()) => {
+                            let written = controller.written_setpoint_w().unwrap_or(neutral);
+                            manual.commanded(written, chrono::Utc::now());
+                        }
+                        Err(e) => error!("Failed to write grid setpoint: {}", e),
+                    }
+                }
+                if let Err(e) = controller.release().await {
+                    error!("Failed to release the ESS: {}", e);
+                }
+                // Through an outage the car stays as it was rather than
+                // drawing on the battery at full current
+                if let Some((charger, scheduler)) = ev_charging.as_mut().filter(|_| !grid.is_lost()) {
+                    scheduler.release(charger.as_ref()).await;
+                }
+                if let Some(heater) = water_heater.as_mut() {
+                    heater.release(&mqtt_client).await;
+                }
+            }
+            controlling = can_write;
+        }
+
+        // Don't plan against an implausible clock (e.g. before NTP sync after
+        // boot); the failsafe setpoint is held only while we may write at all
+        let clock_status = clock.check();
+        if !clock_status.is_ok() {
+            if let Some(status) = ladder.enter(Degradation::Failsafe, Some(clock_status.to_string())) {
+                if let Err(e) = mqtt_client.publish_degradation(&status).await {
+                    error!("Failed to publish degradation: {}", e);
+                }
+            }
+            let failsafe = config.failsafe.setpoint_w.unwrap_or(config.optimizer.setpoint_offset_w);
+            warn!("Suspending optimization ({}), holding failsafe setpoint {:.0}W", clock_status, failsafe);
+            if can_write {
+                match controller.write_setpoint(failsafe).await {
+                    Ok(()) => {
+                        let written = controller.written_setpoint_w().unwrap_or(failsafe);
+                        last_setpoint = Some(written);
+                        manual.commanded(written, chrono::Utc::now());
+                    }
+                    Err(e) => error!("Failed to write grid setpoint: {}", e),
+                }
+            }
+            continue;
+        }
+
+        // Walk down the degradation ladder: stale inputs trip the failsafe, and
+        // without a current price or SoC the optimizer can't run and the
+        // battery falls back to self-consumption
+        let stale = Degradation::stale_inputs(
+            &config.failsafe,
+            battery_state.last_soc_update,
+            &price_cache,
+            mqtt_client.disconnected_since(),
+            chrono::Utc::now(),
         );
+        let (level, reason) = match &current_price {
+            _ if stale.is_some() => (Degradation::Failsafe, stale),
+            None => (Degradation::NoPrices, Some("no price for the current slot".to_string())),
+            Some(_) if battery_state.last_soc_update.is_none() => {
+                (Degradation::NoSoc, Some("no battery SoC received yet".to_string()))
+            }
+            Some(_) => Degradation::for_prices(
+                &price_cache,
+                price_source.refresh_interval_secs(),
+                &config.missing_tomorrow_prices,
+                chrono::Utc::now(),
+            ),
+        };
+        if let Some(status) = ladder.enter(level, reason) {
+            if let Err(e) = mqtt_client.publish_degradation(&status).await {
+                error!("Failed to publish degradation: {}", e);
+            }
+        }
+        if (level == Degradation::MissingTomorrowPrices) != tomorrow_missing {
+            tomorrow_missing = !tomorrow_missing;
+            let message = if tomorrow_missing {
+                format!(
+                    "Tomorrow's prices still missing, planning on forecast prices with a {:.0}% reserve",
+                    config.missing_tomorrow_prices.reserve_soc_percent
+                )
+            } else {
+                "Tomorrow's prices available again".to_string()
+            };
+            if let Err(e) = mqtt_client.publish_alert("tomorrow_prices_missing", &message, tomorrow_missing).await {
+                error!("Failed to publish alert: {}", e);
+            }
+        }
+        let Some(current_price) = current_price.filter(|_| level.optimizes()) else {
+            let setpoint = match level {
+                Degradation::Failsafe => config.failsafe.setpoint_w.unwrap_or(config.optimizer.setpoint_offset_w),
+                _ => config.optimizer.setpoint_offset_w,
+            };
+            warn!("Skipping optimization ({:?}), holding setpoint {:.0}W", level, setpoint);
+            if can_write {
+                match controller.write_setpoint(setpoint).await {
+                    Ok(()) => {
+                        let written = controller.written_setpoint_w().unwrap_or(setpoint);
+                        last_setpoint = Some(written);
+                        manual.commanded(written, chrono::Utc::now());
+                    }
+                    Err(e) => error!("Failed to write grid setpoint: {}", e),
+                }
+            }
+            continue;
+        };
+
+        // Don't charge a battery too cold to take it from the grid
+        let temperature_c =
+            battery_state.battery_temperature_at(chrono::Utc::now(), config.battery_temperature.max_age_minutes);
+        let mut planned_rules = rules.rules().to_vec();
+        planned_rules.extend(config.battery_temperature.cold_rule(temperature_c));
+
+        // Without tomorrow's prices late in the day, plan a day ahead on today's
+        // price shape rather than on the last few slots, but reserve first
+        let price_cache = if level == Degradation::MissingTomorrowPrices {
+            planned_rules.push(config.missing_tomorrow_prices.reserve_rule());
+            Arc::new(price_cache.with_forecast_tomorrow(chrono::Utc::now()))
+        } else {
+            price_cache
+        };
+        optimizer.set_schedule_rules(planned_rules);
+
+        // Switch optimizer presets when the (tariff-local) day or time calls for
+        // another one. The previous preset's plan is dropped, so the ramp and
+        // the divergence check start over from the new one.
+        let local_now = chrono::Utc::now().with_timezone(current_price.starts_at.offset());
+        let today = local_now.date_naive();
+        let preset = presets::select(&config.presets, local_now);
+        let preset_switched = preset.map(|p| &p.name) != active_preset.as_ref();
+        if preset_switched {
+            let optimizer_config = match preset {
+                Some(p) => p.apply(&config.optimizer),
+                None => config.optimizer.clone(),
+            };
+            info!(
+                "Switching to optimizer preset '{}' ({:?} planner)",
+                preset.map_or("default", |p| p.name.as_str()),
+                optimizer_config.planner
+            );
+            accounting.set_preset(preset.map(|p| p.name.clone()), optimizer_config.base_consumption_w);
+            optimizer.set_optimizer_config(optimizer_config);
+            active_preset = preset.map(|p| p.name.clone());
+            published_plan.clear();
+        }
+
+        // Account energy flows; a completed day is reported if opted in
+        let now = chrono::Utc::now().with_timezone(current_price.starts_at.offset());
+        let meter_kwh = battery_state.net_meter_kwh(&config.mqtt);
+        // A fixed-price contract fixes what is paid; exports still earn the sell price
+        let paid_price = config.contract.price_on(today, current_price.total);
+        if let Some(day) = accounting.record(now, battery_state.soc, meter_kwh, paid_price, current_price.sell_price()) {
+            info!(
+                "Day {} complete ({}): savings {:.2} EUR ({:.1}%), {:.2} cycles",
+                day.date,
+                day.presets.join(", "),
+                day.savings(),
+                day.savings_percent(),
+                day.cycles(config.battery.capacity_kwh)
+            );
+            if let Some(drift) = day.drift_kwh() {
+                info!(
+                    "Day {} meter drift {:+.2} kWh ({:.2} metered vs {:.2} accounted), costs reconciled",
+                    day.date,
+                    drift,
+                    day.metered_grid_kwh.unwrap_or_default(),
+                    day.accounted_grid_kwh
+                );
+            }
+            #[cfg(feature = "fleet-report")]
+            if let Some(reporter) = fleet_reporter.clone() {
+                tokio::spawn(async move {
+                    if let Err(e) = reporter.send(&day).await {
+                        warn!("Failed to send fleet report: {}", e);
+                    }
+                });
+            }
+        }
+
+        // Lifetime wear counters; a completed year is published as a report
+        if let Some((year, counters)) = warranty.record(now, battery_state.soc) {
+            let report = WarrantyJson::new(&counters, warranty.capacity_kwh());
+            info!(
+                "Year {} complete: {:.1} cycles, {:.0} kWh throughput, {:.0}h above 90%, {:.0}h below 10%",
+                year, report.cycles, report.throughput_kwh, report.hours_above_90, report.hours_below_10
+            );
+            if let Err(e) = mqtt_client.publish_warranty_report(year, &report).await {
+                error!("Failed to publish warranty report: {}", e);
+            }
+        }
+
+        // Measure the realized round-trip efficiency from AC battery power
+        if let (Some(tracker), Some(power_w)) = (efficiency.as_mut(), battery_state.battery_power_w) {
+            tracker.record(chrono::Utc::now(), battery_state.soc, power_w);
+            optimizer.set_measured_efficiency(tracker.round_trip_efficiency());
+        }
+
+        // Stop demanding power the ESS persistently fails to deliver, or a
+        // cold battery shouldn't take
+        limiter.update(last_setpoint, battery_state.grid_power_w);
+        let charge_limit_w = limiter
+            .charge_limit_w()
+            .into_iter()
+            .chain(config.battery_temperature.charge_limit_w(config.battery.max_charge_power_w, temperature_c))
+            .reduce(f64::min);
+        let discharge_limit_w = limiter
+            .discharge_limit_w()
+            .into_iter()
+            .chain(config.battery_temperature.discharge_limit_w(config.battery.max_discharge_power_w, temperature_c))
+            .reduce(f64::min);
+        optimizer.set_power_limits(charge_limit_w, discharge_limit_w);
+
+        // Learn the house load per hour of day from measured consumption
+        if load_profiler.record(now, &battery_state) {
+            optimizer.set_load_profile(load_profiler.profile().clone());
+            if let Err(e) = mqtt_client.publish_load_profile(load_profiler.profile()).await {
+                error!("Failed to publish load profile: {}", e);
+            }
+        }
+
+        // Compare configured assumptions against the past week's measurements
+        if let Some(samples) = drift.record(now, &battery_state, last_setpoint) {
+            let measured_efficiency = efficiency.as_ref().and_then(|t| t.round_trip_efficiency());
+            let report = DriftReport::new(
+                &samples,
+                &config.battery,
+                &config.optimizer,
+                measured_efficiency,
+                limiter.charge_limit_w(),
+            );
+            let suggestions = report.suggestions();
+            info!("Week {} drift report: {} suggestion(s)", report.week, suggestions.len());
+            for suggestion in &suggestions {
+                warn!("Parameter drift: {}", suggestion);
+            }
+            if let Err(e) = mqtt_client.publish_drift_report(&report).await {
+                error!("Failed to publish drift report: {}", e);
+            }
+        }
+
+        timer.mark("telemetry");
+
+        // Count grid import toward the month's peak, and plan below the cap
+        if let Some(tracker) = peak_tracker.as_mut() {
+            let now = chrono::Utc::now().with_timezone(current_price.starts_at.offset());
+            tracker.record(&battery_state, now);
+            optimizer.set_peak_limit(Some(tracker.limit(now)));
+        }
+
+        // Plan for a lower house load while nobody is home
+        if config.mqtt.occupancy_topic.is_some() {
+            optimizer.set_away(Away::from_state(&config.occupancy, &battery_state, chrono::Utc::now()));
+        }
+
+        // Follow the grid operator limit signal; a change wakes economy sleep
+        let mut grid_limit_changed = false;
+        if let Some(limit) = grid_limit.as_mut() {
+            let message = match limit.update(&battery_state, chrono::Utc::now()) {
+                Some(GridLimitEvent::Started(limit_w)) => {
+                    Some(format!("Grid operator limits import to {:.0}W", limit_w))
+                }
+                Some(GridLimitEvent::Changed(limit_w)) => {
+                    Some(format!("Grid operator limit changed to {:.0}W", limit_w))
+                }
+                Some(GridLimitEvent::Ended) => Some("Grid operator limit lifted".to_string()),
+                None => None,
+            };
+            if let Some(message) = message {
+                grid_limit_changed = true;
+                let active = limit.limit_w().is_some();
+                if let Err(e) = mqtt_client.publish_alert("grid_limit", &message, active).await {
+                    error!("Failed to publish alert: {}", e);
+                }
+            }
+        }
+
+        // Through flat prices, only optimize and publish every economy sleep
+        // interval, unless something changed materially. The held decision
+        // still goes through the device schedulers and the caps below.
+        let now_utc = chrono::Utc::now();
+        let woken =
+            commands_received || holds.active(now_utc).is_some() || capacity_test.is_some() || grid_limit_changed;
+        let asleep = economy
+            .as_mut()
+            .is_some_and(|sleep| sleep.skip(now_utc, battery_state.soc, &price_cache, &current_price, woken));
+        let held = held_result.clone().filter(|_| asleep);
+
+        // Run optimization, unless the battery is held idle, charging is forced or
+        // the contract bills a fixed price this month. A realtime price may
+        // override the current slot, with hysteresis against flapping.
+        let active_hold = holds.active(chrono::Utc::now()).cloned();
+        let fixed_contract = config.contract.is_fixed(today);
+        let cycles_today = warranty.cycles_today();
+        let cycle_limited = warranty.daily_limit_reached(config.battery.max_daily_cycles);
+        if cycle_limited != cycle_limit_reached {
+            match config.battery.max_daily_cycles.filter(|_| cycle_limited) {
+                Some(max) => info!(
+                    "Daily cycle limit {:.1} reached ({:.2} cycles today), self-consumption only",
+                    max, cycles_today
+                ),
+                None => info!("Daily cycle limit reset, resuming optimization"),
+            }
+            cycle_limit_reached = cycle_limited;
+        }
+        let force_charge = overrides.force_charge_until();
+
+        // A running capacity test takes over the setpoint until it completes,
+        // unless a reserve or SoC floor now stands in the way of its discharge
+        let mut testing = None;
+        let min_soc = optimizer.min_soc_now();
+        if capacity_test.as_ref().is_some_and(|test| test.min_soc() < min_soc) {
+            warn!("Capacity test aborted: minimum SoC raised to {:.0}%", min_soc);
+            capacity_test = None;
+        }
+        if let Some(test) = capacity_test.as_mut() {
+            let house_load_w = battery_state
+                .house_load_w
+                .unwrap_or(optimizer.optimizer_config().base_consumption_w);
+            match test.update(chrono::Utc::now(), battery_state.soc, battery_state.battery_power_w, house_load_w) {
+                capacity_test::Step::Control(result) => testing = Some(result),
+                capacity_test::Step::Done(report) => {
+                    info!(
+                        "Capacity test complete: usable capacity {:.2} kWh, round-trip efficiency {:.2}",
+                        report.usable_capacity_kwh, report.round_trip_efficiency
+                    );
+                    capacity_test::save_report(&config.data_dir, &report);
+                    if config.capacity_test.apply_results {
+                        optimizer.set_tested(Some(&report));
+                    }
+                    if let Err(e) = mqtt_client.publish_capacity_test(&report).await {
+                        error!("Failed to publish capacity test: {}", e);
+                    }
+                    capacity_test = None;
+                }
+                capacity_test::Step::Aborted(reason) => {
+                    warn!("Capacity test aborted: {}", reason);
+                    capacity_test = None;
+                }
+            }
+        }
+
+        let result = if let Some(result) = testing.clone() {
+            result
+        } else if let Some(result) = held.clone() {
+            result
+        } else {
+            match (&active_hold, force_charge, realtime.as_mut()) {
+                (Some(window), _, _) => optimizer.hold(window),
+                (None, Some(until), _) => optimizer.force_charge(battery_state.soc, until),
+                (None, None, _) if fixed_contract => {
+                    optimizer.self_consumption_only(battery_state.soc, "Fixed-price contract month")
+                }
+                (None, None, _) if cycle_limited => optimizer.self_consumption_only(
+                    battery_state.soc,
+                    &format!("Daily cycle limit reached ({:.2} cycles today)", cycles_today),
+                ),
+                (None, None, Some(realtime)) => {
+                    let now = chrono::Utc::now();
+                    let price = realtime.adjust_price(&current_price, now);
+                    let result = optimizer.optimize(battery_state.soc, &price, &price_cache);
+                    let soc_limits = (optimizer.min_soc_now(), optimizer.max_soc_now());
+                    realtime.stabilize(current_price.starts_at, result, battery_state.soc, soc_limits, now)
+                }
+                (None, None, None) => optimizer.optimize(battery_state.soc, &current_price, &price_cache),
+            }
+        };
+
+        // Don't fight charge windows the user scheduled in the GX UI
+        let local_now = chrono::Utc::now().with_timezone(current_price.starts_at.offset());
+        let victron_schedule = schedule::active(&battery_state.charge_schedules, local_now);
+        let result = match victron_schedule {
+            Some(window) => {
+                schedule::constrain(result, window, local_now, optimizer.optimizer_config().base_consumption_w)
+            }
+            None => result,
+        };
+
+        // Ease into the next planned slot ahead of the slot boundary
+        let optimizer_decides = active_hold.is_none()
+            && force_charge.is_none()
+            && !fixed_contract
+            && !cycle_limited
+            && victron_schedule.is_none()
+            && testing.is_none();
+        let result = if optimizer_decides && held.is_none() && ladder.level() < Degradation::StalePrices {
+            ramp::ramp(
+                result,
+                &published_plan,
+                local_now,
+                optimizer.optimizer_config().ramp_minutes,
+                optimizer.min_soc_now(),
+                optimizer.capacity_kwh(),
+            )
+        } else {
+            result
+        };
+        let mut result = ladder.constrain(result, optimizer.optimizer_config().setpoint_offset_w);
+        // Whatever decided it (a forced charge, a hold, a capacity test, a GX
+        // schedule), a cold battery isn't charged from the grid
+        if config.battery_temperature.too_cold(temperature_c) {
+            result.block_grid_charge(optimizer.optimizer_config().setpoint_offset_w, "a cold battery");
+        }
+        if held.is_none() {
+            held_result = Some(result.clone());
+        }
+
+        // Charge the car in its cheapest slots, and raise the grid setpoint by
+        // what it draws so the battery doesn't discharge into it
+        let mut ev_decision = None;
+        if let Some((charger, scheduler)) = ev_charging.as_mut() {
+            charger.observe(&battery_state);
+            match charger.status().await {
+                Ok(status) => {
+                    let decision = scheduler.decide(
+                        &status,
+                        &price_cache,
+                        config.main_fuse.as_ref(),
+                        &battery_state,
+                        chrono::Utc::now(),
+                    );
+                    if can_write {
+                        result.grid_setpoint_w += scheduler.apply(charger.as_ref(), &decision, &status).await;
+                    } else {
+                        scheduler.reset();
+                    }
+                    ev_decision = Some(decision);
+                }
+                Err(e) => {
+                    debug!("EV charger {} unavailable: {}", charger.name(), e);
+                    scheduler.reset();
+                }
+            }
+        }
+
+        // Heat water in the cheapest slots the fuse has room for, also raising
+        // the grid setpoint by the element's power
+        let mut water_heater_decision = None;
+        if let Some(heater) = water_heater.as_mut() {
+            let decision = heater.decide(&price_cache, config.main_fuse.as_ref(), &battery_state, chrono::Utc::now());
+            if can_write {
+                result.grid_setpoint_w += heater.apply(&mqtt_client, &decision).await;
+            } else {
+                heater.release(&mqtt_client).await;
+            }
+            water_heater_decision = Some(decision);
+        }
+
+        // Have the battery cover the car and the water heater above the peak cap
+        if let Some(tracker) = &peak_tracker {
+            let limit = tracker.limit(chrono::Utc::now().with_timezone(current_price.starts_at.offset()));
+            result.cap_setpoint(limit.cap_at(current_price.starts_at), "peak shaving");
+        }
+
+        // Keep the battery's charging on top of everything else within the
+        // main fuse and the contracted power
+        if let Some(fuse) = &config.main_fuse {
+            result.cap_setpoint(fuse::max_setpoint_w(fuse, &battery_state, chrono::Utc::now()), "the main fuse");
+        }
+
+        // A limit set by the grid operator caps the setpoint, car and water
+        // heater included, whatever decided it
+        if let Some(limit) = grid_limit.as_mut() {
+            limit.cap(&mut result);
+        }
+
+        timer.mark("optimize");
+
+        if held.is_none() {
+            info!(
+                "Optimization result: mode={}, setpoint={:.0}W, soc={:.1}%, price={:.4} EUR - {}",
+                result.mode, result.grid_setpoint_w, battery_state.soc, current_price.total, result.reason
+            );
+        }
+
+        #[cfg(feature = "storage")]
+        if let Some(storage) = storage.as_mut() {
+            let recorded = storage
+                .record_prices(&price_cache)
+                .and_then(|()| storage.record_cycle(chrono::Utc::now(), current_price.total, battery_state.soc, &result));
+            if let Err(e) = recorded {
+                warn!("Failed to record history: {}", e);
+            }
+        }
 
         // Only publish setpoint if it changed (avoid MQTT spam)
         let should_publish = match last_setpoint {
+            _ if controller.writes_every_cycle() => true,
             None => true,
             Some(last) => (last - result.grid_setpoint_w).abs() > 10.0,
         };
 
-        if should_publish {
-            if let Err(e) = mqtt_client.publish_grid_setpoint(result.grid_setpoint_w).await {
-                error!("Failed to publish grid setpoint: {}", e);
+        if should_publish && can_write {
+            if let Err(e) = controller.write_mode(result.mode, result.grid_setpoint_w).await {
+                error!("Failed to write grid setpoint: {}", e);
             } else {
-                last_setpoint = Some(result.grid_setpoint_w);
+                // Short of the target while the slew rate limits the steps
+                let written = controller.written_setpoint_w().unwrap_or(result.grid_setpoint_w);
+                last_setpoint = Some(written);
+                manual.commanded(written, chrono::Utc::now());
+            }
+        }
+
+        // Sleep while self-consuming through flat prices, once the setpoint
+        // has arrived where the slew rate still ramps it
+        if let Some(sleep) = economy.as_mut().filter(|_| held.is_none()) {
+            let settled = controller
+                .written_setpoint_w()
+                .is_none_or(|written| (written - result.grid_setpoint_w).abs() <= 10.0);
+            let eligible = optimizer_decides
+                && settled
+                && ladder.level() == Degradation::Full
+                && matches!(result.mode, BatteryMode::SelfConsumption | BatteryMode::SelfConsumptionPreventGridPull);
+            let spread = optimizer.optimizer_config().min_discharge_spread;
+            sleep.settle(now_utc, battery_state.soc, &price_cache, &current_price, spread, eligible);
+        }
+        // Curtail PV when the battery is full and exporting would cost money
+        if let (Some(controller), Some(curtailment_config)) = (curtailment.as_mut(), &config.curtailment) {
+            if !can_write {
+                controller.reset();
+            } else if let Some(value) = controller.update(battery_state.soc, &current_price) {
+                if let Err(e) = mqtt_client.publish_value(&curtailment_config.topic, value).await {
+                    error!("Failed to publish curtailment command: {}", e);
+                    controller.reset();
+                }
+            }
+        }
+
+        // Let the charger draw full power while charging at full power
+        if let (Some(controller), Some(limit_config)) = (ac_input_limit.as_mut(), &config.ac_input_limit) {
+            if !can_write {
+                controller.release(&mqtt_client).await;
+            } else if let Some(value) = controller.update(result.mode) {
+                if let Err(e) = mqtt_client.publish_value(&limit_config.topic, value).await {
+                    error!("Failed to publish AC input current limit: {}", e);
+                    controller.reset();
+                }
             }
         }
 
+        // Asleep: the setpoint is written, publishing waits for the next full cycle
+        if held.is_some() {
+            cycle_timer = None;
+            continue;
+        }
+
         // Always publish current price
         if let Err(e) = mqtt_client.publish_price_info(&current_price).await {
             error!("Failed to publish price info: {}", e);
         }
 
+        // The plan only describes what happens while the optimizer decides the setpoint
+        let in_control = can_write && optimizer_decides;
+
         // Publish extended status
-        let forecast = optimizer.get_forecast_info(&price_cache);
+        let forecast = optimizer.get_forecast_info(battery_state.soc, &current_price, &price_cache);
         let status = OptimizerStatus {
             current_price: current_price.total,
+            current_sell_price: current_price.sell_price(),
+            realtime_price: realtime.as_ref().and_then(|r| r.current(chrono::Utc::now())),
             current_mode: result.mode.to_string(),
             grid_setpoint_w: result.grid_setpoint_w,
+            explanation: ExplanationJson::from(&result),
             actual_setpoint_w: battery_state.current_setpoint_w,
             battery_soc: battery_state.soc,
-            price_stats: price_cache.price_stats().map(|s| PriceStatsJson {
-                min: s.min,
-                max: s.max,
-                avg: s.avg,
-                p25: s.p25,
-                p75: s.p75,
-                p90: s.p90,
-            }),
+            grid_power_w: battery_state.grid_power_w,
+            pulse_consumption_kwh,
+            pv_power_w: home_assistant.as_ref().and_then(|ha| ha.pv_power_w()),
+            degraded: if grid.is_lost() {
+                Some("grid_lost".to_string())
+            } else if !inverter_available {
+                Some(format!("inverter_unavailable ({})", inverter_state.as_deref().unwrap_or("unknown")))
+            } else if tasks_failing {
+                Some("task_failure".to_string())
+            } else if manual.is_paused() {
+                Some("manual_override".to_string())
+            } else if overrides.is_paused() {
+                Some("paused".to_string())
+            } else if dry_run {
+                Some("dry_run".to_string())
+            } else {
+                None
+            },
+            inverter_state,
+            failed_tasks,
+            capacity_test: capacity_test.as_ref().map(|test| test.describe(battery_state.soc)),
+            degradation: ladder.level(),
+            hold: active_hold.map(|w| w.describe()),
+            consumption_events: events.upcoming(chrono::Utc::now()).iter().map(|e| e.describe()).collect(),
+            schedule_rules: rules.rules().iter().map(|r| r.describe()).collect(),
+            soc_floors: soc_floors.active(chrono::Utc::now()).iter().map(|f| f.describe()).collect(),
+            victron_schedule: victron_schedule.map(|w| w.describe(local_now)),
+            gx_soc_limit: battery_state.gx_soc_floor(),
+            manual_override_until: manual.paused_until().map(|t| t.to_rfc3339()),
+            overrides: overrides.describe(),
+            plan_divergence: plan_monitor.divergence().map(str::to_string),
+            load_correction: soc_feedback.as_ref().and_then(|f| f.correction()),
+            preset: active_preset.clone(),
+            planner: optimizer.optimizer_config().planner,
+            fixed_contract,
+            cycles_today,
+            cycles_total: warranty.cycles_total(),
+            curtailing: curtailment.as_ref().is_some_and(|c| c.is_active()),
+            ac_input_limit_raised: ac_input_limit.as_ref().is_some_and(|c| c.is_raised()),
+            economy_sleep: economy.as_ref().is_some_and(|sleep| sleep.is_asleep()),
+            dry_run,
+            shed_loads: load_shedder.shed_loads(),
+            ev_current_a: ev_decision.as_ref().map(|d| d.current_a),
+            ev_charging: ev_decision.map(|d| d.reason),
+            water_heater_on: water_heater_decision.as_ref().map(|d| d.on),
+            water_heater: water_heater_decision.map(|d| d.reason),
+            grid_limit_w: grid_limit.as_ref().and_then(|l| l.limit_w()),
+            peak_shaving: peak_tracker
+                .as_ref()
+                .map(|t| t.status(chrono::Utc::now().with_timezone(current_price.starts_at.offset()))),
+            occupied: battery_state.occupied,
+            battery_temperature_c: battery_state.battery_temperature_c,
+            warranty: WarrantyJson::new(&warranty.state().lifetime, warranty.capacity_kwh()),
+            meter_drift_kwh: accounting.today().and_then(|d| d.drift_kwh()),
+            round_trip_efficiency: optimizer.round_trip_efficiency(),
+            charge_limit_w: limiter.charge_limit_w(),
+            discharge_limit_w: limiter.discharge_limit_w(),
+            required_discharge_spread: forecast.required_discharge_spread,
+            price_stats: price_cache.price_stats().map(PriceStatsJson::from),
+            estimated_price_slots: price_cache.all_prices().filter(|p| p.is_estimated()).count(),
             next_cheap_slot: forecast.next_cheap_slot,
             next_expensive_slot: forecast.next_expensive_slot,
             cheap_slots_remaining: forecast.cheap_slots_remaining,
@@ -126,5 +1309,111 @@ async fn main() -> Result<()> {
         if let Err(e) = mqtt_client.publish_status(&status).await {
             error!("Failed to publish status: {}", e);
         }
+        if config.http_server.enabled {
+            server_state.update(chrono::Utc::now(), &status, &price_cache).await;
+        }
+        timer.mark("publish");
+
+        // Cheapest start times for configured appliances
+        if !config.appliances.is_empty() {
+            let recommendations = appliances::recommend(&config.appliances, &price_cache, chrono::Utc::now());
+            if let Err(e) = mqtt_client.publish_appliances(&recommendations).await {
+                error!("Failed to publish appliance recommendations: {}", e);
+            }
+        }
+
+        // The projected plan drives the surplus signal, load shedding, the HTTP API,
+        // the discovered next-mode sensor and the divergence check, and is the optimal planner's output
+        if config.surplus.enabled
+            || !config.load_shedding.is_empty()
+            || config.http_server.enabled
+            || config.mqtt_discovery.enabled
+            || config.plan_divergence.enabled
+            || config.soc_feedback.enabled
+            || optimizer.optimizer_config().planner == config::Planner::Optimal
+            || optimizer.optimizer_config().ramp_minutes > 0.0
+        {
+            let plan = optimizer.plan(battery_state.soc, &current_price, &price_cache);
+            let base_consumption_w = optimizer.optimizer_config().base_consumption_w;
+
+            if let Err(e) = mqtt_client.publish_plan(&plan).await {
+                error!("Failed to publish plan: {}", e);
+            }
+
+            // Alert when reality drifts away from what was planned an hour ago
+            let now = chrono::Utc::now();
+            let following_plan = in_control && !preset_switched;
+            if let Some((active, message)) =
+                plan_monitor.check(now, battery_state.soc, battery_state.grid_power_w, &plan, following_plan)
+            {
+                if let Err(e) = mqtt_client.publish_alert("plan_divergence", &message, active).await {
+                    error!("Failed to publish alert: {}", e);
+                }
+            }
+
+            // Correct the expected house load by where the SoC ended up
+            if let Some(feedback) = soc_feedback.as_mut() {
+                let soc_range = (config.battery.min_soc_percent, config.battery.max_soc_percent);
+                let pv_forecast = match &pv_source {
+                    Some(pv_source) => pv_source.get_forecast().await,
+                    None => None,
+                };
+                if feedback.record(now, battery_state.soc, soc_range, &plan, pv_forecast.as_deref(), following_plan) {
+                    optimizer.set_load_correction(feedback.correction());
+                }
+            }
+
+            // Publish cheap surplus for thermal buffers
+            if config.surplus.enabled {
+                let surplus = surplus::surplus_forecast(&plan, &config.surplus, base_consumption_w);
+                if let Err(e) = mqtt_client.publish_surplus(&surplus).await {
+                    error!("Failed to publish surplus: {}", e);
+                }
+            }
+
+            // Shed non-critical loads rather than run into the reserve at expensive prices
+            for (load, shed) in load_shedder.update(&plan, base_consumption_w) {
+                let payload = if shed { &load.shed_payload } else { &load.restore_payload };
+                if dry_run {
+                    info!("Dry run, not switching load {}", load.name);
+                } else if let Err(e) = mqtt_client.publish_payload(&load.topic, payload).await {
+                    error!("Failed to switch load {}: {}", load.name, e);
+                }
+                let message = if shed {
+                    format!("Shedding {} ({:.0} W) to protect the battery reserve", load.name, load.power_w)
+                } else {
+                    format!("Restored {}", load.name)
+                };
+                if let Err(e) = mqtt_client.publish_alert("load_shed", &message, shed).await {
+                    error!("Failed to publish alert: {}", e);
+                }
+            }
+
+            *server_state.plan.write().await = plan.clone();
+            published_plan = plan;
+            timer.mark("plan");
+        }
+    }
+
+    // As on a pause: the ESS is left on a neutral setpoint, and the car, the
+    // water heater and the AC input limit are handed back
+    info!("Shutting down, handing control back");
+    if controlling {
+        if let Err(e) = controller.write_setpoint(config.optimizer.setpoint_offset_w).await {
+            error!("Failed to write grid setpoint: {}", e);
+        }
+        if let Err(e) = controller.release().await {
+            error!("Failed to release the ESS: {}", e);
+        }
+    }
+    if let Some((charger, scheduler)) = ev_charging.as_mut() {
+        scheduler.release(charger.as_ref()).await;
+    }
+    if let Some(heater) = water_heater.as_mut() {
+        heater.release(&mqtt_client).await;
+    }
+    if let Some(limit) = ac_input_limit.as_mut() {
+        limit.release(&mqtt_client).await;
     }
+    Ok(())
 }
@@ -0,0 +1,28 @@
+use chrono::{DateTime, FixedOffset, NaiveTime, Weekday};
+use serde::{Deserialize, Serialize};
+
+/// A recurring window in which the MQTT broker is expected to be down, e.g.
+/// for nightly backups or updates. Publish failures in it are held back
+/// instead of logged as errors.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MaintenanceWindow {
+    /// Weekdays the window recurs on (`mon`, `tue`, ...; default: every day)
+    #[serde(default)]
+    pub days: Vec<Weekday>,
+    /// Local start and end time; a window ending before it starts runs past
+    /// midnight into the next day
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+}
+
+impl MaintenanceWindow {
+    /// Whether the window covers local time `at`
+    pub fn covers(&self, at: DateTime<FixedOffset>) -> bool {
+        crate::rules::window_covers(&self.days, Some(self.start), Some(self.end), at)
+    }
+}
+
+/// Whether any of `windows` covers local time `at`
+pub fn in_maintenance(windows: &[MaintenanceWindow], at: DateTime<FixedOffset>) -> bool {
+    windows.iter().any(|window| window.covers(at))
+}
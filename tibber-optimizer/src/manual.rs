@@ -0,0 +1,81 @@
+use chrono::{DateTime, Duration, Utc};
+use tracing::{info, warn};
+
+use crate::config::ManualOverrideConfig;
+use crate::mqtt::BatteryState;
+
+/// Time the system gets to echo a written setpoint before a differing
+/// reading counts as a manual change
+const ECHO_GRACE_SECS: i64 = 10;
+
+/// Manual intervention transitions worth alerting on
+#[derive(Debug, Clone, PartialEq)]
+pub enum ManualEvent {
+    /// A setpoint we did not write was detected (W)
+    Detected(f64),
+    Resumed,
+}
+
+/// Detects setpoints written by someone else (e.g. in the Victron UI) and
+/// pauses automatic control for a cooldown instead of fighting the change
+#[derive(Debug)]
+pub struct ManualOverrideDetector {
+    config: ManualOverrideConfig,
+    /// Last setpoint we wrote and when
+    commanded: Option<(f64, DateTime<Utc>)>,
+    paused_until: Option<DateTime<Utc>>,
+}
+
+impl ManualOverrideDetector {
+    pub fn new(config: ManualOverrideConfig) -> Self {
+        Self {
+            config,
+            commanded: None,
+            paused_until: None,
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused_until.is_some()
+    }
+
+    pub fn paused_until(&self) -> Option<DateTime<Utc>> {
+        self.paused_until
+    }
+
+    /// Remember a setpoint we successfully wrote
+    pub fn commanded(&mut self, setpoint_w: f64, now: DateTime<Utc>) {
+        self.commanded = Some((setpoint_w, now));
+    }
+
+    /// Compare the read-back setpoint with the last written one; returns an event on transitions
+    pub fn check(&mut self, state: &BatteryState, now: DateTime<Utc>) -> Option<ManualEvent> {
+        if let Some(until) = self.paused_until {
+            if now < until {
+                return None;
+            }
+            info!("Manual override cooldown ended, resuming automatic control");
+            self.paused_until = None;
+            self.commanded = None;
+            return Some(ManualEvent::Resumed);
+        }
+
+        if !self.config.enabled {
+            return None;
+        }
+        let (commanded, at) = self.commanded?;
+        let (Some(read), Some(updated)) = (state.current_setpoint_w, state.last_setpoint_update) else {
+            return None;
+        };
+
+        if updated > at + Duration::seconds(ECHO_GRACE_SECS) && (read - commanded).abs() > self.config.tolerance_w {
+            warn!(
+                "Setpoint changed to {:.0}W outside the optimizer (wrote {:.0}W), pausing for {}s",
+                read, commanded, self.config.cooldown_secs
+            );
+            self.paused_until = Some(now + Duration::seconds(self.config.cooldown_secs as i64));
+            return Some(ManualEvent::Detected(read));
+        }
+        None
+    }
+}
@@ -0,0 +1,147 @@
+//! Memory use of the process, so long-running deployments on small devices can
+//! confirm it stays flat. The global allocator (the system one, or mimalloc or
+//! jemalloc with the matching feature) is wrapped in [`CountingAllocator`],
+//! which keeps track of the live heap.
+
+use std::alloc::{GlobalAlloc, Layout};
+use std::cell::Cell;
+use std::sync::atomic::{AtomicIsize, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+use tracing::info;
+
+const MEMORY_LOG_INTERVAL: Duration = Duration::from_secs(3600);
+
+static HEAP_BYTES: AtomicIsize = AtomicIsize::new(0);
+static PEAK_HEAP_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    /// Bytes allocated minus bytes freed by this thread
+    static THREAD_HEAP_BYTES: Cell<isize> = const { Cell::new(0) };
+}
+
+/// Counts live heap bytes on top of the allocator `A`
+pub struct CountingAllocator<A>(pub A);
+
+fn record(delta: isize) {
+    let live = HEAP_BYTES.fetch_add(delta, Ordering::Relaxed) + delta;
+    if delta > 0 {
+        PEAK_HEAP_BYTES.fetch_max(live.max(0) as usize, Ordering::Relaxed);
+    }
+    // Fails only while the thread is being torn down
+    let _ = THREAD_HEAP_BYTES.try_with(|bytes| bytes.set(bytes.get() + delta));
+}
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for CountingAllocator<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = self.0.alloc(layout);
+        if !ptr.is_null() {
+            record(layout.size() as isize);
+        }
+        ptr
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = self.0.alloc_zeroed(layout);
+        if !ptr.is_null() {
+            record(layout.size() as isize);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.0.dealloc(ptr, layout);
+        record(-(layout.size() as isize));
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = self.0.realloc(ptr, layout, new_size);
+        if !new_ptr.is_null() {
+            record(new_size as isize - layout.size() as isize);
+        }
+        new_ptr
+    }
+}
+
+/// Name of the allocator this build uses
+pub const ALLOCATOR: &str = if cfg!(feature = "mimalloc") {
+    "mimalloc"
+} else if cfg!(feature = "jemalloc") {
+    "jemalloc"
+} else {
+    "system"
+};
+
+/// Live heap bytes of the whole process
+pub fn heap_bytes() -> usize {
+    HEAP_BYTES.load(Ordering::Relaxed).max(0) as usize
+}
+
+/// Bytes the current thread allocated and hasn't freed itself. Memory freed by
+/// another thread still counts, so this is for checking single-threaded code.
+#[cfg(test)]
+pub fn thread_heap_bytes() -> isize {
+    THREAD_HEAP_BYTES.with(Cell::get)
+}
+
+/// Resident set size from /proc (None where that isn't available)
+pub fn rss_kb() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmRSS:"))
+        .and_then(|value| value.trim().trim_end_matches("kB").trim().parse().ok())
+}
+
+/// Memory use at the end of a control cycle
+#[derive(Debug, Clone, Serialize)]
+pub struct MemoryMetrics {
+    pub allocator: &'static str,
+    pub rss_kb: Option<u64>,
+    pub heap_kb: u64,
+    /// Highest live heap since startup
+    pub peak_heap_kb: u64,
+}
+
+impl MemoryMetrics {
+    pub fn now() -> Self {
+        Self {
+            allocator: ALLOCATOR,
+            rss_kb: rss_kb(),
+            heap_kb: (heap_bytes() / 1024) as u64,
+            peak_heap_kb: (PEAK_HEAP_BYTES.load(Ordering::Relaxed) / 1024) as u64,
+        }
+    }
+}
+
+/// Logs memory use once an hour against the first report after startup, so
+/// growth over days of uptime shows in the log
+#[derive(Debug, Default)]
+pub struct MemoryLog {
+    baseline: Option<MemoryMetrics>,
+    last: Option<Instant>,
+}
+
+impl MemoryLog {
+    pub fn report(&mut self, memory: &MemoryMetrics) {
+        if self.last.is_some_and(|last| last.elapsed() < MEMORY_LOG_INTERVAL) {
+            return;
+        }
+        self.last = Some(Instant::now());
+        let baseline = self.baseline.get_or_insert_with(|| memory.clone());
+        let mb = |kb: u64| kb as f64 / 1024.0;
+        let rss = match (memory.rss_kb, baseline.rss_kb) {
+            (Some(rss), Some(start)) => format!("RSS {:.1} MB ({:+.1} since startup), ", mb(rss), mb(rss) - mb(start)),
+            _ => String::new(),
+        };
+        info!(
+            "Memory: {}heap {:.1} MB ({:+.1} since startup), peak {:.1} MB, {} allocator",
+            rss,
+            mb(memory.heap_kb),
+            mb(memory.heap_kb) - mb(baseline.heap_kb),
+            mb(memory.peak_heap_kb),
+            memory.allocator
+        );
+    }
+}
@@ -0,0 +1,72 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use serde::Serialize;
+use tokio::time::Instant;
+
+use crate::memory::MemoryMetrics;
+
+/// Ticks starting later than this after their schedule are reported as overruns
+pub const MAX_TICK_DRIFT: Duration = Duration::from_secs(5);
+
+/// Timing of one completed control cycle
+#[derive(Debug, Clone, Serialize)]
+pub struct CycleMetrics {
+    /// Time from tick to the last completed phase
+    pub cycle_ms: f64,
+    /// How late the tick fired relative to its schedule
+    pub tick_drift_ms: f64,
+    /// Time spent per phase (a cycle that stops early has fewer phases)
+    pub phases_ms: BTreeMap<&'static str, f64>,
+    /// Memory use when the cycle was reported
+    pub memory: MemoryMetrics,
+}
+
+impl CycleMetrics {
+    /// Whether this cycle started late or ran longer than `period`
+    pub fn overran(&self, period: Duration) -> bool {
+        self.tick_drift_ms > MAX_TICK_DRIFT.as_secs_f64() * 1000.0 || self.cycle_ms > period.as_secs_f64() * 1000.0
+    }
+}
+
+/// Measures a control cycle phase by phase
+#[derive(Debug)]
+pub struct CycleTimer {
+    started: Instant,
+    last_mark: Instant,
+    tick_drift: Duration,
+    phases: BTreeMap<&'static str, Duration>,
+}
+
+impl CycleTimer {
+    /// Start timing a cycle for the tick scheduled at `scheduled`
+    pub fn start(scheduled: Instant) -> Self {
+        let now = Instant::now();
+        Self {
+            started: now,
+            last_mark: now,
+            tick_drift: now.saturating_duration_since(scheduled),
+            phases: BTreeMap::new(),
+        }
+    }
+
+    /// Attribute the time since the previous mark to `phase`
+    pub fn mark(&mut self, phase: &'static str) {
+        let now = Instant::now();
+        *self.phases.entry(phase).or_default() += now - self.last_mark;
+        self.last_mark = now;
+    }
+
+    pub fn finish(self) -> CycleMetrics {
+        CycleMetrics {
+            cycle_ms: millis(self.last_mark - self.started),
+            tick_drift_ms: millis(self.tick_drift),
+            phases_ms: self.phases.into_iter().map(|(phase, d)| (phase, millis(d))).collect(),
+            memory: MemoryMetrics::now(),
+        }
+    }
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
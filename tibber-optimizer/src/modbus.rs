@@ -0,0 +1,163 @@
+//! Grid setpoint writes over Modbus TCP, for GX devices where the MQTT write
+//! topic is unreliable (dbus-mqtt only accepts writes to objects it has
+//! already published). A minimal client over a tokio `TcpStream`: only the
+//! register writes are needed.
+
+use std::sync::atomic::{AtomicU16, Ordering};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::Mutex;
+use tracing::{debug, info, warn};
+
+use crate::config::ModbusConfig;
+use crate::controller::{BatteryController, WriteFuture};
+
+const WRITE_SINGLE_REGISTER: u8 = 0x06;
+const WRITE_MULTIPLE_REGISTERS: u8 = 0x10;
+
+/// A Modbus TCP connection to one unit, kept open between writes and
+/// reopened after an error
+pub struct ModbusClient {
+    host: String,
+    port: u16,
+    unit_id: u8,
+    timeout: Duration,
+    connection: Mutex<Option<TcpStream>>,
+    transaction: AtomicU16,
+}
+
+impl ModbusClient {
+    pub fn new(host: &str, port: u16, unit_id: u8, timeout_secs: u64) -> Self {
+        Self {
+            host: host.to_string(),
+            port,
+            unit_id,
+            timeout: Duration::from_secs(timeout_secs),
+            connection: Mutex::new(None),
+            transaction: AtomicU16::new(0),
+        }
+    }
+
+    /// "Write single register"
+    pub async fn write_register(&self, register: u16, value: u16) -> Result<()> {
+        let mut pdu = vec![WRITE_SINGLE_REGISTER];
+        pdu.extend_from_slice(&register.to_be_bytes());
+        pdu.extend_from_slice(&value.to_be_bytes());
+        // The device echoes the request
+        let expected = pdu.clone();
+        self.request(pdu, &expected).await
+    }
+
+    /// "Write multiple registers", starting at `register`
+    pub async fn write_registers(&self, register: u16, values: &[u16]) -> Result<()> {
+        let count = values.len() as u16;
+        let mut pdu = vec![WRITE_MULTIPLE_REGISTERS];
+        pdu.extend_from_slice(&register.to_be_bytes());
+        pdu.extend_from_slice(&count.to_be_bytes());
+        // The device answers with the start register and count
+        let expected = pdu.clone();
+        pdu.push((values.len() * 2) as u8);
+        for value in values {
+            pdu.extend_from_slice(&value.to_be_bytes());
+        }
+        self.request(pdu, &expected).await
+    }
+
+    async fn request(&self, pdu: Vec<u8>, expected: &[u8]) -> Result<()> {
+        let mut connection = self.connection.lock().await;
+        if connection.is_none() {
+            let address = (self.host.as_str(), self.port);
+            let stream = tokio::time::timeout(self.timeout, TcpStream::connect(address))
+                .await
+                .context("Timed out connecting")??;
+            info!("Connected to Modbus TCP at {}:{}", self.host, self.port);
+            *connection = Some(stream);
+        }
+        let stream = connection.as_mut().expect("connected above");
+
+        let transaction = self.transaction.fetch_add(1, Ordering::Relaxed);
+        let request = frame(transaction, self.unit_id, &pdu);
+        let result = tokio::time::timeout(self.timeout, exchange(stream, &request, expected))
+            .await
+            .context("Timed out waiting for the response")
+            .and_then(|response| response)
+            .with_context(|| format!("Modbus write to {}:{}", self.host, self.port));
+        if result.is_err() {
+            // Start over on a fresh connection rather than reading a late reply
+            *connection = None;
+        }
+        result
+    }
+}
+
+/// MBAP header, then the PDU
+fn frame(transaction: u16, unit_id: u8, pdu: &[u8]) -> Vec<u8> {
+    let mut request = Vec::with_capacity(7 + pdu.len());
+    request.extend_from_slice(&transaction.to_be_bytes());
+    // Protocol ID 0, then the length of what follows the length field
+    request.extend_from_slice(&[0, 0]);
+    request.extend_from_slice(&(pdu.len() as u16 + 1).to_be_bytes());
+    request.push(unit_id);
+    request.extend_from_slice(pdu);
+    request
+}
+
+/// Send `request` and check the response PDU is `expected`
+async fn exchange(stream: &mut TcpStream, request: &[u8], expected: &[u8]) -> Result<()> {
+    stream.write_all(request).await?;
+    let mut header = [0u8; 7];
+    stream.read_exact(&mut header).await?;
+    let length = u16::from_be_bytes([header[4], header[5]]) as usize;
+    if !(2..=253).contains(&length) {
+        anyhow::bail!("Invalid Modbus response length {}", length);
+    }
+    let mut pdu = vec![0u8; length - 1];
+    stream.read_exact(&mut pdu).await?;
+    if header[0..2] != request[0..2] {
+        anyhow::bail!("Modbus response for another transaction");
+    }
+    let function = expected[0];
+    match pdu.first() {
+        Some(&code) if code == function | 0x80 => {
+            anyhow::bail!("Modbus exception {}", pdu.get(1).copied().unwrap_or_default())
+        }
+        Some(_) if pdu[..] == expected[..] => Ok(()),
+        _ => anyhow::bail!("Unexpected Modbus response {:02x?}", pdu),
+    }
+}
+
+/// Writes the ESS setpoint register
+pub struct ModbusController {
+    config: ModbusConfig,
+    client: ModbusClient,
+}
+
+impl ModbusController {
+    pub fn new(config: ModbusConfig) -> Self {
+        let client = ModbusClient::new(&config.host, config.port, config.unit_id, config.timeout_secs);
+        Self { config, client }
+    }
+}
+
+impl BatteryController for ModbusController {
+    fn name(&self) -> &'static str {
+        "Modbus TCP"
+    }
+
+    fn write_setpoint(&self, setpoint_w: f64) -> WriteFuture<'_> {
+        Box::pin(async move {
+            let value = setpoint_w.round().clamp(i16::MIN as f64, i16::MAX as f64);
+            if value != setpoint_w.round() {
+                warn!("Setpoint {:.0}W exceeds the 16-bit register, writing {:.0}W", setpoint_w, value);
+            }
+            self.client
+                .write_register(self.config.setpoint_register, value as i16 as u16)
+                .await?;
+            debug!("Wrote grid setpoint: {} W to register {}", value, self.config.setpoint_register);
+            Ok(())
+        })
+    }
+}
@@ -1,32 +1,453 @@
-use anyhow::Result;
 use rumqttc::{AsyncClient, Event, MqttOptions, Packet, QoS};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
-use tokio::sync::RwLock;
+use tokio::sync::{Mutex, RwLock};
 use tracing::{debug, error, info, warn};
 
-use crate::config::MqttConfig;
+use chrono::{DateTime, Local, Utc};
+
+use crate::commands::Command;
+use crate::config::{MqttConfig, SocSource};
+use crate::controller::{self, BatteryController, WriteFuture};
+use crate::error::{Error, Result};
+use crate::maintenance::{self, MaintenanceWindow};
+use crate::prices::PriceCache;
+use crate::record::Recorder;
+use crate::rpc;
+use crate::schedule::VictronChargeSchedule;
+use crate::schema::{PlanPayload, PricePayload, Versioned};
+use crate::supervisor::Supervisor;
+use crate::telemetry::FastTelemetry;
+
+/// Wait before reconnecting to the broker after a connection error
+const RECONNECT_RETRY: Duration = Duration::from_secs(5);
+
+/// Wait before reconnecting after the broker refused the credentials
+const AUTH_RETRY: Duration = Duration::from_secs(60);
+
+/// Publishes the setpoint on MQTT, as `{"value": x}` for Victron or in a
+/// configured template for other inverters
+pub struct MqttSetpointWriter {
+    broker: Broker,
+    topic: String,
+    payload: String,
+    retain: bool,
+}
+
+impl BatteryController for MqttSetpointWriter {
+    fn name(&self) -> &'static str {
+        "MQTT"
+    }
+
+    fn write_setpoint(&self, setpoint_w: f64) -> WriteFuture<'_> {
+        Box::pin(async move {
+            let topic = controller::render(&self.topic, setpoint_w);
+            let payload = controller::render(&self.payload, setpoint_w);
+            self.broker
+                .publish(&topic, QoS::AtLeastOnce, self.retain, payload)
+                .await?;
+            debug!("Published grid setpoint: {} W to {}", setpoint_w, topic);
+            Ok(())
+        })
+    }
+}
 
 #[derive(Debug, Clone, Default)]
 pub struct BatteryState {
-    /// Current state of charge (0-100)
+    /// Current state of charge (0-100), aggregated over all packs
     pub soc: f64,
+    /// Last reported SoC per configured SoC source
+    pub pack_soc: Vec<Option<f64>>,
     /// Current grid setpoint as read from the system
     pub current_setpoint_w: Option<f64>,
     /// Last SoC update timestamp
     pub last_soc_update: Option<chrono::DateTime<chrono::Utc>>,
     /// Last setpoint update timestamp
     pub last_setpoint_update: Option<chrono::DateTime<chrono::Utc>>,
+    /// Inverter/charger state, if an inverter state topic is configured
+    pub inverter_state: Option<InverterState>,
+    /// Grid-lost alarm state, if a grid-lost topic is configured
+    pub grid_lost: Option<bool>,
+    /// Current grid power in watts (positive = import), if a grid power topic is configured
+    pub grid_power_w: Option<f64>,
+    /// Grid power per phase in watts, from a Shelly Pro 3EM
+    pub grid_phase_power_w: Option<[f64; 3]>,
+    /// Last grid power update timestamp
+    pub last_grid_power_update: Option<chrono::DateTime<chrono::Utc>>,
+    /// AC battery power in watts (positive = charging), if a battery power topic is configured
+    pub battery_power_w: Option<f64>,
+    /// House consumption in watts, if a house load topic is configured
+    pub house_load_w: Option<f64>,
+    /// Cumulative grid import meter reading in kWh
+    pub meter_import_kwh: Option<f64>,
+    /// Cumulative grid export meter reading in kWh
+    pub meter_export_kwh: Option<f64>,
+    /// Charge windows scheduled in the GX UI, by schedule index
+    pub charge_schedules: Vec<VictronChargeSchedule>,
+    /// GX minimum SoC setting, if a minimum SoC limit topic is configured
+    pub gx_min_soc: Option<f64>,
+    /// GX minimum SoC as raised by BatteryLife, if an active SoC limit topic is configured
+    pub gx_active_soc_limit: Option<f64>,
+    /// Whether a car is plugged in, if an EV connected topic is configured
+    pub ev_connected: Option<bool>,
+    /// EV charging power in watts, if an EV power topic is configured
+    pub ev_power_w: Option<f64>,
+    /// Energy charged in the current EV session in kWh, if an EV energy topic
+    /// is configured
+    pub ev_session_kwh: Option<f64>,
+    /// Latest grid operator limit signal, if a grid limit topic is configured
+    pub grid_limit_signal: Option<f64>,
+    /// Last grid operator limit signal timestamp
+    pub last_grid_limit_update: Option<chrono::DateTime<chrono::Utc>>,
+    /// Whether anybody is home, if an occupancy topic is configured
+    pub occupied: Option<bool>,
+    /// When everybody left, while nobody is home
+    pub away_since: Option<chrono::DateTime<chrono::Utc>>,
+    /// Battery temperature in °C, if a battery temperature topic is configured
+    pub battery_temperature_c: Option<f64>,
+    /// Last battery temperature update timestamp
+    pub last_battery_temperature_update: Option<chrono::DateTime<chrono::Utc>>,
 }
 
-pub struct MqttClient {
+impl BatteryState {
+    /// Net grid meter reading (import minus export) in kWh, once all
+    /// configured meter topics have reported
+    pub fn net_meter_kwh(&self, config: &MqttConfig) -> Option<f64> {
+        let import = self.meter_import_kwh?;
+        match (&config.meter_export_topic, self.meter_export_kwh) {
+            (_, Some(export)) => Some(import - export),
+            (Some(_), None) => None,
+            (None, None) => Some(import),
+        }
+    }
+
+    /// SoC the GX won't discharge below, from whichever limits it reported
+    pub fn gx_soc_floor(&self) -> Option<f64> {
+        match (self.gx_min_soc, self.gx_active_soc_limit) {
+            (Some(min), Some(active)) => Some(min.max(active)),
+            (min, active) => min.or(active),
+        }
+    }
+
+    /// Battery temperature, unless the last reading is `max_age_minutes` old
+    pub fn battery_temperature_at(&self, now: DateTime<Utc>, max_age_minutes: u64) -> Option<f64> {
+        let at = self.last_battery_temperature_update?;
+        ((now - at).num_minutes() < max_age_minutes as i64).then_some(self.battery_temperature_c?)
+    }
+
+    /// Whether the inverter can act on setpoints (unknown counts as available)
+    pub fn inverter_available(&self) -> bool {
+        self.inverter_state.is_none_or(|s| s.is_available())
+    }
+}
+
+/// Victron VE.Bus state as published on `N/<portal_id>/vebus/<instance>/State`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InverterState {
+    Off,
+    LowPower,
+    Fault,
+    Bulk,
+    Absorption,
+    Float,
+    Storage,
+    Equalize,
+    Passthru,
+    Inverting,
+    PowerAssist,
+    PowerSupply,
+    ExternalControl,
+    Other(u16),
+}
+
+impl InverterState {
+    pub fn from_code(code: u16) -> Self {
+        match code {
+            0 => InverterState::Off,
+            1 => InverterState::LowPower,
+            2 => InverterState::Fault,
+            3 => InverterState::Bulk,
+            4 => InverterState::Absorption,
+            5 => InverterState::Float,
+            6 => InverterState::Storage,
+            7 => InverterState::Equalize,
+            8 => InverterState::Passthru,
+            9 => InverterState::Inverting,
+            10 => InverterState::PowerAssist,
+            11 => InverterState::PowerSupply,
+            252 => InverterState::ExternalControl,
+            other => InverterState::Other(other),
+        }
+    }
+
+    /// Off or faulted inverters can't follow a grid setpoint
+    pub fn is_available(&self) -> bool {
+        !matches!(self, InverterState::Off | InverterState::Fault)
+    }
+}
+
+impl std::fmt::Display for InverterState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InverterState::Off => write!(f, "off"),
+            InverterState::LowPower => write!(f, "low_power"),
+            InverterState::Fault => write!(f, "fault"),
+            InverterState::Bulk => write!(f, "bulk"),
+            InverterState::Absorption => write!(f, "absorption"),
+            InverterState::Float => write!(f, "float"),
+            InverterState::Storage => write!(f, "storage"),
+            InverterState::Equalize => write!(f, "equalize"),
+            InverterState::Passthru => write!(f, "passthru"),
+            InverterState::Inverting => write!(f, "inverting"),
+            InverterState::PowerAssist => write!(f, "power_assist"),
+            InverterState::PowerSupply => write!(f, "power_supply"),
+            InverterState::ExternalControl => write!(f, "external_control"),
+            InverterState::Other(code) => write!(f, "state_{}", code),
+        }
+    }
+}
+
+/// Applies incoming messages on the subscribed topics to the battery state
+#[derive(Debug, Clone)]
+pub struct MessageHandler {
+    config: MqttConfig,
+    soc_sources: Vec<SocSource>,
+}
+
+impl MessageHandler {
+    pub fn new(config: MqttConfig) -> Self {
+        let soc_sources = config.effective_soc_sources();
+        Self { config, soc_sources }
+    }
+
+    /// Empty state sized for the configured SoC sources
+    pub fn initial_state(&self) -> BatteryState {
+        BatteryState {
+            pack_soc: vec![None; self.soc_sources.len()],
+            ..Default::default()
+        }
+    }
+
+    /// Apply one message received at `now`; returns a command if it was one
+    pub fn handle(&self, state: &mut BatteryState, topic: &str, payload: &str, now: DateTime<Utc>) -> Option<Command> {
+        let config = &self.config;
+        let is = |optional: &Option<String>| optional.as_deref() == Some(topic);
+
+        // Handle Shelly Pro 3EM status notifications
+        if let Some(prefix) = &config.shelly_3em_prefix {
+            if crate::shelly::handle(prefix, state, topic, payload, now) {
+                return None;
+            }
+        }
+
+        // Handle SoC updates (Victron format)
+        if let Some(idx) = self.soc_sources.iter().position(|s| s.topic == topic) {
+            if let Some(value) = parse_victron_soc(payload) {
+                state.pack_soc[idx] = Some(value);
+                // Only report an SoC once every pack has been heard from
+                if let Some(soc) = aggregate_soc(&self.soc_sources, &state.pack_soc) {
+                    state.soc = soc;
+                    state.last_soc_update = Some(now);
+                    debug!("Updated battery SoC: {:.1}%", soc);
+                }
+            }
+        }
+        // Handle setpoint updates
+        else if topic == config.grid_setpoint_read_topic {
+            if let Some(value) = parse_mqtt_value(payload) {
+                state.current_setpoint_w = Some(value);
+                state.last_setpoint_update = Some(now);
+                debug!("Updated grid setpoint reading: {:.0}W", value);
+            }
+        }
+        // Handle runtime commands
+        else if topic == config.command_topic {
+            match serde_json::from_str::<Command>(payload) {
+                Ok(command) => {
+                    info!("Received command: {:?}", command);
+                    return Some(command);
+                }
+                Err(e) => warn!("Ignoring invalid command '{}': {}", payload, e),
+            }
+        }
+        // Handle grid-lost alarm (0 = ok, 2 = alarm)
+        else if is(&config.grid_lost_topic) {
+            if let Some(value) = parse_mqtt_value(payload) {
+                state.grid_lost = Some(value != 0.0);
+                debug!("Updated grid-lost alarm: {}", value);
+            }
+        }
+        // Handle grid power updates
+        else if is(&config.grid_power_topic) {
+            if let Some(value) = parse_mqtt_value(payload) {
+                state.grid_power_w = Some(value);
+                state.last_grid_power_update = Some(now);
+                debug!("Updated grid power: {:.0}W", value);
+            }
+        }
+        // Handle battery power updates
+        else if is(&config.battery_power_topic) {
+            if let Some(value) = parse_mqtt_value(payload) {
+                state.battery_power_w = Some(value);
+                debug!("Updated battery power: {:.0}W", value);
+            }
+        }
+        // Handle house load updates
+        else if is(&config.house_load_topic) {
+            if let Some(value) = parse_mqtt_value(payload) {
+                state.house_load_w = Some(value);
+                debug!("Updated house load: {:.0}W", value);
+            }
+        }
+        // Handle grid meter readings
+        else if is(&config.meter_import_topic) {
+            if let Some(value) = parse_mqtt_value(payload) {
+                state.meter_import_kwh = Some(value);
+                debug!("Updated import meter: {:.2}kWh", value);
+            }
+        }
+        else if is(&config.meter_export_topic) {
+            if let Some(value) = parse_mqtt_value(payload) {
+                state.meter_export_kwh = Some(value);
+                debug!("Updated export meter: {:.2}kWh", value);
+            }
+        }
+        // Handle inverter state updates
+        else if is(&config.inverter_state_topic) {
+            if let Some(value) = parse_mqtt_value(payload) {
+                let inverter_state = InverterState::from_code(value as u16);
+                state.inverter_state = Some(inverter_state);
+                debug!("Updated inverter state: {}", inverter_state);
+            }
+        }
+        // Handle GX SoC limits
+        else if is(&config.min_soc_limit_topic) {
+            if let Some(value) = parse_mqtt_value(payload) {
+                state.gx_min_soc = Some(value);
+                debug!("Updated GX minimum SoC: {:.0}%", value);
+            }
+        }
+        else if is(&config.active_soc_limit_topic) {
+            if let Some(value) = parse_mqtt_value(payload) {
+                state.gx_active_soc_limit = Some(value);
+                debug!("Updated GX active SoC limit: {:.0}%", value);
+            }
+        }
+        // Handle EV charger state
+        else if is(&config.ev_connected_topic) {
+            if let Some(value) = parse_mqtt_value(payload) {
+                state.ev_connected = Some(value != 0.0);
+                debug!("Updated EV connected: {}", value);
+            }
+        }
+        else if is(&config.ev_power_topic) {
+            if let Some(value) = parse_mqtt_value(payload) {
+                state.ev_power_w = Some(value);
+                debug!("Updated EV power: {:.0}W", value);
+            }
+        }
+        else if is(&config.ev_energy_topic) {
+            if let Some(value) = parse_mqtt_value(payload) {
+                state.ev_session_kwh = Some(value);
+                debug!("Updated EV session energy: {:.2}kWh", value);
+            }
+        }
+        // Handle grid operator limits
+        else if is(&config.grid_limit_topic) {
+            if let Some(value) = parse_mqtt_value(payload) {
+                state.grid_limit_signal = Some(value);
+                state.last_grid_limit_update = Some(now);
+                debug!("Updated grid limit signal: {}", value);
+            }
+        }
+        // Handle occupancy
+        else if is(&config.occupancy_topic) {
+            if let Some(occupied) = parse_mqtt_bool(payload) {
+                if occupied {
+                    state.away_since = None;
+                } else if state.occupied != Some(false) {
+                    state.away_since = Some(now);
+                }
+                state.occupied = Some(occupied);
+                debug!("Updated occupancy: {}", if occupied { "home" } else { "away" });
+            }
+        }
+        // Handle battery temperature
+        else if is(&config.battery_temperature_topic) {
+            if let Some(value) = parse_mqtt_value(payload) {
+                state.battery_temperature_c = Some(value);
+                state.last_battery_temperature_update = Some(now);
+                debug!("Updated battery temperature: {:.1}°C", value);
+            }
+        }
+        // Handle GX scheduled-charge settings (`<prefix>/<index>/<field>`)
+        else if let Some((index, field)) = config
+            .charge_schedule_topic
+            .as_deref()
+            .and_then(|prefix| topic.strip_prefix(prefix)?.strip_prefix('/')?.split_once('/'))
+        {
+            if let (Ok(index), Some(value)) = (index.parse::<usize>(), parse_mqtt_value(payload)) {
+                if state.charge_schedules.len() <= index {
+                    state.charge_schedules.resize(index + 1, VictronChargeSchedule::default());
+                }
+                if state.charge_schedules[index].set(field, value) {
+                    debug!("Updated charge schedule {} {}: {}", index, field, value);
+                }
+            }
+        }
+
+        None
+    }
+}
+
+/// The client and its connection state, shared by everything that publishes
+#[derive(Clone)]
+struct Broker {
     client: AsyncClient,
+    /// When the broker connection was lost (or first attempted), None while connected
+    disconnected_since: Arc<std::sync::Mutex<Option<DateTime<Utc>>>>,
+    maintenance_windows: Arc<Vec<MaintenanceWindow>>,
+    /// Set when a publish was skipped for maintenance, until the broker is back
+    held_back: Arc<AtomicBool>,
+}
+
+impl Broker {
+    /// Whether the broker is down during one of its maintenance windows
+    fn in_maintenance(&self) -> bool {
+        self.disconnected_since.lock().unwrap().is_some()
+            && maintenance::in_maintenance(&self.maintenance_windows, Local::now().fixed_offset())
+    }
+
+    /// Publish, or skip quietly while the broker is down for maintenance;
+    /// queueing would only fill the request channel with stale messages
+    async fn publish(&self, topic: &str, qos: QoS, retain: bool, payload: impl Into<Vec<u8>>) -> Result<()> {
+        if self.in_maintenance() {
+            if !self.held_back.swap(true, Ordering::Relaxed) {
+                info!("MQTT broker down during its maintenance window, holding back publishes");
+            }
+            debug!("Skipped publish to {} during broker maintenance", topic);
+            return Ok(());
+        }
+        self.client.publish(topic, qos, retain, payload).await?;
+        Ok(())
+    }
+}
+
+pub struct MqttClient {
+    broker: Broker,
     config: MqttConfig,
     battery_state: Arc<RwLock<BatteryState>>,
+    /// Commands received since the last `take_commands`
+    commands: Arc<Mutex<Vec<Command>>>,
+    /// Latest readings of the high-frequency feeds, kept out of `battery_state`
+    fast_telemetry: Arc<FastTelemetry>,
+    /// Prices the RPC queries are answered from
+    prices: Arc<std::sync::Mutex<Option<Arc<PriceCache>>>>,
 }
 
 impl MqttClient {
-    pub async fn new(config: MqttConfig) -> Result<Self> {
+    pub async fn new(config: MqttConfig, recorder: Option<Recorder>, supervisor: &Supervisor) -> Result<Self> {
         let mut mqtt_options = MqttOptions::new(
             &config.client_id,
             &config.host,
@@ -39,48 +460,97 @@ impl MqttClient {
             mqtt_options.set_credentials(username, password);
         }
 
-        let (client, mut eventloop) = AsyncClient::new(mqtt_options, 100);
-        let battery_state = Arc::new(RwLock::new(BatteryState::default()));
+        let (client, eventloop) = AsyncClient::new(mqtt_options, 100);
+        let handler = MessageHandler::new(config.clone());
+        let soc_sources = handler.soc_sources.clone();
+        let battery_state = Arc::new(RwLock::new(handler.initial_state()));
         let battery_state_clone = battery_state.clone();
-        let soc_topic = config.soc_topic.clone();
-        let setpoint_read_topic = config.grid_setpoint_read_topic.clone();
-
-        // Spawn event loop handler
-        tokio::spawn(async move {
-            loop {
-                match eventloop.poll().await {
-                    Ok(Event::Incoming(Packet::Publish(publish))) => {
-                        if let Ok(payload_str) = std::str::from_utf8(&publish.payload) {
-                            // Handle SoC updates (Victron format)
-                            if publish.topic == soc_topic {
-                                if let Some(value) = parse_victron_soc(payload_str) {
-                                    let mut state = battery_state_clone.write().await;
-                                    state.soc = value;
-                                    state.last_soc_update = Some(chrono::Utc::now());
-                                    debug!("Updated battery SoC: {:.1}%", value);
+        let commands = Arc::new(Mutex::new(Vec::new()));
+        let commands_clone = commands.clone();
+        let disconnected_since = Arc::new(std::sync::Mutex::new(Some(Utc::now())));
+        let disconnected_since_clone = disconnected_since.clone();
+        let fast_telemetry = Arc::new(FastTelemetry::new(&config));
+        let fast_telemetry_clone = fast_telemetry.clone();
+        let maintenance_windows = Arc::new(config.maintenance_windows.clone());
+        let maintenance_windows_clone = maintenance_windows.clone();
+        let prices = Arc::new(std::sync::Mutex::new(None));
+        let prices_clone = prices.clone();
+        let rpc_request_topic = format!("{}/rpc/request", base_topic(&config));
+        let rpc_request_topic_clone = rpc_request_topic.clone();
+        let rpc_response_topic = format!("{}/rpc/response", base_topic(&config));
+        let rpc_client = client.clone();
+
+        // Run the event loop under supervision; a restarted loop picks up the
+        // same connection state
+        let eventloop = Arc::new(Mutex::new(eventloop));
+        let handler = Arc::new(handler);
+        supervisor.spawn("mqtt_event_loop", move || {
+            let eventloop = eventloop.clone();
+            let handler = handler.clone();
+            let recorder = recorder.clone();
+            let battery_state = battery_state_clone.clone();
+            let commands = commands_clone.clone();
+            let disconnected_since = disconnected_since_clone.clone();
+            let fast_telemetry = fast_telemetry_clone.clone();
+            let maintenance_windows = maintenance_windows_clone.clone();
+            let prices = prices_clone.clone();
+            let rpc_request_topic = rpc_request_topic_clone.clone();
+            let rpc_response_topic = rpc_response_topic.clone();
+            let rpc_client = rpc_client.clone();
+            async move {
+                let mut eventloop = eventloop.lock().await;
+                loop {
+                    match eventloop.poll().await {
+                        Ok(Event::Incoming(Packet::Publish(publish))) => {
+                            if let Ok(payload_str) = std::str::from_utf8(&publish.payload) {
+                                if let Some(recorder) = &recorder {
+                                    recorder.mqtt(&publish.topic, payload_str);
                                 }
-                            }
-                            // Handle setpoint updates
-                            else if publish.topic == setpoint_read_topic {
-                                if let Some(value) = parse_mqtt_value(payload_str) {
-                                    let mut state = battery_state_clone.write().await;
-                                    state.current_setpoint_w = Some(value);
-                                    state.last_setpoint_update = Some(chrono::Utc::now());
-                                    debug!("Updated grid setpoint reading: {:.0}W", value);
+                                if fast_telemetry.handle(&publish.topic, payload_str, Utc::now()) {
+                                    continue;
+                                }
+                                // Answer price queries right away; publishing must not
+                                // wait on the event loop it is called from
+                                if publish.topic == rpc_request_topic {
+                                    let prices = prices.lock().unwrap().clone();
+                                    let response = rpc::respond(payload_str, prices.as_deref(), Utc::now());
+                                    let payload = serde_json::to_string(&response).unwrap_or_default();
+                                    let published =
+                                        rpc_client.try_publish(&rpc_response_topic, QoS::AtLeastOnce, false, payload);
+                                    if let Err(e) = published {
+                                        warn!("Failed to publish RPC response: {}", e);
+                                    }
+                                    continue;
+                                }
+                                let command = {
+                                    let mut state = battery_state.write().await;
+                                    handler.handle(&mut state, &publish.topic, payload_str, Utc::now())
+                                };
+                                if let Some(command) = command {
+                                    commands.lock().await.push(command);
                                 }
                             }
                         }
-                    }
-                    Ok(Event::Incoming(Packet::ConnAck(_))) => {
-                        info!("Connected to MQTT broker");
-                    }
-                    Ok(Event::Incoming(Packet::SubAck(_))) => {
-                        debug!("Subscription acknowledged");
-                    }
-                    Ok(_) => {}
-                    Err(e) => {
-                        error!("MQTT connection error: {:?}", e);
-                        tokio::time::sleep(Duration::from_secs(5)).await;
+                        Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                            info!("Connected to MQTT broker");
+                            *disconnected_since.lock().unwrap() = None;
+                        }
+                        Ok(Event::Incoming(Packet::SubAck(_))) => {
+                            debug!("Subscription acknowledged");
+                        }
+                        Ok(_) => {}
+                        Err(e) => {
+                            let e = Error::from(e);
+                            if maintenance::in_maintenance(&maintenance_windows, Local::now().fixed_offset()) {
+                                debug!("{} during broker maintenance", e);
+                            } else {
+                                error!("{}", e);
+                            }
+                            disconnected_since.lock().unwrap().get_or_insert_with(Utc::now);
+                            // Refused credentials won't be accepted on the next attempt either
+                            let retry = if matches!(e, Error::Auth(_)) { AUTH_RETRY } else { RECONNECT_RETRY };
+                            tokio::time::sleep(retry).await;
+                        }
                     }
                 }
             }
@@ -89,76 +559,187 @@ impl MqttClient {
         // Small delay to let connection establish
         tokio::time::sleep(Duration::from_millis(500)).await;
 
-        // Subscribe to SoC topic
-        client
-            .subscribe(&config.soc_topic, QoS::AtLeastOnce)
-            .await?;
-        info!("Subscribed to SoC topic: {}", config.soc_topic);
+        // Subscribe to SoC topic(s); the price publisher has none
+        for source in soc_sources.iter().filter(|s| !s.topic.is_empty()) {
+            client
+                .subscribe(&source.topic, QoS::AtLeastOnce)
+                .await?;
+            info!("Subscribed to SoC topic: {}", source.topic);
+        }
 
         // Subscribe to setpoint read topic
+        if !config.grid_setpoint_read_topic.is_empty() {
+            client
+                .subscribe(&config.grid_setpoint_read_topic, QoS::AtLeastOnce)
+                .await?;
+            info!("Subscribed to setpoint read topic: {}", config.grid_setpoint_read_topic);
+        }
+
+        // Subscribe to command topic
         client
-            .subscribe(&config.grid_setpoint_read_topic, QoS::AtLeastOnce)
+            .subscribe(&config.command_topic, QoS::AtLeastOnce)
             .await?;
-        info!("Subscribed to setpoint read topic: {}", config.grid_setpoint_read_topic);
+        info!("Subscribed to command topic: {}", config.command_topic);
+
+        // Subscribe to price queries
+        client.subscribe(&rpc_request_topic, QoS::AtLeastOnce).await?;
+        info!("Subscribed to RPC request topic: {}", rpc_request_topic);
+
+        // Subscribe to optional telemetry topics
+        let optional_topics = [
+            ("inverter state", &config.inverter_state_topic),
+            ("grid-lost", &config.grid_lost_topic),
+            ("grid power", &config.grid_power_topic),
+            ("battery power", &config.battery_power_topic),
+            ("house load", &config.house_load_topic),
+            ("import meter", &config.meter_import_topic),
+            ("export meter", &config.meter_export_topic),
+            ("GX minimum SoC", &config.min_soc_limit_topic),
+            ("GX active SoC limit", &config.active_soc_limit_topic),
+            ("EV connected", &config.ev_connected_topic),
+            ("EV power", &config.ev_power_topic),
+            ("EV energy", &config.ev_energy_topic),
+            ("grid limit", &config.grid_limit_topic),
+            ("occupancy", &config.occupancy_topic),
+            ("battery temperature", &config.battery_temperature_topic),
+        ];
+        for (name, topic) in optional_topics {
+            if let Some(topic) = topic {
+                if fast_telemetry.covers(topic) {
+                    client.subscribe(topic, QoS::AtMostOnce).await?;
+                    info!("Subscribed to {} topic: {} (QoS 0, fast path)", name, topic);
+                } else {
+                    client.subscribe(topic, QoS::AtLeastOnce).await?;
+                    info!("Subscribed to {} topic: {}", name, topic);
+                }
+            }
+        }
+        if let Some(prefix) = &config.shelly_3em_prefix {
+            for topic in crate::shelly::status_topics(prefix) {
+                client.subscribe(&topic, QoS::AtMostOnce).await?;
+                info!("Subscribed to Shelly Pro 3EM topic: {}", topic);
+            }
+        }
+        if let Some(prefix) = &config.charge_schedule_topic {
+            let topic = format!("{}/#", prefix);
+            client.subscribe(&topic, QoS::AtLeastOnce).await?;
+            info!("Subscribed to charge schedule topics: {}", topic);
+        }
 
         Ok(Self {
-            client,
+            broker: Broker {
+                client,
+                disconnected_since,
+                maintenance_windows,
+                held_back: Arc::new(AtomicBool::new(false)),
+            },
             config,
             battery_state,
+            commands,
+            fast_telemetry,
+            prices,
         })
     }
 
+    /// When the broker connection went down, if it is down
+    pub fn disconnected_since(&self) -> Option<DateTime<Utc>> {
+        *self.broker.disconnected_since.lock().unwrap()
+    }
+
+    /// Whether publishes were held back during broker maintenance and the
+    /// broker is reachable again; true once per maintenance
+    pub fn back_from_maintenance(&self) -> bool {
+        self.disconnected_since().is_none() && self.broker.held_back.swap(false, Ordering::Relaxed)
+    }
+
     pub async fn get_battery_state(&self) -> BatteryState {
-        self.battery_state.read().await.clone()
+        let state = self.battery_state.read().await.clone();
+        self.fast_telemetry.apply(state)
+    }
+
+    /// Drain the commands received since the last call
+    pub async fn take_commands(&self) -> Vec<Command> {
+        std::mem::take(&mut *self.commands.lock().await)
+    }
+
+    /// Answer RPC price queries from `prices` from now on
+    pub fn set_prices(&self, prices: Arc<PriceCache>) {
+        *self.prices.lock().unwrap() = Some(prices);
+    }
+
+    /// Writes the grid setpoint to the `topic` template, in the `payload` template
+    pub fn setpoint_writer(&self, topic: &str, payload: &str, retain: bool) -> MqttSetpointWriter {
+        MqttSetpointWriter {
+            broker: self.broker.clone(),
+            topic: topic.to_string(),
+            payload: payload.to_string(),
+            retain,
+        }
     }
 
-    pub async fn publish_grid_setpoint(&self, setpoint_w: f64) -> Result<()> {
+    /// Publish a Victron-style `{"value": x}` payload to an arbitrary topic
+    pub async fn publish_value(&self, topic: &str, value: f64) -> Result<()> {
         let payload = serde_json::json!({
-            "value": setpoint_w
+            "value": value
         });
 
-        self.client
-            .publish(
-                &self.config.grid_setpoint_write_topic,
-                QoS::AtLeastOnce,
-                false,
-                payload.to_string(),
-            )
+        self.broker
+            .publish(topic, QoS::AtLeastOnce, false, payload.to_string())
             .await?;
 
-        debug!("Published grid setpoint: {} W to {}", setpoint_w, self.config.grid_setpoint_write_topic);
+        debug!("Published {} to {}", value, topic);
         Ok(())
     }
 
-    pub async fn publish_price_info(&self, price: &crate::tibber::PricePoint) -> Result<()> {
-        let payload = serde_json::json!({
-            "total": price.total,
-            "energy": price.energy,
-            "tax": price.tax,
-            "starts_at": price.starts_at.to_rfc3339(),
-            "currency": "EUR"
-        });
+    /// Publish a plain payload, e.g. to switch a load
+    pub async fn publish_payload(&self, topic: &str, payload: &str) -> Result<()> {
+        self.broker
+            .publish(topic, QoS::AtLeastOnce, false, payload)
+            .await?;
+
+        debug!("Published '{}' to {}", payload, topic);
+        Ok(())
+    }
+
+    pub async fn publish_price_info(&self, price: &crate::prices::PricePoint) -> Result<()> {
+        let payload = Versioned::new(PricePayload::from(price));
 
-        self.client
+        self.broker
             .publish(
                 &self.config.price_topic,
                 QoS::AtLeastOnce,
                 true, // Retain so new subscribers get last price
-                payload.to_string(),
+                serde_json::to_string(&payload)?,
             )
             .await?;
 
-        debug!("Published current price: {} EUR/kWh", price.total);
+        debug!("Published current price: {} {}/kWh", price.total, price.currency.as_deref().unwrap_or("EUR"));
+        Ok(())
+    }
+
+    /// Base topic for derived topics (status, alerts)
+    fn base_topic(&self) -> &str {
+        base_topic(&self.config)
+    }
+
+    /// Publish the cached prices with their stats and tiers (price publisher mode)
+    pub async fn publish_price_summary(&self, summary: &crate::schema::PriceSummaryPayload) -> Result<()> {
+        let topic = format!("{}/prices", self.base_topic());
+        self.broker
+            .publish(&topic, QoS::AtLeastOnce, true, serde_json::to_string(&Versioned::new(summary))?)
+            .await?;
+
+        debug!("Published {} prices to {}", summary.prices.len(), topic);
         Ok(())
     }
 
     /// Publish extended price and optimization info
     pub async fn publish_status(&self, status: &OptimizerStatus) -> Result<()> {
-        let topic = format!("{}/status", self.config.price_topic.trim_end_matches("/current"));
+        let topic = format!("{}/status", self.base_topic());
 
-        let payload = serde_json::to_string(status)?;
+        let payload = serde_json::to_string(&Versioned::new(status))?;
 
-        self.client
+        self.broker
             .publish(
                 &topic,
                 QoS::AtLeastOnce,
@@ -169,10 +750,179 @@ impl MqttClient {
 
         Ok(())
     }
+
+    /// Publish the cheap surplus signal for external heating controllers
+    pub async fn publish_surplus(&self, surplus: &crate::surplus::SurplusForecast) -> Result<()> {
+        let topic = format!("{}/surplus", self.base_topic());
+
+        self.broker
+            .publish(&topic, QoS::AtLeastOnce, true, serde_json::to_string(surplus)?)
+            .await?;
+
+        debug!("Published surplus: {:.0} W available now", surplus.current_surplus_w);
+        Ok(())
+    }
+
+    /// Publish when to run each configured appliance (retained)
+    pub async fn publish_appliances(&self, recommendations: &[crate::appliances::ApplianceRecommendation]) -> Result<()> {
+        let topic = format!("{}/appliances", self.base_topic());
+
+        self.broker
+            .publish(&topic, QoS::AtLeastOnce, true, serde_json::to_string(recommendations)?)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Publish a change of the degradation level (retained) and raise or clear its alert
+    pub async fn publish_degradation(&self, status: &crate::degradation::DegradationStatus) -> Result<()> {
+        let topic = format!("{}/degradation", self.base_topic());
+
+        self.broker
+            .publish(&topic, QoS::AtLeastOnce, true, serde_json::to_string(status)?)
+            .await?;
+
+        let message = match &status.reason {
+            Some(reason) => format!("Degraded operation ({}): {}", reason, status.behavior),
+            None => format!("Full operation restored: {}", status.behavior),
+        };
+        self.publish_alert("degraded", &message, status.reason.is_some()).await
+    }
+
+    /// Publish the learned house load profile (retained)
+    pub async fn publish_load_profile(&self, profile: &crate::load_profile::LoadProfile) -> Result<()> {
+        let topic = format!("{}/load_profile", self.base_topic());
+
+        self.broker
+            .publish(&topic, QoS::AtLeastOnce, true, serde_json::to_string(profile)?)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Publish the projected plan, one entry per slot (retained)
+    pub async fn publish_plan(&self, plan: &[crate::optimizer::PlannedSlot]) -> Result<()> {
+        let topic = format!("{}/plan", self.base_topic());
+        let payload = Versioned::new(PlanPayload::from(plan));
+
+        self.broker
+            .publish(&topic, QoS::AtLeastOnce, true, serde_json::to_string(&payload)?)
+            .await?;
+
+        debug!("Published plan with {} slots", plan.len());
+        Ok(())
+    }
+
+    /// Publish the JSON schemas of the versioned payloads (retained)
+    pub async fn publish_schemas(&self) -> Result<()> {
+        let topic = format!("{}/schema", self.base_topic());
+
+        self.broker
+            .publish(&topic, QoS::AtLeastOnce, true, crate::schema::schemas().to_string())
+            .await?;
+
+        Ok(())
+    }
+
+    /// Publish the Home Assistant discovery configs (retained)
+    pub async fn publish_discovery(&self, config: &crate::config::MqttDiscoveryConfig) -> Result<()> {
+        let messages = crate::discovery::messages(config, self.base_topic(), &self.config.command_topic);
+        for (topic, payload) in &messages {
+            self.broker
+                .publish(topic, QoS::AtLeastOnce, true, payload.to_string())
+                .await?;
+        }
+
+        info!("Published {} Home Assistant discovery configs", messages.len());
+        Ok(())
+    }
+
+    /// Publish the warranty counters of a completed year (retained per year)
+    pub async fn publish_warranty_report(&self, year: i32, report: &WarrantyJson) -> Result<()> {
+        let topic = format!("{}/warranty/{}", self.base_topic(), year);
+
+        self.broker
+            .publish(&topic, QoS::AtLeastOnce, true, serde_json::to_string(&Versioned::new(report))?)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Publish the result of a completed capacity test
+    pub async fn publish_capacity_test(&self, report: &crate::capacity_test::CapacityTestReport) -> Result<()> {
+        let topic = format!("{}/capacity_test", self.base_topic());
+
+        self.broker
+            .publish(&topic, QoS::AtLeastOnce, true, serde_json::to_string(&Versioned::new(report))?)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Publish the weekly comparison of configured against measured parameters
+    pub async fn publish_drift_report(&self, report: &crate::drift::DriftReport) -> Result<()> {
+        let topic = format!("{}/drift", self.base_topic());
+
+        self.broker
+            .publish(&topic, QoS::AtLeastOnce, true, serde_json::to_string(&Versioned::new(report))?)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Publish timing of the last control cycle
+    pub async fn publish_metrics(&self, metrics: &crate::metrics::CycleMetrics) -> Result<()> {
+        let topic = format!("{}/metrics", self.base_topic());
+
+        self.broker
+            .publish(&topic, QoS::AtLeastOnce, true, serde_json::to_string(metrics)?)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Publish an alert raised (or cleared) by the optimizer
+    pub async fn publish_alert(&self, kind: &str, message: &str, active: bool) -> Result<()> {
+        let topic = format!("{}/alert", self.base_topic());
+
+        let alert = Alert {
+            kind: kind.to_string(),
+            message: message.to_string(),
+            active,
+            timestamp: chrono::Utc::now().to_rfc3339(),
+        };
+
+        self.broker
+            .publish(&topic, QoS::AtLeastOnce, false, serde_json::to_string(&alert)?)
+            .await?;
+
+        if active {
+            warn!("Alert [{}]: {}", kind, message);
+        } else {
+            info!("Alert cleared [{}]: {}", kind, message);
+        }
+        Ok(())
+    }
+}
+
+/// Base topic for derived topics (status, alerts, RPC), taken from the price topic
+fn base_topic(config: &MqttConfig) -> &str {
+    config.price_topic.trim_end_matches("/current")
+}
+
+/// Capacity-weighted SoC over all sources; None until every source has reported
+fn aggregate_soc(sources: &[SocSource], pack_soc: &[Option<f64>]) -> Option<f64> {
+    let mut weighted = 0.0;
+    let mut total_capacity = 0.0;
+    for (source, soc) in sources.iter().zip(pack_soc) {
+        weighted += (*soc)? * source.capacity_kwh;
+        total_capacity += source.capacity_kwh;
+    }
+    (total_capacity > 0.0).then(|| weighted / total_capacity)
 }
 
 /// Parse a simple value from MQTT payload - handles raw numbers and JSON {"value": x}
-fn parse_mqtt_value(payload: &str) -> Option<f64> {
+pub fn parse_mqtt_value(payload: &str) -> Option<f64> {
     // Try parsing as plain number first
     if let Ok(value) = payload.trim().parse::<f64>() {
         return Some(value);
@@ -189,8 +939,18 @@ fn parse_mqtt_value(payload: &str) -> Option<f64> {
     None
 }
 
+/// Parse an on/off state, e.g. a Home Assistant binary sensor (`on`/`off`),
+/// a device tracker (`home`/`not_home`) or a number (non-zero = on)
+pub fn parse_mqtt_bool(payload: &str) -> Option<bool> {
+    match payload.trim().to_ascii_lowercase().as_str() {
+        "on" | "true" | "home" => Some(true),
+        "off" | "false" | "not_home" | "away" => Some(false),
+        _ => parse_mqtt_value(payload).map(|value| value != 0.0),
+    }
+}
+
 /// Parse SoC from Victron battery JSON: {"value": [{"soc": 75.5, ...}]}
-fn parse_victron_soc(payload: &str) -> Option<f64> {
+pub fn parse_victron_soc(payload: &str) -> Option<f64> {
     // Try the Victron format first: {"value": [{"soc": x}]}
     if let Ok(json) = serde_json::from_str::<serde_json::Value>(payload) {
         if let Some(value_array) = json.get("value").and_then(|v| v.as_array()) {
@@ -215,14 +975,106 @@ fn parse_victron_soc(payload: &str) -> Option<f64> {
     None
 }
 
-#[derive(Debug, Clone, serde::Serialize)]
+#[derive(Debug, Clone, serde::Serialize, schemars::JsonSchema)]
 pub struct OptimizerStatus {
     pub current_price: f64,
+    /// Price paid for exported energy in the current slot
+    pub current_sell_price: f64,
+    /// Latest realtime price, if a realtime feed is configured
+    pub realtime_price: Option<f64>,
     pub current_mode: String,
     pub grid_setpoint_w: f64,
+    /// Why the mode was chosen over the runner-up
+    pub explanation: ExplanationJson,
     pub actual_setpoint_w: Option<f64>,
     pub battery_soc: f64,
+    /// Measured grid power, if a grid power topic or Tibber Pulse is configured
+    pub grid_power_w: Option<f64>,
+    /// Grid import since midnight from the Tibber Pulse (kWh), if streaming
+    pub pulse_consumption_kwh: Option<f64>,
+    /// PV production (W), if a Home Assistant PV entity is configured
+    pub pv_power_w: Option<f64>,
+    /// Inverter state, if an inverter state topic is configured
+    pub inverter_state: Option<String>,
+    /// Background tasks that failed recently and were restarted
+    pub failed_tasks: Vec<String>,
+    /// Progress of a running capacity test
+    pub capacity_test: Option<String>,
+    /// Why the optimizer is not in full control, if it isn't
+    pub degraded: Option<String>,
+    /// Current level of the degradation ladder
+    pub degradation: crate::degradation::Degradation,
+    /// Active battery hold window, if any
+    pub hold: Option<String>,
+    /// Current and upcoming announced consumption events
+    pub consumption_events: Vec<String>,
+    /// Configured and commanded recurring rules
+    pub schedule_rules: Vec<String>,
+    /// Commanded SoC floors in effect
+    pub soc_floors: Vec<String>,
+    /// Active charge window scheduled in the GX UI, if any
+    pub victron_schedule: Option<String>,
+    /// SoC the GX won't discharge below, if its limit topics are configured
+    pub gx_soc_limit: Option<f64>,
+    /// End of the pause after a manual setpoint change, if paused
+    pub manual_override_until: Option<String>,
+    /// Runtime overrides in effect (force_charge, pause, max_soc)
+    pub overrides: Vec<String>,
+    /// How the battery diverged from the plan at the last hourly check, if it did
+    pub plan_divergence: Option<String>,
+    /// Correction of the expected house load from the SoC feedback, once learned
+    pub load_correction: Option<crate::soc_feedback::LoadCorrection>,
+    /// Optimizer preset active now, if any
+    pub preset: Option<String>,
+    /// Strategy the optimizer plans with under the active preset
+    pub planner: crate::config::Planner,
+    /// Whether the contract bills a fixed price this month (self-consumption only)
+    pub fixed_contract: bool,
+    /// Full cycle equivalents since local midnight
+    pub cycles_today: f64,
+    /// Full cycle equivalents over the battery's lifetime
+    pub cycles_total: f64,
+    /// Whether PV feed-in is currently curtailed
+    pub curtailing: bool,
+    /// Whether the AC input current limit is raised for full-power charging
+    pub ac_input_limit_raised: bool,
+    /// Whether the control loop sleeps through flat prices
+    pub economy_sleep: bool,
+    /// Whether setpoints are computed without being written
+    pub dry_run: bool,
+    /// Loads currently shed to protect the reserve
+    pub shed_loads: Vec<String>,
+    /// Charge current set on the EV charger (A), if one is connected
+    pub ev_current_a: Option<f64>,
+    /// Why the EV charges or waits, if a charger is connected
+    pub ev_charging: Option<String>,
+    /// Whether the water heater relay is switched on, if one is configured
+    pub water_heater_on: Option<bool>,
+    /// Why the water heater is on or off
+    pub water_heater: Option<String>,
+    /// Import limit set by the grid operator while one is active (W)
+    pub grid_limit_w: Option<f64>,
+    /// The month's import peak and the cap held to, if peak shaving is enabled
+    pub peak_shaving: Option<crate::peak::PeakStatus>,
+    /// Whether anybody is home, if an occupancy topic is configured
+    pub occupied: Option<bool>,
+    /// Battery temperature (°C), if a battery temperature topic is configured
+    pub battery_temperature_c: Option<f64>,
+    /// Lifetime battery wear counters
+    pub warranty: WarrantyJson,
+    /// Metered minus accounted grid energy today (kWh), if a meter is configured
+    pub meter_drift_kwh: Option<f64>,
+    /// Round-trip efficiency used for planning (measured when available)
+    pub round_trip_efficiency: f64,
+    /// Grid import the ESS was observed to deliver while charging (W), if below the configured maximum
+    pub charge_limit_w: Option<f64>,
+    /// Grid export the ESS was observed to deliver while discharging (W), if below the configured maximum
+    pub discharge_limit_w: Option<f64>,
+    /// Price spread over the cheapest charge price currently required for grid discharge
+    pub required_discharge_spread: f64,
     pub price_stats: Option<PriceStatsJson>,
+    /// Cached price slots interpolated because the provider left them out
+    pub estimated_price_slots: usize,
     pub next_cheap_slot: Option<String>,
     pub next_expensive_slot: Option<String>,
     pub cheap_slots_remaining: usize,
@@ -230,6 +1082,34 @@ pub struct OptimizerStatus {
 }
 
 #[derive(Debug, Clone, serde::Serialize)]
+pub struct Alert {
+    pub kind: String,
+    pub message: String,
+    /// true when raised, false when cleared
+    pub active: bool,
+    pub timestamp: String,
+}
+
+#[derive(Debug, Clone, serde::Serialize, schemars::JsonSchema)]
+pub struct ExplanationJson {
+    pub reason: String,
+    /// Mode that came closest to being chosen instead, if another was considered
+    pub alternative_mode: Option<String>,
+    /// Why it lost, e.g. "spread 0.0300 < required 0.0700 (losses, fees and wear)"
+    pub alternative_rejected: Option<String>,
+}
+
+impl From<&crate::optimizer::OptimizationResult> for ExplanationJson {
+    fn from(result: &crate::optimizer::OptimizationResult) -> Self {
+        Self {
+            reason: result.reason.clone(),
+            alternative_mode: result.alternative.as_ref().map(|a| a.mode.to_string()),
+            alternative_rejected: result.alternative.as_ref().map(|a| a.reason.clone()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, serde::Serialize, schemars::JsonSchema)]
 pub struct PriceStatsJson {
     pub min: f64,
     pub max: f64,
@@ -238,3 +1118,39 @@ pub struct PriceStatsJson {
     pub p75: f64,
     pub p90: f64,
 }
+
+impl From<crate::prices::PriceStats> for PriceStatsJson {
+    fn from(s: crate::prices::PriceStats) -> Self {
+        Self {
+            min: s.min,
+            max: s.max,
+            avg: s.avg,
+            p25: s.p25,
+            p75: s.p75,
+            p90: s.p90,
+        }
+    }
+}
+
+#[derive(Debug, Clone, serde::Serialize, schemars::JsonSchema)]
+pub struct WarrantyJson {
+    pub cycles: f64,
+    pub throughput_kwh: f64,
+    pub charged_kwh: f64,
+    pub discharged_kwh: f64,
+    pub hours_above_90: f64,
+    pub hours_below_10: f64,
+}
+
+impl WarrantyJson {
+    pub fn new(counters: &crate::warranty::WarrantyCounters, capacity_kwh: f64) -> Self {
+        Self {
+            cycles: counters.cycles(capacity_kwh),
+            throughput_kwh: counters.throughput_kwh(),
+            charged_kwh: counters.charged_kwh,
+            discharged_kwh: counters.discharged_kwh,
+            hours_above_90: counters.hours_above_90,
+            hours_below_10: counters.hours_below_10,
+        }
+    }
+}
@@ -0,0 +1,75 @@
+//! Occupancy from a presence sensor, e.g. a Home Assistant binary sensor
+//! published over MQTT. While nobody is home the house draws less, so the
+//! consumption forecast is scaled down for the expected time away and the
+//! reserve carries a smaller buffer: on work-from-office days the battery isn't
+//! filled through cheap midday prices for a house nobody uses.
+
+use chrono::{DateTime, Duration, FixedOffset, Utc};
+
+use crate::config::OccupancyConfig;
+use crate::mqtt::BatteryState;
+
+/// Normal buffer on top of the expected consumption (% of capacity)
+pub const HOME_BUFFER_PERCENT: f64 = 20.0;
+
+/// How the planners treat the time nobody is expected home
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Away {
+    /// When somebody is expected back
+    pub until: DateTime<Utc>,
+    /// Factor on the expected house load until then
+    pub load_factor: f64,
+    /// Buffer on top of the expected consumption (% of capacity)
+    pub buffer_percent: f64,
+}
+
+impl Away {
+    /// While nobody is home: from the time everybody left for `away_hours`,
+    /// and slot by slot for as long as the house stays empty after that
+    pub fn from_state(config: &OccupancyConfig, state: &BatteryState, now: DateTime<Utc>) -> Option<Self> {
+        let since = state.away_since.filter(|_| state.occupied == Some(false))?;
+        let expected_back = since + Duration::seconds((config.away_hours * 3600.0) as i64);
+        Some(Self {
+            until: expected_back.max(now + Duration::minutes(15)),
+            load_factor: config.away_load_factor,
+            buffer_percent: config.away_buffer_percent,
+        })
+    }
+
+    /// Factor on the expected house load at `at`
+    pub fn load_factor_at(&self, at: DateTime<FixedOffset>) -> f64 {
+        if at < self.until {
+            self.load_factor
+        } else {
+            1.0
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scales_the_load_until_somebody_is_expected_back() {
+        let config = OccupancyConfig::default();
+        let left = DateTime::parse_from_rfc3339("2025-12-01T08:00:00+01:00").unwrap();
+        let state = BatteryState {
+            occupied: Some(false),
+            away_since: Some(left.with_timezone(&Utc)),
+            ..BatteryState::default()
+        };
+
+        let away = Away::from_state(&config, &state, left.with_timezone(&Utc)).unwrap();
+        assert_eq!(away.load_factor_at(left + Duration::hours(4)), 0.5);
+        assert_eq!(away.load_factor_at(left + Duration::hours(10)), 1.0);
+
+        // Still away after the expected time: the current slot stays scaled
+        let late = left + Duration::hours(12);
+        let away = Away::from_state(&config, &state, late.with_timezone(&Utc)).unwrap();
+        assert_eq!(away.load_factor_at(late), 0.5);
+
+        let home = BatteryState { occupied: Some(true), ..state };
+        assert_eq!(Away::from_state(&config, &home, late.with_timezone(&Utc)), None);
+    }
+}
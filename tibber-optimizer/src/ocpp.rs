@@ -0,0 +1,487 @@
+//! A minimal OCPP 1.6J central system for a single charge point. The charger
+//! is pointed at `ws://<optimizer>:<port>/<charge point id>` (in place of a
+//! cloud backend: a charge point talks to one central system), optionally
+//! with HTTP basic auth; every session is authorized, and the charge current
+//! is set with a default charging profile. Meter values give the session
+//! energy and the power drawn.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use chrono::{SecondsFormat, Utc};
+use futures_util::{SinkExt, StreamExt};
+use serde_json::{json, Value};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio_tungstenite::tungstenite::handshake::server::{ErrorResponse, Request, Response};
+use tokio_tungstenite::tungstenite::http::{HeaderValue, StatusCode};
+use tokio_tungstenite::tungstenite::Message;
+use tracing::{debug, info, warn};
+
+use crate::config::OcppConfig;
+use crate::controller::WriteFuture;
+use crate::ev::{ChargerStatus, EvCharger, StatusFuture};
+use crate::http::basic_auth;
+use crate::supervisor::Supervisor;
+
+const SUBPROTOCOL: &str = "ocpp1.6";
+/// Heartbeat interval asked of the charge point
+const HEARTBEAT_SECS: u64 = 60;
+/// A charge point silent for this long is considered gone
+const IDLE_TIMEOUT: Duration = Duration::from_secs(5 * HEARTBEAT_SECS);
+/// Connector states in which a car is plugged in
+const PLUGGED_IN: [&str; 5] = ["Preparing", "Charging", "SuspendedEV", "SuspendedEVSE", "Finishing"];
+
+/// What the central system knows about the charge point
+#[derive(Debug, Default)]
+struct ChargePoint {
+    /// Identity of the connected charge point
+    id: Option<String>,
+    /// Messages to send to it; `None` while it isn't connected
+    outbox: Option<mpsc::UnboundedSender<String>>,
+    /// Last reported status of the connector, e.g. `Charging`
+    connector_status: Option<String>,
+    transaction_id: Option<i64>,
+    next_transaction_id: i64,
+    /// Energy register at the start of the session and now (Wh)
+    meter_start_wh: Option<f64>,
+    meter_wh: Option<f64>,
+    power_w: Option<f64>,
+    current_a: Option<f64>,
+    /// Limit last set, set again when the charge point reconnects
+    limit_a: Option<f64>,
+    next_message_id: u64,
+    /// Actions of our calls awaiting a result, by message id
+    pending: HashMap<String, &'static str>,
+}
+
+impl ChargePoint {
+    /// Queue a call to the charge point
+    fn call(&mut self, action: &'static str, payload: Value) -> Result<()> {
+        let outbox = self.outbox.as_ref().context("no charge point connected")?;
+        self.next_message_id += 1;
+        let id = self.next_message_id.to_string();
+        outbox
+            .send(json!([2, id, action, payload]).to_string())
+            .context("charge point disconnected")?;
+        self.pending.insert(id, action);
+        Ok(())
+    }
+
+    /// Set the charge current for every session, and for the running one in
+    /// case the charger only applies the default at a session's start
+    fn set_limit(&mut self, connector_id: u32, current_a: f64) -> Result<()> {
+        let profile = |id: u32, purpose: &str| {
+            json!({
+                "chargingProfileId": id,
+                "stackLevel": 0,
+                "chargingProfilePurpose": purpose,
+                "chargingProfileKind": "Relative",
+                "chargingSchedule": {
+                    "chargingRateUnit": "A",
+                    "chargingSchedulePeriod": [{"startPeriod": 0, "limit": current_a}],
+                },
+            })
+        };
+        self.call(
+            "SetChargingProfile",
+            json!({"connectorId": connector_id, "csChargingProfiles": profile(1, "TxDefaultProfile")}),
+        )?;
+        if let Some(transaction_id) = self.transaction_id {
+            let mut tx_profile = profile(2, "TxProfile");
+            tx_profile["transactionId"] = transaction_id.into();
+            self.call(
+                "SetChargingProfile",
+                json!({"connectorId": connector_id, "csChargingProfiles": tx_profile}),
+            )?;
+        }
+        self.limit_a = Some(current_a);
+        Ok(())
+    }
+
+    /// Answer a call from the charge point
+    fn handle_call(&mut self, connector_id: u32, action: &str, payload: &Value) -> Option<Value> {
+        let now = Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true);
+        let accepted = json!({"idTagInfo": {"status": "Accepted"}});
+        let ours = payload["connectorId"].as_u64() == Some(connector_id as u64);
+        Some(match action {
+            "BootNotification" => {
+                info!(
+                    "OCPP charge point {} booted ({} {})",
+                    self.id.as_deref().unwrap_or_default(),
+                    payload["chargePointVendor"].as_str().unwrap_or("unknown"),
+                    payload["chargePointModel"].as_str().unwrap_or("unknown")
+                );
+                json!({"status": "Accepted", "currentTime": now, "interval": HEARTBEAT_SECS})
+            }
+            "Heartbeat" => json!({"currentTime": now}),
+            "Authorize" => accepted,
+            "StatusNotification" => {
+                if ours {
+                    let status = payload["status"].as_str().unwrap_or_default().to_string();
+                    debug!("OCPP connector {} is {}", connector_id, status);
+                    if status == "Available" {
+                        self.meter_start_wh = None;
+                        self.power_w = None;
+                        self.current_a = None;
+                    }
+                    self.connector_status = Some(status);
+                }
+                json!({})
+            }
+            "StartTransaction" => {
+                self.next_transaction_id += 1;
+                if ours {
+                    self.transaction_id = Some(self.next_transaction_id);
+                    self.meter_start_wh = payload["meterStart"].as_f64();
+                    self.meter_wh = self.meter_start_wh;
+                }
+                json!({"transactionId": self.next_transaction_id, "idTagInfo": {"status": "Accepted"}})
+            }
+            "StopTransaction" => {
+                if payload["transactionId"].as_i64() == self.transaction_id {
+                    self.transaction_id = None;
+                    self.meter_wh = payload["meterStop"].as_f64().or(self.meter_wh);
+                    self.power_w = None;
+                    self.current_a = None;
+                }
+                accepted
+            }
+            "MeterValues" => {
+                if ours {
+                    self.meter_values(payload);
+                }
+                json!({})
+            }
+            "DataTransfer" => json!({"status": "UnknownVendorId"}),
+            "DiagnosticsStatusNotification" | "FirmwareStatusNotification" => json!({}),
+            _ => return None,
+        })
+    }
+
+    /// Take in the latest energy register, power and phase currents
+    fn meter_values(&mut self, payload: &Value) {
+        let Some(sample) = payload["meterValue"].as_array().and_then(|values| values.last()) else {
+            return;
+        };
+        let mut phase_power_w = 0.0;
+        let mut power_w = None;
+        let mut current_a: Option<f64> = None;
+        for value in sample["sampledValue"].as_array().into_iter().flatten() {
+            let Some(reading) = value["value"].as_str().and_then(|v| v.parse::<f64>().ok()) else {
+                continue;
+            };
+            let kilo = value["unit"].as_str().is_some_and(|unit| unit.starts_with('k'));
+            let reading = if kilo { reading * 1000.0 } else { reading };
+            let per_phase = value["phase"].is_string();
+            // The energy register is the default measurand
+            match value["measurand"].as_str().unwrap_or("Energy.Active.Import.Register") {
+                "Energy.Active.Import.Register" if !per_phase => self.meter_wh = Some(reading),
+                "Power.Active.Import" if per_phase => phase_power_w += reading,
+                "Power.Active.Import" => power_w = Some(reading),
+                "Current.Import" => current_a = Some(current_a.map_or(reading, |a| a.max(reading))),
+                _ => {}
+            }
+        }
+        self.power_w = power_w.or((phase_power_w > 0.0).then_some(phase_power_w)).or(self.power_w);
+        self.current_a = current_a.or(self.current_a);
+    }
+
+    fn status(&self) -> ChargerStatus {
+        let connected = self.transaction_id.is_some()
+            || self
+                .connector_status
+                .as_deref()
+                .is_some_and(|status| PLUGGED_IN.contains(&status));
+        let session_kwh = match (self.meter_start_wh, self.meter_wh) {
+            (Some(start), Some(now)) => (now - start).max(0.0) / 1000.0,
+            _ => 0.0,
+        };
+        ChargerStatus {
+            connected,
+            session_kwh,
+            power_w: self.power_w,
+            current_a: self.current_a,
+        }
+    }
+}
+
+/// A charge point connected to the optimizer's OCPP central system
+pub struct OcppCharger {
+    config: OcppConfig,
+    state: Arc<Mutex<ChargePoint>>,
+}
+
+impl OcppCharger {
+    /// Listen for the charge point in the background
+    pub async fn start(config: OcppConfig, supervisor: &Supervisor) -> Result<Self> {
+        let listener = TcpListener::bind(&config.bind)
+            .await
+            .with_context(|| format!("Failed to bind OCPP central system to {}", config.bind))?;
+        info!("OCPP central system listening on {}", config.bind);
+
+        let state = Arc::new(Mutex::new(ChargePoint::default()));
+        let listener = Arc::new(listener);
+        let (task_config, task_state) = (config.clone(), state.clone());
+        supervisor.spawn("ocpp", move || {
+            let listener = listener.clone();
+            let config = task_config.clone();
+            let state = task_state.clone();
+            async move {
+                loop {
+                    match listener.accept().await {
+                        Ok((stream, peer)) => {
+                            let config = config.clone();
+                            let state = state.clone();
+                            tokio::spawn(async move {
+                                if let Err(e) = serve(stream, &config, &state).await {
+                                    warn!("OCPP connection from {} failed: {}", peer, e);
+                                }
+                            });
+                        }
+                        Err(e) => debug!("Failed to accept OCPP connection: {}", e),
+                    }
+                }
+            }
+        });
+        Ok(Self { config, state })
+    }
+}
+
+impl EvCharger for OcppCharger {
+    fn name(&self) -> &'static str {
+        "OCPP 1.6J"
+    }
+
+    fn status(&self) -> StatusFuture<'_> {
+        Box::pin(async move {
+            let state = self.state.lock().unwrap();
+            if state.outbox.is_none() {
+                anyhow::bail!("no charge point connected");
+            }
+            Ok(state.status())
+        })
+    }
+
+    fn set_current(&self, current_a: f64) -> WriteFuture<'_> {
+        Box::pin(async move { self.state.lock().unwrap().set_limit(self.config.connector_id, current_a) })
+    }
+}
+
+/// Run one charge point connection until it closes
+// The handshake callback's error type is tungstenite's HTTP response
+#[allow(clippy::result_large_err)]
+async fn serve(stream: TcpStream, config: &OcppConfig, state: &Mutex<ChargePoint>) -> Result<()> {
+    let mut path = String::new();
+    let mut ws = tokio_tungstenite::accept_hdr_async(stream, |request: &Request, mut response: Response| {
+        path = request.uri().path().to_string();
+        let id = path.rsplit('/').next().unwrap_or_default();
+        if !authorized(config, request, id) {
+            let mut error = ErrorResponse::new(Some("Unauthorized".to_string()));
+            *error.status_mut() = StatusCode::UNAUTHORIZED;
+            error
+                .headers_mut()
+                .insert("WWW-Authenticate", HeaderValue::from_static("Basic realm=\"OCPP\""));
+            return Err(error);
+        }
+        let offered = request
+            .headers()
+            .get("Sec-WebSocket-Protocol")
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|protocols| protocols.split(',').any(|p| p.trim() == SUBPROTOCOL));
+        if offered {
+            response
+                .headers_mut()
+                .insert("Sec-WebSocket-Protocol", HeaderValue::from_static(SUBPROTOCOL));
+        }
+        Ok(response)
+    })
+    .await?;
+
+    let id = path.rsplit('/').next().unwrap_or_default().to_string();
+    let (outbox, mut outgoing) = mpsc::unbounded_channel();
+    let accepted = connect(config, state, &id, &outbox);
+    if accepted.is_err() {
+        ws.close(None).await.ok();
+        return accepted;
+    }
+
+    let result = loop {
+        tokio::select! {
+            message = tokio::time::timeout(IDLE_TIMEOUT, ws.next()) => {
+                let text = match message {
+                    Err(_) => break Err(anyhow::anyhow!("silent for {}s", IDLE_TIMEOUT.as_secs())),
+                    Ok(None) | Ok(Some(Ok(Message::Close(_)))) => break Ok(()),
+                    Ok(Some(Err(e))) => break Err(e.into()),
+                    Ok(Some(Ok(Message::Text(text)))) => text,
+                    Ok(Some(Ok(_))) => continue,
+                };
+                let reply = receive(config, state, &text);
+                if let Some(reply) = reply {
+                    if let Err(e) = ws.send(Message::Text(reply)).await {
+                        break Err(e.into());
+                    }
+                }
+            }
+            Some(call) = outgoing.recv() => {
+                if let Err(e) = ws.send(Message::Text(call)).await {
+                    break Err(e.into());
+                }
+            }
+        }
+    };
+
+    let mut state = state.lock().unwrap();
+    if state.outbox.as_ref().is_some_and(|current| current.same_channel(&outbox)) {
+        info!("OCPP charge point {} disconnected", id);
+        state.outbox = None;
+    }
+    result
+}
+
+/// Whether the request carries the configured authorization key for `id`
+fn authorized(config: &OcppConfig, request: &Request, id: &str) -> bool {
+    let Some(password) = &config.password else {
+        return true;
+    };
+    let expected = basic_auth(id, password);
+    request
+        .headers()
+        .get("Authorization")
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value == expected)
+}
+
+/// Take over as the charge point's connection, unless another charge point
+/// is configured or connected
+fn connect(
+    config: &OcppConfig,
+    state: &Mutex<ChargePoint>,
+    id: &str,
+    outbox: &mpsc::UnboundedSender<String>,
+) -> Result<()> {
+    let mut state = state.lock().unwrap();
+    let expected = match &config.charge_point_id {
+        Some(expected) => Some(expected.clone()),
+        None => state.id.clone().filter(|_| state.outbox.is_some()),
+    };
+    if id.is_empty() || expected.is_some_and(|expected| expected != id) {
+        anyhow::bail!("charge point '{}' is not the configured one", id);
+    }
+    info!("OCPP charge point {} connected", id);
+    // A reconnect replaces the previous connection
+    state.id = Some(id.to_string());
+    state.outbox = Some(outbox.clone());
+    state.pending.clear();
+    if let Some(limit_a) = state.limit_a {
+        state.set_limit(config.connector_id, limit_a)?;
+    }
+    Ok(())
+}
+
+/// Handle one message from the charge point; returns the reply to a call
+fn receive(config: &OcppConfig, state: &Mutex<ChargePoint>, text: &str) -> Option<String> {
+    let Ok(Value::Array(message)) = serde_json::from_str::<Value>(text) else {
+        warn!("Ignoring malformed OCPP message: {}", text);
+        return None;
+    };
+    let id = message.get(1).cloned().unwrap_or_default();
+    let mut state = state.lock().unwrap();
+    match message.first().and_then(Value::as_u64) {
+        Some(2) => {
+            let action = message.get(2).and_then(Value::as_str).unwrap_or_default();
+            let payload = message.get(3).cloned().unwrap_or_default();
+            let reply = match state.handle_call(config.connector_id, action, &payload) {
+                Some(result) => json!([3, id, result]),
+                None => json!([4, id, "NotImplemented", format!("{} is not supported", action), {}]),
+            };
+            Some(reply.to_string())
+        }
+        Some(3) => {
+            let action = id.as_str().and_then(|id| state.pending.remove(id)).unwrap_or("call");
+            let status = message.get(2).and_then(|result| result["status"].as_str());
+            if let Some(status) = status.filter(|status| *status != "Accepted") {
+                warn!("Charge point answered {} with {}", action, status);
+            }
+            None
+        }
+        Some(4) => {
+            let action = id.as_str().and_then(|id| state.pending.remove(id)).unwrap_or("call");
+            let description = message.get(3).and_then(|description| description.as_str()).unwrap_or_default();
+            warn!("Charge point failed {}: {}", action, description);
+            None
+        }
+        _ => {
+            warn!("Ignoring unknown OCPP message: {}", text);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reply(state: &Mutex<ChargePoint>, message: &str) -> Value {
+        let text = receive(&OcppConfig::default(), state, message).unwrap();
+        serde_json::from_str(&text).unwrap()
+    }
+
+    #[test]
+    fn follows_a_session_through_its_messages() {
+        let state = Mutex::new(ChargePoint::default());
+        let started = reply(
+            &state,
+            r#"[2,"1","StartTransaction",{"connectorId":1,"idTag":"car","meterStart":1000,"timestamp":"2025-12-01T18:00:00Z"}]"#,
+        );
+        assert_eq!(started[0], 3);
+        assert_eq!(started[2]["transactionId"], 1);
+        assert_eq!(started[2]["idTagInfo"]["status"], "Accepted");
+
+        reply(
+            &state,
+            r#"[2,"2","MeterValues",{"connectorId":1,"transactionId":1,"meterValue":[{"timestamp":"2025-12-01T18:30:00Z","sampledValue":[
+                {"value":"3.5","unit":"kWh"},
+                {"value":"7200","measurand":"Power.Active.Import","unit":"W"},
+                {"value":"10.4","measurand":"Current.Import","phase":"L1","unit":"A"}]}]}]"#,
+        );
+        let status = state.lock().unwrap().status();
+        assert!(status.connected);
+        assert_eq!(status.session_kwh, 2.5);
+        assert_eq!(status.power_w, Some(7200.0));
+        assert_eq!(status.current_a, Some(10.4));
+
+        reply(&state, r#"[2,"3","StopTransaction",{"transactionId":1,"meterStop":4000,"timestamp":"2025-12-01T19:00:00Z"}]"#);
+        let status = state.lock().unwrap().status();
+        assert!(!status.connected);
+        assert_eq!(status.session_kwh, 3.0);
+
+        let unknown = reply(&state, r#"[2,"4","ReserveNow",{}]"#);
+        assert_eq!(unknown[0], 4);
+        assert_eq!(unknown[2], "NotImplemented");
+    }
+
+    #[test]
+    fn checks_the_authorization_key() {
+        let config = OcppConfig {
+            password: Some("secret".to_string()),
+            ..OcppConfig::default()
+        };
+        let request = |auth: Option<&str>| {
+            let builder = Request::builder().uri("/ocpp/garage");
+            match auth {
+                Some(auth) => builder.header("Authorization", auth),
+                None => builder,
+            }
+            .body(())
+            .unwrap()
+        };
+        assert_eq!(basic_auth("garage", "secret"), "Basic Z2FyYWdlOnNlY3JldA==");
+        assert!(authorized(&config, &request(Some("Basic Z2FyYWdlOnNlY3JldA==")), "garage"));
+        assert!(!authorized(&config, &request(Some("Basic Z2FyYWdlOndyb25n")), "garage"));
+        assert!(!authorized(&config, &request(None), "garage"));
+        assert!(authorized(&OcppConfig::default(), &request(None), "garage"));
+    }
+}
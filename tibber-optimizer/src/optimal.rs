@@ -0,0 +1,315 @@
+//! Cost-optimal charge/discharge schedule over the whole price horizon.
+//!
+//! The SoC range is discretized into small steps and the cheapest path
+//! through it is found by dynamic programming over the price slots. That
+//! is exact for the discretized problem (the linear program a solver would
+//! get, up to the step size) and needs no solver dependency on the GX device.
+//!
+//! Run every cycle with only the first slot executed, this is model predictive
+//! control over a rolling horizon. With `horizon_hours` set, slots beyond the
+//! published prices are filled in with the price a day earlier, so the
+//! horizon keeps its length before tomorrow's prices arrive.
+
+use chrono::{DateTime, Duration, FixedOffset};
+
+use crate::decision::{self, OptimizerInput};
+use crate::floors;
+use crate::optimizer::{BatteryMode, OptimizationResult, PlannedSlot};
+use crate::prices::{PricePoint, SlotOrigin};
+use crate::rules;
+
+/// Resolution of the SoC grid (percent)
+const SOC_STEP_PERCENT: f64 = 0.5;
+
+/// Battery power below this is treated as idle (W)
+const IDLE_POWER_W: f64 = 50.0;
+
+/// Cost per kWh below a schedule rule's reserve (or a SoC floor or a boost's target), per slot (EUR). High enough
+/// that the plan charges ahead of the rule, but a reserve that can't be reached
+/// in time doesn't make the whole plan infeasible.
+const RESERVE_SHORTFALL_COST: f64 = 10.0;
+
+/// Cost per kWh imported above the peak-shaving cap (EUR). Like the reserve
+/// shortfall, high enough that the plan keeps energy for the peak, without
+/// making a peak the battery can't cover infeasible.
+const PEAK_EXCESS_COST: f64 = 10.0;
+
+/// The cost-minimal plan over the published prices and its expected cost
+#[derive(Debug, Clone)]
+pub struct Schedule {
+    pub slots: Vec<PlannedSlot>,
+    /// Net grid cost over `slots` (EUR), excluding the value of the energy left at their end
+    pub cost: f64,
+    /// Slots beyond the published prices that were planned on estimates
+    pub estimated_slots: usize,
+    /// Hours those slots cover
+    pub estimated_hours: f64,
+}
+
+/// Energy flows of one slot the battery doesn't control
+struct SlotLoad {
+    /// Length of the slot (hours)
+    hours: f64,
+    /// House load including announced events, minus forecast PV surplus (kWh)
+    net_kwh: f64,
+    house_w: f64,
+    buy: f64,
+    /// Sell price minus export fees and the configured discharge margin
+    sell: f64,
+    /// Constraints of the schedule rules in effect, with any SoC floor
+    reserve_soc: f64,
+    no_grid_discharge: bool,
+    no_grid_charge: bool,
+    /// Import allowed before peak shaving kicks in (kWh)
+    peak_cap_kwh: Option<f64>,
+}
+
+impl SlotLoad {
+    /// Whether the schedule rules let the battery draw `battery_kwh` in this slot
+    fn allows(&self, battery_kwh: f64) -> bool {
+        let grid_kwh = self.net_kwh + battery_kwh;
+        let discharges_to_grid = battery_kwh < 0.0 && grid_kwh < 0.0;
+        let charges_from_grid = battery_kwh > 0.0 && grid_kwh > 0.0;
+        !(self.no_grid_discharge && discharges_to_grid || self.no_grid_charge && charges_from_grid)
+    }
+}
+
+/// Grid cost of a slot in which the battery draws `battery_kwh` AC energy
+/// (negative = delivers), including wear on discharged energy
+fn slot_cost(input: &OptimizerInput, load: &SlotLoad, battery_kwh: f64) -> f64 {
+    let grid_kwh = load.net_kwh + battery_kwh;
+    let grid_cost = if grid_kwh >= 0.0 { grid_kwh * load.buy } else { grid_kwh * load.sell };
+    grid_cost + (-battery_kwh).max(0.0) * input.optimizer.wear_cost_per_kwh
+}
+
+/// Prices for the slots from `from` to the end of the rolling horizon,
+/// repeating the published price a day earlier
+fn estimated_prices(input: &OptimizerInput, from: DateTime<FixedOffset>) -> Vec<PricePoint> {
+    let Some(hours) = input.optimizer.horizon_hours else {
+        return Vec::new();
+    };
+    let end = input.now + Duration::seconds((hours * 3600.0) as i64);
+    let mut estimates = Vec::new();
+    let mut starts_at = from;
+    while starts_at < end {
+        let Some(day_before) = input.prices.all_prices().find(|p| p.starts_at == starts_at - Duration::days(1)) else {
+            break;
+        };
+        estimates.push(PricePoint {
+            starts_at,
+            ends_at: day_before.ends_at + Duration::days(1),
+            level: None,
+            source: SlotOrigin::Forecast,
+            ..day_before.clone()
+        });
+        starts_at = day_before.ends_at + Duration::days(1);
+    }
+    estimates
+}
+
+/// Solve for the cheapest battery schedule over the current and future slots,
+/// within the SoC limits, power limits and efficiency of `input`
+pub fn solve(input: &OptimizerInput) -> Schedule {
+    let capacity = input.battery.capacity_kwh;
+    let one_way_efficiency = input.round_trip_efficiency.sqrt();
+
+    let mut slots: Vec<&PricePoint> = std::iter::once(input.current_price)
+        .chain(decision::horizon_prices(input.optimizer, input.prices, input.now))
+        .collect();
+    let published = slots.len();
+    let estimates = estimated_prices(input, slots[published - 1].ends_at);
+    slots.extend(&estimates);
+    let loads: Vec<SlotLoad> = slots
+        .iter()
+        .map(|slot| {
+            let event_kwh = input.event_energy_kwh(slot.starts_at, slot.ends_at);
+            let pv_kwh = input.pv_forecast.map_or(0.0, |pv| {
+                pv.surplus_kwh(slot.starts_at, slot.ends_at, input.optimizer.base_consumption_w)
+            });
+            let hours = slot.duration().num_seconds() as f64 / 3600.0;
+            let house_kwh = input.house_load_w(slot.starts_at) / 1000.0 * hours + event_kwh;
+            let constraints = rules::constraints_at(input.schedule_rules, slot.starts_at);
+            // A boost's target counts as a reserve at the end of the slot its deadline falls in
+            let boost_soc = input
+                .boost
+                .filter(|boost| slot.starts_at < boost.by && slot.ends_at >= boost.by)
+                .map_or(0.0, |boost| boost.target_soc.min(input.battery.max_soc_percent));
+            let floor_soc = floors::floor_at(input.soc_floors, slot.starts_at).map_or(0.0, |floor| floor.min_soc);
+            SlotLoad {
+                hours,
+                net_kwh: house_kwh - pv_kwh,
+                house_w: (house_kwh - pv_kwh) / hours * 1000.0,
+                buy: slot.total,
+                sell: slot.sell_price() - input.optimizer.grid_fee_per_kwh - input.optimizer.min_discharge_spread,
+                reserve_soc: constraints.min_soc.map_or(0.0, |(min_soc, _)| min_soc).max(boost_soc).max(floor_soc),
+                no_grid_discharge: constraints.no_grid_discharge.is_some(),
+                no_grid_charge: constraints.no_grid_charge.is_some(),
+                peak_cap_kwh: input.peak_limit.map(|limit| limit.cap_at(slot.starts_at) / 1000.0 * hours),
+            }
+        })
+        .collect();
+
+    // SoC levels are anchored at the current SoC so the path starts exactly there
+    let floor = input.min_soc.min(input.soc);
+    let ceiling = input.battery.max_soc_percent.max(input.soc);
+    let lowest_step = -((input.soc - floor) / SOC_STEP_PERCENT).floor() as i64;
+    let highest_step = ((ceiling - input.soc) / SOC_STEP_PERCENT).floor() as i64;
+    let levels = (highest_step - lowest_step + 1) as usize;
+    let start = (-lowest_step) as usize;
+    let soc_at = |level: usize| input.soc + (level as i64 + lowest_step) as f64 * SOC_STEP_PERCENT;
+    let step_kwh = SOC_STEP_PERCENT / 100.0 * capacity;
+
+    // Steps the battery can move in a slot of `hours` at its power limits
+    let max_up = |hours: f64| (input.max_charge_power_w / 1000.0 * hours * one_way_efficiency / step_kwh).floor() as usize;
+    let max_down =
+        |hours: f64| (input.max_discharge_power_w / 1000.0 * hours / one_way_efficiency / step_kwh).floor() as usize;
+
+    // AC energy for moving between two levels (positive = charging)
+    let battery_kwh = |from: usize, to: usize| {
+        let stored = (to as f64 - from as f64) * step_kwh;
+        if stored >= 0.0 { stored / one_way_efficiency } else { stored * one_way_efficiency }
+    };
+
+    // Ending a slot below a schedule rule's reserve
+    let shortfall_cost = |load: &SlotLoad, level: usize| {
+        (load.reserve_soc - soc_at(level)).max(0.0) / 100.0 * capacity * RESERVE_SHORTFALL_COST
+    };
+
+    // Importing above the peak cap
+    let peak_cost = |load: &SlotLoad, energy_kwh: f64| {
+        load.peak_cap_kwh
+            .map_or(0.0, |cap_kwh| (load.net_kwh + energy_kwh - cap_kwh).max(0.0) * PEAK_EXCESS_COST)
+    };
+
+    // Energy left at the end of the horizon displaces buying at the average price
+    let average_buy = loads.iter().map(|l| l.buy).sum::<f64>() / loads.len().max(1) as f64;
+    let mut cost_to_go: Vec<f64> = (0..levels)
+        .map(|level| -(level as f64 * step_kwh) * one_way_efficiency * average_buy)
+        .collect();
+
+    // Backward pass: the best next level from every level in every slot
+    let mut choices = vec![Vec::new(); loads.len()];
+    for (t, load) in loads.iter().enumerate().rev() {
+        let mut best_costs = vec![f64::INFINITY; levels];
+        let mut best_levels = vec![0; levels];
+        let (max_up, max_down) = (max_up(load.hours), max_down(load.hours));
+        for from in 0..levels {
+            // Staying put first, so ties keep the battery idle
+            let mut best = (
+                slot_cost(input, load, 0.0) + shortfall_cost(load, from) + peak_cost(load, 0.0) + cost_to_go[from],
+                from,
+            );
+            let lowest = from.saturating_sub(max_down);
+            let reachable = &cost_to_go[lowest..=(from + max_up).min(levels - 1)];
+            for (to, remaining) in (lowest..).zip(reachable) {
+                let energy_kwh = battery_kwh(from, to);
+                if !load.allows(energy_kwh) {
+                    continue;
+                }
+                let cost =
+                    slot_cost(input, load, energy_kwh) + shortfall_cost(load, to) + peak_cost(load, energy_kwh) + remaining;
+                if cost < best.0 - 1e-9 {
+                    best = (cost, to);
+                }
+            }
+            (best_costs[from], best_levels[from]) = best;
+        }
+        cost_to_go = best_costs;
+        choices[t] = best_levels;
+    }
+
+    // Forward pass along the chosen levels, reporting the published slots only
+    let mut level = start;
+    let mut cost = 0.0;
+    let planned = slots
+        .iter()
+        .zip(&loads)
+        .zip(&choices)
+        .take(published)
+        .map(|((slot, load), choice)| {
+            let next = choice[level];
+            let energy_kwh = battery_kwh(level, next);
+            cost += slot_cost(input, load, energy_kwh);
+            level = next;
+
+            let battery_power_w = energy_kwh / load.hours * 1000.0;
+            let result = slot_result(input, battery_power_w, load.house_w + battery_power_w);
+            PlannedSlot {
+                starts_at: slot.starts_at,
+                ends_at: slot.ends_at,
+                price: slot.total,
+                sell_price: slot.sell_price(),
+                cheap: slot.total <= input.tiers.cheap_threshold,
+                mode: result.mode,
+                battery_power_w,
+                grid_power_w: load.house_w + battery_power_w,
+                grid_setpoint_w: result.grid_setpoint_w,
+                soc_end: soc_at(next),
+            }
+        })
+        .collect();
+
+    Schedule {
+        slots: planned,
+        cost,
+        estimated_slots: estimates.len(),
+        estimated_hours: loads[published..].iter().map(|load| load.hours).sum(),
+    }
+}
+
+/// Mode and setpoint that realize the planned battery power
+fn slot_result(input: &OptimizerInput, battery_power_w: f64, grid_power_w: f64) -> OptimizationResult {
+    let offset = input.optimizer.setpoint_offset_w;
+    let (mode, grid_setpoint_w) = if battery_power_w > IDLE_POWER_W {
+        let mode = if battery_power_w >= input.max_charge_power_w * 0.9 {
+            BatteryMode::ChargeFull
+        } else {
+            BatteryMode::ChargeReduced
+        };
+        (mode, grid_power_w)
+    } else if battery_power_w < -IDLE_POWER_W {
+        if grid_power_w < -offset {
+            (BatteryMode::DischargeToGrid, grid_power_w)
+        } else if grid_power_w <= offset {
+            // Battery covers the house: keep a margin against grid pull
+            (BatteryMode::SelfConsumptionPreventGridPull, -offset)
+        } else {
+            (BatteryMode::SelfConsumption, grid_power_w)
+        }
+    } else {
+        (BatteryMode::Idle, grid_power_w)
+    };
+    OptimizationResult {
+        mode,
+        grid_setpoint_w: grid_setpoint_w.round(),
+        reason: String::new(),
+        alternative: None,
+    }
+}
+
+/// The first slot of the optimal schedule as a decision
+pub fn optimize(input: &OptimizerInput) -> OptimizationResult {
+    let schedule = solve(input);
+    let Some(slot) = schedule.slots.first() else {
+        return OptimizationResult {
+            mode: BatteryMode::SelfConsumption,
+            grid_setpoint_w: input.optimizer.setpoint_offset_w,
+            reason: "No price data available, defaulting to self-consumption".to_string(),
+            alternative: None,
+        };
+    };
+    let result = slot_result(input, slot.battery_power_w, slot.grid_power_w);
+    let horizon_hours: f64 = schedule.slots.iter().map(PlannedSlot::hours).sum();
+    let estimated = if schedule.estimated_slots > 0 {
+        format!(", {:.1}h more on estimated prices", schedule.estimated_hours)
+    } else {
+        String::new()
+    };
+    OptimizationResult {
+        reason: format!(
+            "Optimal schedule: battery {:+.0}W at {:.4} EUR, SoC {:.1}% -> {:.1}% (expected cost {:.2} EUR over {:.1}h{})",
+            slot.battery_power_w, slot.price, input.soc, slot.soc_end, schedule.cost, horizon_hours, estimated
+        ),
+        ..result
+    }
+}
@@ -1,8 +1,21 @@
-use chrono::{DateTime, FixedOffset};
-use tracing::debug;
+use chrono::{DateTime, FixedOffset, Utc};
+use std::sync::{Arc, Mutex};
 
+use crate::capacity_test::CapacityTestReport;
+use crate::clock::Clock;
 use crate::config::{BatteryConfig, OptimizerConfig};
-use crate::tibber::{PriceCache, PricePoint};
+use crate::decision::{self, OptimizerInput, PriceTiers};
+use crate::events::ConsumptionEvent;
+use crate::floors::{self, SocFloor};
+use crate::hold::HoldWindow;
+use crate::load_profile::LoadProfile;
+use crate::soc_feedback::LoadCorrection;
+use crate::overrides::Boost;
+use crate::occupancy::Away;
+use crate::peak::PeakLimit;
+use crate::prices::{PriceCache, PricePoint};
+use crate::pv_forecast::PvForecast;
+use crate::rules::ScheduleRule;
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum BatteryMode {
@@ -18,6 +31,8 @@ pub enum BatteryMode {
     SelfConsumptionPreventGridPull,
     /// Normal self-consumption (with offset for safety)
     SelfConsumption,
+    /// Battery held idle (maintenance/hold window)
+    Idle,
 }
 
 impl std::fmt::Display for BatteryMode {
@@ -29,6 +44,7 @@ impl std::fmt::Display for BatteryMode {
             BatteryMode::SelfConsumptionPreventFeedIn => write!(f, "self_consumption_no_feedin"),
             BatteryMode::SelfConsumptionPreventGridPull => write!(f, "self_consumption_no_grid"),
             BatteryMode::SelfConsumption => write!(f, "self_consumption"),
+            BatteryMode::Idle => write!(f, "idle"),
         }
     }
 }
@@ -38,11 +54,76 @@ pub struct OptimizationResult {
     pub mode: BatteryMode,
     pub grid_setpoint_w: f64,
     pub reason: String,
+    /// The runner-up mode and why it lost, where one was considered
+    pub alternative: Option<Alternative>,
+}
+
+impl OptimizationResult {
+    /// Hold the grid setpoint to `max_w`, noting `by` what in the reason
+    pub fn cap_setpoint(&mut self, max_w: f64, by: &str) {
+        if self.grid_setpoint_w <= max_w {
+            return;
+        }
+        self.reason = format!("{}; capped at {:.0}W (from {:.0}W) by {}", self.reason, max_w, self.grid_setpoint_w, by);
+        self.grid_setpoint_w = max_w;
+        // Full-power charging would raise the charger's AC input limit
+        if self.mode == BatteryMode::ChargeFull {
+            self.mode = BatteryMode::ChargeReduced;
+        }
+    }
+
+    /// Charge from PV only: hold a grid charge at `setpoint_offset_w`, noting
+    /// `by` what in the reason
+    pub fn block_grid_charge(&mut self, setpoint_offset_w: f64, by: &str) {
+        if !matches!(self.mode, BatteryMode::ChargeFull | BatteryMode::ChargeReduced) {
+            return;
+        }
+        self.cap_setpoint(setpoint_offset_w, by);
+        self.mode = BatteryMode::SelfConsumption;
+    }
+}
+
+/// A mode considered but not chosen
+#[derive(Debug, Clone, PartialEq)]
+pub struct Alternative {
+    pub mode: BatteryMode,
+    /// Why it lost, e.g. "spread 0.0300 < required 0.0700"
+    pub reason: String,
 }
 
 pub struct BatteryOptimizer {
     battery_config: BatteryConfig,
     optimizer_config: OptimizerConfig,
+    /// Tiers computed for the last seen price generation and first future slot
+    tier_cache: Mutex<Option<CachedTiers>>,
+    /// Latest PV forecast, if a forecast provider is configured
+    pv_forecast: Mutex<Option<Arc<PvForecast>>>,
+    /// Temporarily raised minimum SoC (e.g. after a grid outage)
+    min_soc_override: Mutex<Option<f64>>,
+    /// Temporarily lowered maximum SoC (a `set_max_soc` command)
+    max_soc_override: Mutex<Option<f64>>,
+    /// Round-trip efficiency measured on the real system, if available
+    measured_efficiency: Mutex<Option<f64>>,
+    /// Usable capacity and round-trip efficiency from a capacity test, if applied
+    tested: Mutex<Option<(f64, f64)>>,
+    /// Charge and discharge power the ESS was observed to actually deliver, if lower than configured
+    power_limits: Mutex<(Option<f64>, Option<f64>)>,
+    /// Announced extra consumption (e.g. an EV arriving) on top of the base load
+    consumption_events: Mutex<Vec<ConsumptionEvent>>,
+    /// Recurring constraints to plan around
+    schedule_rules: Mutex<Vec<ScheduleRule>>,
+    /// Commanded SoC floors, each until its end
+    soc_floors: Mutex<Vec<SocFloor>>,
+    /// Commanded charge target and deadline
+    boost: Mutex<Option<Boost>>,
+    /// House load learned from measurements, if any
+    load_profile: Mutex<Option<Arc<LoadProfile>>>,
+    /// Correction of the house load from the SoC feedback, if learned
+    load_correction: Mutex<Option<LoadCorrection>>,
+    /// Import cap for capacity tariffs, if peak shaving is enabled
+    peak_limit: Mutex<Option<PeakLimit>>,
+    away: Mutex<Option<Away>>,
+    clock: Clock,
 }
 
 impl BatteryOptimizer {
@@ -50,414 +131,355 @@ impl BatteryOptimizer {
         Self {
             battery_config,
             optimizer_config,
+            tier_cache: Mutex::new(None),
+            pv_forecast: Mutex::new(None),
+            min_soc_override: Mutex::new(None),
+            max_soc_override: Mutex::new(None),
+            measured_efficiency: Mutex::new(None),
+            tested: Mutex::new(None),
+            power_limits: Mutex::new((None, None)),
+            consumption_events: Mutex::new(Vec::new()),
+            schedule_rules: Mutex::new(Vec::new()),
+            soc_floors: Mutex::new(Vec::new()),
+            boost: Mutex::new(None),
+            load_profile: Mutex::new(None),
+            load_correction: Mutex::new(None),
+            peak_limit: Mutex::new(None),
+            away: Mutex::new(None),
+            clock: Clock::default(),
         }
     }
 
-    /// Main optimization function - determines what the battery should do
-    pub fn optimize(
-        &self,
-        current_soc: f64,
-        current_price: &PricePoint,
-        price_cache: &PriceCache,
-    ) -> OptimizationResult {
-        let future_prices = price_cache.future_prices();
-        if future_prices.is_empty() {
-            return OptimizationResult {
-                mode: BatteryMode::SelfConsumption,
-                grid_setpoint_w: self.optimizer_config.setpoint_offset_w,
-                reason: "No price data available, defaulting to self-consumption".to_string(),
-            };
-        }
-
-        let price = current_price.total;
-        let tiers = self.calculate_price_tiers(price_cache);
-
-        debug!(
-            "Price: {:.4}, Tiers - Cheapest: {:.4}, Cheap: {:.4}, Expensive: {:.4}, Premium: {:.4}",
-            price, tiers.cheapest_threshold, tiers.cheap_threshold,
-            tiers.expensive_threshold, tiers.premium_threshold
-        );
-
-        // Check if we should discharge to grid (sell power) - HIGHEST PRIORITY when profitable
-        if let Some(result) = self.check_grid_discharge(current_soc, price, &tiers, price_cache) {
-            return result;
-        }
-
-        // Check charging modes with forward-looking planning
-        if let Some(result) = self.check_charging(current_soc, price, &tiers, price_cache, &current_price.starts_at) {
-            return result;
-        }
-
-        // Determine self-consumption mode based on price level
-        self.determine_self_consumption_mode(price, &tiers)
+    /// Read the time from `clock` instead of the wall clock (replay)
+    pub fn with_clock(self, clock: Clock) -> Self {
+        Self { clock, ..self }
     }
 
-    fn check_grid_discharge(
-        &self,
-        soc: f64,
-        price: f64,
-        tiers: &PriceTiers,
-        cache: &PriceCache,
-    ) -> Option<OptimizationResult> {
-        // Need sufficient SoC to discharge
-        if soc <= self.battery_config.min_soc_percent + 15.0 {
-            return None;
-        }
+    pub fn optimizer_config(&self) -> &OptimizerConfig {
+        &self.optimizer_config
+    }
 
-        // Only discharge at premium prices
-        if price < tiers.premium_threshold {
-            return None;
-        }
+    /// Switch optimizer settings (e.g. a day preset); drops the cached tiers
+    pub fn set_optimizer_config(&mut self, optimizer_config: OptimizerConfig) {
+        self.optimizer_config = optimizer_config;
+        *self.tier_cache.get_mut().unwrap() = None;
+    }
 
-        // Calculate if discharging is profitable considering round-trip efficiency
-        let efficiency = self.battery_config.round_trip_efficiency;
-        let min_profitable_price = tiers.cheapest_threshold / efficiency + self.optimizer_config.min_discharge_spread;
+    /// Temporarily raise the minimum SoC; it never lowers the configured one
+    pub fn set_min_soc_override(&self, min_soc: Option<f64>) {
+        *self.min_soc_override.lock().unwrap() = min_soc;
+    }
 
-        if price < min_profitable_price {
-            debug!(
-                "Price {:.4} below profitable threshold {:.4} (efficiency-adjusted)",
-                price, min_profitable_price
-            );
-            return None;
+    /// Configured minimum SoC, raised by any active override
+    fn effective_min_soc(&self) -> f64 {
+        let configured = self.battery_config.min_soc_percent;
+        match *self.min_soc_override.lock().unwrap() {
+            Some(raised) => configured.max(raised),
+            None => configured,
         }
+    }
 
-        // Check if there are enough cheap hours coming to recharge
-        let energy_available = (soc - self.battery_config.min_soc_percent) / 100.0
-            * self.battery_config.capacity_kwh;
-        let hours_to_recharge = energy_available / (self.battery_config.max_charge_power_w / 1000.0 * efficiency);
-        let slots_needed = (hours_to_recharge * 4.0).ceil() as usize;
+    /// Reserve right now: the configured or raised minimum SoC, or a commanded
+    /// SoC floor above it
+    pub fn min_soc_now(&self) -> f64 {
+        let now = self.clock.now().fixed_offset();
+        let soc_floors = self.soc_floors.lock().unwrap();
+        let floor = floors::floor_at(&soc_floors, now).map_or(0.0, |floor| floor.min_soc);
+        self.effective_min_soc().max(floor)
+    }
 
-        let cheap_slots = self.count_slots_below_threshold(cache, tiers.cheap_threshold);
+    /// Maximum SoC right now, lowered by any active override
+    pub fn max_soc_now(&self) -> f64 {
+        self.effective_battery_config().max_soc_percent
+    }
 
-        if cheap_slots < slots_needed / 2 {
-            debug!(
-                "Only {} cheap slots available, need at least {} to recharge",
-                cheap_slots, slots_needed / 2
-            );
-            return None;
-        }
+    /// Usable capacity: the configured one, or the capacity test's if applied
+    pub fn capacity_kwh(&self) -> f64 {
+        self.effective_battery_config().capacity_kwh
+    }
 
-        Some(OptimizationResult {
-            mode: BatteryMode::DischargeToGrid,
-            grid_setpoint_w: -self.battery_config.max_discharge_power_w,
-            reason: format!(
-                "Premium price {:.4} EUR (threshold {:.4}), discharging to grid. {} cheap slots available for recharge.",
-                price, tiers.premium_threshold, cheap_slots
-            ),
-        })
+    /// Temporarily lower the maximum SoC; it never raises the configured one
+    pub fn set_max_soc_override(&self, max_soc: Option<f64>) {
+        *self.max_soc_override.lock().unwrap() = max_soc;
     }
 
-    fn check_charging(
-        &self,
-        soc: f64,
-        price: f64,
-        tiers: &PriceTiers,
-        cache: &PriceCache,
-        current_time: &DateTime<FixedOffset>,
-    ) -> Option<OptimizationResult> {
-        // Don't charge if already at max SoC
-        if soc >= self.battery_config.max_soc_percent {
-            return None;
+    /// Configured battery settings with the maximum SoC lowered by any active override
+    fn effective_battery_config(&self) -> BatteryConfig {
+        let configured = self.battery_config.max_soc_percent;
+        let max_soc = match *self.max_soc_override.lock().unwrap() {
+            Some(lowered) => configured.min(lowered).max(self.effective_min_soc()),
+            None => configured,
+        };
+        let capacity_kwh = self.tested.lock().unwrap().map_or(self.battery_config.capacity_kwh, |(capacity, _)| capacity);
+        BatteryConfig {
+            max_soc_percent: max_soc,
+            capacity_kwh,
+            ..self.battery_config.clone()
         }
+    }
 
-        // Calculate charge planning parameters
-        let plan = self.calculate_charge_plan(soc, cache, current_time);
-
-        debug!(
-            "Charge plan: need {:.1}kWh, {} cheap slots available, {} cheapest slots, target SoC: {:.1}%",
-            plan.energy_needed_kwh, plan.cheap_slots_available, plan.cheapest_slots_available, plan.target_soc
-        );
-
-        // FULL POWER charging during the absolute cheapest slots
-        if price <= tiers.cheapest_threshold {
-            return Some(OptimizationResult {
-                mode: BatteryMode::ChargeFull,
-                grid_setpoint_w: self.battery_config.max_charge_power_w,
-                reason: format!(
-                    "Cheapest price tier {:.4} EUR, charging at full power. SoC: {:.1}% -> target {:.1}%",
-                    price, soc, plan.target_soc
-                ),
-            });
-        }
+    /// Use a measured round-trip efficiency instead of the configured one
+    pub fn set_measured_efficiency(&self, efficiency: Option<f64>) {
+        *self.measured_efficiency.lock().unwrap() = efficiency;
+    }
 
-        // Charging during cheap (but not cheapest) slots
-        // Always charge if we're in a cheap slot and haven't reached target
-        if price <= tiers.cheap_threshold && soc < plan.target_soc {
-            // Calculate how aggressively we need to charge based on available slots
-            let power_factor = self.calculate_charge_power_factor(&plan, price, tiers);
-            let charge_power = self.battery_config.max_charge_power_w * power_factor;
+    /// Plan with the capacity and efficiency a capacity test measured
+    pub fn set_tested(&self, report: Option<&CapacityTestReport>) {
+        *self.tested.lock().unwrap() = report.map(|r| (r.usable_capacity_kwh, r.round_trip_efficiency));
+    }
 
-            return Some(OptimizationResult {
-                mode: if power_factor >= 0.9 { BatteryMode::ChargeFull } else { BatteryMode::ChargeReduced },
-                grid_setpoint_w: charge_power,
-                reason: format!(
-                    "Cheap price tier {:.4} EUR, charging at {:.0}% power ({:.0}W). SoC: {:.1}% -> target {:.1}%, {} slots remaining",
-                    price, power_factor * 100.0, charge_power, soc, plan.target_soc, plan.cheap_slots_available
-                ),
-            });
-        }
+    /// Tested round-trip efficiency, else the measured one, falling back to the configured one
+    pub fn round_trip_efficiency(&self) -> f64 {
+        let tested = self.tested.lock().unwrap().map(|(_, efficiency)| efficiency);
+        tested
+            .or(*self.measured_efficiency.lock().unwrap())
+            .unwrap_or(self.battery_config.round_trip_efficiency)
+    }
 
-        // Emergency charging if SoC is critically low
-        if soc < self.battery_config.min_soc_percent + 5.0 && price < tiers.expensive_threshold {
-            return Some(OptimizationResult {
-                mode: BatteryMode::ChargeReduced,
-                grid_setpoint_w: self.battery_config.max_charge_power_w * 0.5,
-                reason: format!(
-                    "Critical SoC {:.1}%, emergency charging at 50% power despite moderate price {:.4} EUR",
-                    soc, price
-                ),
-            });
-        }
+    /// Cap charge and discharge power at what the ESS was observed to deliver
+    pub fn set_power_limits(&self, charge_w: Option<f64>, discharge_w: Option<f64>) {
+        *self.power_limits.lock().unwrap() = (charge_w, discharge_w);
+    }
 
-        None
+    /// Configured maximum charge power, lowered by any observed limit
+    fn max_charge_power_w(&self) -> f64 {
+        let configured = self.battery_config.max_charge_power_w;
+        self.power_limits.lock().unwrap().0.map_or(configured, |limit| configured.min(limit))
     }
 
-    /// Calculate a forward-looking charge plan
-    fn calculate_charge_plan(
-        &self,
-        current_soc: f64,
-        cache: &PriceCache,
-        current_time: &DateTime<FixedOffset>,
-    ) -> ChargePlan {
-        let tiers = self.calculate_price_tiers(cache);
-
-        // Count cheap and cheapest slots
-        let cheap_slots_available = self.count_slots_below_threshold(cache, tiers.cheap_threshold);
-        let cheapest_slots_available = self.count_slots_below_threshold(cache, tiers.cheapest_threshold);
-
-        // Calculate hours until next cheap period (for planning reserves)
-        let hours_until_cheap = self.hours_until_next_cheap_period(cache, &tiers, current_time);
-
-        // Estimate energy consumption during expensive period
-        let consumption_kwh = hours_until_cheap * (self.optimizer_config.base_consumption_w / 1000.0);
-
-        // Target SoC: enough to cover consumption until next cheap period + buffer
-        // Minimum target is to always have reserves for one expensive cycle
-        let min_reserve_kwh = consumption_kwh + (self.battery_config.capacity_kwh * 0.2); // 20% buffer
-        let min_reserve_soc = (min_reserve_kwh / self.battery_config.capacity_kwh * 100.0)
-            .min(self.battery_config.max_soc_percent);
-
-        // During cheap periods, aim to charge fully
-        // We want to maximize our charge during cheap periods
-        let target_soc = self.battery_config.max_soc_percent;
-
-        // Energy needed to reach target
-        let energy_needed_kwh = (target_soc - current_soc) / 100.0 * self.battery_config.capacity_kwh;
-
-        // Effective charge rate per slot (15 minutes = 0.25 hours)
-        let efficiency = self.battery_config.round_trip_efficiency;
-        let kwh_per_slot = (self.battery_config.max_charge_power_w / 1000.0) * 0.25 * efficiency;
-
-        // Slots needed at full power
-        let slots_needed_full_power = (energy_needed_kwh / kwh_per_slot).ceil() as usize;
-
-        ChargePlan {
-            target_soc,
-            min_reserve_soc,
-            energy_needed_kwh,
-            cheap_slots_available,
-            cheapest_slots_available,
-            slots_needed_full_power,
-            hours_until_cheap,
-        }
+    /// Configured maximum discharge power, lowered by any observed limit
+    fn max_discharge_power_w(&self) -> f64 {
+        let configured = self.battery_config.max_discharge_power_w;
+        self.power_limits.lock().unwrap().1.map_or(configured, |limit| configured.min(limit))
     }
 
-    /// Calculate how aggressively we should charge based on available slots and energy needed
-    fn calculate_charge_power_factor(&self, plan: &ChargePlan, price: f64, tiers: &PriceTiers) -> f64 {
-        // If we have more cheap slots than needed, we can charge at a lower rate
-        // If we have fewer, we need to charge more aggressively
+    /// Plan for announced extra consumption
+    pub fn set_consumption_events(&self, events: Vec<ConsumptionEvent>) {
+        *self.consumption_events.lock().unwrap() = events;
+    }
 
-        if plan.cheap_slots_available == 0 {
-            return 1.0; // Full power if this is our only chance
-        }
+    /// Plan around recurring rules
+    pub fn set_schedule_rules(&self, rules: Vec<ScheduleRule>) {
+        *self.schedule_rules.lock().unwrap() = rules;
+    }
 
-        // Calculate the ratio of needed slots to available slots
-        let slot_ratio = plan.slots_needed_full_power as f64 / plan.cheap_slots_available as f64;
+    /// Don't discharge below commanded floors before their end
+    pub fn set_soc_floors(&self, floors: Vec<SocFloor>) {
+        *self.soc_floors.lock().unwrap() = floors;
+    }
 
-        // If we need more slots than available, charge at full power
-        if slot_ratio >= 1.0 {
-            return 1.0;
-        }
+    /// Reach a SoC by a deadline in the cheapest slots before it
+    pub fn set_boost(&self, boost: Option<Boost>) {
+        *self.boost.lock().unwrap() = boost;
+    }
 
-        // If we have plenty of slots, scale power based on how cheap this slot is
-        // Cheapest slots: 100% power
-        // Less cheap slots: proportionally less, but minimum 40%
-        let price_range = tiers.cheap_threshold - tiers.cheapest_threshold;
-        if price_range <= 0.0 {
-            return 1.0;
-        }
+    /// Update the learned house load profile
+    pub fn set_load_profile(&self, profile: LoadProfile) {
+        *self.load_profile.lock().unwrap() = Some(Arc::new(profile));
+    }
 
-        let price_position = ((price - tiers.cheapest_threshold) / price_range).clamp(0.0, 1.0);
+    /// Correct the expected house load by the SoC feedback
+    pub fn set_load_correction(&self, correction: Option<LoadCorrection>) {
+        *self.load_correction.lock().unwrap() = correction;
+    }
 
-        // Scale from 100% at cheapest to 40% at cheap threshold
-        // But increase if we're running low on slots
-        let base_factor = 1.0 - (price_position * 0.6);
+    /// Hold grid import below the peak cap
+    pub fn set_peak_limit(&self, limit: Option<PeakLimit>) {
+        *self.peak_limit.lock().unwrap() = limit;
+    }
 
-        // Adjust based on slot availability - if running low, charge harder
-        let urgency_factor = slot_ratio.max(0.4);
+    /// Plan for a lower house load while nobody is home
+    pub fn set_away(&self, away: Option<Away>) {
+        *self.away.lock().unwrap() = away;
+    }
 
-        (base_factor * urgency_factor).clamp(0.4, 1.0)
+    /// Update the PV forecast used when choosing the charge target
+    pub fn set_pv_forecast(&self, forecast: Option<Arc<PvForecast>>) {
+        *self.pv_forecast.lock().unwrap() = forecast;
     }
 
-    /// Calculate hours until the next cheap price period
-    fn hours_until_next_cheap_period(
-        &self,
-        cache: &PriceCache,
-        tiers: &PriceTiers,
-        _current_time: &DateTime<FixedOffset>,
-    ) -> f64 {
-        let future_prices = cache.future_prices();
-
-        // Find the first expensive slot, then find how long until cheap prices return
-        let mut in_expensive_period = false;
-        let mut expensive_start: Option<DateTime<FixedOffset>> = None;
-
-        for price in &future_prices {
-            if price.total > tiers.cheap_threshold {
-                if !in_expensive_period {
-                    in_expensive_period = true;
-                    expensive_start = Some(price.starts_at);
-                }
-            } else if in_expensive_period {
-                // Found cheap price after expensive period
-                if let Some(start) = expensive_start {
-                    let duration = price.starts_at.signed_duration_since(start);
-                    return duration.num_minutes() as f64 / 60.0;
-                }
-            }
+    /// Hold the battery idle: let the grid cover the estimated house load so the
+    /// battery neither charges nor discharges
+    pub fn hold(&self, window: &HoldWindow) -> OptimizationResult {
+        OptimizationResult {
+            mode: BatteryMode::Idle,
+            grid_setpoint_w: self.optimizer_config.base_consumption_w,
+            reason: format!("Battery {}", window.describe()),
+            alternative: None,
         }
-
-        // If we didn't find a transition, estimate based on typical daily cycle
-        // Assume ~8 hours of expensive period
-        8.0
     }
 
-    fn determine_self_consumption_mode(&self, price: f64, tiers: &PriceTiers) -> OptimizationResult {
-        let offset = self.optimizer_config.setpoint_offset_w;
-
-        if price >= tiers.expensive_threshold {
-            // High price - prevent pulling from grid, prefer battery
-            // Negative setpoint means "try to feed X watts to grid" which forces battery use
-            OptimizationResult {
-                mode: BatteryMode::SelfConsumptionPreventGridPull,
-                grid_setpoint_w: -offset,
-                reason: format!(
-                    "Expensive price {:.4} EUR (>= {:.4}), setpoint -{:.0}W to prevent grid pull",
-                    price, tiers.expensive_threshold, offset
-                ),
-            }
-        } else if price <= tiers.cheap_threshold {
-            // Low price but not charging (already full?) - prevent feeding back to grid
-            OptimizationResult {
+    /// Charge from the grid at full power as commanded, up to the maximum SoC
+    pub fn force_charge(&self, current_soc: f64, until: DateTime<FixedOffset>) -> OptimizationResult {
+        let max_soc = self.effective_battery_config().max_soc_percent;
+        if current_soc >= max_soc {
+            return OptimizationResult {
                 mode: BatteryMode::SelfConsumptionPreventFeedIn,
-                grid_setpoint_w: offset,
+                grid_setpoint_w: self.optimizer_config.setpoint_offset_w,
                 reason: format!(
-                    "Low price {:.4} EUR but not charging, setpoint +{:.0}W to prevent feed-in",
-                    price, offset
+                    "Forced charging until {}, SoC {:.1}% at max {:.1}%",
+                    until.to_rfc3339(),
+                    current_soc,
+                    max_soc
                 ),
-            }
-        } else {
-            // Moderate price - slight positive offset to prefer grid over battery discharge
-            OptimizationResult {
-                mode: BatteryMode::SelfConsumption,
-                grid_setpoint_w: offset,
+                alternative: None,
+            };
+        }
+        OptimizationResult {
+            mode: BatteryMode::ChargeFull,
+            grid_setpoint_w: self.max_charge_power_w(),
+            reason: format!("Forced charging at full power until {}", until.to_rfc3339()),
+            alternative: None,
+        }
+    }
+
+    /// Pure self-consumption, never charging from or discharging to the grid:
+    /// in fixed-price contract months price differences don't exist, and past
+    /// the daily cycle limit the battery shouldn't cycle for them. `why` leads
+    /// the reason.
+    pub fn self_consumption_only(&self, current_soc: f64, why: &str) -> OptimizationResult {
+        let min_soc = self.min_soc_now();
+        if current_soc <= min_soc {
+            return OptimizationResult {
+                mode: BatteryMode::Idle,
+                grid_setpoint_w: self.optimizer_config.base_consumption_w,
                 reason: format!(
-                    "Moderate price {:.4} EUR, setpoint +{:.0}W (preserve battery for expensive periods)",
-                    price, offset
+                    "{}, SoC {:.1}% at reserve {:.1}%, holding battery",
+                    why, current_soc, min_soc
                 ),
-            }
+                alternative: None,
+            };
+        }
+        OptimizationResult {
+            mode: BatteryMode::SelfConsumption,
+            grid_setpoint_w: self.optimizer_config.setpoint_offset_w,
+            reason: format!("{}, self-consumption only", why),
+            alternative: None,
         }
     }
 
-    fn calculate_price_tiers(&self, cache: &PriceCache) -> PriceTiers {
-        let prices = cache.future_prices();
-        if prices.is_empty() {
-            return PriceTiers::default();
-        }
+    /// Gather the current state into a decision input and run `decide` on it
+    fn with_input<R>(
+        &self,
+        current_soc: f64,
+        current_price: &PricePoint,
+        price_cache: &PriceCache,
+        decide: impl FnOnce(&OptimizerInput) -> R,
+    ) -> R {
+        let now = self.clock.now();
+        let pv_forecast = self.pv_forecast.lock().unwrap().clone();
+        let consumption_events = self.consumption_events.lock().unwrap().clone();
+        let schedule_rules = self.schedule_rules.lock().unwrap().clone();
+        let soc_floors = self.soc_floors.lock().unwrap().clone();
+        let load_profile = self
+            .load_profile
+            .lock()
+            .unwrap()
+            .clone()
+            .filter(|_| self.optimizer_config.use_load_profile);
+        let battery = self.effective_battery_config();
+        let input = OptimizerInput {
+            battery: &battery,
+            optimizer: &self.optimizer_config,
+            now,
+            soc: current_soc,
+            current_price,
+            prices: price_cache,
+            tiers: self.calculate_price_tiers(price_cache, now),
+            min_soc: self.effective_min_soc(),
+            round_trip_efficiency: self.round_trip_efficiency(),
+            max_charge_power_w: self.max_charge_power_w(),
+            max_discharge_power_w: self.max_discharge_power_w(),
+            pv_forecast: pv_forecast.as_deref(),
+            consumption_events: &consumption_events,
+            schedule_rules: &schedule_rules,
+            soc_floors: &soc_floors,
+            boost: *self.boost.lock().unwrap(),
+            load_profile: load_profile.as_deref(),
+            load_correction: *self.load_correction.lock().unwrap(),
+            peak_limit: *self.peak_limit.lock().unwrap(),
+            away: *self.away.lock().unwrap(),
+        };
+        decide(&input)
+    }
+
+    /// Main optimization function - determines what the battery should do
+    pub fn optimize(
+        &self,
+        current_soc: f64,
+        current_price: &PricePoint,
+        price_cache: &PriceCache,
+    ) -> OptimizationResult {
+        self.with_input(current_soc, current_price, price_cache, decision::optimize)
+    }
+
+    /// Get price tiers, recomputing only when the price data or the slots within the horizon changed
+    fn calculate_price_tiers(&self, cache: &PriceCache, now: DateTime<Utc>) -> PriceTiers {
+        let mut horizon = decision::horizon_prices(&self.optimizer_config, cache, now).map(|p| p.starts_at);
+        let slots = (horizon.next(), horizon.last());
+        let mut cached = self.tier_cache.lock().unwrap();
 
-        let mut sorted: Vec<f64> = prices.iter().map(|p| p.total).collect();
-        sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
-
-        let len = sorted.len();
-
-        // Cheapest tier: bottom 10% of prices (full power charging)
-        let cheapest_idx = ((len as f64 * self.optimizer_config.cheapest_percentile / 100.0) as usize).max(1).min(len - 1);
-        // Cheap tier: bottom 25% of prices (reduced charging)
-        let cheap_idx = ((len as f64 * self.optimizer_config.charge_percentile / 100.0) as usize).min(len - 1);
-        // Expensive tier: top 25% (prevent grid pull)
-        let expensive_idx = ((len as f64 * (100.0 - self.optimizer_config.expensive_percentile) / 100.0) as usize).min(len - 1);
-        // Premium tier: top 10% (discharge to grid)
-        let premium_idx = ((len as f64 * self.optimizer_config.discharge_percentile / 100.0) as usize).min(len - 1);
-
-        PriceTiers {
-            cheapest_threshold: sorted[cheapest_idx],
-            cheap_threshold: sorted[cheap_idx],
-            expensive_threshold: sorted[expensive_idx],
-            premium_threshold: sorted[premium_idx],
+        if let Some(c) = cached.as_ref() {
+            if c.generation == cache.generation && c.slots == slots {
+                return c.tiers.clone();
+            }
         }
+
+        let tiers = PriceTiers::compute(&self.optimizer_config, cache, now);
+        *cached = Some(CachedTiers {
+            generation: cache.generation,
+            slots,
+            tiers: tiers.clone(),
+        });
+        tiers
     }
 
-    fn count_slots_below_threshold(&self, cache: &PriceCache, threshold: f64) -> usize {
-        cache
-            .future_prices()
-            .iter()
-            .filter(|p| p.total <= threshold)
-            .count()
+    /// Project the optimizer's decisions over the current and all future slots,
+    /// simulating the SoC trajectory that results from them
+    pub fn plan(&self, current_soc: f64, current_price: &PricePoint, cache: &PriceCache) -> Vec<PlannedSlot> {
+        self.with_input(current_soc, current_price, cache, decision::plan)
     }
 
     /// Get information about upcoming price conditions
-    pub fn get_forecast_info(&self, cache: &PriceCache) -> ForecastInfo {
-        let tiers = self.calculate_price_tiers(cache);
-        let future = cache.future_prices();
-
-        let next_cheap = future
-            .iter()
-            .find(|p| p.total <= tiers.cheapest_threshold)
-            .map(|p| p.starts_at.to_rfc3339());
-
-        let next_expensive = future
-            .iter()
-            .find(|p| p.total >= tiers.premium_threshold)
-            .map(|p| p.starts_at.to_rfc3339());
-
-        ForecastInfo {
-            next_cheap_slot: next_cheap,
-            next_expensive_slot: next_expensive,
-            cheap_slots_remaining: self.count_slots_below_threshold(cache, tiers.cheap_threshold),
-            cheapest_slots_remaining: self.count_slots_below_threshold(cache, tiers.cheapest_threshold),
-        }
+    pub fn get_forecast_info(&self, current_soc: f64, current_price: &PricePoint, cache: &PriceCache) -> ForecastInfo {
+        self.with_input(current_soc, current_price, cache, decision::forecast_info)
     }
 }
 
-#[derive(Debug, Clone, Default)]
-struct PriceTiers {
-    /// Bottom 10% - full power charging
-    cheapest_threshold: f64,
-    /// Bottom 25% - reduced charging
-    cheap_threshold: f64,
-    /// Top 25% - prevent grid pull
-    expensive_threshold: f64,
-    /// Top 10% - discharge to grid
-    premium_threshold: f64,
+#[derive(Debug, Clone)]
+struct CachedTiers {
+    generation: u64,
+    /// First and last slot within the horizon
+    slots: (Option<DateTime<FixedOffset>>, Option<DateTime<FixedOffset>>),
+    tiers: PriceTiers,
 }
 
+/// One price slot of the projected plan
 #[derive(Debug, Clone)]
-#[allow(dead_code)]
-struct ChargePlan {
-    /// Target SoC to reach during cheap period
-    target_soc: f64,
-    /// Minimum SoC to maintain as reserve
-    min_reserve_soc: f64,
-    /// Energy needed to reach target (kWh)
-    energy_needed_kwh: f64,
-    /// Number of cheap price slots available
-    cheap_slots_available: usize,
-    /// Number of cheapest price slots available
-    cheapest_slots_available: usize,
-    /// Slots needed at full power to reach target
-    slots_needed_full_power: usize,
-    /// Hours until next cheap period
-    hours_until_cheap: f64,
+pub struct PlannedSlot {
+    pub starts_at: DateTime<FixedOffset>,
+    pub ends_at: DateTime<FixedOffset>,
+    pub price: f64,
+    /// Price paid for exported energy
+    pub sell_price: f64,
+    /// Whether the price falls in the cheap tier
+    pub cheap: bool,
+    pub mode: BatteryMode,
+    /// Projected battery power (positive = charging)
+    pub battery_power_w: f64,
+    /// Projected grid power: house load plus battery power (positive = import)
+    pub grid_power_w: f64,
+    /// Grid setpoint the decision for the slot commands
+    pub grid_setpoint_w: f64,
+    /// Projected SoC at the end of the slot
+    pub soc_end: f64,
+}
+
+impl PlannedSlot {
+    /// Length of the slot (hours)
+    pub fn hours(&self) -> f64 {
+        (self.ends_at - self.starts_at).num_seconds() as f64 / 3600.0
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -466,4 +488,26 @@ pub struct ForecastInfo {
     pub next_expensive_slot: Option<String>,
     pub cheap_slots_remaining: usize,
     pub cheapest_slots_remaining: usize,
+    /// Spread over the cheapest charge price a grid discharge currently needs
+    pub required_discharge_spread: f64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn self_consumption_only_holds_the_battery_at_the_reserve() {
+        let battery: BatteryConfig = serde_yaml::from_str("capacity_kwh: 10\nround_trip_efficiency: 0.9\nmin_soc_percent: 20").unwrap();
+        let optimizer = BatteryOptimizer::new(battery, serde_yaml::from_str("{}").unwrap());
+
+        let result = optimizer.self_consumption_only(60.0, "Daily cycle limit reached (1.50 cycles today)");
+        assert_eq!(result.mode, BatteryMode::SelfConsumption);
+        assert_eq!(result.grid_setpoint_w, optimizer.optimizer_config().setpoint_offset_w);
+        assert!(result.reason.starts_with("Daily cycle limit reached"));
+
+        let result = optimizer.self_consumption_only(20.0, "Daily cycle limit reached (1.50 cycles today)");
+        assert_eq!(result.mode, BatteryMode::Idle);
+        assert_eq!(result.grid_setpoint_w, optimizer.optimizer_config().base_consumption_w);
+    }
 }
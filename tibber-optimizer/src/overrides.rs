@@ -0,0 +1,140 @@
+use chrono::{DateTime, FixedOffset, Utc};
+use tracing::info;
+
+/// An override value and when it expires (None = until cancelled)
+#[derive(Debug, Clone, Copy)]
+struct Timed<T> {
+    value: T,
+    until: Option<DateTime<FixedOffset>>,
+}
+
+impl<T> Timed<T> {
+    fn expired(&self, now: DateTime<Utc>) -> bool {
+        self.until.is_some_and(|until| until <= now)
+    }
+
+    fn describe_until(&self) -> String {
+        self.until.map(|until| format!(" until {}", until.to_rfc3339())).unwrap_or_default()
+    }
+}
+
+/// Charge to `target_soc`% by `by` at the lowest cost: the planner picks the
+/// cheapest slots left before the deadline
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Boost {
+    pub target_soc: f64,
+    pub by: DateTime<FixedOffset>,
+}
+
+impl Boost {
+    pub fn describe(&self) -> String {
+        format!("boost to {:.0}% by {}", self.target_soc, self.by.to_rfc3339())
+    }
+}
+
+/// Overrides commanded at runtime, each honored until it expires or is cancelled
+#[derive(Debug, Default)]
+pub struct Overrides {
+    /// Charge from the grid at full power regardless of price
+    force_charge: Option<Timed<()>>,
+    /// Stop writing setpoints, leaving the ESS to its own logic
+    pause: Option<Timed<()>>,
+    /// Highest SoC to charge to, below the configured maximum
+    max_soc: Option<Timed<f64>>,
+    /// Charge to a SoC by a deadline in the cheapest slots
+    boost: Option<Boost>,
+}
+
+impl Overrides {
+    /// Force charging until `until`; replaces a pause
+    pub fn force_charge(&mut self, until: DateTime<FixedOffset>) {
+        info!("Forcing grid charging until {}", until.to_rfc3339());
+        self.pause = None;
+        self.force_charge = Some(Timed { value: (), until: Some(until) });
+    }
+
+    /// Pause control until `until` (default: until cancelled); replaces a forced charge
+    pub fn pause(&mut self, until: Option<DateTime<FixedOffset>>) {
+        let pause = Timed { value: (), until };
+        info!("Pausing control{}", pause.describe_until());
+        self.force_charge = None;
+        self.pause = Some(pause);
+    }
+
+    /// Cap the SoC at `value`% until `until` (default: until cancelled)
+    pub fn set_max_soc(&mut self, value: f64, until: Option<DateTime<FixedOffset>>) {
+        let max_soc = Timed { value: value.clamp(0.0, 100.0), until };
+        info!("Capping SoC at {:.0}%{}", max_soc.value, max_soc.describe_until());
+        self.max_soc = Some(max_soc);
+    }
+
+    /// Charge to `target_soc`% by `by`; replaces a previous boost
+    pub fn boost(&mut self, target_soc: f64, by: DateTime<FixedOffset>) {
+        let boost = Boost { target_soc: target_soc.clamp(0.0, 100.0), by };
+        info!("Planning {}", boost.describe());
+        self.boost = Some(boost);
+    }
+
+    /// Cancel all overrides
+    pub fn clear(&mut self) {
+        if !self.describe().is_empty() {
+            info!("Cancelled runtime overrides");
+        }
+        *self = Self::default();
+    }
+
+    /// Drop overrides that have expired at `now`
+    pub fn expire(&mut self, now: DateTime<Utc>) {
+        if self.force_charge.is_some_and(|o| o.expired(now)) {
+            info!("Forced charging ended");
+            self.force_charge = None;
+        }
+        if self.pause.is_some_and(|o| o.expired(now)) {
+            info!("Pause ended, resuming control");
+            self.pause = None;
+        }
+        if self.max_soc.is_some_and(|o| o.expired(now)) {
+            info!("SoC cap ended");
+            self.max_soc = None;
+        }
+        if self.boost.is_some_and(|b| b.by <= now) {
+            info!("Boost deadline reached");
+            self.boost = None;
+        }
+    }
+
+    /// End of the forced charge, if one is active
+    pub fn force_charge_until(&self) -> Option<DateTime<FixedOffset>> {
+        self.force_charge.and_then(|o| o.until)
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.pause.is_some()
+    }
+
+    pub fn max_soc(&self) -> Option<f64> {
+        self.max_soc.map(|o| o.value)
+    }
+
+    pub fn boost_target(&self) -> Option<Boost> {
+        self.boost
+    }
+
+    /// Active overrides for the status, e.g. "max_soc 80% until ..."
+    pub fn describe(&self) -> Vec<String> {
+        let mut active = Vec::new();
+        if let Some(o) = &self.force_charge {
+            active.push(format!("force_charge{}", o.describe_until()));
+        }
+        if let Some(o) = &self.pause {
+            active.push(format!("pause{}", o.describe_until()));
+        }
+        if let Some(o) = &self.max_soc {
+            active.push(format!("max_soc {:.0}%{}", o.value, o.describe_until()));
+        }
+        if let Some(boost) = &self.boost {
+            active.push(boost.describe());
+        }
+        active
+    }
+}
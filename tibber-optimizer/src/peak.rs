@@ -0,0 +1,332 @@
+//! Peak shaving for capacity-based grid tariffs (Norwegian kapasitetsledd,
+//! Belgian capaciteitstarief). Grid import is averaged over clock-aligned
+//! windows (an hour, or a quarter hour), and each day's highest window average
+//! is tracked through the month. The bill goes by the average of the highest
+//! few days' peaks (or the single highest). The planners keep import below a
+//! cap: the configured threshold, or that billed peak once it is higher, as
+//! shaving below a peak already billed saves nothing.
+
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Duration, FixedOffset, NaiveDate, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::config::PeakShavingConfig;
+use crate::mqtt::BatteryState;
+use crate::persist;
+use crate::stats::MAX_SAMPLE_GAP_HOURS;
+
+/// Grid readings older than this don't count as a measurement
+const MAX_AGE_SECS: i64 = 120;
+
+/// A day's highest window average import
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct DailyPeak {
+    /// Tariff-local day
+    day: NaiveDate,
+    peak_w: f64,
+    at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct PeakState {
+    /// Month the peaks are for (tariff-local, `YYYY-MM`)
+    month: Option<String>,
+    /// Highest window average import per day this month
+    #[serde(default)]
+    daily_peaks: Vec<DailyPeak>,
+    window_start: Option<DateTime<Utc>>,
+    /// Energy imported in the current window so far (kWh)
+    window_kwh: f64,
+}
+
+/// How much may be imported, for the planners
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PeakLimit {
+    /// Highest average import over a window (W)
+    pub cap_w: f64,
+    /// Highest average import over the rest of the current window (W)
+    pub current_cap_w: f64,
+    pub window_ends_at: DateTime<Utc>,
+}
+
+impl PeakLimit {
+    /// Highest average import in the slot starting at `starts_at` (W)
+    pub fn cap_at(&self, starts_at: DateTime<FixedOffset>) -> f64 {
+        if starts_at < self.window_ends_at {
+            self.current_cap_w
+        } else {
+            self.cap_w
+        }
+    }
+}
+
+/// The month's peaks and the cap held to
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct PeakStatus {
+    /// Highest window average import this month (W)
+    pub month_peak_w: f64,
+    pub month_peak_at: Option<String>,
+    /// Average of the highest daily peaks the tariff bills this month (W)
+    pub billed_peak_w: f64,
+    /// Average import the planners hold to (W)
+    pub cap_w: f64,
+    /// Average import over the current window so far (W)
+    pub window_average_w: f64,
+}
+
+#[derive(Debug)]
+pub struct PeakTracker {
+    config: PeakShavingConfig,
+    path: PathBuf,
+    state: PeakState,
+    /// Last grid reading and when it was taken
+    last_sample: Option<(DateTime<Utc>, f64)>,
+    last_save: Option<DateTime<Utc>>,
+}
+
+impl PeakTracker {
+    /// Load this month's peak from `<data_dir>/peak.json`, starting fresh if absent
+    pub fn load(config: PeakShavingConfig, data_dir: &str) -> Self {
+        let path = Path::new(data_dir).join("peak.json");
+        Self {
+            config,
+            state: persist::load_json(&path),
+            path,
+            last_sample: None,
+            last_save: None,
+        }
+    }
+
+    fn window(&self) -> Duration {
+        Duration::minutes(self.config.window_minutes as i64)
+    }
+
+    /// Start of the clock-aligned window containing `at`
+    fn window_start(&self, at: DateTime<Utc>) -> DateTime<Utc> {
+        let window_secs = self.window().num_seconds();
+        let secs = at.timestamp() - at.timestamp().rem_euclid(window_secs);
+        DateTime::from_timestamp(secs, 0).unwrap_or(at)
+    }
+
+    /// Tariff-local month of `at`, as `YYYY-MM`
+    fn month(at: DateTime<FixedOffset>) -> String {
+        at.format("%Y-%m").to_string()
+    }
+
+    /// Daily peaks in the month of `at`
+    fn month_peaks(&self, at: DateTime<FixedOffset>) -> &[DailyPeak] {
+        if self.state.month.as_deref() == Some(Self::month(at).as_str()) {
+            &self.state.daily_peaks
+        } else {
+            &[]
+        }
+    }
+
+    /// Highest daily peak in the month of `at`
+    fn month_peak(&self, at: DateTime<FixedOffset>) -> Option<DailyPeak> {
+        self.month_peaks(at).iter().copied().max_by(|a, b| a.peak_w.total_cmp(&b.peak_w))
+    }
+
+    /// Average of the highest `billed_peaks` of `peaks` (W)
+    fn billed_w(&self, peaks: &[DailyPeak]) -> f64 {
+        let mut peaks_w: Vec<f64> = peaks.iter().map(|peak| peak.peak_w).collect();
+        peaks_w.sort_by(|a, b| b.total_cmp(a));
+        peaks_w.truncate(self.config.billed_peaks as usize);
+        if peaks_w.is_empty() {
+            0.0
+        } else {
+            peaks_w.iter().sum::<f64>() / peaks_w.len() as f64
+        }
+    }
+
+    /// Average of the highest daily peaks billed in the month of `at` (W)
+    fn billed_peak_w(&self, at: DateTime<FixedOffset>) -> f64 {
+        self.billed_w(self.month_peaks(at))
+    }
+
+    /// Close the current window, raising its day's peak if it was higher
+    fn close_window(&mut self, start: DateTime<Utc>, offset: &FixedOffset) {
+        let average_w = self.state.window_kwh * 1000.0 / (self.window().num_seconds() as f64 / 3600.0);
+        let local_start = start.with_timezone(offset);
+        let month = Self::month(local_start);
+        if self.state.month.as_deref() != Some(month.as_str()) {
+            if let Some(previous) = &self.state.month {
+                info!("Billed peak import in {}: {:.0}W", previous, self.billed_w(&self.state.daily_peaks));
+            }
+            self.state.month = Some(month);
+            self.state.daily_peaks.clear();
+        }
+        let billed_w = self.billed_peak_w(local_start);
+        let day = local_start.date_naive();
+        match self.state.daily_peaks.iter_mut().find(|peak| peak.day == day) {
+            Some(peak) if average_w > peak.peak_w => {
+                peak.peak_w = average_w;
+                peak.at = start;
+            }
+            Some(_) => {}
+            None => self.state.daily_peaks.push(DailyPeak {
+                day,
+                peak_w: average_w,
+                at: start,
+            }),
+        }
+        let raised_w = self.billed_peak_w(local_start);
+        if raised_w > billed_w && raised_w > self.config.threshold_w {
+            warn!(
+                "Billed peak import this month up to {:.0}W (threshold {:.0}W)",
+                raised_w, self.config.threshold_w
+            );
+        }
+        self.state.window_kwh = 0.0;
+    }
+
+    /// Count the import since the last reading into the windows it fell in
+    pub fn record(&mut self, state: &BatteryState, local_now: DateTime<FixedOffset>) {
+        let now = local_now.with_timezone(&Utc);
+        let fresh = state
+            .last_grid_power_update
+            .is_some_and(|at| (now - at).num_seconds() <= MAX_AGE_SECS);
+        let Some(grid_power_w) = state.grid_power_w.filter(|_| fresh) else {
+            self.last_sample = None;
+            return;
+        };
+
+        let mut from = match self.last_sample {
+            Some((at, _)) if (now - at).num_seconds() as f64 / 3600.0 <= MAX_SAMPLE_GAP_HOURS => at,
+            _ => now,
+        };
+        let import_w = self.last_sample.map_or(0.0, |(_, power_w)| power_w.max(0.0));
+        let mut start = self.state.window_start.unwrap_or_else(|| self.window_start(from));
+        while start + self.window() <= now {
+            let end = start + self.window();
+            self.state.window_kwh += import_w / 1000.0 * (end - from.min(end)).num_seconds() as f64 / 3600.0;
+            self.close_window(start, local_now.offset());
+            from = from.max(end);
+            start = if from < now { end } else { self.window_start(now) };
+        }
+        self.state.window_kwh += import_w / 1000.0 * (now - from).num_seconds().max(0) as f64 / 3600.0;
+        self.state.window_start = Some(start);
+        self.last_sample = Some((now, grid_power_w));
+
+        let due = self
+            .last_save
+            .is_none_or(|last| (now - last).num_seconds() >= persist::SAVE_INTERVAL_SECS);
+        if due {
+            match persist::save_json(&self.path, &self.state) {
+                Ok(()) => self.last_save = Some(now),
+                Err(e) => warn!("Failed to save peak to {}: {}", self.path.display(), e),
+            }
+        }
+    }
+
+    /// Average import to hold to (W)
+    fn cap_w(&self, now: DateTime<FixedOffset>) -> f64 {
+        self.config.threshold_w.max(self.billed_peak_w(now))
+    }
+
+    /// The cap for the planners, with what the current window has left
+    pub fn limit(&self, now: DateTime<FixedOffset>) -> PeakLimit {
+        let utc_now = now.with_timezone(&Utc);
+        let start = self.state.window_start.unwrap_or_else(|| self.window_start(utc_now));
+        let window_ends_at = start + self.window();
+        let window_hours = self.window().num_seconds() as f64 / 3600.0;
+        let remaining_hours = ((window_ends_at - utc_now).num_seconds() as f64 / 3600.0).max(1.0 / 60.0);
+        let allowed_kwh = self.cap_w(now) / 1000.0 * window_hours - self.state.window_kwh;
+        PeakLimit {
+            cap_w: self.cap_w(now),
+            current_cap_w: (allowed_kwh * 1000.0 / remaining_hours).max(0.0),
+            window_ends_at,
+        }
+    }
+
+    pub fn status(&self, now: DateTime<FixedOffset>) -> PeakStatus {
+        let utc_now = now.with_timezone(&Utc);
+        let start = self.state.window_start.unwrap_or_else(|| self.window_start(utc_now));
+        let elapsed_hours = (utc_now - start).num_seconds() as f64 / 3600.0;
+        let month_peak = self.month_peak(now);
+        PeakStatus {
+            month_peak_w: month_peak.map_or(0.0, |peak| peak.peak_w),
+            month_peak_at: month_peak.map(|peak| peak.at.to_rfc3339()),
+            billed_peak_w: self.billed_peak_w(now),
+            cap_w: self.cap_w(now),
+            window_average_w: if elapsed_hours > 0.0 {
+                self.state.window_kwh * 1000.0 / elapsed_hours
+            } else {
+                0.0
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tracker(name: &str, billed_peaks: u32) -> (PeakTracker, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("{}_{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let config = PeakShavingConfig {
+            enabled: true,
+            threshold_w: 5000.0,
+            window_minutes: 60,
+            billed_peaks,
+        };
+        let mut tracker = PeakTracker::load(config, dir.to_str().unwrap());
+        tracker.state = PeakState::default();
+        (tracker, dir)
+    }
+
+    /// Import `power_w` for the hour from `start`, then 2 kW, sampling every 5
+    /// minutes up to `minutes` in
+    fn import(tracker: &mut PeakTracker, start: DateTime<FixedOffset>, power_w: f64, minutes: i64) {
+        let mut state = BatteryState::default();
+        for minute in (0..=minutes).step_by(5) {
+            let now = start + Duration::minutes(minute);
+            state.grid_power_w = Some(if minute < 60 { power_w } else { 2000.0 });
+            state.last_grid_power_update = Some(now.with_timezone(&Utc));
+            tracker.record(&state, now);
+        }
+    }
+
+    #[test]
+    fn tracks_the_hourly_peak_and_what_the_window_has_left() {
+        let (mut tracker, dir) = tracker("peak_test", 1);
+        let start = DateTime::parse_from_rfc3339("2025-12-10T17:00:00+01:00").unwrap();
+
+        // 8 kW through 17:00-18:00, then 2 kW
+        import(&mut tracker, start, 8000.0, 90);
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!((tracker.status(start).month_peak_w - 8000.0).abs() < 1.0, "{:?}", tracker.state);
+
+        // Half of the 18:00 window at 2 kW leaves 14 kW on average for the rest
+        let limit = tracker.limit(start + Duration::minutes(90));
+        assert_eq!(limit.cap_w, 8000.0);
+        assert!((limit.current_cap_w - 14000.0).abs() < 1.0, "{:?}", limit);
+    }
+
+    #[test]
+    fn caps_at_the_average_of_the_highest_daily_peaks() {
+        let (mut tracker, dir) = tracker("peak_days_test", 3);
+        let day = DateTime::parse_from_rfc3339("2025-12-10T17:00:00+01:00").unwrap();
+
+        for (hours, power_w) in [(0, 8000.0), (24, 6000.0), (26, 5000.0), (48, 7000.0), (72, 2000.0)] {
+            import(&mut tracker, day + Duration::hours(hours), power_w, 60);
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        // One peak per day, the billed three being 8, 7 and 6 kW
+        let now = day + Duration::days(3) + Duration::hours(2);
+        let status = tracker.status(now);
+        assert_eq!(tracker.state.daily_peaks.len(), 4);
+        assert!((status.month_peak_w - 8000.0).abs() < 1.0, "{:?}", status);
+        assert!((status.billed_peak_w - 7000.0).abs() < 1.0, "{:?}", status);
+        assert!((tracker.limit(now).cap_w - 7000.0).abs() < 1.0);
+
+        // A new month starts over at the threshold
+        let next_month = DateTime::parse_from_rfc3339("2026-01-01T00:30:00+01:00").unwrap();
+        assert_eq!(tracker.limit(next_month).cap_w, 5000.0);
+    }
+}
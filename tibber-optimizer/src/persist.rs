@@ -0,0 +1,35 @@
+use std::path::Path;
+
+use anyhow::Result;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use tracing::{info, warn};
+
+/// How often periodically updated state is flushed to disk (SD cards on GX
+/// devices don't like a write every minute)
+pub const SAVE_INTERVAL_SECS: i64 = 900;
+
+/// Load JSON state from `path`, starting fresh if it is missing or unreadable
+pub fn load_json<T: DeserializeOwned + Default>(path: &Path) -> T {
+    match std::fs::read_to_string(path) {
+        Ok(content) => match serde_json::from_str(&content) {
+            Ok(state) => {
+                info!("Loaded state from {}", path.display());
+                state
+            }
+            Err(e) => {
+                warn!("Ignoring unreadable state in {}: {}", path.display(), e);
+                T::default()
+            }
+        },
+        Err(_) => T::default(),
+    }
+}
+
+/// Write JSON state atomically (temp file + rename)
+pub fn save_json<T: Serialize>(path: &Path, state: &T) -> Result<()> {
+    let tmp = path.with_extension("json.tmp");
+    std::fs::write(&tmp, serde_json::to_vec_pretty(state)?)?;
+    std::fs::rename(&tmp, path)?;
+    Ok(())
+}
@@ -0,0 +1,178 @@
+//! Tesla Powerwall over the Gateway's local API. A Powerwall can't follow a
+//! grid setpoint, so each optimizer mode is mapped onto the operation mode,
+//! backup reserve and grid export rule instead: raising the reserve above the
+//! SoC charges from the grid, and allowing battery export sells to it.
+
+use std::sync::Mutex;
+
+use anyhow::{Context, Result};
+use tracing::{debug, info};
+
+use crate::config::PowerwallConfig;
+use crate::controller::{BatteryController, WriteFuture};
+use crate::http::HttpClient;
+use crate::mqtt::BatteryState;
+use crate::optimizer::BatteryMode;
+
+/// What one mode asks of the Gateway
+#[derive(Debug, Clone, PartialEq)]
+struct Settings {
+    real_mode: &'static str,
+    reserve_percent: f64,
+    export_rule: &'static str,
+}
+
+pub struct PowerwallController {
+    config: PowerwallConfig,
+    http_client: HttpClient,
+    /// Reserve outside charge windows (%)
+    reserve_percent: f64,
+    /// Reserve that makes the Powerwall charge from the grid (%)
+    charge_percent: f64,
+    /// Latest SoC, to hold the battery where it is
+    soc: Mutex<Option<f64>>,
+    token: tokio::sync::Mutex<Option<String>>,
+    /// Last mode and settings written; the Gateway reloads its configuration
+    /// on every write, so unchanged settings aren't sent again
+    written: Mutex<Option<(BatteryMode, Settings)>>,
+}
+
+impl PowerwallController {
+    pub fn new(config: PowerwallConfig, min_soc_percent: f64, max_soc_percent: f64) -> Result<Self> {
+        Ok(Self {
+            reserve_percent: config.reserve_percent.unwrap_or(min_soc_percent),
+            charge_percent: max_soc_percent,
+            config,
+            http_client: HttpClient::accepting_invalid_certs()?,
+            soc: Mutex::new(None),
+            token: tokio::sync::Mutex::new(None),
+            written: Mutex::new(None),
+        })
+    }
+
+    fn settings(&self, mode: BatteryMode) -> Settings {
+        let (real_mode, reserve_percent, export_rule) = match mode {
+            // The reserve can't set a charge rate: both charge at full power
+            BatteryMode::ChargeFull | BatteryMode::ChargeReduced => {
+                ("self_consumption", self.charge_percent, "pv_only")
+            }
+            BatteryMode::DischargeToGrid => ("autonomous", self.reserve_percent, "battery_ok"),
+            BatteryMode::SelfConsumptionPreventFeedIn => ("self_consumption", self.reserve_percent, "never"),
+            BatteryMode::SelfConsumption | BatteryMode::SelfConsumptionPreventGridPull => {
+                ("self_consumption", self.reserve_percent, "pv_only")
+            }
+            BatteryMode::Idle => {
+                let soc = self.soc.lock().unwrap().unwrap_or(self.reserve_percent);
+                ("self_consumption", soc.round().max(self.reserve_percent), "pv_only")
+            }
+        };
+        Settings {
+            real_mode,
+            reserve_percent,
+            export_rule,
+        }
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("https://{}{}", self.config.host, path)
+    }
+
+    async fn login(&self) -> Result<String> {
+        let body = serde_json::json!({
+            "username": "customer",
+            "email": self.config.email,
+            "password": self.config.password,
+        });
+        let response = self.http_client.post_json(&self.url("/api/login/Basic"), &[], &body).await?;
+        if !response.is_success() {
+            anyhow::bail!("Powerwall login failed: {} - {}", response.status, response.text());
+        }
+        let body: serde_json::Value = serde_json::from_slice(&response.body)?;
+        let token = body["token"].as_str().context("Powerwall login returned no token")?;
+        info!("Logged in to the Powerwall Gateway at {}", self.config.host);
+        Ok(token.to_string())
+    }
+
+    async fn apply(&self, settings: &Settings) -> Result<()> {
+        let mut token = self.token.lock().await;
+        if token.is_none() {
+            *token = Some(self.login().await?);
+        }
+        let auth = format!("Bearer {}", token.as_deref().expect("logged in above"));
+        let headers = [("Authorization", auth.as_str())];
+
+        let mut operation = serde_json::json!({
+            "real_mode": settings.real_mode,
+            "backup_reserve_percent": settings.reserve_percent,
+        });
+        if self.config.set_export_rule {
+            operation["customer_preferred_export_rule"] = settings.export_rule.into();
+        }
+        let response = self.http_client.post_json(&self.url("/api/operation"), &headers, &operation).await?;
+        if matches!(response.status, 401 | 403) {
+            // Log in again on the next write
+            *token = None;
+        }
+        if !response.is_success() {
+            anyhow::bail!("Powerwall operation update failed: {} - {}", response.status, response.text());
+        }
+
+        // The new settings only take effect once the configuration is committed
+        let response = self.http_client.get(&self.url("/api/config/completed"), &headers).await?;
+        if !response.is_success() {
+            anyhow::bail!("Powerwall configuration commit failed: {} - {}", response.status, response.text());
+        }
+        Ok(())
+    }
+}
+
+impl BatteryController for PowerwallController {
+    fn name(&self) -> &'static str {
+        "Powerwall local API"
+    }
+
+    /// Fallback writes carry no mode: plain self-consumption
+    fn write_setpoint(&self, _setpoint_w: f64) -> WriteFuture<'_> {
+        self.write_mode(BatteryMode::SelfConsumption, 0.0)
+    }
+
+    fn write_mode(&self, mode: BatteryMode, _setpoint_w: f64) -> WriteFuture<'_> {
+        Box::pin(async move {
+            let settings = self.settings(mode);
+            let unchanged = match &*self.written.lock().unwrap() {
+                // Keep holding at the SoC the idle window started at
+                Some((BatteryMode::Idle, _)) if mode == BatteryMode::Idle => true,
+                Some((_, written)) => *written == settings,
+                None => false,
+            };
+            if unchanged {
+                return Ok(());
+            }
+            self.apply(&settings).await.with_context(|| format!("Powerwall at {}", self.config.host))?;
+            debug!(
+                "Powerwall set to {} with {:.0}% reserve, export {} for {}",
+                settings.real_mode, settings.reserve_percent, settings.export_rule, mode
+            );
+            *self.written.lock().unwrap() = Some((mode, settings));
+            Ok(())
+        })
+    }
+
+    fn observe(&self, state: &BatteryState) {
+        *self.soc.lock().unwrap() = Some(state.soc);
+    }
+
+    fn writes_every_cycle(&self) -> bool {
+        true
+    }
+
+    /// Back to plain self-consumption with the usual reserve, so a raised
+    /// charge reserve isn't left behind
+    fn release(&self) -> WriteFuture<'_> {
+        Box::pin(async move {
+            self.write_mode(BatteryMode::SelfConsumption, 0.0).await?;
+            *self.written.lock().unwrap() = None;
+            Ok(())
+        })
+    }
+}
@@ -0,0 +1,122 @@
+use chrono::{DateTime, Duration, FixedOffset, NaiveDate, NaiveTime, Weekday};
+use serde::Deserialize;
+
+use crate::config::{OptimizerConfig, Planner};
+use crate::rules::window_covers;
+
+/// Optimizer settings overridden on selected days or times of day, e.g.
+/// home-office Fridays with a higher daytime load, or a different strategy
+/// overnight than during the day
+#[derive(Debug, Clone, Deserialize)]
+pub struct OptimizerPreset {
+    pub name: String,
+    /// Weekdays this preset applies to (`mon`, `tue`, ...; default: every
+    /// day when a time window is set)
+    #[serde(default)]
+    pub days: Vec<Weekday>,
+    /// Specific dates this preset applies to; these win over weekday presets
+    #[serde(default)]
+    pub dates: Vec<NaiveDate>,
+    /// Local start and end time (default: the whole day); a window ending
+    /// before it starts runs past midnight into the next day
+    #[serde(default)]
+    pub start: Option<NaiveTime>,
+    #[serde(default)]
+    pub end: Option<NaiveTime>,
+    /// Strategy to plan with while the preset is active
+    pub planner: Option<Planner>,
+    pub min_discharge_spread: Option<f64>,
+    pub cheapest_percentile: Option<f64>,
+    pub charge_percentile: Option<f64>,
+    pub cheapest_charge_soc_percent: Option<f64>,
+    pub cheap_charge_soc_percent: Option<f64>,
+    pub expensive_percentile: Option<f64>,
+    pub discharge_percentile: Option<f64>,
+    pub base_consumption_w: Option<f64>,
+    pub setpoint_offset_w: Option<f64>,
+    pub horizon_hours: Option<f64>,
+}
+
+impl OptimizerPreset {
+    /// The base optimizer settings with this preset's overrides applied
+    pub fn apply(&self, base: &OptimizerConfig) -> OptimizerConfig {
+        OptimizerConfig {
+            min_discharge_spread: self.min_discharge_spread.unwrap_or(base.min_discharge_spread),
+            cheapest_percentile: self.cheapest_percentile.unwrap_or(base.cheapest_percentile),
+            charge_percentile: self.charge_percentile.unwrap_or(base.charge_percentile),
+            cheapest_charge_soc_percent: self.cheapest_charge_soc_percent.or(base.cheapest_charge_soc_percent),
+            cheap_charge_soc_percent: self.cheap_charge_soc_percent.or(base.cheap_charge_soc_percent),
+            expensive_percentile: self.expensive_percentile.unwrap_or(base.expensive_percentile),
+            discharge_percentile: self.discharge_percentile.unwrap_or(base.discharge_percentile),
+            base_consumption_w: self.base_consumption_w.unwrap_or(base.base_consumption_w),
+            setpoint_offset_w: self.setpoint_offset_w.unwrap_or(base.setpoint_offset_w),
+            horizon_hours: self.horizon_hours.or(base.horizon_hours),
+            planner: self.planner.unwrap_or(base.planner),
+            ..base.clone()
+        }
+    }
+
+    fn has_window(&self) -> bool {
+        self.start.is_some() || self.end.is_some()
+    }
+
+    /// Whether one of the preset's dates covers local time `at`; a window
+    /// running past midnight belongs to the date it starts on
+    fn covers_date(&self, at: DateTime<FixedOffset>) -> bool {
+        if !window_covers(&[], self.start, self.end, at) {
+            return false;
+        }
+        let started_yesterday = match (self.start, self.end) {
+            (start, Some(end)) => end <= start.unwrap_or(NaiveTime::MIN) && at.time() < end,
+            _ => false,
+        };
+        let date = if started_yesterday {
+            at.date_naive() - Duration::days(1)
+        } else {
+            at.date_naive()
+        };
+        self.dates.contains(&date)
+    }
+
+    /// Whether the preset's weekdays (or every day, with a window) cover
+    /// local time `at`
+    fn covers_day(&self, at: DateTime<FixedOffset>) -> bool {
+        (!self.days.is_empty() || self.has_window()) && window_covers(&self.days, self.start, self.end, at)
+    }
+}
+
+/// The preset for local time `at`: a date match first, then the first listed
+/// preset whose weekdays or time window cover it
+pub fn select(presets: &[OptimizerPreset], at: DateTime<FixedOffset>) -> Option<&OptimizerPreset> {
+    presets
+        .iter()
+        .filter(|p| !p.dates.is_empty())
+        .find(|p| p.covers_date(at))
+        .or_else(|| presets.iter().filter(|p| p.dates.is_empty()).find(|p| p.covers_day(at)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn preset(yaml: &str) -> OptimizerPreset {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn selects_presets_by_date_weekday_and_time_of_day() {
+        let presets = vec![
+            preset("name: holiday\ndates: [\"2025-12-25\"]"),
+            preset("name: day\nstart: \"07:00\"\nend: \"21:00\"\nplanner: tiers"),
+            preset("name: night\nstart: \"21:00\"\nend: \"07:00\"\nplanner: optimal"),
+        ];
+        let at = |time: &str| DateTime::parse_from_rfc3339(time).unwrap();
+        let name = |time: &str| select(&presets, at(time)).map(|p| p.name.as_str());
+
+        assert_eq!(name("2025-12-24T12:00:00+01:00"), Some("day"));
+        assert_eq!(name("2025-12-24T23:00:00+01:00"), Some("night"));
+        assert_eq!(name("2025-12-25T06:59:00+01:00"), Some("holiday"));
+        assert_eq!(name("2025-12-26T06:59:00+01:00"), Some("night"));
+        assert_eq!(name("2025-12-26T07:00:00+01:00"), Some("day"));
+    }
+}
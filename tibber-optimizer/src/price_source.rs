@@ -0,0 +1,168 @@
+use anyhow::Result;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+use tracing::{debug, info, warn};
+
+use crate::config::{BiddingZone, Config, ExportConfig, PriceProviderKind, TariffConfig};
+use crate::error::Error;
+use crate::prices::{PriceCache, PricePoint};
+use crate::record::Recorder;
+
+pub type FetchFuture<'a> = Pin<Box<dyn Future<Output = Result<PriceCache>> + Send + 'a>>;
+
+/// Returned by a provider whose prices belong to another market than the
+/// configured bidding zone
+#[derive(Debug, thiserror::Error)]
+#[error("prices don't match bidding zone {expected}: {reason}")]
+pub struct ZoneMismatch {
+    pub expected: BiddingZone,
+    pub reason: String,
+}
+
+/// A day-ahead price source feeding the shared `PriceCache`
+pub trait PriceProvider: Send + Sync {
+    /// Name used in logs
+    fn name(&self) -> &'static str;
+
+    /// How often to refresh prices (in seconds)
+    fn refresh_interval_secs(&self) -> u64;
+
+    /// Fetch today's and tomorrow's prices as a new snapshot with `generation`
+    fn fetch(&self, generation: u64) -> FetchFuture<'_>;
+}
+
+/// The configured price provider and its latest snapshot
+pub struct PriceSource {
+    provider: Box<dyn PriceProvider>,
+    /// Grid fees added to the fetched buy prices
+    tariff: TariffConfig,
+    /// Derives the sell price series from the fetched buy prices
+    export: ExportConfig,
+    /// Latest price snapshot; readers share it via `Arc` instead of cloning
+    cache: RwLock<Arc<PriceCache>>,
+    /// When the provider last rejected the credentials; those are retried at
+    /// the refresh interval rather than every cycle
+    auth_failed_at: RwLock<Option<chrono::DateTime<chrono::Utc>>>,
+}
+
+impl PriceSource {
+    pub fn new(provider: Box<dyn PriceProvider>, tariff: TariffConfig, export: ExportConfig) -> Self {
+        Self {
+            provider,
+            tariff,
+            export,
+            cache: RwLock::new(Arc::new(PriceCache::default())),
+            auth_failed_at: RwLock::new(None),
+        }
+    }
+
+    /// The provider selected by `price_provider`
+    pub fn from_config(
+        config: &Config,
+        #[cfg_attr(not(feature = "tibber"), allow(unused_variables))] recorder: Option<Recorder>,
+    ) -> Result<Self> {
+        let provider: Box<dyn PriceProvider> = match config.price_provider {
+            #[cfg(feature = "tibber")]
+            PriceProviderKind::Tibber => {
+                let tibber = config
+                    .tibber
+                    .clone()
+                    .ok_or_else(|| anyhow::anyhow!("price_provider is tibber but the tibber section is missing"))?;
+                Box::new(crate::tibber::TibberClient::new(tibber, recorder))
+            }
+            #[cfg(feature = "entsoe")]
+            PriceProviderKind::Entsoe => {
+                let entsoe = config
+                    .entsoe
+                    .clone()
+                    .ok_or_else(|| anyhow::anyhow!("price_provider is entsoe but the entsoe section is missing"))?;
+                Box::new(crate::entsoe::EntsoeClient::new(entsoe))
+            }
+            #[allow(unreachable_patterns)]
+            other => anyhow::bail!("Price provider {:?} is not included in this build", other),
+        };
+        Ok(Self::new(provider, config.tariff.clone(), config.export.clone()))
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.provider.name()
+    }
+
+    pub fn refresh_interval_secs(&self) -> u64 {
+        self.provider.refresh_interval_secs()
+    }
+
+    pub async fn fetch_prices(&self) -> Result<()> {
+        info!("Fetching prices from {}", self.provider.name());
+
+        let generation = self.cache.read().await.generation + 1;
+        let mut cache = match self.provider.fetch(generation).await {
+            Ok(cache) => cache,
+            Err(e) if e.is::<ZoneMismatch>() => {
+                // Another market's prices are worse than none: drop the cached
+                // ones and retry at the next refresh
+                let empty = PriceCache::default().with_generation(generation);
+                *self.cache.write().await = Arc::new(empty.fetched_at(chrono::Utc::now().fixed_offset()));
+                return Err(e);
+            }
+            Err(e) => return Err(e),
+        };
+        let filled = cache.fill_gaps();
+        if filled > 0 {
+            warn!("{} price slots missing from {}, interpolated from their neighbors", filled, self.provider.name());
+        }
+        cache.apply_tariff(&self.tariff, &self.export);
+
+        match cache.slots().last() {
+            Some(last) => info!("Fetched {} price slots up to {}", cache.slots().len(), last.ends_at),
+            None => info!("Fetched no price slots"),
+        }
+
+        if !cache.has_tomorrow(chrono::Utc::now()) {
+            debug!("Tomorrow's prices not yet available (usually published in the afternoon)");
+        }
+
+        // Swap in the new snapshot; readers holding the old one keep it alive
+        *self.cache.write().await = Arc::new(cache);
+
+        Ok(())
+    }
+
+    pub async fn get_cache(&self) -> Arc<PriceCache> {
+        self.cache.read().await.clone()
+    }
+
+    pub async fn get_current_price(&self) -> Option<PricePoint> {
+        // The slot containing the current time, else the provider's current slot
+        self.get_cache().await.price_at(chrono::Utc::now()).cloned()
+    }
+
+    /// Check if cache needs refresh
+    pub async fn needs_refresh(&self) -> bool {
+        let interval_passed = |since: chrono::DateTime<chrono::Utc>| {
+            chrono::Utc::now().signed_duration_since(since).num_seconds() as u64 >= self.provider.refresh_interval_secs()
+        };
+        if self.auth_failed_at.read().await.is_some_and(|at| !interval_passed(at)) {
+            return false;
+        }
+
+        let cache = self.cache.read().await;
+        match cache.last_fetch {
+            None => true,
+            Some(last_fetch) => interval_passed(last_fetch.with_timezone(&chrono::Utc)),
+        }
+    }
+
+    /// Refresh prices if needed
+    pub async fn refresh_if_needed(&self) -> Result<bool> {
+        if !self.needs_refresh().await {
+            return Ok(false);
+        }
+        let result = self.fetch_prices().await;
+        let auth_failed = matches!(result.as_ref().err().and_then(|e| e.downcast_ref::<Error>()), Some(Error::Auth(_)));
+        *self.auth_failed_at.write().await = auth_failed.then(chrono::Utc::now);
+        result.map(|()| true)
+    }
+}
@@ -0,0 +1,394 @@
+use chrono::{DateTime, Duration, FixedOffset, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::config::{ExportConfig, ExportModel, TariffConfig};
+
+/// Where a slot's price comes from
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SlotOrigin {
+    /// Published by the price provider
+    #[default]
+    Published,
+    /// Missing from the provider's data, interpolated from neighboring slots
+    Interpolated,
+    /// Not published yet, forecast from an earlier day's prices
+    Forecast,
+    /// The provider's slot with a realtime price in place of the day-ahead one
+    Realtime,
+}
+
+/// One price slot: its time span, buy and sell price, and where they come from
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PricePoint {
+    /// Buy price including fees and taxes
+    pub total: f64,
+    pub energy: f64,
+    pub tax: f64,
+    #[serde(rename = "startsAt")]
+    pub starts_at: DateTime<FixedOffset>,
+    /// End of the slot; slots need not all have the same length
+    #[serde(rename = "endsAt")]
+    pub ends_at: DateTime<FixedOffset>,
+    /// The provider's price tier (Tibber: VERY_CHEAP, CHEAP, NORMAL, EXPENSIVE, VERY_EXPENSIVE)
+    #[serde(default)]
+    pub level: Option<String>,
+    #[serde(default)]
+    pub currency: Option<String>,
+    /// Price paid for exported energy, set from the export model
+    #[serde(default)]
+    pub sell: Option<f64>,
+    #[serde(default)]
+    pub source: SlotOrigin,
+}
+
+impl PricePoint {
+    /// Price paid for exported energy; without an export model export earns the buy price
+    pub fn sell_price(&self) -> f64 {
+        self.sell.unwrap_or(self.total)
+    }
+
+    /// Length of the slot
+    pub fn duration(&self) -> chrono::Duration {
+        self.ends_at - self.starts_at
+    }
+
+    /// Whether `time` falls within the slot
+    pub fn contains(&self, time: DateTime<Utc>) -> bool {
+        self.starts_at <= time && time < self.ends_at
+    }
+
+    /// Not published by the provider but filled in or forecast
+    pub fn is_estimated(&self) -> bool {
+        matches!(self.source, SlotOrigin::Interpolated | SlotOrigin::Forecast)
+    }
+}
+
+/// Known price slots, sorted by start time and without overlaps, so the slot
+/// for any time is found by bisection whichever day or resolution it has
+#[derive(Debug, Clone, Default)]
+pub struct PriceCache {
+    /// The provider's current slot, for when the slots don't cover now
+    pub current: Option<PricePoint>,
+    slots: Vec<PricePoint>,
+    pub last_fetch: Option<DateTime<FixedOffset>>,
+    /// Incremented on every successful fetch so derived data can be cached
+    pub generation: u64,
+}
+
+impl PriceCache {
+    /// A cache of `slots` in any order; of overlapping slots the one starting
+    /// first, or else the one given first, is kept
+    pub fn new(mut slots: Vec<PricePoint>, current: Option<PricePoint>) -> Self {
+        slots.sort_by_key(|p| p.starts_at);
+        let mut sorted: Vec<PricePoint> = Vec::with_capacity(slots.len());
+        for slot in slots {
+            if sorted.last().is_none_or(|last| last.ends_at <= slot.starts_at) {
+                sorted.push(slot);
+            }
+        }
+        Self {
+            current,
+            slots: sorted,
+            ..Self::default()
+        }
+    }
+
+    pub fn with_generation(self, generation: u64) -> Self {
+        Self { generation, ..self }
+    }
+
+    /// Mark the cache as fetched at `fetched_at`
+    pub fn fetched_at(self, fetched_at: DateTime<FixedOffset>) -> Self {
+        Self {
+            last_fetch: Some(fetched_at),
+            ..self
+        }
+    }
+
+    /// All known slots in time order
+    pub fn slots(&self) -> &[PricePoint] {
+        &self.slots
+    }
+
+    /// Get all available prices in time order
+    pub fn all_prices(&self) -> impl Iterator<Item = &PricePoint> + Clone {
+        self.slots.iter()
+    }
+
+    /// Index of the slot containing `time`
+    pub fn index_at(&self, time: DateTime<Utc>) -> Option<usize> {
+        let index = self.slots.partition_point(|p| p.ends_at <= time);
+        self.slots.get(index).filter(|p| p.contains(time)).map(|_| index)
+    }
+
+    /// Whether the slots reach into the day after `now`, in the prices' time zone
+    pub fn has_tomorrow(&self, now: DateTime<Utc>) -> bool {
+        self.slots
+            .last()
+            .is_some_and(|last| last.starts_at.date_naive() > now.with_timezone(last.starts_at.offset()).date_naive())
+    }
+
+    /// The cache with the day after `now` forecast as the day of `now` a day
+    /// later, for when the provider hasn't published it
+    pub fn with_forecast_tomorrow(&self, now: DateTime<Utc>) -> PriceCache {
+        let Some(offset) = self.slots.first().map(|p| *p.starts_at.offset()) else {
+            return self.clone();
+        };
+        let today = now.with_timezone(&offset).date_naive();
+        let forecast = self.slots.iter().filter(|p| p.starts_at.date_naive() == today).map(|p| PricePoint {
+            starts_at: p.starts_at + chrono::Duration::days(1),
+            ends_at: p.ends_at + chrono::Duration::days(1),
+            level: None,
+            source: SlotOrigin::Forecast,
+            ..p.clone()
+        });
+        // Published slots come first, so they win over forecast ones
+        let slots = self.slots.iter().cloned().chain(forecast).collect();
+        PriceCache {
+            last_fetch: self.last_fetch,
+            generation: self.generation,
+            ..PriceCache::new(slots, self.current.clone())
+        }
+    }
+
+    /// Interpolate slots missing between published ones, so slot counts and
+    /// windows don't silently come up short. The gap is filled with slots as
+    /// long as the one before it. Returns how many were filled.
+    pub fn fill_gaps(&mut self) -> usize {
+        let mut filled = Vec::new();
+        for pair in self.slots.windows(2) {
+            let (before, after) = (&pair[0], &pair[1]);
+            let length = before.duration();
+            if length <= chrono::Duration::zero() {
+                continue;
+            }
+            let missing = (after.starts_at - before.ends_at).num_seconds() / length.num_seconds();
+            for slot in 1..=missing {
+                let weight = slot as f64 / (missing + 1) as f64;
+                let between = |a: f64, b: f64| a + (b - a) * weight;
+                filled.push(PricePoint {
+                    total: between(before.total, after.total),
+                    energy: between(before.energy, after.energy),
+                    tax: between(before.tax, after.tax),
+                    starts_at: before.starts_at + length * slot as i32,
+                    ends_at: before.ends_at + length * slot as i32,
+                    level: None,
+                    sell: None,
+                    source: SlotOrigin::Interpolated,
+                    ..before.clone()
+                });
+            }
+        }
+        let count = filled.len();
+        self.slots.extend(filled);
+        self.slots.sort_by_key(|p| p.starts_at);
+        count
+    }
+
+    /// Add the grid fees to every slot's buy price, and fill in its sell
+    /// price from the provider's price, before the fees, by the export model.
+    /// The grid operator's feed-in compensation comes on top, except with net
+    /// metering, where export already earns the buy price.
+    pub fn apply_tariff(&mut self, tariff: &TariffConfig, export: &ExportConfig) {
+        let compensation = match export.model {
+            ExportModel::NetMetering => 0.0,
+            ExportModel::Spot | ExportModel::Fixed => tariff.feed_in_compensation_per_kwh,
+        };
+        for price in self.slots.iter_mut().chain(self.current.as_mut()) {
+            price.sell = Some(export.sell_price(price) + compensation);
+            let fees = tariff.fees_at(price.starts_at);
+            price.total += fees;
+            price.tax += fees;
+        }
+    }
+
+    /// Get future prices (from now onwards)
+    pub fn future_prices(&self) -> impl Iterator<Item = &PricePoint> + Clone {
+        self.prices_from(Utc::now())
+    }
+
+    /// Prices of slots starting at or after `now`
+    pub fn prices_from(&self, now: DateTime<Utc>) -> impl Iterator<Item = &PricePoint> + Clone {
+        let start = self.slots.partition_point(|p| p.starts_at < now);
+        self.slots[start..].iter()
+    }
+
+    /// The slot containing `time`, falling back to the provider's current
+    /// slot if that one does
+    pub fn price_at(&self, time: DateTime<Utc>) -> Option<&PricePoint> {
+        self.index_at(time)
+            .map(|index| &self.slots[index])
+            .or(self.current.as_ref().filter(|p| p.contains(time)))
+    }
+
+    /// First and last slot and time-weighted average price of the cheapest
+    /// contiguous run lasting at least `length`, starting no earlier than the
+    /// slot containing `now` and ending by `deadline`. Slots may differ in
+    /// length, e.g. hourly ones after quarter-hourly ones.
+    pub fn cheapest_window(
+        &self,
+        now: DateTime<Utc>,
+        length: Duration,
+        deadline: Option<DateTime<Utc>>,
+    ) -> Option<(&PricePoint, &PricePoint, f64)> {
+        let candidates: Vec<&PricePoint> = self
+            .all_prices()
+            .skip_while(|p| p.ends_at <= now)
+            .take_while(|p| deadline.is_none_or(|deadline| p.ends_at <= deadline))
+            .collect();
+        (0..candidates.len())
+            .filter_map(|start| {
+                let (last, average) = span(&candidates[start..], length)?;
+                Some((candidates[start], last, average))
+            })
+            .min_by(|a, b| a.2.total_cmp(&b.2))
+    }
+
+    /// Last slot and time-weighted average price of the run lasting at least
+    /// `length` from the slot containing `now`
+    pub fn window_at(&self, now: DateTime<Utc>, length: Duration) -> Option<(&PricePoint, f64)> {
+        let candidates: Vec<&PricePoint> = self.all_prices().skip_while(|p| p.ends_at <= now).collect();
+        span(&candidates, length).filter(|_| candidates[0].contains(now))
+    }
+
+    /// Calculate price statistics
+    pub fn price_stats(&self) -> Option<PriceStats> {
+        let mut sorted: Vec<f64> = self.future_prices().map(|p| p.total).collect();
+        if sorted.is_empty() {
+            return None;
+        }
+        sorted.sort_by(|a, b| a.total_cmp(b));
+
+        let min = *sorted.first()?;
+        let max = *sorted.last()?;
+        let avg = sorted.iter().sum::<f64>() / sorted.len() as f64;
+
+        // Calculate percentile thresholds
+        let p25_idx = (sorted.len() as f64 * 0.25) as usize;
+        let p75_idx = (sorted.len() as f64 * 0.75) as usize;
+        let p90_idx = (sorted.len() as f64 * 0.90) as usize;
+
+        Some(PriceStats {
+            min,
+            max,
+            avg,
+            p25: sorted.get(p25_idx).copied().unwrap_or(min),
+            p75: sorted.get(p75_idx).copied().unwrap_or(max),
+            p90: sorted.get(p90_idx).copied().unwrap_or(max),
+        })
+    }
+}
+
+/// Last slot and time-weighted average price of the contiguous run from
+/// `slots[0]` lasting at least `length`
+fn span<'a>(slots: &[&'a PricePoint], length: Duration) -> Option<(&'a PricePoint, f64)> {
+    let mut covered = Duration::zero();
+    let mut weighted = 0.0;
+    for (index, slot) in slots.iter().enumerate() {
+        if index > 0 && slots[index - 1].ends_at != slot.starts_at {
+            return None;
+        }
+        covered += slot.duration();
+        weighted += slot.total * slot.duration().num_seconds() as f64;
+        if covered >= length {
+            return Some((slot, weighted / covered.num_seconds() as f64));
+        }
+    }
+    None
+}
+
+#[derive(Debug, Clone)]
+pub struct PriceStats {
+    pub min: f64,
+    pub max: f64,
+    pub avg: f64,
+    pub p25: f64,
+    pub p75: f64,
+    pub p90: f64,
+}
+
+#[cfg(test)]
+pub mod test_support {
+    use super::*;
+
+    /// Quarter-hour slots from `start` at the `totals` buy prices
+    pub fn quarter_hours(totals: &[f64], start: DateTime<FixedOffset>) -> PriceCache {
+        let prices = totals
+            .iter()
+            .enumerate()
+            .map(|(i, total)| PricePoint {
+                total: *total,
+                energy: *total,
+                tax: 0.0,
+                starts_at: start + Duration::minutes(15 * i as i64),
+                ends_at: start + Duration::minutes(15 * (i as i64 + 1)),
+                level: None,
+                currency: Some("EUR".to_string()),
+                sell: None,
+                source: SlotOrigin::Published,
+            })
+            .collect();
+        PriceCache::new(prices, None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn slot(starts_at: &str, total: f64, energy: f64) -> PricePoint {
+        let starts_at = DateTime::parse_from_rfc3339(starts_at).unwrap();
+        PricePoint {
+            total,
+            energy,
+            tax: total - energy,
+            starts_at,
+            ends_at: starts_at + Duration::hours(1),
+            level: None,
+            currency: None,
+            sell: None,
+            source: SlotOrigin::Published,
+        }
+    }
+
+    fn tariffed(export: ExportConfig) -> PriceCache {
+        let tariff = TariffConfig {
+            grid_fee_per_kwh: 0.05,
+            feed_in_compensation_per_kwh: 0.01,
+            ..TariffConfig::default()
+        };
+        let mut cache = PriceCache::new(vec![slot("2025-12-01T12:00:00+01:00", 0.30, 0.10)], None);
+        cache.apply_tariff(&tariff, &export);
+        cache
+    }
+
+    #[test]
+    fn adds_the_fees_to_the_buy_price_but_not_to_the_sell_price() {
+        let cache = tariffed(ExportConfig::default());
+        let price = &cache.slots()[0];
+        assert!((price.total - 0.35).abs() < 1e-9);
+        assert!((price.tax - 0.25).abs() < 1e-9);
+        assert_eq!(price.energy, 0.10);
+        // Net metering credits the provider's price, without the grid fees or compensation
+        assert!((price.sell_price() - 0.30).abs() < 1e-9);
+    }
+
+    #[test]
+    fn derives_the_sell_price_from_the_spot_price() {
+        let spot = tariffed(ExportConfig {
+            model: ExportModel::Spot,
+            fee_per_kwh: 0.02,
+            ..ExportConfig::default()
+        });
+        assert!((spot.slots()[0].sell_price() - 0.09).abs() < 1e-9);
+
+        let fixed = tariffed(ExportConfig {
+            model: ExportModel::Fixed,
+            feed_in_tariff: 0.07,
+            ..ExportConfig::default()
+        });
+        assert!((fixed.slots()[0].sell_price() - 0.08).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,64 @@
+//! `mode: price_publisher`: no battery logic, only the price source. Prices
+//! are fetched as usual and published with their stats and tiers, as a
+//! lightweight price service for households that control the battery by
+//! other means.
+
+use std::time::Duration;
+
+use anyhow::Result;
+use chrono::{DateTime, FixedOffset, Utc};
+use tracing::{error, info, warn};
+
+use crate::config::Config;
+use crate::decision::PriceTiers;
+use crate::mqtt::{MqttClient, PriceStatsJson};
+use crate::price_source::PriceSource;
+use crate::schema::{PricePayload, PriceSummaryPayload, TiersPayload};
+use crate::supervisor::Supervisor;
+
+pub async fn run(config: Config) -> Result<()> {
+    let price_source = PriceSource::from_config(&config, None)?;
+    let mqtt_client = MqttClient::new(config.mqtt.clone(), None, &Supervisor::new()).await?;
+    if let Err(e) = mqtt_client.publish_schemas().await {
+        error!("Failed to publish payload schemas: {}", e);
+    }
+    info!("Price publisher mode: publishing {} prices, battery control disabled", price_source.name());
+
+    if let Err(e) = price_source.fetch_prices().await {
+        error!("Failed to fetch initial prices: {}", e);
+    }
+
+    let mut interval = tokio::time::interval(Duration::from_secs(60));
+    // The summary is republished when prices arrive or the current slot moves on
+    let mut published: Option<(u64, DateTime<FixedOffset>)> = None;
+    loop {
+        interval.tick().await;
+        if let Err(e) = price_source.refresh_if_needed().await {
+            warn!("Failed to refresh prices: {}", e);
+        }
+
+        let Some(current_price) = price_source.get_current_price().await else {
+            warn!("No price data for the current slot");
+            continue;
+        };
+        if let Err(e) = mqtt_client.publish_price_info(&current_price).await {
+            error!("Failed to publish price info: {}", e);
+        }
+
+        let cache = price_source.get_cache().await;
+        mqtt_client.set_prices(cache.clone());
+        let key = (cache.generation, current_price.starts_at);
+        if published == Some(key) {
+            continue;
+        }
+        let summary = PriceSummaryPayload {
+            stats: cache.price_stats().map(PriceStatsJson::from),
+            tiers: TiersPayload::from(&PriceTiers::compute(&config.optimizer, &cache, Utc::now())),
+            prices: cache.all_prices().map(PricePayload::from).collect(),
+        };
+        match mqtt_client.publish_price_summary(&summary).await {
+            Ok(()) => published = Some(key),
+            Err(e) => error!("Failed to publish price summary: {}", e),
+        }
+    }
+}
@@ -0,0 +1,372 @@
+use anyhow::Result;
+use chrono::{DateTime, FixedOffset, Utc};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+use tracing::warn;
+
+use crate::config::{Config, PvBlend};
+
+pub type ForecastFuture<'a> = Pin<Box<dyn Future<Output = Result<PvForecast>> + Send + 'a>>;
+
+/// A PV production forecast backend
+pub trait PvForecastProvider: Send + Sync {
+    /// Name used in logs
+    fn name(&self) -> &'static str;
+
+    /// How often to refresh the forecast (in seconds)
+    fn refresh_interval_secs(&self) -> u64;
+
+    fn fetch(&self) -> ForecastFuture<'_>;
+}
+
+/// Energy produced in one forecast period
+#[derive(Debug, Clone, Copy)]
+struct Period {
+    start: DateTime<FixedOffset>,
+    end: DateTime<FixedOffset>,
+    wh: f64,
+}
+
+/// PV production forecast as energy per period, possibly from several
+/// providers that are blended when it is used
+#[derive(Debug, Clone, Default)]
+pub struct PvForecast {
+    /// One period series per provider, each sorted by time
+    series: Vec<Vec<Period>>,
+    blend: PvBlend,
+}
+
+impl PvForecast {
+    /// A forecast from (period end, energy produced in the period in Wh)
+    /// pairs; each period starts where the previous one ended, the first one
+    /// an hour before its end
+    #[cfg(any(feature = "forecast-solar", test))]
+    pub fn new(mut periods: Vec<(DateTime<FixedOffset>, f64)>) -> Self {
+        periods.sort_by_key(|(end, _)| *end);
+        let mut previous_end: Option<DateTime<FixedOffset>> = None;
+        let series = periods
+            .into_iter()
+            .map(|(end, wh)| {
+                let start = previous_end.unwrap_or(end - chrono::Duration::hours(1));
+                previous_end = Some(end);
+                Period { start, end, wh }
+            })
+            .collect();
+        Self {
+            series: vec![series],
+            blend: PvBlend::default(),
+        }
+    }
+
+    /// A forecast from periods of a fixed length, given by their end and energy in Wh
+    #[cfg(feature = "solcast")]
+    pub fn with_period(mut periods: Vec<(DateTime<FixedOffset>, f64)>, length: chrono::Duration) -> Self {
+        periods.sort_by_key(|(end, _)| *end);
+        let series = periods
+            .into_iter()
+            .map(|(end, wh)| Period { start: end - length, end, wh })
+            .collect();
+        Self {
+            series: vec![series],
+            blend: PvBlend::default(),
+        }
+    }
+
+    /// Combine the forecasts of several providers
+    pub fn blend(forecasts: &[Arc<PvForecast>], blend: PvBlend) -> Self {
+        Self {
+            series: forecasts.iter().flat_map(|f| f.series.iter().cloned()).collect(),
+            blend,
+        }
+    }
+
+    /// Expected PV energy beyond the house base load between `from` and `to` (kWh),
+    /// i.e. what would flow into the battery for free
+    pub fn surplus_kwh(&self, from: DateTime<FixedOffset>, to: DateTime<FixedOffset>, base_consumption_w: f64) -> f64 {
+        let estimates = self
+            .series
+            .iter()
+            .map(|series| Self::series_surplus_kwh(series, from, to, base_consumption_w));
+        match self.blend {
+            PvBlend::Average => {
+                let (sum, count) = estimates.fold((0.0, 0), |(sum, count), kwh| (sum + kwh, count + 1));
+                if count == 0 { 0.0 } else { sum / count as f64 }
+            }
+            PvBlend::Minimum => estimates.reduce(f64::min).unwrap_or(0.0),
+        }
+    }
+
+    fn series_surplus_kwh(
+        series: &[Period],
+        from: DateTime<FixedOffset>,
+        to: DateTime<FixedOffset>,
+        base_consumption_w: f64,
+    ) -> f64 {
+        series
+            .iter()
+            .filter(|p| p.end > from && p.start < to)
+            .map(|p| {
+                let hours = p.end.signed_duration_since(p.start).num_minutes() as f64 / 60.0;
+                (p.wh / 1000.0 - base_consumption_w / 1000.0 * hours).max(0.0)
+            })
+            .sum()
+    }
+}
+
+/// A provider's latest forecast and when it was fetched
+type FetchedForecast = (DateTime<Utc>, Arc<PvForecast>);
+
+/// The configured PV forecast providers and their latest forecasts
+pub struct PvForecastSource {
+    providers: Vec<Box<dyn PvForecastProvider>>,
+    /// Latest forecast per provider
+    forecasts: RwLock<Vec<Option<FetchedForecast>>>,
+    blend: PvBlend,
+}
+
+impl PvForecastSource {
+    pub fn new(providers: Vec<Box<dyn PvForecastProvider>>, blend: PvBlend) -> Self {
+        Self {
+            forecasts: RwLock::new(vec![None; providers.len()]),
+            providers,
+            blend,
+        }
+    }
+
+    /// The configured providers, or `None` when no PV forecast is configured
+    pub fn from_config(config: &Config) -> Option<Self> {
+        #[cfg_attr(not(any(feature = "forecast-solar", feature = "solcast")), allow(unused_mut))]
+        let mut providers: Vec<Box<dyn PvForecastProvider>> = Vec::new();
+        if let Some(pv_config) = &config.pv_forecast {
+            #[cfg(feature = "forecast-solar")]
+            providers.push(Box::new(ForecastSolarClient::new(pv_config.clone())));
+            #[cfg(not(feature = "forecast-solar"))]
+            {
+                let _ = pv_config;
+                warn!("pv_forecast is configured but this build lacks the `forecast-solar` feature");
+            }
+        }
+        if let Some(solcast_config) = &config.solcast {
+            #[cfg(feature = "solcast")]
+            providers.push(Box::new(SolcastClient::new(solcast_config.clone())));
+            #[cfg(not(feature = "solcast"))]
+            {
+                let _ = solcast_config;
+                warn!("solcast is configured but this build lacks the `solcast` feature");
+            }
+        }
+        (!providers.is_empty()).then(|| Self::new(providers, config.pv_blend))
+    }
+
+    /// Refresh each provider's forecast once it is older than its interval.
+    /// A failing provider keeps its last forecast.
+    pub async fn refresh_if_needed(&self) {
+        let now = Utc::now();
+        for (i, provider) in self.providers.iter().enumerate() {
+            let needs_refresh = match &self.forecasts.read().await[i] {
+                None => true,
+                Some((fetched, _)) => {
+                    now.signed_duration_since(*fetched).num_seconds() as u64 >= provider.refresh_interval_secs()
+                }
+            };
+            if !needs_refresh {
+                continue;
+            }
+            match provider.fetch().await {
+                Ok(forecast) => self.forecasts.write().await[i] = Some((now, Arc::new(forecast))),
+                Err(e) => warn!("Failed to refresh {} PV forecast: {}", provider.name(), e),
+            }
+        }
+    }
+
+    /// The blended forecast of all providers that have delivered one
+    pub async fn get_forecast(&self) -> Option<Arc<PvForecast>> {
+        let forecasts: Vec<Arc<PvForecast>> =
+            self.forecasts.read().await.iter().flatten().map(|(_, f)| f.clone()).collect();
+        match forecasts.len() {
+            0 => None,
+            1 => forecasts.into_iter().next(),
+            _ => Some(Arc::new(PvForecast::blend(&forecasts, self.blend))),
+        }
+    }
+}
+
+#[cfg(feature = "forecast-solar")]
+pub use forecast_solar::ForecastSolarClient;
+
+#[cfg(feature = "forecast-solar")]
+mod forecast_solar {
+    use anyhow::Result;
+    use serde::Deserialize;
+    use std::collections::HashMap;
+    use tracing::info;
+
+    use super::{ForecastFuture, PvForecast, PvForecastProvider};
+    use crate::config::PvForecastConfig;
+    use crate::http::HttpClient;
+
+    #[derive(Debug, Deserialize)]
+    struct ApiResponse {
+        result: ApiResult,
+    }
+
+    #[derive(Debug, Deserialize)]
+    struct ApiResult {
+        watt_hours_period: HashMap<String, f64>,
+    }
+
+    /// Client for the Forecast.Solar estimate API
+    pub struct ForecastSolarClient {
+        config: PvForecastConfig,
+        http_client: HttpClient,
+    }
+
+    impl ForecastSolarClient {
+        pub fn new(config: PvForecastConfig) -> Self {
+            Self {
+                config,
+                http_client: HttpClient::new(),
+            }
+        }
+
+        async fn fetch_forecast(&self) -> Result<PvForecast> {
+            let c = &self.config;
+            let key = c.api_key.as_deref().map(|k| format!("/{}", k)).unwrap_or_default();
+            let url = format!(
+                "https://api.forecast.solar{}/estimate/{}/{}/{}/{}/{}?time=iso8601",
+                key, c.latitude, c.longitude, c.declination, c.azimuth, c.kwp
+            );
+
+            let response = self.http_client.get(&url, &[("Accept", "application/json")]).await?;
+            if !response.is_success() {
+                anyhow::bail!("Forecast.Solar API error: {} - {}", response.status, response.text());
+            }
+
+            let api_response: ApiResponse = serde_json::from_slice(&response.body)?;
+            let periods = api_response
+                .result
+                .watt_hours_period
+                .into_iter()
+                .filter_map(|(time, wh)| chrono::DateTime::parse_from_rfc3339(&time).ok().map(|t| (t, wh)))
+                .collect::<Vec<_>>();
+
+            info!("Fetched Forecast.Solar PV forecast with {} periods", periods.len());
+            Ok(PvForecast::new(periods))
+        }
+    }
+
+    impl PvForecastProvider for ForecastSolarClient {
+        fn name(&self) -> &'static str {
+            "Forecast.Solar"
+        }
+
+        fn refresh_interval_secs(&self) -> u64 {
+            self.config.refresh_interval_secs
+        }
+
+        fn fetch(&self) -> ForecastFuture<'_> {
+            Box::pin(self.fetch_forecast())
+        }
+    }
+}
+
+#[cfg(feature = "solcast")]
+pub use solcast::SolcastClient;
+
+#[cfg(feature = "solcast")]
+mod solcast {
+    use anyhow::Result;
+    use serde::Deserialize;
+    use tracing::info;
+
+    use super::{ForecastFuture, PvForecast, PvForecastProvider};
+    use crate::config::SolcastConfig;
+    use crate::http::HttpClient;
+
+    #[derive(Debug, Deserialize)]
+    struct ApiResponse {
+        forecasts: Vec<ApiForecast>,
+    }
+
+    #[derive(Debug, Deserialize)]
+    struct ApiForecast {
+        /// Average power over the period (kW)
+        pv_estimate: f64,
+        period_end: String,
+        /// ISO 8601 duration, e.g. `PT30M`
+        period: String,
+    }
+
+    /// `PT30M` / `PT60M` / `PT1H` as a duration
+    fn parse_period(value: &str) -> Option<chrono::Duration> {
+        let value = value.strip_prefix("PT")?;
+        if let Some(minutes) = value.strip_suffix('M') {
+            minutes.parse().ok().map(chrono::Duration::minutes)
+        } else {
+            value.strip_suffix('H')?.parse().ok().map(chrono::Duration::hours)
+        }
+    }
+
+    /// Client for the Solcast rooftop site forecast API
+    pub struct SolcastClient {
+        config: SolcastConfig,
+        http_client: HttpClient,
+    }
+
+    impl SolcastClient {
+        pub fn new(config: SolcastConfig) -> Self {
+            Self {
+                config,
+                http_client: HttpClient::new(),
+            }
+        }
+
+        async fn fetch_forecast(&self) -> Result<PvForecast> {
+            let url = format!(
+                "{}/rooftop_sites/{}/forecasts?format=json",
+                self.config.api_url, self.config.resource_id
+            );
+            let auth = format!("Bearer {}", self.config.api_key);
+            let response = self
+                .http_client
+                .get(&url, &[("Accept", "application/json"), ("Authorization", &auth)])
+                .await?;
+            if !response.is_success() {
+                anyhow::bail!("Solcast API error: {} - {}", response.status, response.text());
+            }
+
+            let api_response: ApiResponse = serde_json::from_slice(&response.body)?;
+            let mut length = None;
+            let periods = api_response
+                .forecasts
+                .into_iter()
+                .filter_map(|f| {
+                    let period = parse_period(&f.period)?;
+                    length.get_or_insert(period);
+                    let end = chrono::DateTime::parse_from_rfc3339(&f.period_end).ok()?;
+                    let hours = period.num_minutes() as f64 / 60.0;
+                    Some((end, f.pv_estimate * 1000.0 * hours))
+                })
+                .collect::<Vec<_>>();
+
+            info!("Fetched Solcast PV forecast with {} periods", periods.len());
+            Ok(PvForecast::with_period(periods, length.unwrap_or(chrono::Duration::minutes(30))))
+        }
+    }
+
+    impl PvForecastProvider for SolcastClient {
+        fn name(&self) -> &'static str {
+            "Solcast"
+        }
+
+        fn refresh_interval_secs(&self) -> u64 {
+            self.config.refresh_interval_secs
+        }
+
+        fn fetch(&self) -> ForecastFuture<'_> {
+            Box::pin(self.fetch_forecast())
+        }
+    }
+}
@@ -0,0 +1,100 @@
+use chrono::{DateTime, FixedOffset};
+
+use crate::optimizer::{OptimizationResult, PlannedSlot};
+
+/// Blend the decision for the current slot toward the next planned slot's
+/// setpoint over the last `ramp_minutes` of the slot, so the setpoint doesn't
+/// step exactly on the slot boundary when other loads switch as well. Easing
+/// into a discharge draws from the battery ahead of the plan, so no more than
+/// the slot is projected to leave above `min_soc`.
+pub fn ramp(
+    result: OptimizationResult,
+    plan: &[PlannedSlot],
+    now: DateTime<FixedOffset>,
+    ramp_minutes: f64,
+    min_soc: f64,
+    capacity_kwh: f64,
+) -> OptimizationResult {
+    if ramp_minutes <= 0.0 {
+        return result;
+    }
+    let Some(current) = plan.iter().find(|slot| slot.starts_at <= now && now < slot.ends_at) else {
+        return result;
+    };
+    let ends_at = current.ends_at;
+    let Some(next) = plan.iter().find(|slot| slot.starts_at == ends_at) else {
+        return result;
+    };
+
+    let remaining_minutes = ends_at.signed_duration_since(now).num_seconds() as f64 / 60.0;
+    if remaining_minutes >= ramp_minutes || (next.grid_setpoint_w - result.grid_setpoint_w).abs() < 1.0 {
+        return result;
+    }
+    let weight = 1.0 - remaining_minutes / ramp_minutes;
+    let mut grid_setpoint_w = result.grid_setpoint_w + (next.grid_setpoint_w - result.grid_setpoint_w) * weight;
+    if grid_setpoint_w < result.grid_setpoint_w && remaining_minutes > 0.0 {
+        let headroom_kwh = ((current.soc_end - min_soc) / 100.0 * capacity_kwh).max(0.0);
+        let max_extra_w = headroom_kwh * 1000.0 / (remaining_minutes / 60.0);
+        grid_setpoint_w = grid_setpoint_w.max(result.grid_setpoint_w - max_extra_w);
+    }
+    if (grid_setpoint_w - result.grid_setpoint_w).abs() < 1.0 {
+        return result;
+    }
+    OptimizationResult {
+        grid_setpoint_w: grid_setpoint_w.round(),
+        reason: format!(
+            "{} (ramping {:.0}% toward the next slot's {:.0}W)",
+            result.reason,
+            weight * 100.0,
+            next.grid_setpoint_w
+        ),
+        ..result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::optimizer::BatteryMode;
+    use chrono::Duration;
+
+    fn slot(starts_at: DateTime<FixedOffset>, grid_setpoint_w: f64, soc_end: f64) -> PlannedSlot {
+        PlannedSlot {
+            starts_at,
+            ends_at: starts_at + Duration::minutes(60),
+            price: 0.30,
+            sell_price: 0.10,
+            cheap: false,
+            mode: BatteryMode::SelfConsumption,
+            battery_power_w: 0.0,
+            grid_power_w: grid_setpoint_w,
+            grid_setpoint_w,
+            soc_end,
+        }
+    }
+
+    #[test]
+    fn eases_into_a_discharge_only_down_to_the_reserve() {
+        let start = DateTime::parse_from_rfc3339("2025-12-01T17:00:00+01:00").unwrap();
+        let result = OptimizationResult {
+            mode: BatteryMode::SelfConsumption,
+            grid_setpoint_w: 0.0,
+            reason: "self-consumption".to_string(),
+            alternative: None,
+        };
+        // Halfway into a 20-minute ramp at the end of an hourly slot
+        let now = start + Duration::minutes(50);
+        let plan = [slot(start, 0.0, 30.0), slot(start + Duration::minutes(60), -8000.0, 10.0)];
+        let ramped = ramp(result.clone(), &plan, now, 20.0, 10.0, 10.0);
+        assert_eq!(ramped.grid_setpoint_w, -4000.0);
+
+        // With the slot ending 0.5 kWh above the reserve, 10 minutes allow 3000W more
+        let plan = [slot(start, 0.0, 15.0), slot(start + Duration::minutes(60), -8000.0, 10.0)];
+        let ramped = ramp(result.clone(), &plan, now, 20.0, 10.0, 10.0);
+        assert_eq!(ramped.grid_setpoint_w, -3000.0);
+
+        // Ending at the reserve, nothing is drawn ahead of the plan
+        let plan = [slot(start, 0.0, 10.0), slot(start + Duration::minutes(60), -8000.0, 10.0)];
+        assert_eq!(ramp(result, &plan, now, 20.0, 10.0, 10.0).grid_setpoint_w, 0.0);
+    }
+}
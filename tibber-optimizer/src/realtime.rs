@@ -0,0 +1,183 @@
+use anyhow::Result;
+use chrono::{DateTime, FixedOffset, Utc};
+use tracing::{debug, info};
+
+use crate::config::RealtimePriceConfig;
+use crate::http::HttpClient;
+use crate::optimizer::{BatteryMode, OptimizationResult};
+use crate::prices::{PricePoint, SlotOrigin};
+
+/// Optional fast price layer: a frequently updated realtime/intraday price that
+/// overrides the day-ahead price of the current slot when it deviates enough
+pub struct RealtimePriceLayer {
+    config: RealtimePriceConfig,
+    http_client: HttpClient,
+    latest: Option<(DateTime<Utc>, f64)>,
+    /// Whether the realtime price currently overrides the day-ahead price
+    overriding: bool,
+    /// Decision taken within the current slot and when its mode last changed
+    decision: Option<(DateTime<FixedOffset>, OptimizationResult, DateTime<Utc>)>,
+}
+
+impl RealtimePriceLayer {
+    pub fn new(config: RealtimePriceConfig) -> Self {
+        Self {
+            config,
+            http_client: HttpClient::new(),
+            latest: None,
+            overriding: false,
+            decision: None,
+        }
+    }
+
+    pub async fn fetch(&mut self, now: DateTime<Utc>) -> Result<()> {
+        let response = self.http_client.get(&self.config.url, &[("Accept", "application/json")]).await?;
+        if !response.is_success() {
+            anyhow::bail!("Realtime price error: {} - {}", response.status, response.text());
+        }
+
+        let body: serde_json::Value = serde_json::from_slice(&response.body)?;
+        let price = body
+            .pointer(&self.config.json_pointer)
+            .and_then(|v| v.as_f64().or_else(|| v.as_str().and_then(|s| s.parse().ok())))
+            .ok_or_else(|| anyhow::anyhow!("No price at {} in realtime response", self.config.json_pointer))?;
+
+        debug!("Realtime price: {:.4}", price);
+        self.latest = Some((now, price));
+        Ok(())
+    }
+
+    /// Poll the feed if the last value is older than the poll interval
+    pub async fn refresh_if_needed(&mut self, now: DateTime<Utc>) -> Result<()> {
+        let due = self.latest.is_none_or(|(fetched, _)| {
+            now.signed_duration_since(fetched).num_seconds() as u64 >= self.config.poll_interval_secs
+        });
+        if due {
+            self.fetch(now).await?;
+        }
+        Ok(())
+    }
+
+    /// Latest realtime price, unless it is too old to act on
+    pub fn current(&self, now: DateTime<Utc>) -> Option<f64> {
+        self.latest
+            .filter(|(fetched, _)| now.signed_duration_since(*fetched).num_seconds() as u64 <= self.config.max_age_secs)
+            .map(|(_, price)| price)
+    }
+
+    /// The price to optimize the current slot with. The realtime price takes
+    /// over once it deviates by `min_deviation` and lets go below half of that.
+    pub fn adjust_price(&mut self, day_ahead: &PricePoint, now: DateTime<Utc>) -> PricePoint {
+        let Some(realtime) = self.current(now) else {
+            self.overriding = false;
+            return day_ahead.clone();
+        };
+
+        let deviation = (realtime - day_ahead.total).abs();
+        let overriding = if self.overriding {
+            deviation >= self.config.min_deviation / 2.0
+        } else {
+            deviation >= self.config.min_deviation
+        };
+        if overriding != self.overriding {
+            info!(
+                "Realtime price {:.4} vs day-ahead {:.4}: {}",
+                realtime,
+                day_ahead.total,
+                if overriding { "overriding current slot" } else { "back to day-ahead" }
+            );
+            self.overriding = overriding;
+        }
+
+        if !overriding {
+            return day_ahead.clone();
+        }
+        // Fees and taxes are unaffected, only the energy part moves
+        PricePoint {
+            total: realtime,
+            energy: day_ahead.energy + realtime - day_ahead.total,
+            source: SlotOrigin::Realtime,
+            ..day_ahead.clone()
+        }
+    }
+
+    /// Keep the current slot's mode for at least `min_hold_secs` before the
+    /// realtime price may change it again; a new day-ahead slot always applies,
+    /// and so does a change forced by `soc` reaching `soc_limits` (min, max)
+    pub fn stabilize(
+        &mut self,
+        slot: DateTime<FixedOffset>,
+        result: OptimizationResult,
+        soc: f64,
+        soc_limits: (f64, f64),
+        now: DateTime<Utc>,
+    ) -> OptimizationResult {
+        if let Some((decided_slot, previous, changed_at)) = &mut self.decision {
+            if *decided_slot == slot {
+                if previous.mode == result.mode {
+                    // Same mode: refresh the setpoint, keep the time of the last mode change
+                    *previous = result.clone();
+                    return result;
+                }
+                let held_secs = now.signed_duration_since(*changed_at).num_seconds().max(0) as u64;
+                if held_secs < self.config.min_hold_secs && !past_soc_limit(previous.mode, soc, soc_limits) {
+                    debug!(
+                        "Holding {} for another {}s (realtime hysteresis)",
+                        previous.mode,
+                        self.config.min_hold_secs - held_secs
+                    );
+                    return previous.clone();
+                }
+            }
+        }
+
+        self.decision = Some((slot, result.clone(), now));
+        result
+    }
+}
+
+/// Whether keeping `mode` would take the battery past its SoC limits
+fn past_soc_limit(mode: BatteryMode, soc: f64, (min_soc, max_soc): (f64, f64)) -> bool {
+    match mode {
+        BatteryMode::ChargeFull | BatteryMode::ChargeReduced => soc >= max_soc,
+        BatteryMode::Idle => false,
+        _ => soc <= min_soc,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn result(mode: BatteryMode) -> OptimizationResult {
+        OptimizationResult {
+            mode,
+            grid_setpoint_w: 0.0,
+            reason: String::new(),
+            alternative: None,
+        }
+    }
+
+    #[test]
+    fn holds_a_mode_unless_the_soc_limits_end_it() {
+        let mut layer = RealtimePriceLayer::new(RealtimePriceConfig {
+            url: String::new(),
+            json_pointer: "/price".to_string(),
+            poll_interval_secs: 300,
+            max_age_secs: 900,
+            min_deviation: 0.05,
+            min_hold_secs: 600,
+        });
+        let slot = DateTime::parse_from_rfc3339("2025-12-01T18:00:00+01:00").unwrap();
+        let now = slot.with_timezone(&Utc);
+        let limits = (10.0, 90.0);
+
+        layer.stabilize(slot, result(BatteryMode::DischargeToGrid), 50.0, limits, now);
+        let held = layer.stabilize(slot, result(BatteryMode::SelfConsumption), 49.0, limits, now);
+        assert_eq!(held.mode, BatteryMode::DischargeToGrid);
+
+        // Down at the reserve the discharge stops within the hold time
+        let stopped = layer.stabilize(slot, result(BatteryMode::Idle), 10.0, limits, now);
+        assert_eq!(stopped.mode, BatteryMode::Idle);
+    }
+}
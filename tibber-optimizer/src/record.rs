@@ -0,0 +1,110 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::config::{BatteryConfig, MqttConfig, OptimizerConfig};
+
+/// One captured input, in the order it arrived
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Entry {
+    /// Settings the recording was made with
+    Config(Box<RecordedConfig>),
+    /// Message on a subscribed topic
+    Mqtt { topic: String, payload: String },
+    /// Tibber price query response
+    Tibber { body: serde_json::Value },
+    /// Start of a control cycle
+    Tick,
+}
+
+/// The settings needed to replay (credentials are never recorded)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordedConfig {
+    pub mqtt: MqttConfig,
+    pub battery: BatteryConfig,
+    pub optimizer: OptimizerConfig,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Record {
+    pub at: DateTime<Utc>,
+    #[serde(flatten)]
+    pub entry: Entry,
+}
+
+/// Appends inputs to a JSON-lines bundle that can be attached to bug reports
+#[derive(Clone)]
+pub struct Recorder {
+    file: Arc<Mutex<File>>,
+}
+
+impl Recorder {
+    pub fn create(path: &str) -> Result<Self> {
+        let file = File::create(path).with_context(|| format!("Cannot create recording {}", path))?;
+        info!("Recording inputs to {}", path);
+        Ok(Self {
+            file: Arc::new(Mutex::new(file)),
+        })
+    }
+
+    pub fn config(&self, mqtt: &MqttConfig, battery: &BatteryConfig, optimizer: &OptimizerConfig) {
+        self.write(Entry::Config(Box::new(RecordedConfig {
+            mqtt: mqtt.clone(),
+            battery: battery.clone(),
+            optimizer: optimizer.clone(),
+        })));
+    }
+
+    pub fn mqtt(&self, topic: &str, payload: &str) {
+        self.write(Entry::Mqtt {
+            topic: topic.to_string(),
+            payload: payload.to_string(),
+        });
+    }
+
+    #[cfg(feature = "tibber")]
+    pub fn tibber(&self, body: &[u8]) {
+        match serde_json::from_slice(body) {
+            Ok(body) => self.write(Entry::Tibber { body }),
+            Err(e) => warn!("Not recording unparseable Tibber response: {}", e),
+        }
+    }
+
+    pub fn tick(&self) {
+        self.write(Entry::Tick);
+    }
+
+    fn write(&self, entry: Entry) {
+        let record = Record { at: Utc::now(), entry };
+        let result = serde_json::to_string(&record).map_err(anyhow::Error::from).and_then(|line| {
+            // Flush every line so a crash still leaves a usable recording
+            let mut file = self.file.lock().unwrap();
+            writeln!(file, "{}", line)?;
+            file.flush()?;
+            Ok(())
+        });
+        if let Err(e) = result {
+            warn!("Failed to write recording: {}", e);
+        }
+    }
+}
+
+/// Read all records from a bundle
+pub fn load(path: &Path) -> Result<Vec<Record>> {
+    let file = File::open(path).with_context(|| format!("Cannot open recording {}", path.display()))?;
+    BufReader::new(file)
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| line.as_ref().map_or(true, |l| !l.trim().is_empty()))
+        .map(|(idx, line)| {
+            serde_json::from_str(&line?).with_context(|| format!("Invalid record on line {}", idx + 1))
+        })
+        .collect()
+}
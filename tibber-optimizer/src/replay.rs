@@ -0,0 +1,137 @@
+use std::io::Write;
+use std::path::Path;
+
+use anyhow::Result;
+use chrono::DateTime;
+
+use crate::clock::Clock;
+use crate::mqtt::MessageHandler;
+use crate::optimizer::BatteryOptimizer;
+use crate::prices::PriceCache;
+use crate::record::{self, Entry, Record};
+#[cfg(feature = "tibber")]
+use crate::tibber;
+
+/// `tibber-optimizer replay <bundle>`: drive the optimizer from a recording and
+/// print its decision for every recorded control cycle
+pub fn run(path: &str) -> Result<()> {
+    let records = record::load(Path::new(path))?;
+    let cycles = replay(records, &mut std::io::stdout().lock())?;
+    eprintln!("Replayed {} control cycles from {}", cycles, path);
+    Ok(())
+}
+
+/// Replay `records` in order, writing a CSV line per control cycle with a
+/// price and SoC to go on; returns the number of cycles
+fn replay(records: Vec<Record>, out: &mut impl Write) -> Result<usize> {
+    // The optimizer reads the time from the clock, which follows the recording
+    let clock = Clock::simulated(DateTime::UNIX_EPOCH);
+    let mut handler = None;
+    let mut optimizer = None;
+    let mut state = None;
+    #[cfg_attr(not(feature = "tibber"), allow(unused_mut))]
+    let mut cache = PriceCache::default();
+    let mut cycles = 0;
+
+    writeln!(out, "time,soc,price,mode,setpoint_w,reason")?;
+    for record in records {
+        clock.set(record.at);
+        match record.entry {
+            Entry::Config(config) => {
+                let new_handler = MessageHandler::new(config.mqtt);
+                state = Some(new_handler.initial_state());
+                handler = Some(new_handler);
+                optimizer = Some(BatteryOptimizer::new(config.battery, config.optimizer).with_clock(clock.clone()));
+            }
+            Entry::Mqtt { topic, payload } => {
+                if let (Some(handler), Some(state)) = (&handler, state.as_mut()) {
+                    handler.handle(state, &topic, &payload, record.at);
+                }
+            }
+            #[cfg(feature = "tibber")]
+            Entry::Tibber { body } => {
+                cache = tibber::parse_prices(&serde_json::to_vec(&body)?, cache.generation + 1, record.at.fixed_offset())?;
+            }
+            #[cfg(not(feature = "tibber"))]
+            Entry::Tibber { .. } => anyhow::bail!("Replaying Tibber prices needs the `tibber` feature"),
+            Entry::Tick => {
+                let (Some(optimizer), Some(state)) = (&optimizer, &state) else {
+                    anyhow::bail!("Recording doesn't start with its settings");
+                };
+                let Some(price) = cache.price_at(record.at).cloned() else {
+                    continue;
+                };
+                if state.last_soc_update.is_none() {
+                    continue;
+                }
+
+                let result = optimizer.optimize(state.soc, &price, &cache);
+                writeln!(
+                    out,
+                    "{},{:.1},{:.4},{},{:.0},{:?}",
+                    record.at.to_rfc3339(),
+                    state.soc,
+                    price.total,
+                    result.mode,
+                    result.grid_setpoint_w,
+                    result.reason
+                )?;
+                cycles += 1;
+            }
+        }
+    }
+
+    Ok(cycles)
+}
+
+#[cfg(all(test, feature = "tibber"))]
+mod tests {
+    use super::*;
+    use crate::config::{BatteryConfig, MqttConfig, OptimizerConfig};
+    use crate::record::Recorder;
+    use chrono::{Duration, DurationRound, Utc};
+
+    #[test]
+    fn replays_what_was_recorded() {
+        let dir = std::env::temp_dir().join(format!("replay_test_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("recording.jsonl");
+        let recorder = Recorder::create(path.to_str().unwrap()).unwrap();
+
+        let mqtt: MqttConfig = serde_yaml::from_str("host: localhost\nsoc_topic: N/soc\nprice_topic: tibber/price").unwrap();
+        let battery: BatteryConfig = serde_yaml::from_str("capacity_kwh: 10\nround_trip_efficiency: 0.9").unwrap();
+        recorder.config(&mqtt, &battery, &OptimizerConfig::default());
+        // A day of hourly prices from the hour the recording is made in
+        let hour = Utc::now().duration_trunc(Duration::hours(1)).unwrap();
+        let today: Vec<serde_json::Value> = (0..24)
+            .map(|h| {
+                serde_json::json!({
+                    "total": 0.20 + 0.01 * h as f64,
+                    "energy": 0.10,
+                    "tax": 0.10 + 0.01 * h as f64,
+                    "startsAt": (hour + Duration::hours(h)).fixed_offset().to_rfc3339(),
+                })
+            })
+            .collect();
+        let body = serde_json::json!({"data": {"viewer": {"homes": [{"currentSubscription": {"priceInfo": {
+            "today": today,
+            "tomorrow": [],
+        }}}]}}});
+        recorder.tick();
+        recorder.tibber(&serde_json::to_vec(&body).unwrap());
+        recorder.mqtt("N/soc", r#"{"value": 55}"#);
+        recorder.tick();
+        recorder.tick();
+
+        let records = record::load(&path).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(records.len(), 6);
+        let mut out = Vec::new();
+        // The first tick comes before any price and SoC, so it isn't a cycle
+        assert_eq!(replay(records, &mut out).unwrap(), 2);
+        let out = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[1].contains(",55.0,0.20"), "{}", lines[1]);
+    }
+}
@@ -0,0 +1,232 @@
+//! Price queries over MQTT request/response, so other home automations can
+//! reuse the live price cache instead of fetching and ranking prices
+//! themselves. A request on `<base>/rpc/request`, e.g.
+//! `{"id":"dryer","query":"cheapest_window","hours":3,"within_hours":24}`,
+//! is answered on `<base>/rpc/response` with the same `id`.
+
+use chrono::{DateTime, Duration, FixedOffset, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::prices::{PriceCache, PricePoint};
+
+/// How far ahead a query looks unless it says otherwise (h)
+const DEFAULT_WITHIN_HOURS: f64 = 24.0;
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "query", rename_all = "snake_case")]
+enum Query {
+    /// Cheapest contiguous run of `hours` ending within `within_hours`
+    CheapestWindow {
+        hours: f64,
+        #[serde(default = "default_within_hours")]
+        within_hours: f64,
+    },
+    /// The `count` cheapest slots within `within_hours`, in time order
+    CheapestSlots {
+        count: usize,
+        #[serde(default = "default_within_hours")]
+        within_hours: f64,
+    },
+}
+
+fn default_within_hours() -> f64 {
+    DEFAULT_WITHIN_HOURS
+}
+
+/// A span of slots and its average buy price
+#[derive(Debug, Serialize)]
+pub struct Window {
+    pub start: String,
+    pub end: String,
+    pub average_price: f64,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum Answer {
+    Window(Window),
+    Slots { slots: Vec<Window> },
+}
+
+/// The reply to one request: an answer or why there is none
+#[derive(Debug, Serialize)]
+pub struct Response {
+    /// Echoed from the request, to match replies to requests
+    pub id: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<Answer>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Answer the request in `payload` from `prices` (None until prices are loaded)
+pub fn respond(payload: &str, prices: Option<&PriceCache>, now: DateTime<Utc>) -> Response {
+    let mut request: serde_json::Value = match serde_json::from_str(payload) {
+        Ok(request) => request,
+        Err(e) => return Response::error(None, format!("invalid request: {}", e)),
+    };
+    let id = request.as_object_mut().and_then(|fields| fields.remove("id"));
+    let query = match serde_json::from_value::<Query>(request) {
+        Ok(query) => query,
+        Err(e) => return Response::error(id, format!("invalid query: {}", e)),
+    };
+    let Some(prices) = prices else {
+        return Response::error(id, "no prices loaded yet".to_string());
+    };
+    match answer(&query, prices, now) {
+        Ok(result) => Response {
+            id,
+            result: Some(result),
+            error: None,
+        },
+        Err(message) => Response::error(id, message),
+    }
+}
+
+impl Response {
+    fn error(id: Option<serde_json::Value>, message: String) -> Self {
+        Self {
+            id,
+            result: None,
+            error: Some(message),
+        }
+    }
+}
+
+fn answer(query: &Query, prices: &PriceCache, now: DateTime<Utc>) -> Result<Answer, String> {
+    let horizon = prices.all_prices().last().map(|p| p.ends_at).ok_or("no prices loaded yet")?;
+    match *query {
+        Query::CheapestWindow { hours, within_hours } => {
+            let length = duration(hours, "hours")?.ok_or("hours is out of range")?;
+            let (first, last, average_price) = prices
+                .cheapest_window(now, length, Some(deadline(now, within_hours, horizon)?))
+                .ok_or_else(|| format!("no contiguous {:.1}h within the next {:.0}h of prices", hours, within_hours))?;
+            Ok(Answer::Window(Window {
+                start: first.starts_at.to_rfc3339(),
+                end: last.ends_at.to_rfc3339(),
+                average_price,
+            }))
+        }
+        Query::CheapestSlots { count, within_hours } => {
+            let deadline = deadline(now, within_hours, horizon)?;
+            let mut slots: Vec<&PricePoint> = prices
+                .all_prices()
+                .filter(|p| p.ends_at > now && p.ends_at <= deadline)
+                .collect();
+            slots.sort_by(|a, b| a.total.total_cmp(&b.total));
+            slots.truncate(count);
+            slots.sort_by_key(|p| p.starts_at);
+            Ok(Answer::Slots {
+                slots: slots
+                    .into_iter()
+                    .map(|p| Window {
+                        start: p.starts_at.to_rfc3339(),
+                        end: p.ends_at.to_rfc3339(),
+                        average_price: p.total,
+                    })
+                    .collect(),
+            })
+        }
+    }
+}
+
+/// `hours` as a duration; `None` if it is too long to represent
+fn duration(hours: f64, field: &str) -> Result<Option<Duration>, String> {
+    if !hours.is_finite() || hours <= 0.0 {
+        return Err(format!("{} must be a positive number", field));
+    }
+    Ok(Duration::try_seconds((hours * 3600.0) as i64))
+}
+
+/// `within_hours` from now, but no later than the end of the known prices
+fn deadline(now: DateTime<Utc>, within_hours: f64, horizon: DateTime<FixedOffset>) -> Result<DateTime<Utc>, String> {
+    let horizon = horizon.with_timezone(&Utc);
+    let deadline = duration(within_hours, "within_hours")?.and_then(|within| now.checked_add_signed(within));
+    Ok(deadline.map_or(horizon, |deadline| deadline.min(horizon)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prices::SlotOrigin;
+    use chrono::{FixedOffset, TimeZone};
+
+    #[test]
+    fn finds_the_cheapest_contiguous_window() {
+        let day = FixedOffset::east_opt(3600).unwrap().with_ymd_and_hms(2025, 12, 1, 0, 0, 0).unwrap();
+        let hourly = [0.30, 0.20, 0.10, 0.12, 0.05, 0.40];
+        let slots = (0..hourly.len() as i64)
+            .map(|hour| PricePoint {
+                total: hourly[hour as usize],
+                energy: hourly[hour as usize],
+                tax: 0.0,
+                starts_at: day + Duration::hours(hour),
+                ends_at: day + Duration::hours(hour + 1),
+                level: None,
+                currency: None,
+                sell: None,
+                source: SlotOrigin::Published,
+            })
+            .collect();
+        let prices = PriceCache::new(slots, None);
+        let now = day.with_timezone(&Utc);
+
+        let response = respond(r#"{"id":"dryer","query":"cheapest_window","hours":2}"#, Some(&prices), now);
+        assert_eq!(response.id, Some(serde_json::json!("dryer")));
+        let Some(Answer::Window(window)) = response.result else {
+            panic!("no window: {:?}", response.error);
+        };
+        assert_eq!(window.start, "2025-12-01T03:00:00+01:00");
+        assert_eq!(window.end, "2025-12-01T05:00:00+01:00");
+        assert!((window.average_price - 0.085).abs() < 1e-9);
+
+        // Longer than the known prices
+        let response = respond(r#"{"query":"cheapest_window","hours":8}"#, Some(&prices), now);
+        assert!(response.result.is_none() && response.error.is_some());
+
+        // Nonsense durations are answered with an error, an endless look-ahead
+        // stops at the known prices
+        for request in [
+            r#"{"query":"cheapest_window","hours":-1}"#,
+            r#"{"query":"cheapest_window","hours":1e300}"#,
+            r#"{"query":"cheapest_slots","count":2,"within_hours":0}"#,
+        ] {
+            let response = respond(request, Some(&prices), now);
+            assert!(response.result.is_none() && response.error.is_some(), "{}", request);
+        }
+        let response = respond(r#"{"query":"cheapest_slots","count":1,"within_hours":1e300}"#, Some(&prices), now);
+        let Some(Answer::Slots { slots }) = response.result else {
+            panic!("no slots: {:?}", response.error);
+        };
+        assert_eq!(slots[0].start, "2025-12-01T04:00:00+01:00");
+    }
+
+    #[test]
+    fn measures_windows_across_slot_lengths() {
+        let day = FixedOffset::east_opt(3600).unwrap().with_ymd_and_hms(2025, 12, 1, 23, 0, 0).unwrap();
+        // Four quarter hours, then an hourly slot
+        let slots: Vec<PricePoint> = [(0, 15, 0.30), (15, 30, 0.30), (30, 45, 0.10), (45, 60, 0.10), (60, 120, 0.10)]
+            .into_iter()
+            .map(|(start, end, total)| PricePoint {
+                total,
+                energy: total,
+                tax: 0.0,
+                starts_at: day + Duration::minutes(start),
+                ends_at: day + Duration::minutes(end),
+                level: None,
+                currency: None,
+                sell: None,
+                source: SlotOrigin::Published,
+            })
+            .collect();
+        let prices = PriceCache::new(slots, None);
+
+        let response = respond(r#"{"query":"cheapest_window","hours":1.5}"#, Some(&prices), day.with_timezone(&Utc));
+        let Some(Answer::Window(window)) = response.result else {
+            panic!("no window: {:?}", response.error);
+        };
+        assert_eq!(window.start, "2025-12-01T23:30:00+01:00");
+        assert_eq!(window.end, "2025-12-02T01:00:00+01:00");
+        assert!((window.average_price - 0.10).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,139 @@
+use chrono::{DateTime, Datelike, FixedOffset, NaiveTime, Weekday};
+use serde::Deserialize;
+use tracing::info;
+
+/// A recurring constraint the optimizer plans around, e.g. "never discharge
+/// to the grid on weekdays 06:00-08:00" or "hold a 60% reserve on Fridays"
+#[derive(Debug, Clone, Deserialize)]
+pub struct ScheduleRule {
+    pub name: String,
+    /// Weekdays the rule applies on (`mon`, `tue`, ...; default: every day)
+    #[serde(default)]
+    pub days: Vec<Weekday>,
+    /// Local start and end time (default: the whole day); a window ending
+    /// before it starts runs past midnight into the next day
+    #[serde(default)]
+    pub start: Option<NaiveTime>,
+    #[serde(default)]
+    pub end: Option<NaiveTime>,
+    /// Don't discharge the battery into the grid
+    #[serde(default)]
+    pub no_grid_discharge: bool,
+    /// Don't charge the battery from the grid (PV surplus still charges it)
+    #[serde(default)]
+    pub no_grid_charge: bool,
+    /// Keep at least this SoC (%)
+    #[serde(default)]
+    pub min_soc: Option<f64>,
+}
+
+/// Whether a recurring window on `days` (every day if empty) from `start` to
+/// `end` covers local time `at`; a window ending before it starts runs past
+/// midnight into the next day
+pub fn window_covers(days: &[Weekday], start: Option<NaiveTime>, end: Option<NaiveTime>, at: DateTime<FixedOffset>) -> bool {
+    let applies_on = |weekday: Weekday| days.is_empty() || days.contains(&weekday);
+    let time = at.time();
+    let start = start.unwrap_or(NaiveTime::MIN);
+    match end {
+        Some(end) if end <= start => {
+            // Past midnight: the evening belongs to today, the morning to yesterday's window
+            (time >= start && applies_on(at.weekday())) || (time < end && applies_on(at.weekday().pred()))
+        }
+        end => time >= start && end.is_none_or(|end| time < end) && applies_on(at.weekday()),
+    }
+}
+
+impl ScheduleRule {
+    /// Whether the rule covers local time `at`
+    pub fn applies_at(&self, at: DateTime<FixedOffset>) -> bool {
+        window_covers(&self.days, self.start, self.end, at)
+    }
+
+    pub fn describe(&self) -> String {
+        let mut constraints = Vec::new();
+        if self.no_grid_discharge {
+            constraints.push("no grid discharge".to_string());
+        }
+        if self.no_grid_charge {
+            constraints.push("no grid charging".to_string());
+        }
+        if let Some(min_soc) = self.min_soc {
+            constraints.push(format!("reserve {:.0}%", min_soc));
+        }
+        let days = if self.days.is_empty() {
+            "daily".to_string()
+        } else {
+            self.days.iter().map(|d| d.to_string()).collect::<Vec<_>>().join(",")
+        };
+        let window = match (self.start, self.end) {
+            (None, None) => String::new(),
+            (start, end) => format!(
+                " {}-{}",
+                start.unwrap_or(NaiveTime::MIN).format("%H:%M"),
+                end.map_or_else(|| "24:00".to_string(), |end| end.format("%H:%M").to_string())
+            ),
+        };
+        format!("{}: {} {}{}", self.name, constraints.join(", "), days, window)
+    }
+}
+
+/// What the rules in effect at one moment allow, naming the rule behind each
+/// constraint for the decision's reason
+#[derive(Debug, Clone, Default)]
+pub struct Constraints<'a> {
+    pub no_grid_discharge: Option<&'a str>,
+    pub no_grid_charge: Option<&'a str>,
+    /// Highest reserve of the rules in effect
+    pub min_soc: Option<(f64, &'a str)>,
+}
+
+/// Combined constraints of the rules in effect at local time `at`
+pub fn constraints_at(rules: &[ScheduleRule], at: DateTime<FixedOffset>) -> Constraints<'_> {
+    let mut constraints = Constraints::default();
+    for rule in rules.iter().filter(|rule| rule.applies_at(at)) {
+        if rule.no_grid_discharge {
+            constraints.no_grid_discharge.get_or_insert(&rule.name);
+        }
+        if rule.no_grid_charge {
+            constraints.no_grid_charge.get_or_insert(&rule.name);
+        }
+        if let Some(min_soc) = rule.min_soc {
+            if constraints.min_soc.is_none_or(|(highest, _)| min_soc > highest) {
+                constraints.min_soc = Some((min_soc, &rule.name));
+            }
+        }
+    }
+    constraints
+}
+
+/// Configured and commanded recurring rules
+#[derive(Debug, Default)]
+pub struct RuleSchedule {
+    rules: Vec<ScheduleRule>,
+}
+
+impl RuleSchedule {
+    pub fn new(rules: Vec<ScheduleRule>) -> Self {
+        Self { rules }
+    }
+
+    /// Add a rule, replacing a rule with the same name
+    pub fn add(&mut self, rule: ScheduleRule) {
+        info!("Added schedule rule {}", rule.describe());
+        self.rules.retain(|r| r.name != rule.name);
+        self.rules.push(rule);
+    }
+
+    /// Remove the named rule, or all rules
+    pub fn cancel(&mut self, name: Option<&str>) {
+        let before = self.rules.len();
+        self.rules.retain(|r| name.is_some_and(|name| r.name != name));
+        if self.rules.len() < before {
+            info!("Removed {} schedule rule(s)", before - self.rules.len());
+        }
+    }
+
+    pub fn rules(&self) -> &[ScheduleRule] {
+        &self.rules
+    }
+}
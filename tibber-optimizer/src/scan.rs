@@ -0,0 +1,199 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use anyhow::Result;
+use rumqttc::{AsyncClient, Event, MqttOptions, Packet, QoS};
+
+use crate::config::MqttConfig;
+use crate::mqtt::parse_mqtt_value;
+
+/// Victron VenusOS topic patterns (`+` matches one level), used to suggest topics
+pub const SOC_PATTERNS: &[&str] = &[
+    "N/+/system/0/Batteries",
+    "N/+/system/0/Dc/Battery/Soc",
+    "N/+/battery/+/Soc",
+];
+pub const SETPOINT_PATTERN: &str = "N/+/settings/0/Settings/CGwacs/AcPowerSetPoint";
+pub const INVERTER_STATE_PATTERN: &str = "N/+/vebus/+/State";
+pub const GRID_LOST_PATTERN: &str = "N/+/vebus/+/Alarms/GridLost";
+pub const GRID_POWER_PATTERN: &str = "N/+/grid/+/Ac/Power";
+pub const BATTERY_POWER_PATTERN: &str = "N/+/vebus/+/Ac/ActiveIn/P";
+/// `Day` setting of each GX scheduled-charge window; the configured topic is the prefix before `/<index>/Day`
+#[cfg(feature = "tibber")]
+pub const CHARGE_SCHEDULE_PATTERN: &str = "N/+/settings/0/Settings/CGwacs/BatteryLife/Schedule/Charge/+/Day";
+#[cfg(feature = "tibber")]
+pub const MIN_SOC_LIMIT_PATTERN: &str = "N/+/settings/0/Settings/CGwacs/BatteryLife/MinimumSocLimit";
+#[cfg(feature = "tibber")]
+pub const ACTIVE_SOC_LIMIT_PATTERN: &str = "N/+/system/0/Control/ActiveSocLimit";
+#[cfg(feature = "tibber")]
+pub const BATTERY_CAPACITY_PATTERN: &str = "N/+/battery/+/InstalledCapacity";
+
+/// Broker connection settings for a scan
+#[derive(Debug, Clone)]
+pub struct BrokerSettings {
+    pub host: String,
+    pub port: u16,
+    pub username: Option<String>,
+    pub password: Option<String>,
+}
+
+impl From<&MqttConfig> for BrokerSettings {
+    fn from(config: &MqttConfig) -> Self {
+        Self {
+            host: config.host.clone(),
+            port: config.port,
+            username: config.username.clone(),
+            password: config.password.clone(),
+        }
+    }
+}
+
+impl BrokerSettings {
+    fn options(&self, client_id: &str) -> MqttOptions {
+        let mut options = MqttOptions::new(client_id, &self.host, self.port);
+        options.set_keep_alive(Duration::from_secs(30));
+        if let (Some(username), Some(password)) = (&self.username, &self.password) {
+            options.set_credentials(username, password);
+        }
+        options
+    }
+}
+
+/// Retained and live messages seen on the broker during a scan
+#[derive(Debug, Default)]
+pub struct BrokerScan {
+    /// Victron portal ID, if a VenusOS device was seen
+    pub portal_id: Option<String>,
+    /// Last payload per topic
+    pub topics: BTreeMap<String, String>,
+}
+
+impl BrokerScan {
+    /// Topics (with payloads) matching an MQTT pattern
+    pub fn matching<'a>(&'a self, pattern: &'a str) -> impl Iterator<Item = (&'a str, &'a str)> + 'a {
+        self.topics
+            .iter()
+            .filter(move |(topic, _)| topic_matches(pattern, topic))
+            .map(|(topic, payload)| (topic.as_str(), payload.as_str()))
+    }
+
+    /// First topic matching an MQTT pattern
+    #[cfg(feature = "tibber")]
+    pub fn first(&self, pattern: &str) -> Option<String> {
+        self.matching(pattern).next().map(|(topic, _)| topic.to_string())
+    }
+}
+
+/// Whether `topic` matches an MQTT subscription pattern (`+` and trailing `#`)
+pub fn topic_matches(pattern: &str, topic: &str) -> bool {
+    let mut topic_levels = topic.split('/');
+    for level in pattern.split('/') {
+        match (level, topic_levels.next()) {
+            ("#", _) => return true,
+            ("+", Some(_)) => {}
+            (expected, Some(actual)) if expected == actual => {}
+            _ => return false,
+        }
+    }
+    topic_levels.next().is_none()
+}
+
+/// Subscribe to everything for `duration`, waking up Victron devices with a
+/// keepalive so they publish their full topic tree
+pub async fn scan(settings: &BrokerSettings, duration: Duration) -> Result<BrokerScan> {
+    let (client, mut eventloop) = AsyncClient::new(settings.options("tibber-optimizer-scan"), 100);
+    client.subscribe("#", QoS::AtMostOnce).await?;
+
+    let mut result = BrokerScan::default();
+    let deadline = tokio::time::Instant::now() + duration;
+    loop {
+        let event = match tokio::time::timeout_at(deadline, eventloop.poll()).await {
+            Ok(event) => event?,
+            Err(_) => break,
+        };
+
+        if let Event::Incoming(Packet::Publish(publish)) = event {
+            // VenusOS only publishes its N/ tree after a keepalive on R/<portal_id>/keepalive
+            if result.portal_id.is_none() {
+                if let Some(portal_id) = publish.topic.strip_prefix("N/").and_then(|t| t.split('/').next()) {
+                    result.portal_id = Some(portal_id.to_string());
+                    client
+                        .publish(format!("R/{}/keepalive", portal_id), QoS::AtMostOnce, false, "")
+                        .await?;
+                }
+            }
+            let payload = String::from_utf8_lossy(&publish.payload).into_owned();
+            result.topics.insert(publish.topic, payload);
+        }
+    }
+
+    client.disconnect().await.ok();
+    Ok(result)
+}
+
+/// Whether `payload` reports `value`, as written by `write_echoed`
+fn echoes(payload: &[u8], value: f64) -> bool {
+    parse_mqtt_value(&String::from_utf8_lossy(payload)).is_some_and(|echoed| (echoed - value).abs() < 0.5)
+}
+
+/// Write `value` to `write_topic` and wait for the device to echo it on
+/// `read_topic`, which proves the write reached it (brokers silently drop
+/// publishes denied by their ACL). Only the value written counts, not the
+/// republish of the old one the keepalive triggers, so `value` should differ
+/// from the current one.
+pub async fn write_echoed(
+    settings: &BrokerSettings,
+    portal_id: Option<&str>,
+    write_topic: &str,
+    read_topic: &str,
+    value: f64,
+    timeout: Duration,
+) -> Result<bool> {
+    let payload = serde_json::json!({ "value": value }).to_string();
+    let (client, mut eventloop) = AsyncClient::new(settings.options("tibber-optimizer-diagnose"), 10);
+    client.subscribe(read_topic, QoS::AtMostOnce).await?;
+
+    let deadline = tokio::time::Instant::now() + timeout;
+    let mut written = false;
+    loop {
+        let event = match tokio::time::timeout_at(deadline, eventloop.poll()).await {
+            Ok(event) => event?,
+            Err(_) => break,
+        };
+
+        match event {
+            // Write once subscribed, so the echo can't be missed
+            Event::Incoming(Packet::SubAck(_)) if !written => {
+                if let Some(portal_id) = portal_id {
+                    client
+                        .publish(format!("R/{}/keepalive", portal_id), QoS::AtMostOnce, false, "")
+                        .await?;
+                }
+                client.publish(write_topic, QoS::AtLeastOnce, false, payload.clone()).await?;
+                written = true;
+            }
+            Event::Incoming(Packet::Publish(publish))
+                if written && publish.topic == read_topic && echoes(&publish.payload, value) =>
+            {
+                client.disconnect().await.ok();
+                return Ok(true);
+            }
+            _ => {}
+        }
+    }
+
+    client.disconnect().await.ok();
+    Ok(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_the_value_written_counts_as_its_echo() {
+        assert!(echoes(br#"{"value": 51.0}"#, 51.0));
+        assert!(!echoes(br#"{"value": 50.0}"#, 51.0));
+        assert!(!echoes(b"", 51.0));
+    }
+}
@@ -0,0 +1,100 @@
+use chrono::{DateTime, Datelike, Duration, FixedOffset, NaiveTime, Weekday};
+
+use crate::optimizer::{Alternative, BatteryMode, OptimizationResult};
+
+/// One scheduled charge window configured in the GX UI, as published under
+/// `N/<portal_id>/settings/0/Settings/CGwacs/BatteryLife/Schedule/Charge/<n>/...`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct VictronChargeSchedule {
+    /// 0 = Sunday .. 6 = Saturday, 7 = every day, 8 = weekdays, 9 = weekends;
+    /// negative when the schedule is disabled
+    pub day: Option<i64>,
+    /// Start in seconds after midnight (GX local time)
+    pub start_secs: Option<i64>,
+    pub duration_secs: Option<i64>,
+    /// SoC the GX charges to during the window
+    pub soc: Option<f64>,
+}
+
+impl VictronChargeSchedule {
+    /// Apply one settings field (`Day`, `Start`, `Duration`, `Soc`); returns false for unknown fields
+    pub fn set(&mut self, field: &str, value: f64) -> bool {
+        match field {
+            "Day" => self.day = Some(value as i64),
+            "Start" => self.start_secs = Some(value as i64),
+            "Duration" => self.duration_secs = Some(value as i64),
+            "Soc" => self.soc = Some(value),
+            _ => return false,
+        }
+        true
+    }
+
+    fn applies_on(&self, weekday: Weekday) -> bool {
+        match self.day {
+            Some(day @ 0..=6) => weekday.num_days_from_sunday() as i64 == day,
+            Some(7) => true,
+            Some(8) => !matches!(weekday, Weekday::Sat | Weekday::Sun),
+            Some(9) => matches!(weekday, Weekday::Sat | Weekday::Sun),
+            _ => false,
+        }
+    }
+
+    /// Whether the window covers `now` (local time), including windows that
+    /// started yesterday and run past midnight
+    pub fn is_active(&self, now: DateTime<FixedOffset>) -> bool {
+        let (Some(start), Some(duration)) = (self.start_secs, self.duration_secs) else {
+            return false;
+        };
+        let since_midnight = now.time().signed_duration_since(NaiveTime::MIN).num_seconds();
+
+        let today = self.applies_on(now.weekday()) && since_midnight >= start && since_midnight < start + duration;
+        let yesterday = self.applies_on(now.weekday().pred()) && since_midnight + 86400 < start + duration;
+        today || yesterday
+    }
+
+    /// Local end of the window active at `now`
+    fn end(&self, now: DateTime<FixedOffset>) -> Option<DateTime<FixedOffset>> {
+        let start = self.start_secs?;
+        let duration = self.duration_secs?;
+        let midnight = now - (now.time().signed_duration_since(NaiveTime::MIN));
+        let started_today = self.applies_on(now.weekday())
+            && now.signed_duration_since(midnight).num_seconds() >= start;
+        let day_start = if started_today { midnight } else { midnight - Duration::days(1) };
+        Some(day_start + Duration::seconds(start + duration))
+    }
+
+    pub fn describe(&self, now: DateTime<FixedOffset>) -> String {
+        format!(
+            "scheduled charge to {:.0}% until {}",
+            self.soc.unwrap_or(100.0),
+            self.end(now).map_or_else(|| "unknown".to_string(), |end| end.format("%H:%M").to_string())
+        )
+    }
+}
+
+/// The first externally scheduled charge window active at `now`
+pub fn active(schedules: &[VictronChargeSchedule], now: DateTime<FixedOffset>) -> Option<&VictronChargeSchedule> {
+    schedules.iter().find(|s| s.is_active(now))
+}
+
+/// Keep the optimizer from discharging while the GX runs its own charge
+/// schedule; charging and neutral decisions pass through unchanged
+pub fn constrain(
+    result: OptimizationResult,
+    schedule: &VictronChargeSchedule,
+    now: DateTime<FixedOffset>,
+    base_consumption_w: f64,
+) -> OptimizationResult {
+    match result.mode {
+        BatteryMode::DischargeToGrid | BatteryMode::SelfConsumptionPreventGridPull => OptimizationResult {
+            mode: BatteryMode::Idle,
+            grid_setpoint_w: base_consumption_w,
+            reason: format!("Victron {}, not discharging ({})", schedule.describe(now), result.reason),
+            alternative: Some(Alternative {
+                mode: result.mode,
+                reason: format!("Victron {}", schedule.describe(now)),
+            }),
+        },
+        _ => result,
+    }
+}
@@ -0,0 +1,182 @@
+//! Versioned payloads. Every published status, plan, price and report, and
+//! every HTTP API response, carries `schema_version`, and the JSON schemas
+//! generated from these types are published retained on `<base>/schema`.
+//! Adding an optional field keeps the version; renaming, removing or retyping
+//! a field bumps it.
+
+use schemars::{schema_for, JsonSchema};
+use serde::Serialize;
+
+use crate::capacity_test::CapacityTestReport;
+use crate::drift::DriftReport;
+use crate::decision::PriceTiers;
+use crate::mqtt::{OptimizerStatus, PriceStatsJson, WarrantyJson};
+use crate::optimizer::PlannedSlot;
+use crate::prices::{PricePoint, SlotOrigin};
+
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// A payload with the schema version it follows
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct Versioned<T> {
+    pub schema_version: u32,
+    #[serde(flatten)]
+    pub payload: T,
+}
+
+impl<T> Versioned<T> {
+    pub fn new(payload: T) -> Self {
+        Self {
+            schema_version: SCHEMA_VERSION,
+            payload,
+        }
+    }
+}
+
+/// The current slot's price
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct PricePayload {
+    pub total: f64,
+    pub energy: f64,
+    pub tax: f64,
+    /// Price paid for exported energy
+    pub sell: f64,
+    pub starts_at: String,
+    pub ends_at: String,
+    pub level: Option<String>,
+    pub currency: String,
+    /// Missing from the provider's data and interpolated from neighboring
+    /// slots, or not published yet and forecast
+    pub estimated: bool,
+    /// Where the price comes from
+    pub source: SlotOrigin,
+}
+
+impl From<&PricePoint> for PricePayload {
+    fn from(price: &PricePoint) -> Self {
+        Self {
+            total: price.total,
+            energy: price.energy,
+            tax: price.tax,
+            sell: price.sell_price(),
+            starts_at: price.starts_at.to_rfc3339(),
+            ends_at: price.ends_at.to_rfc3339(),
+            level: price.level.clone(),
+            currency: price.currency.clone().unwrap_or_else(|| "EUR".to_string()),
+            estimated: price.is_estimated(),
+            source: price.source,
+        }
+    }
+}
+
+/// All cached prices, served over HTTP
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct PriceListPayload {
+    pub prices: Vec<PricePayload>,
+}
+
+/// Cached prices with their stats and tiers, published by the price publisher
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct PriceSummaryPayload {
+    pub stats: Option<PriceStatsJson>,
+    pub tiers: TiersPayload,
+    pub prices: Vec<PricePayload>,
+}
+
+/// Price tier thresholds over the upcoming slots (EUR/kWh)
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct TiersPayload {
+    pub cheapest: f64,
+    pub cheap: f64,
+    pub expensive: f64,
+    /// Sell price threshold for discharging to the grid
+    pub premium: f64,
+}
+
+impl From<&PriceTiers> for TiersPayload {
+    fn from(tiers: &PriceTiers) -> Self {
+        Self {
+            cheapest: tiers.cheapest_threshold,
+            cheap: tiers.cheap_threshold,
+            expensive: tiers.expensive_threshold,
+            premium: tiers.premium_threshold,
+        }
+    }
+}
+
+/// Recent control cycles, served over HTTP
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct HistoryPayload {
+    pub cycles: Vec<CyclePayload>,
+}
+
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct CyclePayload {
+    pub at: String,
+    pub price: f64,
+    pub soc: f64,
+    pub mode: String,
+    pub grid_setpoint_w: f64,
+    /// Measured grid power (W, positive = import)
+    pub grid_power_w: Option<f64>,
+}
+
+/// The projected plan, one entry per price slot
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct PlanPayload {
+    pub slots: Vec<PlanSlotPayload>,
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct PlanSlotPayload {
+    pub starts_at: String,
+    pub ends_at: String,
+    pub price: f64,
+    pub sell_price: f64,
+    pub mode: String,
+    /// Projected battery power (W, positive = charging)
+    pub battery_power_w: f64,
+    /// Projected grid power (W, positive = import)
+    pub grid_power_w: f64,
+    pub grid_setpoint_w: f64,
+    /// Projected SoC at the end of the slot (%)
+    pub soc_end: f64,
+}
+
+impl From<&[PlannedSlot]> for PlanPayload {
+    fn from(plan: &[PlannedSlot]) -> Self {
+        let slots = plan
+            .iter()
+            .map(|slot| PlanSlotPayload {
+                starts_at: slot.starts_at.to_rfc3339(),
+                ends_at: slot.ends_at.to_rfc3339(),
+                price: slot.price,
+                sell_price: slot.sell_price,
+                mode: slot.mode.to_string(),
+                battery_power_w: slot.battery_power_w.round(),
+                grid_power_w: slot.grid_power_w.round(),
+                grid_setpoint_w: slot.grid_setpoint_w.round(),
+                soc_end: (slot.soc_end * 10.0).round() / 10.0,
+            })
+            .collect();
+        Self { slots }
+    }
+}
+
+/// JSON schemas of all versioned payloads, by payload name
+pub fn schemas() -> serde_json::Value {
+    serde_json::json!({
+        "schema_version": SCHEMA_VERSION,
+        "payloads": {
+            "status": schema_for!(Versioned<OptimizerStatus>),
+            "plan": schema_for!(Versioned<PlanPayload>),
+            "price": schema_for!(Versioned<PricePayload>),
+            "prices": schema_for!(Versioned<PriceListPayload>),
+            "history": schema_for!(Versioned<HistoryPayload>),
+            "price_summary": schema_for!(Versioned<PriceSummaryPayload>),
+            "drift_report": schema_for!(Versioned<DriftReport>),
+            "warranty_report": schema_for!(Versioned<WarrantyJson>),
+            "capacity_test": schema_for!(Versioned<CapacityTestReport>),
+        }
+    })
+}
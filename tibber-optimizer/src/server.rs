@@ -0,0 +1,269 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{Mutex, RwLock};
+use tracing::{debug, info, warn};
+
+use crate::commands::Command;
+use crate::config::HttpServerConfig;
+use crate::ics;
+use crate::mqtt::OptimizerStatus;
+use crate::optimizer::PlannedSlot;
+use crate::prices::{PriceCache, PricePoint};
+use crate::schema::{CyclePayload, HistoryPayload, PlanPayload, PriceListPayload, PricePayload, Versioned};
+use crate::supervisor::Supervisor;
+
+/// Largest request head and body accepted
+const MAX_REQUEST_BYTES: usize = 8 * 1024;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+/// How far back `/history` reaches
+const HISTORY_HOURS: i64 = 24;
+
+const DASHBOARD: &str = include_str!("dashboard.html");
+
+/// Data served over HTTP, updated by the control loop every cycle
+#[derive(Debug, Default)]
+pub struct ServerState {
+    pub plan: RwLock<Vec<PlannedSlot>>,
+    status: RwLock<Option<OptimizerStatus>>,
+    prices: RwLock<Vec<PricePoint>>,
+    /// Control cycles of the last `HISTORY_HOURS`, oldest first
+    history: RwLock<VecDeque<(DateTime<Utc>, CyclePayload)>>,
+    /// Commands posted since the last `take_commands`
+    commands: Mutex<Vec<Command>>,
+}
+
+impl ServerState {
+    /// Record a completed control cycle
+    pub async fn update(&self, now: DateTime<Utc>, status: &OptimizerStatus, prices: &PriceCache) {
+        *self.status.write().await = Some(status.clone());
+        *self.prices.write().await = prices.all_prices().cloned().collect();
+
+        let mut history = self.history.write().await;
+        let cutoff = now - chrono::Duration::hours(HISTORY_HOURS);
+        while history.front().is_some_and(|(at, _)| *at < cutoff) {
+            history.pop_front();
+        }
+        history.push_back((
+            now,
+            CyclePayload {
+                at: now.to_rfc3339(),
+                price: status.current_price,
+                soc: status.battery_soc,
+                mode: status.current_mode.clone(),
+                grid_setpoint_w: status.grid_setpoint_w,
+                grid_power_w: status.grid_power_w,
+            },
+        ));
+    }
+
+    /// Drain the commands received since the last call
+    pub async fn take_commands(&self) -> Vec<Command> {
+        std::mem::take(&mut *self.commands.lock().await)
+    }
+}
+
+/// A response to write back; connections are closed after each one
+struct Response {
+    status: &'static str,
+    content_type: &'static str,
+    body: String,
+}
+
+impl Response {
+    fn ok(content_type: &'static str, body: String) -> Self {
+        Self { status: "200 OK", content_type, body }
+    }
+
+    /// A versioned JSON payload
+    fn json<T: Serialize>(payload: T) -> Self {
+        match serde_json::to_string(&Versioned::new(payload)) {
+            Ok(body) => Self::ok("application/json", body),
+            Err(_) => Self::error("500 Internal Server Error"),
+        }
+    }
+
+    fn error(status: &'static str) -> Self {
+        Self { status, content_type: "text/plain; charset=utf-8", body: format!("{}\n", status) }
+    }
+}
+
+/// Bind the configured address and serve requests in the background.
+///
+/// Serves the dashboard at `/`, the JSON API (`/status`, `/plan`, `/prices`,
+/// `/history`), the plan calendar and commands.
+///
+/// A deliberately small plain-HTTP/1.1 server over tokio (like the minimal
+/// HTTP client), so the GX build needs no extra dependencies. Put a reverse
+/// proxy in front of it for TLS. Commands need the configured token and are
+/// refused without one.
+pub async fn start(config: &HttpServerConfig, state: Arc<ServerState>, supervisor: &Supervisor) -> Result<()> {
+    let listener = TcpListener::bind(&config.bind)
+        .await
+        .with_context(|| format!("Failed to bind HTTP server to {}", config.bind))?;
+    info!("HTTP server listening on {}", config.bind);
+
+    if config.token.is_none() {
+        info!("http_server.token is not set: commands over HTTP are refused");
+    }
+
+    let listener = Arc::new(listener);
+    let token: Option<Arc<str>> = config.token.as_deref().map(Arc::from);
+    supervisor.spawn("http_server", move || {
+        let listener = listener.clone();
+        let state = state.clone();
+        let token = token.clone();
+        async move {
+            loop {
+                match listener.accept().await {
+                    Ok((stream, peer)) => {
+                        let state = state.clone();
+                        let token = token.clone();
+                        tokio::spawn(async move {
+                            if let Err(e) = handle(stream, peer, token.as_deref(), &state).await {
+                                debug!("HTTP request from {} failed: {}", peer, e);
+                            }
+                        });
+                    }
+                    Err(e) => debug!("Failed to accept HTTP connection: {}", e),
+                }
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Whether a request may change anything: it must carry the configured token
+/// as a bearer token, and nothing may without one
+fn may_command(head: &str, token: Option<&str>) -> bool {
+    let Some(token) = token else {
+        return false;
+    };
+    head.lines()
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("authorization"))
+        .and_then(|(_, value)| value.trim().strip_prefix("Bearer "))
+        .is_some_and(|given| constant_time_eq(given.trim().as_bytes(), token.as_bytes()))
+}
+
+/// Compare without returning early at the first differing byte, so the time
+/// taken doesn't tell how much of a guessed token was right
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+async fn handle(mut stream: TcpStream, peer: SocketAddr, token: Option<&str>, state: &ServerState) -> Result<()> {
+    let (head, body) = tokio::time::timeout(REQUEST_TIMEOUT, read_request(&mut stream))
+        .await
+        .context("Timed out reading request")??;
+
+    let mut parts = head.lines().next().unwrap_or_default().split_whitespace();
+    let (method, target) = (parts.next().unwrap_or_default(), parts.next().unwrap_or_default());
+    let path = target.split('?').next().unwrap_or_default();
+
+    let response = match (method, path) {
+        ("GET" | "HEAD", "/") => Response::ok("text/html; charset=utf-8", DASHBOARD.to_string()),
+        ("GET" | "HEAD", "/status") => match state.status.read().await.as_ref() {
+            Some(status) => Response::json(status),
+            None => Response::error("503 Service Unavailable"),
+        },
+        ("GET" | "HEAD", "/plan") => Response::json(PlanPayload::from(&state.plan.read().await[..])),
+        ("GET" | "HEAD", "/prices") => Response::json(PriceListPayload {
+            prices: state.prices.read().await.iter().map(PricePayload::from).collect(),
+        }),
+        ("GET" | "HEAD", "/history") => Response::json(HistoryPayload {
+            cycles: state.history.read().await.iter().map(|(_, cycle)| cycle.clone()).collect(),
+        }),
+        ("GET" | "HEAD", "/plan.ics") => {
+            let plan = state.plan.read().await;
+            Response::ok("text/calendar; charset=utf-8", ics::plan_calendar(&plan, chrono::Utc::now()))
+        }
+        ("POST", "/command") if !may_command(&head, token) => {
+            warn!("Refusing HTTP command from {} without the token", peer);
+            Response::error("401 Unauthorized")
+        }
+        ("POST", "/command") => match serde_json::from_slice::<Command>(&body) {
+            Ok(command) => {
+                info!("Received command over HTTP: {:?}", command);
+                state.commands.lock().await.push(command);
+                Response::ok("text/plain; charset=utf-8", "accepted\n".to_string())
+            }
+            Err(e) => {
+                warn!("Ignoring invalid HTTP command: {}", e);
+                Response::error("400 Bad Request")
+            }
+        },
+        ("GET" | "HEAD", _) | ("POST", _) => Response::error("404 Not Found"),
+        _ => Response::error("405 Method Not Allowed"),
+    };
+
+    let head = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n",
+        response.status,
+        response.content_type,
+        response.body.len()
+    );
+    stream.write_all(head.as_bytes()).await?;
+    if method != "HEAD" {
+        stream.write_all(response.body.as_bytes()).await?;
+    }
+    stream.shutdown().await?;
+    Ok(())
+}
+
+/// Read the request head and a `Content-Length` body, if any
+async fn read_request(stream: &mut TcpStream) -> Result<(String, Vec<u8>)> {
+    let mut buf = Vec::with_capacity(1024);
+    let mut chunk = [0u8; 1024];
+    let mut head: Option<(String, usize)> = None;
+    loop {
+        if head.is_none() {
+            if let Some(end) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+                let text = String::from_utf8_lossy(&buf[..end]).into_owned();
+                let length = text
+                    .lines()
+                    .filter_map(|line| line.split_once(':'))
+                    .find(|(name, _)| name.trim().eq_ignore_ascii_case("content-length"))
+                    .and_then(|(_, value)| value.trim().parse::<usize>().ok())
+                    .unwrap_or(0);
+                buf.drain(..end + 4);
+                head = Some((text, length));
+            }
+        }
+        if let Some((text, length)) = &head {
+            anyhow::ensure!(*length <= MAX_REQUEST_BYTES, "Request body too large");
+            if buf.len() >= *length {
+                buf.truncate(*length);
+                return Ok((text.clone(), buf));
+            }
+        }
+        anyhow::ensure!(buf.len() <= MAX_REQUEST_BYTES, "Request head too large");
+
+        let n = stream.read(&mut chunk).await?;
+        anyhow::ensure!(n > 0, "Connection closed before end of request");
+        buf.extend_from_slice(&chunk[..n]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn commands_need_the_token() {
+        let head = "POST /command HTTP/1.1\r\nHost: optimizer\r\nauthorization: Bearer s3cret";
+
+        assert!(!may_command("POST /command HTTP/1.1", None));
+        assert!(!may_command(head, None));
+        assert!(may_command(head, Some("s3cret")));
+        assert!(!may_command(head, Some("other")));
+        assert!(!may_command(head, Some("s3cret2")));
+        assert!(!may_command("POST /command HTTP/1.1", Some("s3cret")));
+    }
+}
@@ -0,0 +1,126 @@
+//! Shelly Pro 3EM as the grid meter: per-phase and total power from its `EM`
+//! component and the energy counters from `EMData`, read from its MQTT status
+//! notifications or polled over its HTTP RPC API. The house load follows from
+//! grid minus battery power where no house load source is configured.
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use tracing::{debug, warn};
+
+use crate::http::HttpClient;
+use crate::mqtt::BatteryState;
+
+/// `EM.GetStatus`, also published to `<prefix>/status/em:0`
+#[derive(Debug, Clone, Deserialize)]
+pub struct EmStatus {
+    /// Active power per phase (W, positive = import)
+    pub a_act_power: f64,
+    pub b_act_power: f64,
+    pub c_act_power: f64,
+    pub total_act_power: f64,
+}
+
+/// `EMData.GetStatus`, also published to `<prefix>/status/emdata:0`
+#[derive(Debug, Clone, Deserialize)]
+pub struct EmData {
+    /// Imported energy over all phases (Wh)
+    pub total_act: f64,
+    /// Exported energy over all phases (Wh)
+    pub total_act_ret: f64,
+}
+
+impl EmStatus {
+    pub fn apply(&self, state: &mut BatteryState, now: DateTime<Utc>) {
+        state.grid_power_w = Some(self.total_act_power);
+        state.grid_phase_power_w = Some([self.a_act_power, self.b_act_power, self.c_act_power]);
+        state.last_grid_power_update = Some(now);
+    }
+}
+
+impl EmData {
+    pub fn apply(&self, state: &mut BatteryState) {
+        state.meter_import_kwh = Some(self.total_act / 1000.0);
+        state.meter_export_kwh = Some(self.total_act_ret / 1000.0);
+    }
+}
+
+/// The status topics of the meter with MQTT topic prefix `prefix`
+pub fn status_topics(prefix: &str) -> [String; 2] {
+    [format!("{}/status/em:0", prefix), format!("{}/status/emdata:0", prefix)]
+}
+
+/// Apply a status notification on one of [`status_topics`]; returns false
+/// for other topics
+pub fn handle(prefix: &str, state: &mut BatteryState, topic: &str, payload: &str, now: DateTime<Utc>) -> bool {
+    let Some(component) = topic.strip_prefix(prefix).and_then(|t| t.strip_prefix("/status/")) else {
+        return false;
+    };
+    match component {
+        "em:0" => match serde_json::from_str::<EmStatus>(payload) {
+            Ok(status) => {
+                status.apply(state, now);
+                debug!("Updated Shelly grid power: {:.0}W", status.total_act_power);
+            }
+            Err(e) => warn!("Ignoring invalid Shelly EM status: {}", e),
+        },
+        "emdata:0" => match serde_json::from_str::<EmData>(payload) {
+            Ok(data) => data.apply(state),
+            Err(e) => warn!("Ignoring invalid Shelly EM data: {}", e),
+        },
+        _ => return false,
+    }
+    true
+}
+
+/// Polls the meter's HTTP RPC API, for meters without MQTT
+pub struct ShellyPoller {
+    host: String,
+    http_client: HttpClient,
+    status: Option<(EmStatus, DateTime<Utc>)>,
+    data: Option<EmData>,
+}
+
+impl ShellyPoller {
+    pub fn new(host: String) -> Self {
+        Self {
+            host,
+            http_client: HttpClient::new(),
+            status: None,
+            data: None,
+        }
+    }
+
+    async fn rpc<T: serde::de::DeserializeOwned>(&self, method: &str) -> Result<T> {
+        let url = format!("http://{}/rpc/{}?id=0", self.host, method);
+        let response = self.http_client.get(&url, &[]).await?;
+        if !response.is_success() {
+            anyhow::bail!("Shelly {} failed: {} - {}", method, response.status, response.text());
+        }
+        Ok(serde_json::from_slice(&response.body)?)
+    }
+
+    /// Refresh the readings; a failed read keeps the last ones, which go stale
+    /// by their timestamp
+    pub async fn poll(&mut self, now: DateTime<Utc>) {
+        match self.rpc::<EmStatus>("EM.GetStatus").await {
+            Ok(status) => self.status = Some((status, now)),
+            Err(e) => warn!("Failed to read Shelly at {}: {}", self.host, e),
+        }
+        match self.rpc::<EmData>("EMData.GetStatus").await {
+            Ok(data) => self.data = Some(data),
+            Err(e) => warn!("Failed to read Shelly energy counters at {}: {}", self.host, e),
+        }
+    }
+
+    /// The MQTT state with the meter's readings taking precedence
+    pub fn apply(&self, mut state: BatteryState) -> BatteryState {
+        if let Some((status, at)) = &self.status {
+            status.apply(&mut state, *at);
+        }
+        if let Some(data) = &self.data {
+            data.apply(&mut state);
+        }
+        state
+    }
+}
@@ -0,0 +1,230 @@
+//! Slew-rate limiting of the written grid setpoint. More import or export,
+//! e.g. swinging from +15000W to -15000W, is reached in steps of at most
+//! `max_step_w` every `step_secs` instead of at once, so a grid-code ripple
+//! relay isn't tripped and the Multiplus doesn't hunt. Backing off toward zero
+//! (a fuse or grid operator cap, the failsafe) is written at once.
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::Result;
+use tokio::time::Instant;
+use tracing::{debug, warn};
+
+use crate::config::SetpointSlewConfig;
+use crate::controller::{BatteryController, WriteFuture};
+use crate::mqtt::BatteryState;
+use crate::optimizer::BatteryMode;
+use crate::supervisor::Supervisor;
+
+/// The setpoint one step from `current_w` toward `target_w`: less import or
+/// export at once, more by at most `max_step_w`. Crossing zero backs off to
+/// zero first.
+pub fn next_step(current_w: Option<f64>, target_w: f64, max_step_w: f64) -> f64 {
+    let Some(current_w) = current_w else {
+        return target_w;
+    };
+    if current_w * target_w < 0.0 {
+        return 0.0;
+    }
+    let from_w = if current_w * target_w > 0.0 { current_w } else { 0.0 };
+    if target_w.abs() <= from_w.abs() + max_step_w {
+        target_w
+    } else {
+        from_w + max_step_w.copysign(target_w)
+    }
+}
+
+/// Whether going from `from_w` to `to_w` means more import or export
+fn raises(from_w: f64, to_w: f64) -> bool {
+    if from_w * to_w > 0.0 {
+        to_w.abs() > from_w.abs()
+    } else {
+        to_w != 0.0
+    }
+}
+
+/// Writes setpoints through another controller. Raises are written one step at
+/// a time; a background task carries on with the steps between control cycles.
+pub struct SlewLimitedController {
+    slew: Arc<Slew>,
+}
+
+struct Slew {
+    inner: Box<dyn BatteryController>,
+    max_step_w: f64,
+    step: Duration,
+    state: Mutex<SlewState>,
+    /// Held while a step is written, so the task and the control loop take turns
+    writing: tokio::sync::Mutex<()>,
+}
+
+#[derive(Debug, Default)]
+struct SlewState {
+    /// Setpoint last written, or read back from the ESS before the first write
+    written_w: Option<f64>,
+    /// Setpoint the steps head for, and the mode to write them with
+    target: Option<(Option<BatteryMode>, f64)>,
+    /// When the setpoint was last raised
+    raised_at: Option<Instant>,
+}
+
+impl SlewLimitedController {
+    pub fn new(inner: Box<dyn BatteryController>, config: &SetpointSlewConfig, supervisor: &Supervisor) -> Self {
+        let step = Duration::from_secs(config.step_secs);
+        let slew = Arc::new(Slew {
+            inner,
+            max_step_w: config.max_step_w,
+            step,
+            state: Mutex::default(),
+            writing: tokio::sync::Mutex::new(()),
+        });
+        let task_slew = slew.clone();
+        supervisor.spawn("setpoint_slew", move || {
+            let slew = task_slew.clone();
+            async move {
+                let mut interval = tokio::time::interval_at(Instant::now() + step, step);
+                loop {
+                    interval.tick().await;
+                    if let Err(e) = slew.step(false).await {
+                        warn!("Failed to write setpoint step: {}", e);
+                    }
+                }
+            }
+        });
+        Self { slew }
+    }
+}
+
+impl Slew {
+    /// Write the next step toward the target. The control loop `rewrite`s the
+    /// current setpoint while the next raise isn't due yet; the task only
+    /// writes progress.
+    async fn step(&self, rewrite: bool) -> Result<()> {
+        let _writing = self.writing.lock().await;
+        let (mode, setpoint_w, target_w, raised) = {
+            let state = self.state.lock().unwrap();
+            let Some((mode, target_w)) = state.target else {
+                return Ok(());
+            };
+            let raise_due = state.raised_at.is_none_or(|at| at.elapsed() >= self.step);
+            let max_step_w = if raise_due { self.max_step_w } else { 0.0 };
+            let setpoint_w = next_step(state.written_w, target_w, max_step_w);
+            if !rewrite && state.written_w == Some(setpoint_w) {
+                return Ok(());
+            }
+            let raised = state.written_w.is_some_and(|written_w| raises(written_w, setpoint_w));
+            (mode, setpoint_w, target_w, raised)
+        };
+
+        match mode {
+            Some(mode) => self.inner.write_mode(mode, setpoint_w).await?,
+            None => self.inner.write_setpoint(setpoint_w).await?,
+        }
+        let mut state = self.state.lock().unwrap();
+        state.written_w = Some(setpoint_w);
+        if raised {
+            state.raised_at = Some(Instant::now());
+        }
+        if setpoint_w != target_w {
+            debug!("Ramping setpoint: {:.0}W toward {:.0}W", setpoint_w, target_w);
+        }
+        Ok(())
+    }
+
+    async fn write(&self, mode: Option<BatteryMode>, target_w: f64) -> Result<()> {
+        self.state.lock().unwrap().target = Some((mode, target_w));
+        self.step(true).await
+    }
+}
+
+impl BatteryController for SlewLimitedController {
+    fn name(&self) -> &'static str {
+        self.slew.inner.name()
+    }
+
+    fn write_setpoint(&self, setpoint_w: f64) -> WriteFuture<'_> {
+        Box::pin(self.slew.write(None, setpoint_w))
+    }
+
+    fn write_mode(&self, mode: BatteryMode, setpoint_w: f64) -> WriteFuture<'_> {
+        Box::pin(self.slew.write(Some(mode), setpoint_w))
+    }
+
+    fn observe(&self, state: &BatteryState) {
+        // Start ramping from what the ESS is running on, also after a pause or
+        // manual override
+        let mut slew_state = self.slew.state.lock().unwrap();
+        if slew_state.written_w.is_none() {
+            slew_state.written_w = state.current_setpoint_w;
+        }
+        drop(slew_state);
+        self.slew.inner.observe(state);
+    }
+
+    fn writes_every_cycle(&self) -> bool {
+        self.slew.inner.writes_every_cycle()
+    }
+
+    fn written_setpoint_w(&self) -> Option<f64> {
+        self.slew.state.lock().unwrap().written_w
+    }
+
+    /// Stop the steps still under way; the next ramp starts from the read-back setpoint
+    fn release(&self) -> WriteFuture<'_> {
+        Box::pin(async move {
+            let _writing = self.slew.writing.lock().await;
+            *self.slew.state.lock().unwrap() = SlewState::default();
+            self.slew.inner.release().await
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Keeps every setpoint it is asked to write
+    struct Recording(Arc<Mutex<Vec<f64>>>);
+
+    impl BatteryController for Recording {
+        fn name(&self) -> &'static str {
+            "recording"
+        }
+
+        fn write_setpoint(&self, setpoint_w: f64) -> WriteFuture<'_> {
+            self.0.lock().unwrap().push(setpoint_w);
+            Box::pin(async { Ok(()) })
+        }
+    }
+
+    #[test]
+    fn steps_toward_the_target() {
+        assert_eq!(next_step(Some(15000.0), 3000.0, 1000.0), 3000.0);
+        assert_eq!(next_step(Some(15000.0), -15000.0, 1000.0), 0.0);
+        assert_eq!(next_step(Some(0.0), -15000.0, 1000.0), -1000.0);
+        assert_eq!(next_step(None, -15000.0, 1000.0), -15000.0);
+    }
+
+    #[tokio::test]
+    async fn backs_off_at_once_and_raises_once_per_step() {
+        let written = Arc::new(Mutex::new(Vec::new()));
+        let config = SetpointSlewConfig { max_step_w: 1000.0, step_secs: 3600 };
+        let controller = SlewLimitedController::new(Box::new(Recording(written.clone())), &config, &Supervisor::new());
+        controller.observe(&BatteryState {
+            current_setpoint_w: Some(-15000.0),
+            ..BatteryState::default()
+        });
+
+        // A cap takes effect at once, the swing to import drops to zero before
+        // it is raised one step, and the next raise waits for its step
+        for setpoint_w in [-4200.0, 3000.0, 3000.0, 3000.0, -500.0] {
+            controller.write_setpoint(setpoint_w).await.unwrap();
+        }
+        assert_eq!(*written.lock().unwrap(), [-4200.0, 0.0, 1000.0, 1000.0, 0.0]);
+        assert_eq!(controller.written_setpoint_w(), Some(0.0));
+
+        controller.release().await.unwrap();
+        assert_eq!(controller.written_setpoint_w(), None);
+    }
+}
@@ -0,0 +1,112 @@
+//! SMA Sunny Boy Storage / Sunny Island over Modbus TCP. Unlike a Victron ESS,
+//! these take a battery power setpoint rather than a grid setpoint, so the
+//! grid setpoint is turned into battery power against the measured net load
+//! (house consumption minus PV) and rewritten every cycle as the load changes.
+//! Releasing control hands the battery back to the inverter's own control.
+
+use std::sync::Mutex;
+
+use tracing::debug;
+
+use crate::config::SmaConfig;
+use crate::controller::{BatteryController, WriteFuture};
+use crate::modbus::ModbusClient;
+use crate::mqtt::BatteryState;
+
+/// Active power setpoint (S32, W, positive = discharge), followed by the
+/// power control mode (U32) at 40151
+const POWER_SETPOINT_REGISTER: u16 = 40149;
+
+/// Power control mode: "active power via communication"
+const EXTERNAL_CONTROL: u32 = 802;
+
+/// Power control mode: external control off
+const INTERNAL_CONTROL: u32 = 803;
+
+pub struct SmaController {
+    client: ModbusClient,
+    max_charge_power_w: f64,
+    max_discharge_power_w: f64,
+    /// Net load (W) assumed until one is measured
+    fallback_load_w: f64,
+    /// Latest measured net load (W)
+    load_w: Mutex<Option<f64>>,
+    /// Last battery power written (W, positive = discharge)
+    written_w: Mutex<Option<f64>>,
+}
+
+impl SmaController {
+    pub fn new(config: &SmaConfig, max_charge_power_w: f64, max_discharge_power_w: f64, fallback_load_w: f64) -> Self {
+        Self {
+            client: ModbusClient::new(&config.host, config.port, config.unit_id, config.timeout_secs),
+            max_charge_power_w: config.max_charge_power_w.unwrap_or(max_charge_power_w),
+            max_discharge_power_w: config.max_discharge_power_w.unwrap_or(max_discharge_power_w),
+            fallback_load_w,
+            load_w: Mutex::new(None),
+            written_w: Mutex::new(None),
+        }
+    }
+
+    /// Battery power (W, positive = discharge) that brings the grid to `setpoint_w`
+    fn battery_power(&self, setpoint_w: f64) -> f64 {
+        let load_w = self.load_w.lock().unwrap().unwrap_or(self.fallback_load_w);
+        (load_w - setpoint_w).clamp(-self.max_charge_power_w, self.max_discharge_power_w)
+    }
+}
+
+impl BatteryController for SmaController {
+    fn name(&self) -> &'static str {
+        "SMA Modbus TCP"
+    }
+
+    fn write_setpoint(&self, setpoint_w: f64) -> WriteFuture<'_> {
+        Box::pin(async move {
+            let power_w = self.battery_power(setpoint_w).round() as i32;
+            let [power_high, power_low] = split(power_w as u32);
+            let [mode_high, mode_low] = split(EXTERNAL_CONTROL);
+            self.client
+                .write_registers(POWER_SETPOINT_REGISTER, &[power_high, power_low, mode_high, mode_low])
+                .await?;
+            *self.written_w.lock().unwrap() = Some(power_w as f64);
+            debug!("Wrote battery power: {} W for grid setpoint {:.0} W", power_w, setpoint_w);
+            Ok(())
+        })
+    }
+
+    fn observe(&self, state: &BatteryState) {
+        // Grid power plus battery discharge is what the house draws net of PV;
+        // without a battery power reading, assume the last setpoint was followed
+        let discharge_w = state
+            .battery_power_w
+            .map(|charge_w| -charge_w)
+            .or(*self.written_w.lock().unwrap());
+        let load_w = state.house_load_w.or(match (state.grid_power_w, discharge_w) {
+            (Some(grid_w), Some(discharge_w)) => Some(grid_w + discharge_w),
+            _ => None,
+        });
+        if load_w.is_some() {
+            *self.load_w.lock().unwrap() = load_w;
+        }
+    }
+
+    fn writes_every_cycle(&self) -> bool {
+        true
+    }
+
+    fn release(&self) -> WriteFuture<'_> {
+        Box::pin(async move {
+            let [mode_high, mode_low] = split(INTERNAL_CONTROL);
+            self.client
+                .write_registers(POWER_SETPOINT_REGISTER, &[0, 0, mode_high, mode_low])
+                .await?;
+            *self.written_w.lock().unwrap() = None;
+            debug!("Handed battery power back to the inverter");
+            Ok(())
+        })
+    }
+}
+
+/// A 32-bit value as two registers, high word first
+fn split(value: u32) -> [u16; 2] {
+    [(value >> 16) as u16, value as u16]
+}
@@ -0,0 +1,254 @@
+//! Feedback from the realized SoC into the consumption estimate. Over each
+//! slot in which the battery follows the house load, the SoC reached is
+//! compared with the planned one; the difference, as house load, updates an
+//! exponentially weighted mean and variance. The mean corrects the planned
+//! house load, and the upper end of the interval around it sizes the reserve.
+//! Slots with forecast PV production are left out, so a PV forecast error isn't
+//! learned as house load and applied at night.
+
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Duration, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+
+use crate::config::SocFeedbackConfig;
+use crate::optimizer::{BatteryMode, PlannedSlot};
+use crate::persist;
+use crate::pv_forecast::PvForecast;
+
+/// Slots compared before the correction is applied
+const MIN_SAMPLES: u32 = 8;
+
+/// How late after the end of a slot its SoC still counts
+const MAX_LATE_MINUTES: i64 = 5;
+
+/// SoC margin (percentage points) from the limits; a battery resting at its
+/// minimum or maximum doesn't follow the load
+const LIMIT_MARGIN: f64 = 1.0;
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+struct FeedbackState {
+    /// Running mean of the house load error (W, positive = more than planned)
+    bias_w: f64,
+    /// Running variance of the house load error (W²)
+    variance_w2: f64,
+    samples: u32,
+}
+
+/// Correction of the expected house load from the SoC feedback
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, JsonSchema)]
+pub struct LoadCorrection {
+    /// Added to the expected house load (W)
+    pub bias_w: f64,
+    /// Confidence interval of the house load error (W)
+    pub lower_w: f64,
+    pub upper_w: f64,
+    /// Slots compared so far
+    pub samples: u32,
+}
+
+/// A slot being followed: when it ends, and where the SoC should be by then
+#[derive(Debug, Clone, Copy)]
+struct Followed {
+    started: DateTime<Utc>,
+    ends_at: DateTime<Utc>,
+    start_soc: f64,
+    /// Planned SoC change (percentage points per hour)
+    planned_rate: f64,
+    /// Correction the plan was made with (W)
+    applied_w: f64,
+}
+
+#[derive(Debug)]
+pub struct SocFeedback {
+    config: SocFeedbackConfig,
+    path: PathBuf,
+    capacity_kwh: f64,
+    state: FeedbackState,
+    followed: Option<Followed>,
+    last_save: Option<DateTime<Utc>>,
+}
+
+impl SocFeedback {
+    /// Load the running estimate from `<data_dir>/soc_feedback.json`, starting
+    /// fresh if absent
+    pub fn load(config: SocFeedbackConfig, data_dir: &str, capacity_kwh: f64) -> Self {
+        let path = Path::new(data_dir).join("soc_feedback.json");
+        Self {
+            config,
+            state: persist::load_json(&path),
+            path,
+            capacity_kwh,
+            followed: None,
+            last_save: None,
+        }
+    }
+
+    /// Compare the SoC with the plan for the slot that just ended, and follow
+    /// the current one. `plan` must start with the current slot, planned from
+    /// `soc`. Pass `following = false` while something other than the plan
+    /// decides the setpoint. Returns true when the estimate was updated.
+    pub fn record(
+        &mut self,
+        now: DateTime<Utc>,
+        soc: f64,
+        soc_range: (f64, f64),
+        plan: &[PlannedSlot],
+        pv_forecast: Option<&PvForecast>,
+        following: bool,
+    ) -> bool {
+        let inside = |soc: f64| soc > soc_range.0 + LIMIT_MARGIN && soc < soc_range.1 - LIMIT_MARGIN;
+        if !following || !inside(soc) {
+            self.followed = None;
+            return false;
+        }
+
+        let mut updated = false;
+        if let Some(followed) = self.followed.filter(|f| now >= f.ends_at) {
+            self.followed = None;
+            if now - followed.ends_at <= Duration::minutes(MAX_LATE_MINUTES) {
+                let hours = (now - followed.started).num_seconds() as f64 / 3600.0;
+                let expected_soc = followed.start_soc + followed.planned_rate * hours;
+                let error_w = (expected_soc - soc) / 100.0 * self.capacity_kwh * 1000.0 / hours;
+                self.update(followed.applied_w + error_w);
+                updated = true;
+            }
+        }
+
+        if self.followed.is_none() {
+            let applied_w = self.correction().map_or(0.0, |c| c.bias_w);
+            self.followed = plan.first().and_then(|slot| {
+                let ends_at = slot.ends_at.with_timezone(&Utc);
+                let follows_load = matches!(
+                    slot.mode,
+                    BatteryMode::SelfConsumption
+                        | BatteryMode::SelfConsumptionPreventFeedIn
+                        | BatteryMode::SelfConsumptionPreventGridPull
+                );
+                let sunny = pv_forecast.is_some_and(|pv| pv.surplus_kwh(slot.starts_at, slot.ends_at, 0.0) > 0.0);
+                (follows_load && !sunny && inside(slot.soc_end) && now < ends_at).then(|| Followed {
+                    started: now,
+                    ends_at,
+                    start_soc: soc,
+                    planned_rate: (slot.soc_end - soc) / slot.hours(),
+                    applied_w,
+                })
+            });
+        }
+
+        if updated {
+            let due = self
+                .last_save
+                .is_none_or(|last| now.signed_duration_since(last).num_seconds() >= persist::SAVE_INTERVAL_SECS);
+            if due {
+                match persist::save_json(&self.path, &self.state) {
+                    Ok(()) => self.last_save = Some(now),
+                    Err(e) => warn!("Failed to save SoC feedback to {}: {}", self.path.display(), e),
+                }
+            }
+        }
+        updated
+    }
+
+    /// Fold one slot's house load error (W, against the uncorrected
+    /// estimate) into the running mean and variance
+    fn update(&mut self, error_w: f64) {
+        let alpha = self.config.smoothing.clamp(0.01, 1.0);
+        let state = &mut self.state;
+        if state.samples == 0 {
+            state.bias_w = error_w;
+        } else {
+            let deviation_w = error_w - state.bias_w;
+            state.bias_w += alpha * deviation_w;
+            state.variance_w2 = (1.0 - alpha) * (state.variance_w2 + alpha * deviation_w * deviation_w);
+        }
+        state.samples = state.samples.saturating_add(1);
+        debug!(
+            "SoC feedback: house load {:+.0}W off the estimate, correction {:+.0}W",
+            error_w, state.bias_w
+        );
+    }
+
+    /// The correction to plan with, once enough slots were compared
+    pub fn correction(&self) -> Option<LoadCorrection> {
+        let state = &self.state;
+        if state.samples < MIN_SAMPLES {
+            return None;
+        }
+        let spread_w = self.config.interval_sigmas * state.variance_w2.sqrt();
+        Some(LoadCorrection {
+            bias_w: state.bias_w,
+            lower_w: state.bias_w - spread_w,
+            upper_w: state.bias_w + spread_w,
+            samples: state.samples,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn slot(starts_at: &str, soc_end: f64) -> PlannedSlot {
+        let starts_at = DateTime::parse_from_rfc3339(starts_at).unwrap();
+        PlannedSlot {
+            starts_at,
+            ends_at: starts_at + Duration::minutes(15),
+            price: 0.30,
+            sell_price: 0.10,
+            cheap: false,
+            mode: BatteryMode::SelfConsumption,
+            battery_power_w: -1000.0,
+            grid_power_w: 0.0,
+            grid_setpoint_w: 0.0,
+            soc_end,
+        }
+    }
+
+    #[test]
+    fn learns_a_higher_house_load_from_the_soc() {
+        let dir = std::env::temp_dir().join(format!("soc_feedback_test_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut feedback = SocFeedback::load(SocFeedbackConfig::default(), dir.to_str().unwrap(), 10.0);
+        feedback.state = FeedbackState::default();
+        let start = DateTime::parse_from_rfc3339("2025-12-01T18:00:00+00:00").unwrap().with_timezone(&Utc);
+
+        // Planned 2.5 points (1 kW) per slot, but every slot drops 3.75 (1.5 kW)
+        let mut soc = 80.0;
+        for i in 0..MIN_SAMPLES as i64 + 1 {
+            let now = start + Duration::minutes(15 * i);
+            let plan = [slot(&now.to_rfc3339(), soc - 2.5)];
+            feedback.record(now, soc, (10.0, 100.0), &plan, None, true);
+            soc -= 3.75;
+        }
+
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let correction = feedback.correction().unwrap();
+        assert!((correction.bias_w - 500.0).abs() < 1.0, "{:?}", correction);
+        assert!(correction.lower_w <= correction.bias_w && correction.upper_w >= correction.bias_w);
+    }
+
+    #[test]
+    fn leaves_out_slots_with_forecast_pv() {
+        let dir = std::env::temp_dir().join(format!("soc_feedback_pv_test_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut feedback = SocFeedback::load(SocFeedbackConfig::default(), dir.to_str().unwrap(), 10.0);
+        feedback.state = FeedbackState::default();
+        let start = DateTime::parse_from_rfc3339("2025-06-01T12:00:00+00:00").unwrap();
+        let pv = PvForecast::new((1..=4).map(|hour| (start + Duration::hours(hour), 2000.0)).collect());
+
+        // PV falling short of its forecast looks like house load, and isn't learned
+        let mut soc = 80.0;
+        for i in 0..MIN_SAMPLES as i64 + 1 {
+            let now = start + Duration::minutes(15 * i);
+            let plan = [slot(&now.to_rfc3339(), soc - 2.5)];
+            assert!(!feedback.record(now.with_timezone(&Utc), soc, (10.0, 100.0), &plan, Some(&pv), true));
+            soc -= 3.75;
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(feedback.state.samples, 0);
+    }
+}
@@ -0,0 +1,63 @@
+use tracing::{info, warn};
+
+/// GX floors within this of `min_soc_percent` don't count as a conflict (%)
+const TOLERANCE_PERCENT: f64 = 0.5;
+
+/// Reconciles the GX's own SoC floor with `min_soc_percent`. The GX refuses to
+/// discharge below its MinimumSocLimit (or the higher ActiveSocLimit while
+/// BatteryLife raises it), so a plan built on a lower floor counts on energy
+/// the ESS won't deliver.
+#[derive(Debug)]
+pub struct SocLimitCheck {
+    configured: f64,
+    adopt: bool,
+    /// GX floor currently conflicting with the configured one
+    conflict: Option<f64>,
+}
+
+impl SocLimitCheck {
+    pub fn new(configured: f64, adopt: bool) -> Self {
+        Self {
+            configured,
+            adopt,
+            conflict: None,
+        }
+    }
+
+    /// Track the GX floor; returns an alert message when a conflict starts
+    /// (true) or ends (false)
+    pub fn update(&mut self, gx_floor: Option<f64>) -> Option<(String, bool)> {
+        let conflict = gx_floor.filter(|&floor| floor > self.configured + TOLERANCE_PERCENT);
+        let changed = match (self.conflict, conflict) {
+            (None, None) => false,
+            (Some(before), Some(now)) => (before - now).abs() > TOLERANCE_PERCENT,
+            _ => true,
+        };
+        self.conflict = conflict;
+        if !changed {
+            return None;
+        }
+
+        match conflict {
+            Some(floor) => {
+                let message = format!(
+                    "GX SoC limit {:.0}% is above min_soc_percent {:.0}%{}",
+                    floor,
+                    self.configured,
+                    if self.adopt { ", planning with the GX limit" } else { ", discharges below it will be refused" }
+                );
+                warn!("{}", message);
+                Some((message, true))
+            }
+            None => {
+                info!("GX SoC limit no longer above min_soc_percent {:.0}%", self.configured);
+                Some(("GX SoC limit back within min_soc_percent".to_string(), false))
+            }
+        }
+    }
+
+    /// The stricter GX floor, when configured to adopt it
+    pub fn adopted(&self) -> Option<f64> {
+        self.conflict.filter(|_| self.adopt)
+    }
+}
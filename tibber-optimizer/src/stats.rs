@@ -0,0 +1,216 @@
+use chrono::{DateTime, FixedOffset, NaiveDate};
+use serde::Serialize;
+
+/// Longest gap between two samples that is still accounted (longer gaps,
+/// e.g. after a restart, would attribute hours of energy to one price)
+pub const MAX_SAMPLE_GAP_HOURS: f64 = 0.25;
+
+/// Largest plausible meter change between two samples; bigger jumps are meter
+/// resets or replacements and are skipped
+const MAX_METER_STEP_KWH: f64 = 50.0;
+
+/// Energy and cost accounting for one (tariff-local) day
+#[derive(Debug, Clone, Serialize)]
+pub struct DailyStats {
+    pub date: NaiveDate,
+    /// Optimizer presets active during this day, in the order they were
+    /// switched to (`default` for the base settings)
+    pub presets: Vec<String>,
+    /// Energy stored into the battery (kWh, battery side)
+    pub charged_kwh: f64,
+    /// Energy taken out of the battery (kWh, battery side)
+    pub discharged_kwh: f64,
+    /// Cost of the estimated house load without a battery (EUR)
+    pub baseline_cost: f64,
+    /// Cost including battery charging/discharging (EUR)
+    pub actual_cost: f64,
+    /// Net grid energy the optimizer accounted for (estimated house load plus battery)
+    pub accounted_grid_kwh: f64,
+    /// Net grid energy according to the meter, if a meter is configured
+    pub metered_grid_kwh: Option<f64>,
+    /// Net grid cost according to the meter (EUR)
+    pub metered_cost: Option<f64>,
+}
+
+impl DailyStats {
+    fn new(date: NaiveDate, preset: Option<String>) -> Self {
+        Self {
+            date,
+            presets: vec![preset.unwrap_or_else(|| "default".to_string())],
+            charged_kwh: 0.0,
+            discharged_kwh: 0.0,
+            baseline_cost: 0.0,
+            actual_cost: 0.0,
+            accounted_grid_kwh: 0.0,
+            metered_grid_kwh: None,
+            metered_cost: None,
+        }
+    }
+
+    /// Metered minus accounted grid energy (kWh)
+    pub fn drift_kwh(&self) -> Option<f64> {
+        self.metered_grid_kwh.map(|metered| metered - self.accounted_grid_kwh)
+    }
+
+    /// Replace the estimated costs by the metered ones. The battery's effect
+    /// (savings) is kept; the baseline is what the meter would have shown without it.
+    pub fn reconcile(&mut self) {
+        if let Some(metered_cost) = self.metered_cost {
+            let savings = self.savings();
+            self.actual_cost = metered_cost;
+            self.baseline_cost = metered_cost + savings;
+        }
+    }
+
+    pub fn savings(&self) -> f64 {
+        self.baseline_cost - self.actual_cost
+    }
+
+    pub fn savings_percent(&self) -> f64 {
+        if self.baseline_cost.abs() < f64::EPSILON {
+            0.0
+        } else {
+            self.savings() / self.baseline_cost * 100.0
+        }
+    }
+
+    /// Full cycle equivalents: average of charged and discharged energy over capacity
+    pub fn cycles(&self, capacity_kwh: f64) -> f64 {
+        (self.charged_kwh + self.discharged_kwh) / 2.0 / capacity_kwh
+    }
+}
+
+/// Tracks battery energy flows from SoC changes and compares the resulting
+/// grid cost against a no-battery baseline
+#[derive(Debug)]
+pub struct EnergyAccounting {
+    capacity_kwh: f64,
+    one_way_efficiency: f64,
+    base_consumption_w: f64,
+    last_sample: Option<(DateTime<FixedOffset>, f64)>,
+    /// Last net (import minus export) meter reading in kWh
+    last_meter_kwh: Option<f64>,
+    /// Preset active now, tagged onto newly started days
+    preset: Option<String>,
+    today: Option<DailyStats>,
+}
+
+impl EnergyAccounting {
+    pub fn new(capacity_kwh: f64, round_trip_efficiency: f64, base_consumption_w: f64) -> Self {
+        Self {
+            capacity_kwh,
+            one_way_efficiency: round_trip_efficiency.sqrt(),
+            base_consumption_w,
+            last_sample: None,
+            last_meter_kwh: None,
+            preset: None,
+            today: None,
+        }
+    }
+
+    /// Switch to a preset, which changes the estimated house load
+    pub fn set_preset(&mut self, preset: Option<String>, base_consumption_w: f64) {
+        if let Some(today) = self.today.as_mut() {
+            let name = preset.as_deref().unwrap_or("default");
+            if !today.presets.iter().any(|p| p == name) {
+                today.presets.push(name.to_string());
+            }
+        }
+        self.preset = preset;
+        self.base_consumption_w = base_consumption_w;
+    }
+
+    /// Today's stats so far
+    pub fn today(&self) -> Option<&DailyStats> {
+        self.today.as_ref()
+    }
+
+    /// Record an SoC sample and, if available, the net grid meter reading (kWh)
+    /// at the given (tariff-local) time, buy and sell price. Returns the
+    /// completed previous day, reconciled against the meter, when the date
+    /// rolls over.
+    pub fn record(
+        &mut self,
+        now: DateTime<FixedOffset>,
+        soc: f64,
+        meter_kwh: Option<f64>,
+        buy_price: f64,
+        sell_price: f64,
+    ) -> Option<DailyStats> {
+        // Net import is paid at the buy price, net export earns the sell price
+        let cost = |grid_kwh: f64| grid_kwh * if grid_kwh >= 0.0 { buy_price } else { sell_price };
+        let date = now.date_naive();
+        let finished = match &self.today {
+            Some(today) if today.date != date => self.today.replace(DailyStats::new(date, self.preset.clone())),
+            Some(_) => None,
+            None => {
+                self.today = Some(DailyStats::new(date, self.preset.clone()));
+                None
+            }
+        };
+
+        if let Some((last_time, last_soc)) = self.last_sample {
+            let hours = now.signed_duration_since(last_time).num_seconds() as f64 / 3600.0;
+            if hours > 0.0 && hours <= MAX_SAMPLE_GAP_HOURS {
+                let today = self.today.as_mut().expect("initialized above");
+                let battery_kwh = (soc - last_soc) / 100.0 * self.capacity_kwh;
+
+                // Grid-side energy for the battery: charging costs more than is
+                // stored, discharging delivers less than is taken out
+                let grid_kwh = if battery_kwh >= 0.0 {
+                    today.charged_kwh += battery_kwh;
+                    battery_kwh / self.one_way_efficiency
+                } else {
+                    today.discharged_kwh += -battery_kwh;
+                    battery_kwh * self.one_way_efficiency
+                };
+
+                let house_kwh = self.base_consumption_w / 1000.0 * hours;
+                today.baseline_cost += cost(house_kwh);
+                today.actual_cost += cost(house_kwh + grid_kwh);
+                today.accounted_grid_kwh += house_kwh + grid_kwh;
+
+                if let (Some(last), Some(meter)) = (self.last_meter_kwh, meter_kwh) {
+                    let delta = meter - last;
+                    if delta.abs() <= MAX_METER_STEP_KWH {
+                        *today.metered_grid_kwh.get_or_insert(0.0) += delta;
+                        *today.metered_cost.get_or_insert(0.0) += cost(delta);
+                    }
+                }
+            }
+        }
+
+        self.last_sample = Some((now, soc));
+        self.last_meter_kwh = meter_kwh;
+        finished.map(|mut day| {
+            day.reconcile();
+            day
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pays_imports_at_the_buy_price_and_earns_exports_at_the_sell_price() {
+        let mut accounting = EnergyAccounting::new(10.0, 1.0, 0.0);
+        let at = |time: &str| DateTime::parse_from_rfc3339(time).unwrap();
+
+        assert!(accounting.record(at("2025-12-01T12:00:00+01:00"), 50.0, Some(100.0), 0.30, 0.10).is_none());
+        // 1 kWh discharged and exported, then 2 kWh charged from the grid
+        assert!(accounting.record(at("2025-12-01T12:15:00+01:00"), 40.0, Some(99.0), 0.30, 0.10).is_none());
+        assert!(accounting.record(at("2025-12-01T12:30:00+01:00"), 60.0, Some(101.0), 0.30, 0.10).is_none());
+
+        let today = accounting.today().unwrap();
+        assert_eq!(today.discharged_kwh, 1.0);
+        assert_eq!(today.charged_kwh, 2.0);
+        assert!((today.actual_cost - 0.50).abs() < 1e-9, "{}", today.actual_cost);
+
+        let day = accounting.record(at("2025-12-02T00:00:00+01:00"), 60.0, Some(101.0), 0.30, 0.10).unwrap();
+        assert_eq!(day.metered_grid_kwh, Some(1.0));
+        assert!((day.metered_cost.unwrap() - 0.50).abs() < 1e-9);
+        assert!((day.savings() + 0.50).abs() < 1e-9);
+    }
+}
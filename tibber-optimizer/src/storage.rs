@@ -0,0 +1,114 @@
+use std::path::Path;
+
+use anyhow::Result;
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use rusqlite::{params, Connection};
+use tracing::{debug, info};
+
+use crate::optimizer::OptimizationResult;
+use crate::prices::PriceCache;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS cycles (
+    at TEXT PRIMARY KEY,
+    price REAL NOT NULL,
+    soc REAL NOT NULL,
+    mode TEXT NOT NULL,
+    setpoint_w REAL NOT NULL,
+    reason TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS prices (
+    starts_at TEXT PRIMARY KEY,
+    total REAL NOT NULL,
+    energy REAL NOT NULL,
+    tax REAL NOT NULL,
+    level TEXT,
+    currency TEXT,
+    fetched_at TEXT
+);
+";
+
+/// History of optimization cycles and fetched prices in an embedded SQLite
+/// database, for analysis and savings reports. Times are stored as UTC RFC 3339.
+pub struct Storage {
+    conn: Connection,
+    retention_days: u32,
+    /// Price snapshot last written, so unchanged prices aren't rewritten every cycle
+    price_generation: Option<u64>,
+    /// Day old rows were last pruned
+    pruned_on: Option<NaiveDate>,
+}
+
+impl Storage {
+    /// Open (or create) `<data_dir>/history.sqlite`
+    pub fn open(data_dir: &str, retention_days: u32) -> Result<Self> {
+        let path = Path::new(data_dir).join("history.sqlite");
+        let conn = Connection::open(&path)?;
+        conn.execute_batch(SCHEMA)?;
+        info!("Recording history to {}", path.display());
+        Ok(Self {
+            conn,
+            retention_days,
+            price_generation: None,
+            pruned_on: None,
+        })
+    }
+
+    /// Record one optimization cycle
+    pub fn record_cycle(&mut self, now: DateTime<Utc>, price: f64, soc: f64, result: &OptimizationResult) -> Result<()> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO cycles (at, price, soc, mode, setpoint_w, reason) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                now.to_rfc3339(),
+                price,
+                soc,
+                result.mode.to_string(),
+                result.grid_setpoint_w,
+                result.reason
+            ],
+        )?;
+        self.prune(now)
+    }
+
+    /// Record all slots of a price snapshot, once per fetch
+    pub fn record_prices(&mut self, prices: &PriceCache) -> Result<()> {
+        if self.price_generation == Some(prices.generation) {
+            return Ok(());
+        }
+        let fetched_at = prices.last_fetch.map(|at| at.to_rfc3339());
+        let tx = self.conn.transaction()?;
+        {
+            let mut insert = tx.prepare(
+                "INSERT OR REPLACE INTO prices (starts_at, total, energy, tax, level, currency, fetched_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            )?;
+            for slot in prices.all_prices() {
+                insert.execute(params![
+                    slot.starts_at.with_timezone(&Utc).to_rfc3339(),
+                    slot.total,
+                    slot.energy,
+                    slot.tax,
+                    slot.level,
+                    slot.currency,
+                    fetched_at
+                ])?;
+            }
+        }
+        tx.commit()?;
+        self.price_generation = Some(prices.generation);
+        Ok(())
+    }
+
+    /// Drop rows past the retention period, once a day
+    fn prune(&mut self, now: DateTime<Utc>) -> Result<()> {
+        if self.retention_days == 0 || self.pruned_on == Some(now.date_naive()) {
+            return Ok(());
+        }
+        let cutoff = (now - Duration::days(self.retention_days as i64)).to_rfc3339();
+        let cycles = self.conn.execute("DELETE FROM cycles WHERE at < ?1", params![cutoff])?;
+        let prices = self.conn.execute("DELETE FROM prices WHERE starts_at < ?1", params![cutoff])?;
+        debug!("Pruned {} cycles and {} prices older than {} days", cycles, prices, self.retention_days);
+        self.pruned_on = Some(now.date_naive());
+        Ok(())
+    }
+}
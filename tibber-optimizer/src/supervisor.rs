@@ -0,0 +1,129 @@
+//! Supervision of background tasks. A task that panics or returns is logged
+//! and restarted with backoff instead of silently disappearing (a dead MQTT
+//! event loop would otherwise freeze all telemetry while the control loop
+//! keeps running on the last readings).
+
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use tokio::task::JoinError;
+use tracing::{error, info, warn};
+
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// A task that ran this long before failing starts over at the minimum backoff
+const HEALTHY_RUN: Duration = Duration::from_secs(300);
+
+/// How long a failure keeps a task reported as unhealthy after its restart
+const FAILURE_WINDOW_MINUTES: i64 = 15;
+
+#[derive(Debug, Default)]
+struct TaskHealth {
+    restarts: u32,
+    last_failure: Option<(DateTime<Utc>, String)>,
+    /// Failed and waiting for its restart
+    down: bool,
+}
+
+/// Spawns background tasks and restarts them when they fail
+#[derive(Debug, Clone, Default)]
+pub struct Supervisor {
+    tasks: Arc<Mutex<BTreeMap<&'static str, TaskHealth>>>,
+}
+
+impl Supervisor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Run the future made by `task` in the background, making a fresh one
+    /// whenever the previous one panics or returns
+    pub fn spawn<F, Fut>(&self, name: &'static str, mut task: F)
+    where
+        F: FnMut() -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let tasks = self.tasks.clone();
+        tasks.lock().unwrap().entry(name).or_default();
+        tokio::spawn(async move {
+            let mut backoff = MIN_BACKOFF;
+            loop {
+                let started = Instant::now();
+                let failure = match tokio::spawn(task()).await {
+                    Ok(()) => "exited".to_string(),
+                    Err(e) => describe(e),
+                };
+                if started.elapsed() >= HEALTHY_RUN {
+                    backoff = MIN_BACKOFF;
+                }
+                error!("Background task {} {}, restarting in {}s", name, failure, backoff.as_secs());
+                {
+                    let mut tasks = tasks.lock().unwrap();
+                    let health = tasks.entry(name).or_default();
+                    health.restarts += 1;
+                    health.last_failure = Some((Utc::now(), failure));
+                    health.down = true;
+                }
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+                info!("Restarting background task {}", name);
+                if let Some(health) = tasks.lock().unwrap().get_mut(name) {
+                    health.down = false;
+                }
+            }
+        });
+    }
+
+    /// Tasks that are down or failed recently, e.g. "mqtt_event_loop panicked: ... (2 restarts)"
+    pub fn unhealthy(&self, now: DateTime<Utc>) -> Vec<String> {
+        let window = chrono::Duration::minutes(FAILURE_WINDOW_MINUTES);
+        self.tasks
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|(name, health)| {
+                let (at, failure) = health.last_failure.as_ref()?;
+                (health.down || now.signed_duration_since(*at) < window)
+                    .then(|| format!("{} {} ({} restarts)", name, failure, health.restarts))
+            })
+            .collect()
+    }
+}
+
+fn describe(error: JoinError) -> String {
+    if !error.is_panic() {
+        return "was cancelled".to_string();
+    }
+    let panic = error.into_panic();
+    let message = panic
+        .downcast_ref::<&str>()
+        .map(|s| s.to_string())
+        .or_else(|| panic.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown panic".to_string());
+    format!("panicked: {}", message)
+}
+
+/// Resolves on SIGTERM, as sent when the add-on or container is stopped, or
+/// on Ctrl-C
+pub async fn shutdown_signal() {
+    #[cfg(unix)]
+    match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+        Ok(mut terminate) => {
+            tokio::select! {
+                _ = terminate.recv() => info!("SIGTERM received"),
+                _ = tokio::signal::ctrl_c() => info!("Ctrl-C received"),
+            }
+            return;
+        }
+        Err(e) => warn!("Can't listen for SIGTERM: {}", e),
+    }
+    if tokio::signal::ctrl_c().await.is_ok() {
+        info!("Ctrl-C received");
+    } else {
+        std::future::pending::<()>().await;
+    }
+}
@@ -0,0 +1,57 @@
+use serde::Serialize;
+
+use crate::config::SurplusConfig;
+use crate::optimizer::PlannedSlot;
+
+/// Cheap power available to external loads (e.g. heat pump buffers) per slot
+#[derive(Debug, Clone, Serialize)]
+pub struct SurplusForecast {
+    /// Surplus available right now
+    pub current_surplus_w: f64,
+    pub slots: Vec<SurplusSlot>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SurplusSlot {
+    pub starts_at: String,
+    pub price: f64,
+    pub sell_price: f64,
+    /// Planned battery mode for the slot
+    pub mode: String,
+    /// Projected battery SoC at the end of the slot
+    pub projected_soc: f64,
+    /// Grid power available beyond house load and planned battery charging
+    pub surplus_w: f64,
+}
+
+/// Compute the surplus signal for the next `config.slots` slots of the plan.
+///
+/// Only cheap slots carry a surplus: whatever the grid connection can still
+/// deliver after the estimated house load and the planned battery charging.
+/// A full battery therefore frees up the whole connection for thermal buffers.
+pub fn surplus_forecast(plan: &[PlannedSlot], config: &SurplusConfig, base_consumption_w: f64) -> SurplusForecast {
+    let slots: Vec<SurplusSlot> = plan
+        .iter()
+        .take(config.slots)
+        .map(|slot| {
+            let surplus_w = if slot.cheap {
+                (config.grid_limit_w - base_consumption_w - slot.battery_power_w.max(0.0)).max(0.0)
+            } else {
+                0.0
+            };
+            SurplusSlot {
+                starts_at: slot.starts_at.to_rfc3339(),
+                price: slot.price,
+                sell_price: slot.sell_price,
+                mode: slot.mode.to_string(),
+                projected_soc: slot.soc_end,
+                surplus_w,
+            }
+        })
+        .collect();
+
+    SurplusForecast {
+        current_surplus_w: slots.first().map(|s| s.surplus_w).unwrap_or(0.0),
+        slots,
+    }
+}
@@ -0,0 +1,106 @@
+//! Fast path for high-frequency power feeds. Readings on these topics skip
+//! the `RwLock` around the battery state: the MQTT event loop stores each one
+//! in an atomic cell and the control loop picks up the latest when it reads
+//! the state, so a 1 Hz feed never contends with a control cycle.
+
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+
+use chrono::{DateTime, Utc};
+use tracing::debug;
+
+use crate::config::{MqttConfig, TelemetryFeed};
+use crate::mqtt::{parse_mqtt_value, BatteryState};
+
+/// The latest reading of one feed. Value and timestamp are separate atomics,
+/// so a reader may pair a value with its neighbor's timestamp; harmless for
+/// readings a second apart.
+#[derive(Debug, Default)]
+struct LatestValue {
+    value: AtomicU64,
+    /// Milliseconds since the epoch, 0 until the first reading
+    at_ms: AtomicI64,
+}
+
+impl LatestValue {
+    fn set(&self, value: f64, at: DateTime<Utc>) {
+        self.value.store(value.to_bits(), Ordering::Relaxed);
+        self.at_ms.store(at.timestamp_millis(), Ordering::Release);
+    }
+
+    fn get(&self) -> Option<(f64, DateTime<Utc>)> {
+        let at_ms = self.at_ms.load(Ordering::Acquire);
+        if at_ms == 0 {
+            return None;
+        }
+        let value = f64::from_bits(self.value.load(Ordering::Relaxed));
+        Some((value, DateTime::from_timestamp_millis(at_ms)?))
+    }
+}
+
+/// Latest values of the feeds listed in `mqtt.fast_telemetry`
+#[derive(Debug, Default)]
+pub struct FastTelemetry {
+    /// Topic of each fast feed
+    topics: Vec<(String, TelemetryFeed)>,
+    grid_power: LatestValue,
+    battery_power: LatestValue,
+    house_load: LatestValue,
+}
+
+impl FastTelemetry {
+    pub fn new(config: &MqttConfig) -> Self {
+        let topics = config
+            .fast_telemetry
+            .iter()
+            .filter_map(|&feed| {
+                let topic = match feed {
+                    TelemetryFeed::GridPower => &config.grid_power_topic,
+                    TelemetryFeed::BatteryPower => &config.battery_power_topic,
+                    TelemetryFeed::HouseLoad => &config.house_load_topic,
+                };
+                Some((topic.clone()?, feed))
+            })
+            .collect();
+        Self { topics, ..Default::default() }
+    }
+
+    /// Whether `topic` is a fast feed
+    pub fn covers(&self, topic: &str) -> bool {
+        self.topics.iter().any(|(t, _)| t == topic)
+    }
+
+    fn cell(&self, feed: TelemetryFeed) -> &LatestValue {
+        match feed {
+            TelemetryFeed::GridPower => &self.grid_power,
+            TelemetryFeed::BatteryPower => &self.battery_power,
+            TelemetryFeed::HouseLoad => &self.house_load,
+        }
+    }
+
+    /// Store a message on a fast feed; returns false for other topics
+    pub fn handle(&self, topic: &str, payload: &str, now: DateTime<Utc>) -> bool {
+        let Some(&(_, feed)) = self.topics.iter().find(|(t, _)| t == topic) else {
+            return false;
+        };
+        if let Some(value) = parse_mqtt_value(payload) {
+            self.cell(feed).set(value, now);
+            debug!("Updated {:?}: {:.0}W", feed, value);
+        }
+        true
+    }
+
+    /// The state with the latest fast readings filled in
+    pub fn apply(&self, mut state: BatteryState) -> BatteryState {
+        if let Some((value, at)) = self.grid_power.get() {
+            state.grid_power_w = Some(value);
+            state.last_grid_power_update = Some(at);
+        }
+        if let Some((value, _)) = self.battery_power.get() {
+            state.battery_power_w = Some(value);
+        }
+        if let Some((value, _)) = self.house_load.get() {
+            state.house_load_w = Some(value);
+        }
+        state
+    }
+}
@@ -1,271 +1,457 @@
-use anyhow::Result;
-use chrono::{DateTime, FixedOffset};
-use serde::{Deserialize, Serialize};
-use std::sync::Arc;
-use tokio::sync::RwLock;
-use tracing::{debug, info};
-
-use crate::config::TibberConfig;
-
-const GRAPHQL_QUERY: &str = r#"
-{
-  viewer {
-    homes {
-      currentSubscription {
-        priceInfo(resolution: QUARTER_HOURLY) {
-          current {
-            total
-            energy
-            tax
-            startsAt
-          }
-          today {
-            total
-            energy
-            tax
-            startsAt
-          }
-          tomorrow {
-            total
-            energy
-            tax
-            startsAt
-          }
-        }
-      }
-    }
-  }
+use chrono::{DateTime, Duration, FixedOffset};
+use std::sync::Mutex;
+
+use tibber_client::{Client, Home, HomePrices, Price, PriceInfo, PriceResolution, Transport, Viewer};
+use tokio::sync::OnceCell;
+use tracing::{info, warn};
+
+use crate::config::{BiddingZone, TibberConfig};
+use crate::error::{Error, Result};
+use crate::http::HttpClient;
+use crate::price_source::{FetchFuture, PriceProvider, ZoneMismatch};
+use crate::prices::{PriceCache, PricePoint, SlotOrigin};
+use crate::record::Recorder;
+
+/// Slot length of a quarter-hourly subscription
+const QUARTER_HOUR_MINUTES: i64 = 15;
+
+/// Build a price snapshot from a Tibber price query response
+pub fn parse_prices(body: &[u8], generation: u64, fetched_at: DateTime<FixedOffset>) -> Result<PriceCache> {
+    Ok(price_cache(PriceInfo::from_response(body)?, generation, fetched_at))
 }
-"#;
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct PricePoint {
-    pub total: f64,
-    pub energy: f64,
-    pub tax: f64,
-    #[serde(rename = "startsAt")]
-    pub starts_at: DateTime<FixedOffset>,
+
+/// One slot per price, as long as the resolution they come in
+fn price_cache(price_info: PriceInfo, generation: u64, fetched_at: DateTime<FixedOffset>) -> PriceCache {
+    let minutes = slot_minutes(&price_info.today)
+        .or_else(|| slot_minutes(&price_info.tomorrow))
+        .unwrap_or(QUARTER_HOUR_MINUTES);
+    let day_minutes = |day: &[Price]| slot_minutes(day).unwrap_or(minutes);
+    let (today_minutes, tomorrow_minutes) = (day_minutes(&price_info.today), day_minutes(&price_info.tomorrow));
+    let current = price_info.current.map(|price| price_point(&price, today_minutes));
+    let slots = price_info
+        .today
+        .iter()
+        .map(|price| price_point(price, today_minutes))
+        .chain(price_info.tomorrow.iter().map(|price| price_point(price, tomorrow_minutes)))
+        .collect();
+    PriceCache::new(slots, current).with_generation(generation).fetched_at(fetched_at)
 }
 
-#[derive(Debug, Clone, Default)]
-pub struct PriceCache {
-    pub current: Option<PricePoint>,
-    pub today: Vec<PricePoint>,
-    pub tomorrow: Vec<PricePoint>,
-    pub last_fetch: Option<DateTime<FixedOffset>>,
+/// Minutes between the starts of consecutive slots
+fn slot_minutes(slots: &[Price]) -> Option<i64> {
+    slots
+        .windows(2)
+        .map(|pair| (pair[1].starts_at - pair[0].starts_at).num_minutes())
+        .filter(|minutes| *minutes > 0)
+        .min()
 }
 
-impl PriceCache {
-    /// Get all available prices (today + tomorrow) sorted by time
-    pub fn all_prices(&self) -> Vec<&PricePoint> {
-        let mut prices: Vec<&PricePoint> = self.today.iter().chain(self.tomorrow.iter()).collect();
-        prices.sort_by_key(|p| p.starts_at);
-        prices
-    }
+/// Whether a day of quarter-hour slots repeats one price through every hour,
+/// as for subscriptions billed hourly
+fn repeats_hourly(slots: &[Price]) -> bool {
+    slots.len() >= 8
+        && slots
+            .chunk_by(|a, b| a.starts_at.timestamp() / 3600 == b.starts_at.timestamp() / 3600)
+            .all(|hour| hour.iter().all(|price| price.total == hour[0].total))
+}
 
-    /// Get future prices (from now onwards)
-    pub fn future_prices(&self) -> Vec<&PricePoint> {
-        let now = chrono::Utc::now();
-        self.all_prices()
-            .into_iter()
-            .filter(|p| p.starts_at.with_timezone(&chrono::Utc) >= now)
-            .collect()
+/// Check the home's country and the price currency against `zone`. Zones
+/// within one country (SE1-SE4, NO1-NO5) can't be told apart this way.
+fn check_zone(zone: BiddingZone, prices: &HomePrices) -> Result<(), ZoneMismatch> {
+    let mismatch = |reason: String| ZoneMismatch { expected: zone, reason };
+    let country = prices.address.as_ref().and_then(|address| address.country.as_deref());
+    if let Some(country) = country {
+        if !zone.countries().iter().any(|c| c.eq_ignore_ascii_case(country)) {
+            return Err(mismatch(format!("the home is in {}", country)));
+        }
     }
-
-    /// Calculate price statistics
-    pub fn price_stats(&self) -> Option<PriceStats> {
-        let prices = self.future_prices();
-        if prices.is_empty() {
-            return None;
+    let info = &prices.price_info;
+    let currency = info.current.iter().chain(&info.today).chain(&info.tomorrow).find_map(|p| p.currency.as_deref());
+    if let Some(currency) = currency {
+        if !currency.eq_ignore_ascii_case(zone.currency()) {
+            return Err(mismatch(format!("prices are in {} instead of {}", currency, zone.currency())));
         }
-
-        let totals: Vec<f64> = prices.iter().map(|p| p.total).collect();
-        let mut sorted = totals.clone();
-        sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
-
-        let min = *sorted.first()?;
-        let max = *sorted.last()?;
-        let avg = totals.iter().sum::<f64>() / totals.len() as f64;
-
-        // Calculate percentile thresholds
-        let p25_idx = (sorted.len() as f64 * 0.25) as usize;
-        let p75_idx = (sorted.len() as f64 * 0.75) as usize;
-        let p90_idx = (sorted.len() as f64 * 0.90) as usize;
-
-        Some(PriceStats {
-            min,
-            max,
-            avg,
-            p25: sorted.get(p25_idx).copied().unwrap_or(min),
-            p75: sorted.get(p75_idx).copied().unwrap_or(max),
-            p90: sorted.get(p90_idx).copied().unwrap_or(max),
-        })
     }
+    Ok(())
 }
 
-#[derive(Debug, Clone)]
-pub struct PriceStats {
-    pub min: f64,
-    pub max: f64,
-    pub avg: f64,
-    pub p25: f64,
-    pub p75: f64,
-    pub p90: f64,
-}
-
-// API Response structures
-#[derive(Debug, Deserialize)]
-struct ApiResponse {
-    data: ApiData,
-}
-
-#[derive(Debug, Deserialize)]
-struct ApiData {
-    viewer: Viewer,
-}
-
-#[derive(Debug, Deserialize)]
-struct Viewer {
-    homes: Vec<Home>,
+/// The configured home of the account: by id, by position, else the first
+pub fn selected_home<'a>(config: &TibberConfig, viewer: &'a Viewer) -> Result<&'a Home> {
+    match (&config.home_id, config.home_index) {
+        (Some(id), _) => viewer
+            .home(id)
+            .ok_or_else(|| Error::validation(format!("Tibber home {} not found in the account", id))),
+        (None, Some(index)) => viewer.homes.get(index).ok_or_else(|| {
+            Error::validation(format!("No Tibber home at index {}, the account has {}", index, viewer.homes.len()))
+        }),
+        (None, None) => viewer
+            .homes
+            .first()
+            .ok_or_else(|| Error::validation("No homes found in Tibber account")),
+    }
 }
 
-#[derive(Debug, Deserialize)]
-struct Home {
-    #[serde(rename = "currentSubscription")]
-    current_subscription: Option<Subscription>,
+/// A home's id and address, for listing the account's homes
+pub fn describe_home(home: &Home) -> String {
+    match &home.address {
+        Some(address) => format!("{} ({})", home.id, address),
+        None => home.id.clone(),
+    }
 }
 
-#[derive(Debug, Deserialize)]
-struct Subscription {
-    #[serde(rename = "priceInfo")]
-    price_info: PriceInfo,
+fn price_point(price: &Price, minutes: i64) -> PricePoint {
+    PricePoint {
+        total: price.total,
+        energy: price.energy,
+        tax: price.tax,
+        starts_at: price.starts_at,
+        ends_at: price.starts_at + Duration::minutes(minutes),
+        level: price.level.map(|level| level.as_str().to_string()),
+        currency: price.currency.clone(),
+        sell: None,
+        source: SlotOrigin::Published,
+    }
 }
 
-#[derive(Debug, Deserialize)]
-struct PriceInfo {
-    current: Option<PricePoint>,
-    today: Vec<PricePoint>,
-    tomorrow: Vec<PricePoint>,
+/// Find the resolution the subscription really provides: accounts still
+/// billed hourly may get hourly slots, or none at all, for a
+/// quarter-hourly query
+async fn detect_resolution<T: Transport>(client: &Client<T>, home_id: &str) -> Result<PriceResolution> {
+    let query = tibber_client::home_price_info_query(PriceResolution::QuarterHourly, Some(home_id));
+    let today = HomePrices::from_response(&client.query(&query).await?)?.price_info.today;
+    let resolution = match slot_minutes(&today) {
+        Some(QUARTER_HOUR_MINUTES) if repeats_hourly(&today) => {
+            info!("Tibber subscription has hourly prices, in quarter-hour slots");
+            PriceResolution::QuarterHourly
+        }
+        Some(QUARTER_HOUR_MINUTES) => {
+            info!("Tibber subscription has quarter-hourly prices");
+            PriceResolution::QuarterHourly
+        }
+        Some(60) => {
+            info!("Tibber subscription has hourly prices");
+            PriceResolution::Hourly
+        }
+        Some(minutes) => return Err(Error::Parse(format!("Tibber returned slots {} minutes apart", minutes))),
+        None => {
+            let query = tibber_client::home_price_info_query(PriceResolution::Hourly, Some(home_id));
+            let hourly = HomePrices::from_response(&client.query(&query).await?)?.price_info.today;
+            if hourly.is_empty() {
+                return Err(Error::Parse("Tibber returned no prices for today, quarter-hourly or hourly".to_string()));
+            }
+            warn!("Tibber returned no quarter-hourly prices, using hourly ones");
+            PriceResolution::Hourly
+        }
+    };
+    Ok(resolution)
 }
 
 pub struct TibberClient {
     config: TibberConfig,
-    http_client: reqwest::Client,
-    cache: Arc<RwLock<PriceCache>>,
+    client: Client<HttpClient>,
+    recorder: Option<Recorder>,
+    /// Home the prices are fetched for, looked up on the first fetch
+    home_id: OnceCell<String>,
+    /// Resolution the subscription provides, probed again after a failed
+    /// fetch and until the day-ahead prices are in
+    resolution: Mutex<Option<PriceResolution>>,
 }
 
 impl TibberClient {
-    pub fn new(config: TibberConfig) -> Self {
-        let http_client = reqwest::Client::new();
+    pub fn new(config: TibberConfig, recorder: Option<Recorder>) -> Self {
+        let client = Client::new(HttpClient::new(), &config.api_token).with_api_url(&config.api_url);
         Self {
             config,
-            http_client,
-            cache: Arc::new(RwLock::new(PriceCache::default())),
+            client,
+            recorder,
+            home_id: OnceCell::new(),
+            resolution: Mutex::new(None),
         }
     }
 
-    pub async fn fetch_prices(&self) -> Result<()> {
-        info!("Fetching prices from Tibber API");
-
-        let response = self
-            .http_client
-            .post(&self.config.api_url)
-            .header("Authorization", format!("Bearer {}", self.config.api_token))
-            .header("Content-Type", "application/json")
-            .json(&serde_json::json!({
-                "query": GRAPHQL_QUERY
-            }))
-            .send()
-            .await?;
-
-        if !response.status().is_success() {
-            let status = response.status();
-            let body = response.text().await.unwrap_or_default();
-            anyhow::bail!("Tibber API error: {} - {}", status, body);
+    /// List the account's homes and pick the configured one
+    async fn select_home(&self) -> Result<String> {
+        let viewer = self.client.viewer().await?;
+        let selected = selected_home(&self.config, &viewer)?;
+        if viewer.homes.len() > 1 {
+            for (index, home) in viewer.homes.iter().enumerate() {
+                info!("Tibber home {}: {}", index, describe_home(home));
+            }
         }
+        info!("Fetching prices for Tibber home {}", describe_home(selected));
+        Ok(selected.id.clone())
+    }
 
-        let api_response: ApiResponse = response.json().await?;
+    /// Fetch the prices; fails with an [`Error`], or a [`ZoneMismatch`] for
+    /// another market's prices
+    async fn fetch_prices(&self, generation: u64) -> anyhow::Result<PriceCache> {
+        let home_id = self.home_id.get_or_try_init(|| self.select_home()).await?;
+        let cached = *self.resolution.lock().unwrap();
+        let resolution = match cached {
+            Some(resolution) => resolution,
+            None => detect_resolution(&self.client, home_id).await?,
+        };
+        let fetched = self.fetch_at(resolution, home_id, generation).await;
+        // A subscription may change resolution with the next day's prices
+        let settled = fetched.as_ref().is_ok_and(|(_, day_ahead)| *day_ahead);
+        *self.resolution.lock().unwrap() = settled.then_some(resolution);
+        fetched.map(|(cache, _)| cache)
+    }
 
-        // Get first home's subscription
-        let home = api_response
-            .data
-            .viewer
-            .homes
-            .into_iter()
-            .next()
-            .ok_or_else(|| anyhow::anyhow!("No homes found in Tibber account"))?;
-
-        let subscription = home
-            .current_subscription
-            .ok_or_else(|| anyhow::anyhow!("No active subscription found"))?;
-
-        let price_info = subscription.price_info;
-
-        // Update cache
-        let mut cache = self.cache.write().await;
-        cache.current = price_info.current;
-        cache.today = price_info.today;
-        cache.tomorrow = price_info.tomorrow;
-        cache.last_fetch = Some(chrono::Utc::now().fixed_offset());
-
-        info!(
-            "Fetched {} today prices, {} tomorrow prices",
-            cache.today.len(),
-            cache.tomorrow.len()
-        );
-
-        if cache.tomorrow.is_empty() {
-            debug!("Tomorrow's prices not yet available (usually published after 14:00)");
+    /// Fetch the prices at `resolution`, and whether tomorrow's are in
+    async fn fetch_at(
+        &self,
+        resolution: PriceResolution,
+        home_id: &str,
+        generation: u64,
+    ) -> anyhow::Result<(PriceCache, bool)> {
+        let query = tibber_client::home_price_info_query(resolution, Some(home_id));
+        let body = self.client.query(&query).await.map_err(Error::from)?;
+
+        if let Some(recorder) = &self.recorder {
+            recorder.tibber(&body);
+        }
+
+        let prices = HomePrices::from_response(&body).map_err(Error::from)?;
+        if let Some(zone) = self.config.bidding_zone {
+            check_zone(zone, &prices)?;
+        }
+        if prices.price_info.today.is_empty() && prices.price_info.tomorrow.is_empty() {
+            return Err(Error::Parse("Tibber returned no prices".to_string()).into());
+        }
+        let day_ahead = !prices.price_info.tomorrow.is_empty();
+        Ok((price_cache(prices.price_info, generation, chrono::Utc::now().fixed_offset()), day_ahead))
+    }
+
+    /// Check the API token; returns the account holder and their homes
+    pub async fn validate_token(&self) -> Result<Viewer> {
+        let viewer = self.client.viewer().await.map_err(|e| match e {
+            tibber_client::Error::GraphQl(_) | tibber_client::Error::MissingData => {
+                Error::Auth(format!("Token rejected: {}", e))
+            }
+            e => e.into(),
+        })?;
+        if viewer.homes.is_empty() {
+            return Err(Error::validation("No homes found in Tibber account"));
         }
+        Ok(viewer)
+    }
+}
 
-        Ok(())
+impl PriceProvider for TibberClient {
+    fn name(&self) -> &'static str {
+        "Tibber API"
     }
 
-    pub async fn get_cache(&self) -> PriceCache {
-        self.cache.read().await.clone()
+    fn refresh_interval_secs(&self) -> u64 {
+        self.config.refresh_interval_secs
     }
 
-    pub async fn get_current_price(&self) -> Option<PricePoint> {
-        let cache = self.cache.read().await;
+    fn fetch(&self, generation: u64) -> FetchFuture<'_> {
+        Box::pin(self.fetch_prices(generation))
+    }
+}
 
-        // Try to get the actual current price slot based on time
-        let now = chrono::Utc::now();
+/// Real-time meter readings from a Tibber Pulse or Watty, kept up to date by
+/// a background subscription
+#[cfg(feature = "tibber-live")]
+pub mod live {
+    use std::sync::Arc;
+    use std::time::Duration;
 
-        // Find the price slot that contains the current time
-        for price in cache.today.iter().chain(cache.tomorrow.iter()) {
-            let slot_start = price.starts_at.with_timezone(&chrono::Utc);
-            let slot_end = slot_start + chrono::Duration::minutes(15);
+    use chrono::{DateTime, Utc};
+    use tibber_client::live::{LiveMeasurement, LiveStream};
+    use tibber_client::Client;
+    use tokio::sync::RwLock;
+    use tracing::{debug, info, warn};
 
-            if now >= slot_start && now < slot_end {
-                return Some(price.clone());
-            }
-        }
+    use crate::config::TibberConfig;
+    use crate::error::{Error, Result};
+    use crate::http::HttpClient;
+    use crate::supervisor::Supervisor;
 
-        // Fall back to the "current" field from API
-        cache.current.clone()
+    /// No reading for this long means the connection is dead
+    const IDLE_TIMEOUT: Duration = Duration::from_secs(90);
+
+    const MAX_BACKOFF: Duration = Duration::from_secs(300);
+
+    /// Readings older than this are not used
+    const MAX_AGE_SECS: i64 = 60;
+
+    const USER_AGENT: &str = concat!("tibber-optimizer/", env!("CARGO_PKG_VERSION"));
+
+    /// Keeps a subscription open in the background, reconnecting with backoff
+    pub struct LiveMeasurements {
+        latest: Arc<RwLock<Option<LiveMeasurement>>>,
     }
 
-    /// Check if cache needs refresh
-    pub async fn needs_refresh(&self) -> bool {
-        let cache = self.cache.read().await;
+    impl LiveMeasurements {
+        pub fn spawn(config: TibberConfig, supervisor: &Supervisor) -> Self {
+            let latest = Arc::new(RwLock::new(None));
+            let writer = latest.clone();
+            supervisor.spawn("tibber_live", move || {
+                let client = Client::new(HttpClient::new(), &config.api_token).with_api_url(&config.api_url);
+                let writer = writer.clone();
+                let config = config.clone();
+                async move {
+                    let mut backoff = Duration::from_secs(10);
+                    loop {
+                        match subscribe(&client, &config, &writer).await {
+                            Ok(received) => {
+                                info!("Tibber live measurement stream ended, reconnecting");
+                                if received {
+                                    backoff = Duration::from_secs(10);
+                                }
+                            }
+                            Err(e) => {
+                                warn!("Tibber live measurement stream failed: {}", e);
+                                // A rejected token stays rejected, retry at the slowest pace
+                                if matches!(e, Error::Auth(_)) {
+                                    backoff = MAX_BACKOFF;
+                                }
+                            }
+                        }
+                        tokio::time::sleep(backoff).await;
+                        backoff = (backoff * 2).min(MAX_BACKOFF);
+                    }
+                }
+            });
+            Self { latest }
+        }
 
-        match cache.last_fetch {
-            None => true,
-            Some(last_fetch) => {
-                let elapsed = chrono::Utc::now()
-                    .signed_duration_since(last_fetch.with_timezone(&chrono::Utc));
-                elapsed.num_seconds() as u64 >= self.config.refresh_interval_secs
-            }
+        /// The latest reading, unless it is too old to act on
+        pub async fn latest(&self, now: DateTime<Utc>) -> Option<LiveMeasurement> {
+            let latest = *self.latest.read().await;
+            latest.filter(|m| now.signed_duration_since(m.timestamp).num_seconds() <= MAX_AGE_SECS)
         }
     }
 
-    /// Refresh prices if needed
-    pub async fn refresh_if_needed(&self) -> Result<bool> {
-        if self.needs_refresh().await {
-            self.fetch_prices().await?;
-            Ok(true)
+    /// Run one subscription until it ends; returns whether any reading arrived
+    async fn subscribe(
+        client: &Client<HttpClient>,
+        config: &TibberConfig,
+        latest: &RwLock<Option<LiveMeasurement>>,
+    ) -> Result<bool> {
+        let viewer = client.viewer().await?;
+        let url = viewer
+            .websocket_subscription_url
+            .as_deref()
+            .ok_or_else(|| Error::Parse("No websocket subscription URL".to_string()))?;
+        // A configured home is the one the battery is in; otherwise any with a Pulse
+        let home = if config.home_id.is_some() || config.home_index.is_some() {
+            Some(super::selected_home(config, &viewer)?).filter(|home| home.has_real_time_consumption())
         } else {
-            Ok(false)
+            viewer.live_home()
+        }
+        .ok_or_else(|| Error::validation("No home with real-time consumption (Pulse/Watty) enabled"))?;
+
+        info!("Subscribing to Tibber live measurements for home {}", home.id);
+        let mut stream = LiveStream::connect(url, client.token(), &home.id, USER_AGENT).await?;
+        let mut received = false;
+        loop {
+            let measurement = match tokio::time::timeout(IDLE_TIMEOUT, stream.next_measurement()).await {
+                Ok(measurement) => measurement?,
+                Err(_) => return Err(Error::Network(format!("no reading for {}s", IDLE_TIMEOUT.as_secs()))),
+            };
+            let Some(measurement) = measurement else {
+                return Ok(received);
+            };
+            debug!(
+                "Tibber live: {:.0}W grid, {:.2} kWh today",
+                measurement.grid_power_w(),
+                measurement.accumulated_consumption
+            );
+            *latest.write().await = Some(measurement);
+            received = true;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tibber_client::{HttpResponse, TransportFuture};
+
+    /// Answers price queries by the resolution asked for
+    struct Canned {
+        quarter_hourly: Vec<u8>,
+        hourly: Vec<u8>,
+    }
+
+    impl Transport for Canned {
+        fn post<'a>(&'a self, _: &'a str, _: &'a [(&'a str, &'a str)], body: Vec<u8>) -> TransportFuture<'a> {
+            let query = String::from_utf8(body).unwrap();
+            let body = if query.contains("QUARTER_HOURLY") { &self.quarter_hourly } else { &self.hourly };
+            let response = HttpResponse { status: 200, body: body.clone() };
+            Box::pin(async move { Ok(response) })
         }
     }
+
+    /// A price response with today's slots `minutes` apart at `totals`
+    fn today(minutes: i64, totals: &[f64]) -> Vec<u8> {
+        let start = DateTime::parse_from_rfc3339("2025-12-01T00:00:00+01:00").unwrap();
+        let today: Vec<Price> = totals
+            .iter()
+            .enumerate()
+            .map(|(i, &total)| Price {
+                total,
+                energy: total,
+                tax: 0.0,
+                starts_at: start + Duration::minutes(minutes * i as i64),
+                level: None,
+                currency: None,
+            })
+            .collect();
+        serde_json::to_vec(&serde_json::json!({"data": {"viewer": {"home": {"currentSubscription": {"priceInfo": {
+            "current": null, "today": today, "tomorrow": []
+        }}}}}}))
+        .unwrap()
+    }
+
+    async fn detect(quarter_hourly: Vec<u8>, hourly: Vec<u8>) -> Result<PriceResolution> {
+        detect_resolution(&Client::new(Canned { quarter_hourly, hourly }, "token"), "home").await
+    }
+
+    #[tokio::test]
+    async fn detects_the_resolution_the_subscription_provides() {
+        let varied = [0.20, 0.21, 0.22, 0.23, 0.30, 0.31, 0.32, 0.33];
+        let flat_hours = [0.20, 0.20, 0.20, 0.20, 0.30, 0.30, 0.30, 0.30];
+        let hourly = today(60, &[0.20, 0.30]);
+
+        assert_eq!(detect(today(15, &varied), hourly.clone()).await.unwrap(), PriceResolution::QuarterHourly);
+        assert_eq!(detect(today(15, &flat_hours), hourly.clone()).await.unwrap(), PriceResolution::QuarterHourly);
+        assert_eq!(detect(today(60, &[0.20, 0.30]), hourly.clone()).await.unwrap(), PriceResolution::Hourly);
+        assert_eq!(detect(today(15, &[]), hourly).await.unwrap(), PriceResolution::Hourly);
+        assert!(detect(today(15, &[]), today(60, &[])).await.is_err());
+        assert!(detect(today(30, &[0.20, 0.30]), today(60, &[])).await.is_err());
+    }
+
+    #[test]
+    fn recognizes_hours_repeated_in_quarter_hours() {
+        let prices = |body: Vec<u8>| HomePrices::from_response(&body).unwrap().price_info.today;
+        assert!(repeats_hourly(&prices(today(15, &[0.20, 0.20, 0.20, 0.20, 0.30, 0.30, 0.30, 0.30]))));
+        assert!(!repeats_hourly(&prices(today(15, &[0.20, 0.20, 0.20, 0.21, 0.30, 0.30, 0.30, 0.30]))));
+        assert!(!repeats_hourly(&prices(today(15, &[0.20, 0.20, 0.20, 0.20]))));
+    }
+
+    #[test]
+    fn keeps_hourly_prices_in_hourly_slots() {
+        let body = br#"{"data": {"viewer": {"homes": [{"currentSubscription": {"priceInfo": {
+            "current": {"total": 0.25, "energy": 0.1, "tax": 0.15, "startsAt": "2025-12-01T00:00:00+01:00"},
+            "today": [
+                {"total": 0.25, "energy": 0.1, "tax": 0.15, "startsAt": "2025-12-01T00:00:00+01:00"},
+                {"total": 0.30, "energy": 0.15, "tax": 0.15, "startsAt": "2025-12-01T01:00:00+01:00"}
+            ],
+            "tomorrow": []
+        }}}]}}}"#;
+        let fetched_at = DateTime::parse_from_rfc3339("2025-12-01T00:00:00+01:00").unwrap();
+        let cache = parse_prices(body, 1, fetched_at).unwrap();
+
+        let slots = cache.slots();
+        assert_eq!(slots.len(), 2);
+        assert!(slots.iter().all(|slot| slot.duration() == Duration::minutes(60)));
+        assert_eq!(slots[0].total, 0.25);
+        assert_eq!(slots[1].total, 0.30);
+        assert_eq!(cache.current.unwrap().duration(), Duration::minutes(60));
+    }
 }
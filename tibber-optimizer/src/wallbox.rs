@@ -0,0 +1,165 @@
+//! Wallbox chargers over the myWallbox cloud API: the charge current is set
+//! as the charger's maximum charging current, and below it charging is paused
+//! with a remote action rather than a current the car won't accept. The API is
+//! HTTPS only, so this needs the default `reqwest` feature. The status is read
+//! at most once a minute, as the cloud API rate-limits its clients.
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use tracing::info;
+
+use crate::config::WallboxConfig;
+use crate::controller::WriteFuture;
+use crate::ev::{CachedStatus, ChargerStatus, EvCharger, StatusFuture};
+use crate::http::{basic_auth, HttpClient, HttpResponse};
+
+const API_URL: &str = "https://api.wall-box.com";
+
+/// How long a status read from the cloud is used for
+const STATUS_MAX_AGE: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// Remote actions of `POST /v3/chargers/{id}/remote-action`
+const RESUME: u8 = 1;
+const PAUSE: u8 = 2;
+
+/// The fields used from `GET /chargers/status/{id}`
+#[derive(Debug, Deserialize)]
+struct ChargerState {
+    /// 161/162 = ready without a car, 178-196 = a car connected (charging,
+    /// paused, scheduled or waiting for it), 209 = locked, 210 = locked with
+    /// a car connected; 0 and 163 = disconnected, 14/15 = error
+    status_id: u16,
+    /// Charging power (kW)
+    #[serde(default)]
+    charging_power: f64,
+    /// Energy delivered in the current session (kWh)
+    #[serde(default)]
+    added_energy: f64,
+}
+
+impl ChargerState {
+    fn status(&self) -> ChargerStatus {
+        ChargerStatus {
+            connected: matches!(self.status_id, 178..=196 | 210),
+            session_kwh: self.added_energy,
+            power_w: Some(self.charging_power * 1000.0),
+            current_a: None,
+        }
+    }
+}
+
+pub struct WallboxCharger {
+    config: WallboxConfig,
+    http_client: HttpClient,
+    token: tokio::sync::Mutex<Option<String>>,
+    state: CachedStatus,
+}
+
+impl WallboxCharger {
+    pub fn new(config: WallboxConfig) -> Self {
+        Self {
+            config,
+            http_client: HttpClient::new(),
+            token: tokio::sync::Mutex::new(None),
+            state: CachedStatus::new(STATUS_MAX_AGE),
+        }
+    }
+
+    async fn login(&self) -> Result<String> {
+        let auth = basic_auth(&self.config.username, &self.config.password);
+        let url = format!("{}/auth/token/user", API_URL);
+        let response = self.http_client.get(&url, &[("Authorization", auth.as_str())]).await?;
+        if !response.is_success() {
+            anyhow::bail!("Wallbox login failed: {} - {}", response.status, response.text());
+        }
+        let body: serde_json::Value = serde_json::from_slice(&response.body)?;
+        let token = body["jwt"].as_str().context("Wallbox login returned no token")?;
+        info!("Logged in to the myWallbox cloud for charger {}", self.config.charger_id);
+        Ok(token.to_string())
+    }
+
+    /// Send a request with the token, logging in first when there is none; a
+    /// rejected token is dropped so the next request logs in again
+    async fn request(&self, method: &str, path: &str, body: Option<&serde_json::Value>) -> Result<HttpResponse> {
+        let mut token = self.token.lock().await;
+        if token.is_none() {
+            *token = Some(self.login().await?);
+        }
+        let auth = format!("Bearer {}", token.as_deref().expect("logged in above"));
+        let headers = [("Authorization", auth.as_str())];
+        let url = format!("{}{}", API_URL, path);
+        let response = match (method, body) {
+            ("PUT", Some(body)) => self.http_client.put_json(&url, &headers, body).await?,
+            (_, Some(body)) => self.http_client.post_json(&url, &headers, body).await?,
+            (_, None) => self.http_client.get(&url, &headers).await?,
+        };
+        if matches!(response.status, 401 | 403) {
+            *token = None;
+        }
+        if !response.is_success() {
+            anyhow::bail!("Wallbox {} failed: {} - {}", path, response.status, response.text());
+        }
+        Ok(response)
+    }
+
+    async fn remote_action(&self, action: u8) -> Result<()> {
+        let path = format!("/v3/chargers/{}/remote-action", self.config.charger_id);
+        self.request("POST", &path, Some(&serde_json::json!({ "action": action }))).await?;
+        Ok(())
+    }
+}
+
+impl EvCharger for WallboxCharger {
+    fn name(&self) -> &'static str {
+        "myWallbox cloud API"
+    }
+
+    fn status(&self) -> StatusFuture<'_> {
+        Box::pin(self.state.get(async move {
+            let path = format!("/chargers/status/{}", self.config.charger_id);
+            let response = self.request("GET", &path, None).await?;
+            let state: ChargerState = serde_json::from_slice(&response.body)?;
+            Ok(state.status())
+        }))
+    }
+
+    fn set_current(&self, current_a: f64) -> WriteFuture<'_> {
+        Box::pin(async move {
+            if current_a > 0.0 {
+                let path = format!("/v2/charger/{}", self.config.charger_id);
+                let settings = serde_json::json!({ "maxChargingCurrent": current_a.round() });
+                self.request("PUT", &path, Some(&settings)).await?;
+                self.remote_action(RESUME).await?;
+            } else {
+                self.remote_action(PAUSE).await?;
+            }
+            self.state.invalidate();
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_the_car_and_session_from_the_charger_status() {
+        let body = r#"{"status_id": 194, "charging_power": 11.04, "added_energy": 6.2,
+            "config_data": {"max_charging_current": 16}}"#;
+        let state: ChargerState = serde_json::from_str(body).unwrap();
+        assert_eq!(
+            state.status(),
+            ChargerStatus {
+                connected: true,
+                session_kwh: 6.2,
+                power_w: Some(11040.0),
+                current_a: None,
+            }
+        );
+
+        let state: ChargerState = serde_json::from_str(r#"{"status_id": 161}"#).unwrap();
+        assert!(!state.status().connected);
+        assert_eq!(state.status().session_kwh, 0.0);
+    }
+}
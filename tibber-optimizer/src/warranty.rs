@@ -0,0 +1,198 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use chrono::{DateTime, Datelike, FixedOffset, NaiveDate};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+
+use crate::persist;
+use crate::stats::MAX_SAMPLE_GAP_HOURS;
+
+const HIGH_SOC_PERCENT: f64 = 90.0;
+const LOW_SOC_PERCENT: f64 = 10.0;
+
+/// Counters battery warranties are typically written against
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct WarrantyCounters {
+    /// Energy stored into the battery (kWh, battery side)
+    pub charged_kwh: f64,
+    /// Energy taken out of the battery (kWh, battery side)
+    pub discharged_kwh: f64,
+    /// Time spent above 90% SoC
+    pub hours_above_90: f64,
+    /// Time spent below 10% SoC
+    pub hours_below_10: f64,
+}
+
+impl WarrantyCounters {
+    /// Charged plus discharged energy
+    pub fn throughput_kwh(&self) -> f64 {
+        self.charged_kwh + self.discharged_kwh
+    }
+
+    /// Full cycle equivalents
+    pub fn cycles(&self, capacity_kwh: f64) -> f64 {
+        self.throughput_kwh() / 2.0 / capacity_kwh
+    }
+
+    fn add_sample(&mut self, battery_kwh: f64, hours: f64, soc: f64) {
+        if battery_kwh >= 0.0 {
+            self.charged_kwh += battery_kwh;
+        } else {
+            self.discharged_kwh += -battery_kwh;
+        }
+        if soc > HIGH_SOC_PERCENT {
+            self.hours_above_90 += hours;
+        } else if soc < LOW_SOC_PERCENT {
+            self.hours_below_10 += hours;
+        }
+    }
+}
+
+/// Persisted lifetime and per-year counters
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct WarrantyState {
+    /// First time a sample was recorded
+    pub since: Option<DateTime<FixedOffset>>,
+    pub lifetime: WarrantyCounters,
+    pub years: BTreeMap<i32, WarrantyCounters>,
+    /// Local day `today` counts, for the daily cycle limit
+    #[serde(default)]
+    pub day: Option<NaiveDate>,
+    #[serde(default)]
+    pub today: WarrantyCounters,
+}
+
+/// Tracks battery wear over its lifetime, persisted in the data directory
+#[derive(Debug)]
+pub struct WarrantyTracker {
+    path: PathBuf,
+    capacity_kwh: f64,
+    state: WarrantyState,
+    last_sample: Option<(DateTime<FixedOffset>, f64)>,
+    last_save: Option<DateTime<FixedOffset>>,
+}
+
+impl WarrantyTracker {
+    /// Load counters from `<data_dir>/warranty.json`, starting fresh if absent
+    pub fn load(data_dir: &str, capacity_kwh: f64) -> Self {
+        let path = Path::new(data_dir).join("warranty.json");
+        let state = persist::load_json(&path);
+
+        Self {
+            path,
+            capacity_kwh,
+            state,
+            last_sample: None,
+            last_save: None,
+        }
+    }
+
+    pub fn state(&self) -> &WarrantyState {
+        &self.state
+    }
+
+    pub fn capacity_kwh(&self) -> f64 {
+        self.capacity_kwh
+    }
+
+    /// Full cycle equivalents since local midnight
+    pub fn cycles_today(&self) -> f64 {
+        self.state.today.cycles(self.capacity_kwh)
+    }
+
+    /// Full cycle equivalents over the battery's lifetime
+    pub fn cycles_total(&self) -> f64 {
+        self.state.lifetime.cycles(self.capacity_kwh)
+    }
+
+    /// Whether today's cycles reached `max_daily_cycles`, if set
+    pub fn daily_limit_reached(&self, max_daily_cycles: Option<f64>) -> bool {
+        max_daily_cycles.is_some_and(|max| self.cycles_today() >= max)
+    }
+
+    /// Record an SoC sample. Returns the completed previous year's counters
+    /// when the year rolls over.
+    pub fn record(&mut self, now: DateTime<FixedOffset>, soc: f64) -> Option<(i32, WarrantyCounters)> {
+        self.state.since.get_or_insert(now);
+
+        let finished = self.last_sample.and_then(|(last_time, _)| {
+            let year = last_time.year();
+            (now.year() != year).then(|| (year, self.state.years.get(&year).cloned().unwrap_or_default()))
+        });
+
+        if self.state.day != Some(now.date_naive()) {
+            self.state.day = Some(now.date_naive());
+            self.state.today = WarrantyCounters::default();
+        }
+
+        if let Some((last_time, last_soc)) = self.last_sample {
+            let hours = now.signed_duration_since(last_time).num_seconds() as f64 / 3600.0;
+            if hours > 0.0 && hours <= MAX_SAMPLE_GAP_HOURS {
+                let battery_kwh = (soc - last_soc) / 100.0 * self.capacity_kwh;
+                self.state.lifetime.add_sample(battery_kwh, hours, soc);
+                self.state.years.entry(now.year()).or_default().add_sample(battery_kwh, hours, soc);
+                self.state.today.add_sample(battery_kwh, hours, soc);
+            }
+        }
+        self.last_sample = Some((now, soc));
+
+        let save_due = finished.is_some()
+            || self
+                .last_save
+                .is_none_or(|last| now.signed_duration_since(last).num_seconds() >= persist::SAVE_INTERVAL_SECS);
+        if save_due {
+            match self.save() {
+                Ok(()) => self.last_save = Some(now),
+                Err(e) => warn!("Failed to save warranty counters: {}", e),
+            }
+        }
+
+        finished
+    }
+
+    pub fn save(&self) -> Result<()> {
+        persist::save_json(&self.path, &self.state)?;
+        debug!("Saved warranty counters to {}", self.path.display());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(time: &str) -> DateTime<FixedOffset> {
+        DateTime::parse_from_rfc3339(time).unwrap()
+    }
+
+    #[test]
+    fn counts_cycles_per_day_and_over_the_lifetime() {
+        let dir = std::env::temp_dir().join(format!("warranty_test_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut tracker = WarrantyTracker::load(dir.to_str().unwrap(), 10.0);
+
+        // 20% up and 60% down on a 10 kWh battery: 8 kWh throughput, 0.4 cycles
+        for (time, soc) in [
+            ("2025-12-01T23:00:00+01:00", 50.0),
+            ("2025-12-01T23:15:00+01:00", 70.0),
+            ("2025-12-01T23:30:00+01:00", 40.0),
+            ("2025-12-01T23:45:00+01:00", 10.0),
+        ] {
+            tracker.record(at(time), soc);
+        }
+        assert!((tracker.cycles_today() - 0.4).abs() < 1e-9);
+        assert!(tracker.daily_limit_reached(Some(0.4)));
+        assert!(!tracker.daily_limit_reached(Some(1.0)));
+        assert!(!tracker.daily_limit_reached(None));
+
+        // Past local midnight the day starts over, the lifetime count carries on
+        tracker.record(at("2025-12-02T00:00:00+01:00"), 30.0);
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(tracker.state().day, NaiveDate::from_ymd_opt(2025, 12, 2));
+        assert!((tracker.cycles_today() - 0.1).abs() < 1e-9);
+        assert!(!tracker.daily_limit_reached(Some(0.4)));
+        assert!((tracker.cycles_total() - 0.5).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,237 @@
+//! Hot water heater on a relay, run in the cheapest slots of each
+//! (tariff-local) day with a minimum daily runtime. The element only switches
+//! on when the main fuse has room for it next to the house and the battery
+//! (battery charging comes first; missed time is caught up later in the day),
+//! and the grid setpoint is raised by its power so the battery doesn't feed it.
+
+use chrono::{DateTime, FixedOffset, NaiveDate, Utc};
+use tracing::{info, warn};
+
+use crate::config::{MainFuseConfig, WaterHeaterConfig};
+use crate::fuse;
+use crate::mqtt::{BatteryState, MqttClient};
+use crate::prices::{PriceCache, PricePoint};
+use crate::stats::MAX_SAMPLE_GAP_HOURS;
+
+/// This cycle's heating decision
+#[derive(Debug, Clone, PartialEq)]
+pub struct WaterHeaterDecision {
+    pub on: bool,
+    /// Human-readable reason, e.g. "cheapest slot, 45/120 min today"
+    pub reason: String,
+}
+
+#[derive(Debug)]
+pub struct WaterHeaterScheduler {
+    config: WaterHeaterConfig,
+    /// Day the runtime is counted for
+    day: Option<NaiveDate>,
+    /// Time the relay was on today (minutes)
+    runtime_minutes: f64,
+    last_sample: Option<DateTime<Utc>>,
+    /// Relay state last written
+    written: Option<bool>,
+}
+
+impl WaterHeaterScheduler {
+    pub fn new(config: WaterHeaterConfig) -> Self {
+        Self {
+            config,
+            day: None,
+            runtime_minutes: 0.0,
+            last_sample: None,
+            written: None,
+        }
+    }
+
+    /// Current per phase the element draws (A)
+    fn current_a(&self, fuse: &MainFuseConfig) -> f64 {
+        self.config.power_w / fuse.voltage_v / self.config.phases.max(1) as f64
+    }
+
+    /// `slots` from the cheapest up
+    fn cheapest_first<'a>(slots: &[&'a PricePoint]) -> Vec<&'a PricePoint> {
+        let mut slots = slots.to_vec();
+        slots.sort_by(|a, b| a.total.total_cmp(&b.total));
+        slots
+    }
+
+    /// Whether the slot starting at `current` is among the `count` cheapest of `slots`
+    fn among_cheapest(slots: &[&PricePoint], current: DateTime<FixedOffset>, count: usize) -> bool {
+        Self::cheapest_first(slots).iter().take(count).any(|p| p.starts_at == current)
+    }
+
+    /// Whether the slot starting at `current` is among the cheapest of `slots`
+    /// that together last `minutes`
+    fn among_cheapest_for(slots: &[&PricePoint], current: DateTime<FixedOffset>, minutes: f64) -> bool {
+        let mut covered_minutes = 0.0;
+        for slot in Self::cheapest_first(slots) {
+            if covered_minutes >= minutes {
+                return false;
+            }
+            if slot.starts_at == current {
+                return true;
+            }
+            covered_minutes += slot.duration().num_seconds() as f64 / 60.0;
+        }
+        false
+    }
+
+    /// Count the time the relay was on since the last cycle
+    fn count_runtime(&mut self, today: NaiveDate, now: DateTime<Utc>) {
+        if let (Some(true), Some(last)) = (self.written, self.last_sample) {
+            let hours = (now - last).num_seconds() as f64 / 3600.0;
+            if hours > 0.0 && hours <= MAX_SAMPLE_GAP_HOURS {
+                self.runtime_minutes += hours * 60.0;
+            }
+        }
+        self.last_sample = Some(now);
+        if self.day != Some(today) {
+            if let Some(day) = self.day {
+                info!("Water heater ran {:.0} min on {}", self.runtime_minutes, day);
+            }
+            self.day = Some(today);
+            self.runtime_minutes = 0.0;
+        }
+    }
+
+    /// Decide whether to heat in this cycle from today's prices and the
+    /// headroom on the main fuse
+    pub fn decide(
+        &mut self,
+        prices: &PriceCache,
+        fuse: Option<&MainFuseConfig>,
+        state: &BatteryState,
+        now: DateTime<Utc>,
+    ) -> WaterHeaterDecision {
+        let Some(current) = prices.price_at(now) else {
+            return WaterHeaterDecision {
+                on: false,
+                reason: "no price for the current slot".to_string(),
+            };
+        };
+        let today = current.starts_at.date_naive();
+        self.count_runtime(today, now);
+
+        let day_slots: Vec<&PricePoint> = prices
+            .all_prices()
+            .filter(|p| p.starts_at.date_naive() == today)
+            .collect();
+        let remaining: Vec<&PricePoint> = day_slots.iter().copied().filter(|p| p.ends_at > now).collect();
+        let short_minutes = self.config.min_runtime_minutes - self.runtime_minutes;
+        let progress = format!("{:.0}/{:.0} min today", self.runtime_minutes, self.config.min_runtime_minutes);
+
+        let (on, reason) = if Self::among_cheapest(&day_slots, current.starts_at, self.config.slots_per_day) {
+            (true, format!("cheapest slot, {}", progress))
+        } else if short_minutes > 0.0 && Self::among_cheapest_for(&remaining, current.starts_at, short_minutes) {
+            (true, format!("catching up on the minimum runtime, {}", progress))
+        } else {
+            (false, format!("waiting for cheaper slots, {}", progress))
+        };
+
+        // Leave the fuse to the house and the battery
+        let Some(fuse) = fuse.filter(|_| on) else {
+            return WaterHeaterDecision { on, reason };
+        };
+        let current_a = self.current_a(fuse);
+        let own_current_a = if self.written == Some(true) { current_a } else { 0.0 };
+        match fuse::headroom_a(fuse, state, own_current_a, now) {
+            Some(headroom_a) if headroom_a < current_a => WaterHeaterDecision {
+                on: false,
+                reason: format!("{}, deferred for the main fuse ({:.1}A left)", reason, headroom_a),
+            },
+            Some(_) => WaterHeaterDecision { on, reason },
+            None => WaterHeaterDecision {
+                on: false,
+                reason: format!("{}, no grid reading for the main fuse", reason),
+            },
+        }
+    }
+
+    /// Switch the relay when the decision changed; returns the power the
+    /// element is expected to draw (W) for the grid setpoint
+    pub async fn apply(&mut self, mqtt_client: &MqttClient, decision: &WaterHeaterDecision) -> f64 {
+        if self.written != Some(decision.on) {
+            let payload = if decision.on { &self.config.on_payload } else { &self.config.off_payload };
+            match mqtt_client.publish_payload(&self.config.topic, payload).await {
+                Ok(()) => {
+                    info!("Water heater {}: {}", if decision.on { "on" } else { "off" }, decision.reason);
+                    self.written = Some(decision.on);
+                }
+                Err(e) => {
+                    warn!("Failed to switch water heater: {}", e);
+                    self.written = None;
+                }
+            }
+        }
+        if self.written == Some(true) {
+            self.config.power_w
+        } else {
+            0.0
+        }
+    }
+
+    /// Switch the element off while control is given up, so it doesn't heat
+    /// on through expensive hours. A failed switch is retried on the next
+    /// call; once off, the relay is switched again when control returns.
+    pub async fn release(&mut self, mqtt_client: &MqttClient) {
+        if self.written == Some(true) {
+            match mqtt_client.publish_payload(&self.config.topic, &self.config.off_payload).await {
+                Ok(()) => info!("Water heater off: control handed back"),
+                Err(e) => {
+                    warn!("Failed to switch water heater off: {}", e);
+                    return;
+                }
+            }
+        }
+        self.written = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prices::test_support::quarter_hours;
+    use chrono::Duration;
+
+    #[test]
+    fn heats_in_the_cheapest_slots_and_catches_up_on_the_minimum_runtime() {
+        let config: WaterHeaterConfig =
+            serde_yaml::from_str("topic: boiler/set\npower_w: 2000\nslots_per_day: 1\nmin_runtime_minutes: 30\n")
+                .unwrap();
+        let mut heater = WaterHeaterScheduler::new(config);
+        let start = DateTime::parse_from_rfc3339("2025-12-01T12:00:00+01:00").unwrap();
+        let prices = quarter_hours(&[0.10, 0.30, 0.20, 0.25], start);
+        let now = start.with_timezone(&Utc);
+        let state = BatteryState::default();
+
+        // The cheapest slot of the day runs
+        assert!(heater.decide(&prices, None, &state, now).on);
+        heater.written = Some(true);
+        // 15 of 30 minutes done: one more slot, the cheapest one left
+        let later = now + Duration::minutes(15);
+        assert!(!heater.decide(&prices, None, &state, later).on);
+        heater.written = Some(false);
+        assert!(heater.decide(&prices, None, &state, later + Duration::minutes(15)).on);
+    }
+
+    #[test]
+    fn catches_up_in_hourly_slots_by_their_length() {
+        let start = DateTime::parse_from_rfc3339("2025-12-01T12:00:00+01:00").unwrap();
+        let hourly: Vec<PricePoint> = [0.30, 0.20, 0.25]
+            .iter()
+            .enumerate()
+            .map(|(i, total)| PricePoint {
+                starts_at: start + Duration::hours(i as i64),
+                ends_at: start + Duration::hours(i as i64 + 1),
+                ..quarter_hours(&[*total], start).slots()[0].clone()
+            })
+            .collect();
+        let slots: Vec<&PricePoint> = hourly.iter().collect();
+
+        // An hour short is made up by the cheapest hourly slot alone, not four of them
+        assert!(WaterHeaterScheduler::among_cheapest_for(&slots, slots[1].starts_at, 60.0));
+        assert!(!WaterHeaterScheduler::among_cheapest_for(&slots, slots[2].starts_at, 60.0));
+        assert!(WaterHeaterScheduler::among_cheapest_for(&slots, slots[2].starts_at, 61.0));
+    }
+}
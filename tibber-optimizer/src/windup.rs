@@ -0,0 +1,169 @@
+use tracing::info;
+
+use crate::config::AntiWindupConfig;
+
+/// Commanded setpoints below this magnitude (W) say nothing about the ESS limits
+const MIN_LIMITED_SETPOINT_W: f64 = 1000.0;
+
+/// Learned limit for one power direction
+#[derive(Debug, Default)]
+struct DirectionLimit {
+    shortfall_cycles: u32,
+    limit_w: Option<f64>,
+}
+
+impl DirectionLimit {
+    /// `commanded` and `measured` are magnitudes in this direction (W)
+    fn update(&mut self, config: &AntiWindupConfig, commanded: f64, measured: f64, max_w: f64, direction: &str) {
+        if commanded - measured > config.tolerance_w {
+            self.shortfall_cycles += 1;
+            if self.shortfall_cycles >= config.cycles {
+                let achievable = measured.max(MIN_LIMITED_SETPOINT_W);
+                if self.limit_w.is_none_or(|limit| (limit - achievable).abs() > config.tolerance_w) {
+                    info!(
+                        "ESS delivers only {:.0}W of the commanded {:.0}W {}, limiting setpoint",
+                        measured, commanded, direction
+                    );
+                }
+                self.limit_w = Some(achievable);
+                self.shortfall_cycles = 0;
+            }
+            return;
+        }
+
+        self.shortfall_cycles = 0;
+        // Delivered at the limit: probe upwards so a lifted limit is noticed
+        if let Some(limit) = self.limit_w {
+            if commanded >= limit - 1.0 {
+                let raised = limit * (1.0 + config.recovery_percent / 100.0);
+                if raised >= max_w {
+                    info!("ESS delivers the full {} power again, removing setpoint limit", direction);
+                    self.limit_w = None;
+                } else {
+                    self.limit_w = Some(raised);
+                }
+            }
+        }
+    }
+}
+
+/// Learns the grid power the ESS actually delivers when it persistently falls
+/// short of the commanded setpoint (e.g. charger or inverter limits, BMS
+/// current limits near full or empty), so the plan stops demanding unattainable power
+#[derive(Debug)]
+pub struct SetpointLimiter {
+    config: AntiWindupConfig,
+    max_charge_w: f64,
+    max_discharge_w: f64,
+    charge: DirectionLimit,
+    discharge: DirectionLimit,
+}
+
+impl SetpointLimiter {
+    pub fn new(config: AntiWindupConfig, max_charge_w: f64, max_discharge_w: f64) -> Self {
+        Self {
+            config,
+            max_charge_w,
+            max_discharge_w,
+            charge: DirectionLimit::default(),
+            discharge: DirectionLimit::default(),
+        }
+    }
+
+    /// Compare the last commanded setpoint with the measured grid power (both
+    /// positive = import)
+    pub fn update(&mut self, commanded_w: Option<f64>, grid_power_w: Option<f64>) {
+        if !self.config.enabled {
+            return;
+        }
+        let (Some(commanded), Some(measured)) = (commanded_w, grid_power_w) else {
+            return;
+        };
+
+        if commanded >= MIN_LIMITED_SETPOINT_W {
+            self.charge
+                .update(&self.config, commanded, measured.max(0.0), self.max_charge_w, "charging");
+        } else if commanded <= -MIN_LIMITED_SETPOINT_W {
+            self.discharge
+                .update(&self.config, -commanded, (-measured).max(0.0), self.max_discharge_w, "discharging");
+        }
+    }
+
+    /// Learned achievable grid import while charging (W), if limited
+    pub fn charge_limit_w(&self) -> Option<f64> {
+        self.charge.limit_w
+    }
+
+    /// Learned achievable grid export while discharging (W), if limited
+    pub fn discharge_limit_w(&self) -> Option<f64> {
+        self.discharge.limit_w
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Run `cycles` control cycles against an ESS that imports at most `cap_w`,
+    /// commanding the configured maximum lowered to the learned limit; returns
+    /// the limit after each cycle
+    fn run(limiter: &mut SetpointLimiter, cycles: usize, cap_w: f64) -> Vec<Option<f64>> {
+        (0..cycles)
+            .map(|_| {
+                let max_w = limiter.max_charge_w;
+                let commanded = limiter.charge_limit_w().map_or(max_w, |limit| limit.min(max_w));
+                limiter.update(Some(commanded), Some(commanded.min(cap_w)));
+                limiter.charge_limit_w()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn saturates_at_the_delivered_power_and_recovers_without_overshoot() {
+        let config = AntiWindupConfig::default();
+        let mut limiter = SetpointLimiter::new(config.clone(), 10000.0, 10000.0);
+
+        // A persistent shortfall limits the setpoint to what arrives, once
+        let limits = run(&mut limiter, config.cycles as usize, 6000.0);
+        assert!(limits[..limits.len() - 1].iter().all(Option::is_none));
+        assert_eq!(limiter.charge_limit_w(), Some(6000.0));
+
+        // Probing upwards takes at most one step past the tolerance over the
+        // cap before the limit falls back to it
+        let overshoot = (6000.0 + config.tolerance_w) * (1.0 + config.recovery_percent / 100.0);
+        let limits = run(&mut limiter, 50, 6000.0);
+        assert!(limits.iter().flatten().all(|limit| *limit <= overshoot));
+        assert!(limits.contains(&Some(6000.0)));
+        assert_eq!(limiter.discharge_limit_w(), None);
+
+        // Once the cap is lifted the limit rises step by step and is removed
+        // instead of passing the configured maximum
+        let limits = run(&mut limiter, 100, f64::MAX);
+        let rising: Vec<f64> = limits.iter().map_while(|limit| *limit).collect();
+        let step = 1.0 + config.recovery_percent / 100.0;
+        assert!(rising.windows(2).all(|pair| pair[1] > pair[0] && pair[1] <= pair[0] * step + 1e-6));
+        assert!(rising.iter().all(|limit| *limit < 10000.0));
+        assert!(limits.iter().skip(rising.len()).all(Option::is_none));
+        assert_eq!(limiter.charge_limit_w(), None);
+    }
+
+    #[test]
+    fn small_setpoints_and_a_disabled_limiter_learn_nothing() {
+        let mut limiter = SetpointLimiter::new(AntiWindupConfig::default(), 10000.0, 10000.0);
+        for _ in 0..20 {
+            limiter.update(Some(800.0), Some(0.0));
+            limiter.update(Some(-5000.0), None);
+        }
+        assert_eq!((limiter.charge_limit_w(), limiter.discharge_limit_w()), (None, None));
+
+        let config = AntiWindupConfig {
+            enabled: false,
+            ..Default::default()
+        };
+        let mut limiter = SetpointLimiter::new(config, 10000.0, 10000.0);
+        for _ in 0..20 {
+            limiter.update(Some(-10000.0), Some(-3000.0));
+        }
+        assert_eq!(limiter.discharge_limit_w(), None);
+    }
+}
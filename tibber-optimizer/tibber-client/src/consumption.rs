@@ -0,0 +1,93 @@
+use chrono::{DateTime, FixedOffset};
+use serde::{Deserialize, Serialize};
+
+use crate::Error;
+
+/// Length of a consumption period
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConsumptionResolution {
+    Hourly,
+    Daily,
+    Weekly,
+    Monthly,
+    Annual,
+}
+
+impl ConsumptionResolution {
+    fn as_str(self) -> &'static str {
+        match self {
+            ConsumptionResolution::Hourly => "HOURLY",
+            ConsumptionResolution::Daily => "DAILY",
+            ConsumptionResolution::Weekly => "WEEKLY",
+            ConsumptionResolution::Monthly => "MONTHLY",
+            ConsumptionResolution::Annual => "ANNUAL",
+        }
+    }
+}
+
+/// Grid import and its cost over one period; the values are absent for
+/// periods the meter hasn't reported yet
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Consumption {
+    pub from: DateTime<FixedOffset>,
+    pub to: DateTime<FixedOffset>,
+    /// Energy imported (kWh)
+    pub consumption: Option<f64>,
+    /// What the energy cost, including taxes and fees
+    pub cost: Option<f64>,
+    /// Average price per kWh, excluding VAT
+    pub unit_price: Option<f64>,
+    /// VAT part of the price per kWh
+    #[serde(rename = "unitPriceVAT")]
+    pub unit_price_vat: Option<f64>,
+    pub currency: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ConsumptionHome {
+    consumption: Option<ConsumptionConnection>,
+}
+
+#[derive(Deserialize)]
+struct ConsumptionConnection {
+    nodes: Vec<Consumption>,
+}
+
+pub(crate) fn query(resolution: ConsumptionResolution, last: u32, home_id: Option<&str>) -> String {
+    format!(
+        "{{ viewer {{ {} {{ consumption(resolution: {}, last: {}) {{ nodes {{ from to consumption cost unitPrice unitPriceVAT currency }} }} }} }} }}",
+        crate::home_selection(home_id),
+        resolution.as_str(),
+        last
+    )
+}
+
+/// Decode the response to a [`Client::consumption`](crate::Client::consumption) query
+pub(crate) fn from_response(body: &[u8]) -> Result<Vec<Consumption>, Error> {
+    let viewer: crate::HomesViewer<ConsumptionHome> = crate::decode_viewer(body)?;
+    let home = viewer.into_home()?;
+    Ok(home.consumption.map(|connection| connection.nodes).unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_periods_the_meter_has_not_reported() {
+        let body = br#"{"data": {"viewer": {"homes": [{"consumption": {"nodes": [
+            {"from": "2025-12-01T00:00:00+01:00", "to": "2025-12-01T01:00:00+01:00", "consumption": 0.42,
+                "cost": 0.11, "unitPrice": 0.21, "unitPriceVAT": 0.05, "currency": "EUR"},
+            {"from": "2025-12-01T01:00:00+01:00", "to": "2025-12-01T02:00:00+01:00", "consumption": null,
+                "cost": null, "unitPrice": null, "unitPriceVAT": null, "currency": "EUR"}
+        ]}}]}}}"#;
+        let periods = from_response(body).unwrap();
+        assert_eq!(periods.len(), 2);
+        assert_eq!(periods[0].unit_price_vat, Some(0.05));
+        assert_eq!(periods[1].consumption, None);
+
+        let text = query(ConsumptionResolution::Hourly, 24, None);
+        assert!(text.contains("consumption(resolution: HOURLY, last: 24)"), "{}", text);
+    }
+}
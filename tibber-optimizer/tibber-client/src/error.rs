@@ -0,0 +1,43 @@
+/// Error returned by a [`Transport`](crate::Transport)
+pub type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// The request could not be sent or its response not read
+    #[error("Tibber request failed: {0}")]
+    Transport(#[source] BoxError),
+    /// The API answered with a non-2xx status
+    #[error("Tibber API error: {status} - {body}")]
+    Status { status: u16, body: String },
+    /// The API rejected the token (a GraphQL `UNAUTHENTICATED` error, also
+    /// when it comes with a non-2xx status)
+    #[error("Tibber token rejected: {}", .0.join("; "))]
+    Unauthenticated(Vec<String>),
+    /// The API answered with GraphQL errors and no data
+    #[error("Tibber GraphQL error: {}", .0.join("; "))]
+    GraphQl(Vec<String>),
+    /// The response had neither data nor errors, e.g. a null viewer
+    #[error("Tibber response has no data")]
+    MissingData,
+    #[error("Invalid Tibber response: {0}")]
+    Decode(#[from] serde_json::Error),
+    #[error("No homes found in Tibber account")]
+    NoHomes,
+    #[error("No active subscription found")]
+    NoSubscription,
+    #[cfg(feature = "live")]
+    #[error("Tibber websocket error: {0}")]
+    WebSocket(#[source] Box<tokio_tungstenite::tungstenite::Error>),
+    /// The live subscription was rejected or failed server-side
+    #[cfg(feature = "live")]
+    #[error("Tibber subscription error: {0}")]
+    Subscription(String),
+}
+
+// Boxed, as the websocket error would make every `Result` of the crate large
+#[cfg(feature = "live")]
+impl From<tokio_tungstenite::tungstenite::Error> for Error {
+    fn from(e: tokio_tungstenite::tungstenite::Error) -> Self {
+        Error::WebSocket(Box::new(e))
+    }
+}
@@ -0,0 +1,256 @@
+//! Typed client for the [Tibber](https://developer.tibber.com/) GraphQL API:
+//! electricity prices, historical consumption and, with the `live` feature,
+//! real-time readings from a Tibber Pulse or Watty.
+//!
+//! Requests go through a [`Transport`], so the client works with whatever
+//! HTTP stack the application already has. The `reqwest` feature implements
+//! it for `reqwest::Client`:
+//!
+//! ```no_run
+//! # #[cfg(feature = "reqwest")]
+//! # async fn example() -> Result<(), tibber_client::Error> {
+//! use tibber_client::{Client, PriceResolution};
+//!
+//! let client = Client::new(reqwest::Client::new(), "<token>");
+//! let prices = client.price_info(PriceResolution::QuarterHourly).await?;
+//! for price in prices.today.iter().chain(&prices.tomorrow) {
+//!     println!("{} {:.4}", price.starts_at, price.total);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Queries cover the first home of the account, as most accounts have one;
+//! [`Client::with_home`] selects another one of the homes
+//! [`Client::viewer`] lists.
+
+use std::future::Future;
+use std::pin::Pin;
+
+use serde::de::{DeserializeOwned, IgnoredAny};
+use serde::Deserialize;
+
+mod consumption;
+mod error;
+#[cfg(feature = "live")]
+pub mod live;
+mod prices;
+mod viewer;
+
+pub use consumption::{Consumption, ConsumptionResolution};
+pub use error::{BoxError, Error};
+pub use prices::{home_price_info_query, price_info_query, HomePrices, Price, PriceInfo, PriceLevel, PriceResolution};
+pub use viewer::{Address, Home, HomeFeatures, Viewer};
+
+/// The public Tibber API endpoint
+pub const DEFAULT_API_URL: &str = "https://api.tibber.com/v1-beta/gql";
+
+/// A fully buffered HTTP response
+#[derive(Debug, Clone)]
+pub struct HttpResponse {
+    pub status: u16,
+    pub body: Vec<u8>,
+}
+
+pub type TransportFuture<'a> = Pin<Box<dyn Future<Output = Result<HttpResponse, BoxError>> + Send + 'a>>;
+
+/// Sends the GraphQL requests
+pub trait Transport: Send + Sync {
+    /// POST the JSON `body` to `url` with the given extra headers
+    fn post<'a>(&'a self, url: &'a str, headers: &'a [(&'a str, &'a str)], body: Vec<u8>) -> TransportFuture<'a>;
+}
+
+#[cfg(feature = "reqwest")]
+impl Transport for reqwest::Client {
+    fn post<'a>(&'a self, url: &'a str, headers: &'a [(&'a str, &'a str)], body: Vec<u8>) -> TransportFuture<'a> {
+        Box::pin(async move {
+            let mut request = reqwest::Client::post(self, url)
+                .header("Content-Type", "application/json")
+                .body(body);
+            for (name, value) in headers {
+                request = request.header(*name, *value);
+            }
+            let response = request.send().await?;
+            let status = response.status().as_u16();
+            let body = response.bytes().await?.to_vec();
+            Ok(HttpResponse { status, body })
+        })
+    }
+}
+
+/// Client for one account's API token
+pub struct Client<T> {
+    transport: T,
+    token: String,
+    api_url: String,
+    home_id: Option<String>,
+}
+
+impl<T: Transport> Client<T> {
+    pub fn new(transport: T, token: impl Into<String>) -> Self {
+        Self {
+            transport,
+            token: token.into(),
+            api_url: DEFAULT_API_URL.to_string(),
+            home_id: None,
+        }
+    }
+
+    /// Query the home with this id instead of the first one
+    pub fn with_home(mut self, home_id: impl Into<String>) -> Self {
+        self.home_id = Some(home_id.into());
+        self
+    }
+
+    /// Use another endpoint, e.g. a local TLS-terminating proxy
+    pub fn with_api_url(mut self, api_url: impl Into<String>) -> Self {
+        self.api_url = api_url.into();
+        self
+    }
+
+    pub fn token(&self) -> &str {
+        &self.token
+    }
+
+    /// Run a GraphQL query and return the raw body of a successful response,
+    /// for callers that keep it (e.g. to record it) before decoding it with
+    /// [`PriceInfo::from_response`] and friends
+    pub async fn query(&self, query: &str) -> Result<Vec<u8>, Error> {
+        let auth = format!("Bearer {}", self.token);
+        let body = serde_json::to_vec(&serde_json::json!({ "query": query }))?;
+        let response = self
+            .transport
+            .post(&self.api_url, &[("Authorization", auth.as_str())], body)
+            .await
+            .map_err(Error::Transport)?;
+        if !(200..300).contains(&response.status) {
+            return Err(status_error(response.status, &response.body));
+        }
+        Ok(response.body)
+    }
+
+    /// The account holder, their homes and the live subscription endpoint
+    pub async fn viewer(&self) -> Result<Viewer, Error> {
+        let body = self.query(viewer::QUERY).await?;
+        Viewer::from_response(&body)
+    }
+
+    /// Current, today's and (once published) tomorrow's prices
+    pub async fn price_info(&self, resolution: PriceResolution) -> Result<PriceInfo, Error> {
+        let body = self.query(&home_price_info_query(resolution, self.home_id.as_deref())).await?;
+        PriceInfo::from_response(&body)
+    }
+
+    /// Current, today's and tomorrow's prices with the address of the home
+    pub async fn home_prices(&self, resolution: PriceResolution) -> Result<HomePrices, Error> {
+        let body = self.query(&home_price_info_query(resolution, self.home_id.as_deref())).await?;
+        HomePrices::from_response(&body)
+    }
+
+    /// The last `last` consumption periods at `resolution`, oldest first
+    pub async fn consumption(&self, resolution: ConsumptionResolution, last: u32) -> Result<Vec<Consumption>, Error> {
+        let body = self.query(&consumption::query(resolution, last, self.home_id.as_deref())).await?;
+        consumption::from_response(&body)
+    }
+}
+
+#[derive(Deserialize)]
+struct Response<V> {
+    data: Option<Data<V>>,
+    #[serde(default)]
+    errors: Vec<GraphQlError>,
+}
+
+#[derive(Deserialize)]
+struct Data<V> {
+    viewer: Option<V>,
+}
+
+#[derive(Deserialize)]
+struct GraphQlError {
+    message: String,
+    #[serde(default)]
+    extensions: Option<serde_json::Value>,
+}
+
+impl GraphQlError {
+    fn unauthenticated(&self) -> bool {
+        self.extensions.as_ref().and_then(|extensions| extensions["code"].as_str()) == Some("UNAUTHENTICATED")
+    }
+}
+
+/// The error for GraphQL `errors`: a rejected token, or any other failure
+fn graphql_error(errors: Vec<GraphQlError>) -> Error {
+    let unauthenticated = errors.iter().any(GraphQlError::unauthenticated);
+    let messages = errors.into_iter().map(|e| e.message).collect();
+    if unauthenticated {
+        Error::Unauthenticated(messages)
+    } else {
+        Error::GraphQl(messages)
+    }
+}
+
+/// The error for a non-2xx response; the API answers a rejected token with
+/// a 400 carrying an `UNAUTHENTICATED` GraphQL error
+fn status_error(status: u16, body: &[u8]) -> Error {
+    match serde_json::from_slice::<Response<IgnoredAny>>(body) {
+        Ok(response) if response.errors.iter().any(GraphQlError::unauthenticated) => graphql_error(response.errors),
+        _ => Error::Status {
+            status,
+            body: String::from_utf8_lossy(body).into_owned(),
+        },
+    }
+}
+
+/// The `viewer` field holding the queried home: `home(id: ...)` for a
+/// selected home, else the list of all homes
+fn home_selection(home_id: Option<&str>) -> String {
+    match home_id {
+        // A JSON string is a valid GraphQL string literal
+        Some(id) => format!("home(id: {})", serde_json::Value::from(id)),
+        None => "homes".to_string(),
+    }
+}
+
+/// A viewer queried through [`home_selection`]
+#[derive(Deserialize)]
+struct HomesViewer<H> {
+    #[serde(default = "Vec::new")]
+    homes: Vec<H>,
+    #[serde(default = "Option::default")]
+    home: Option<H>,
+}
+
+impl<H> HomesViewer<H> {
+    /// The selected home, else the first one
+    fn into_home(self) -> Result<H, Error> {
+        self.home.or_else(|| self.homes.into_iter().next()).ok_or(Error::NoHomes)
+    }
+}
+
+/// The `viewer` of a GraphQL response, or its errors
+fn decode_viewer<V: DeserializeOwned>(body: &[u8]) -> Result<V, Error> {
+    // Deserialize straight from the buffered body, avoiding an intermediate String
+    let response: Response<V> = serde_json::from_slice(body)?;
+    match response.data.and_then(|data| data.viewer) {
+        Some(viewer) => Ok(viewer),
+        None if !response.errors.is_empty() => Err(graphql_error(response.errors)),
+        None => Err(Error::MissingData),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recognizes_a_rejected_token() {
+        let body = br#"{"errors": [{"message": "invalid token", "extensions": {"code": "UNAUTHENTICATED"}}]}"#;
+        assert!(matches!(status_error(400, body), Error::Unauthenticated(errors) if errors == ["invalid token"]));
+        assert!(matches!(decode_viewer::<IgnoredAny>(body), Err(Error::Unauthenticated(_))));
+
+        let body = br#"{"errors": [{"message": "syntax error"}]}"#;
+        assert!(matches!(status_error(400, body), Error::Status { status: 400, .. }));
+        assert!(matches!(status_error(502, b"Bad Gateway"), Error::Status { status: 502, .. }));
+    }
+}
@@ -0,0 +1,95 @@
+//! Real-time meter readings from a Tibber Pulse or Watty, streamed over the
+//! GraphQL `liveMeasurement` websocket subscription (`graphql-transport-ws`)
+
+use chrono::{DateTime, FixedOffset};
+use futures_util::{SinkExt, StreamExt};
+use serde::Deserialize;
+use tokio::net::TcpStream;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::http::HeaderValue;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+
+use crate::Error;
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LiveMeasurement {
+    pub timestamp: DateTime<FixedOffset>,
+    /// Grid import (W)
+    pub power: f64,
+    /// Grid export (W), on meters that report it
+    pub power_production: Option<f64>,
+    /// Grid import since midnight (kWh)
+    pub accumulated_consumption: f64,
+}
+
+impl LiveMeasurement {
+    /// Net grid power (positive = import)
+    pub fn grid_power_w(&self) -> f64 {
+        self.power - self.power_production.unwrap_or(0.0)
+    }
+}
+
+/// One subscription to a home's readings. It doesn't reconnect: open a new
+/// one when [`LiveStream::next_measurement`] ends or fails.
+pub struct LiveStream {
+    socket: WebSocketStream<MaybeTlsStream<TcpStream>>,
+    home_id: String,
+}
+
+impl LiveStream {
+    /// Connect to `url` ([`Viewer::websocket_subscription_url`](crate::Viewer))
+    /// and subscribe to the readings of `home_id`. Tibber requires a
+    /// `user_agent` naming the application and its version.
+    pub async fn connect(url: &str, token: &str, home_id: &str, user_agent: &str) -> Result<Self, Error> {
+        let mut request = url.into_client_request()?;
+        let headers = request.headers_mut();
+        headers.insert("Sec-WebSocket-Protocol", HeaderValue::from_static("graphql-transport-ws"));
+        let user_agent = HeaderValue::from_str(user_agent)
+            .map_err(|e| tokio_tungstenite::tungstenite::Error::HttpFormat(e.into()))?;
+        headers.insert("User-Agent", user_agent);
+        let (mut socket, _) = tokio_tungstenite::connect_async(request).await?;
+
+        let init = serde_json::json!({ "type": "connection_init", "payload": { "token": token } });
+        socket.send(Message::Text(init.to_string())).await?;
+        Ok(Self {
+            socket,
+            home_id: home_id.to_string(),
+        })
+    }
+
+    /// The next reading; `None` once the server ends the subscription
+    pub async fn next_measurement(&mut self) -> Result<Option<LiveMeasurement>, Error> {
+        loop {
+            let text = match self.socket.next().await {
+                Some(message) => match message? {
+                    Message::Text(text) => text,
+                    Message::Close(_) => return Ok(None),
+                    _ => continue,
+                },
+                None => return Ok(None),
+            };
+
+            let message: serde_json::Value = serde_json::from_str(&text)?;
+            match message["type"].as_str() {
+                Some("connection_ack") => {
+                    let query = format!(
+                        "subscription {{ liveMeasurement(homeId: \"{}\") {{ timestamp power powerProduction accumulatedConsumption }} }}",
+                        self.home_id
+                    );
+                    let subscribe = serde_json::json!({ "id": "1", "type": "subscribe", "payload": { "query": query } });
+                    self.socket.send(Message::Text(subscribe.to_string())).await?;
+                }
+                Some("next") => {
+                    let measurement = serde_json::from_value(message["payload"]["data"]["liveMeasurement"].clone())?;
+                    return Ok(Some(measurement));
+                }
+                Some("ping") => self.socket.send(Message::Text(r#"{"type":"pong"}"#.to_string())).await?,
+                Some("error") => return Err(Error::Subscription(message["payload"].to_string())),
+                Some("complete") => return Ok(None),
+                _ => {}
+            }
+        }
+    }
+}
@@ -0,0 +1,183 @@
+use chrono::{DateTime, FixedOffset};
+use serde::{Deserialize, Serialize};
+
+use crate::{Address, Error};
+
+/// Granularity of the price slots
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PriceResolution {
+    Hourly,
+    QuarterHourly,
+}
+
+impl PriceResolution {
+    fn as_str(self) -> &'static str {
+        match self {
+            PriceResolution::Hourly => "HOURLY",
+            PriceResolution::QuarterHourly => "QUARTER_HOURLY",
+        }
+    }
+}
+
+/// Tibber's own classification of a price against the recent average
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum PriceLevel {
+    VeryCheap,
+    Cheap,
+    Normal,
+    Expensive,
+    VeryExpensive,
+    /// A level this version doesn't know
+    #[serde(other)]
+    Other,
+}
+
+impl PriceLevel {
+    /// The API's name for the level, e.g. `VERY_CHEAP`
+    pub fn as_str(self) -> &'static str {
+        match self {
+            PriceLevel::VeryCheap => "VERY_CHEAP",
+            PriceLevel::Cheap => "CHEAP",
+            PriceLevel::Normal => "NORMAL",
+            PriceLevel::Expensive => "EXPENSIVE",
+            PriceLevel::VeryExpensive => "VERY_EXPENSIVE",
+            PriceLevel::Other => "OTHER",
+        }
+    }
+}
+
+/// The price of one slot
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Price {
+    /// Energy plus taxes and fees, per kWh
+    pub total: f64,
+    /// Spot price including the supplier markup, per kWh
+    pub energy: f64,
+    /// Taxes and fees, per kWh
+    pub tax: f64,
+    pub starts_at: DateTime<FixedOffset>,
+    #[serde(default)]
+    pub level: Option<PriceLevel>,
+    #[serde(default)]
+    pub currency: Option<String>,
+}
+
+/// Prices of the current subscription
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct PriceInfo {
+    pub current: Option<Price>,
+    /// Today's slots, sorted by start time
+    pub today: Vec<Price>,
+    /// Tomorrow's slots, sorted by start time; empty until published (around 13:00 CET)
+    pub tomorrow: Vec<Price>,
+}
+
+impl PriceInfo {
+    /// Decode the response to a [`Client::price_info`](crate::Client::price_info) query
+    pub fn from_response(body: &[u8]) -> Result<Self, Error> {
+        HomePrices::from_response(body).map(|prices| prices.price_info)
+    }
+}
+
+/// Prices together with the address of the home they are for, to tell which
+/// market they come from
+#[derive(Debug, Clone, PartialEq)]
+pub struct HomePrices {
+    /// Absent in responses to queries that didn't ask for it
+    pub address: Option<Address>,
+    pub price_info: PriceInfo,
+}
+
+impl HomePrices {
+    /// Decode the response to a [`Client::home_prices`](crate::Client::home_prices)
+    /// or [`Client::price_info`](crate::Client::price_info) query
+    pub fn from_response(body: &[u8]) -> Result<Self, Error> {
+        let viewer: crate::HomesViewer<PriceHome> = crate::decode_viewer(body)?;
+        let home = viewer.into_home()?;
+        let mut price_info = home.current_subscription.ok_or(Error::NoSubscription)?.price_info;
+        price_info.today.sort_by_key(|p| p.starts_at);
+        price_info.tomorrow.sort_by_key(|p| p.starts_at);
+        Ok(HomePrices {
+            address: home.address,
+            price_info,
+        })
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PriceHome {
+    #[serde(default)]
+    address: Option<Address>,
+    current_subscription: Option<Subscription>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Subscription {
+    price_info: PriceInfo,
+}
+
+/// The query [`Client::price_info`](crate::Client::price_info) runs, for use with [`Client::query`](crate::Client::query)
+pub fn price_info_query(resolution: PriceResolution) -> String {
+    home_price_info_query(resolution, None)
+}
+
+/// [`price_info_query`] for the home `home_id`, or the first home
+pub fn home_price_info_query(resolution: PriceResolution, home_id: Option<&str>) -> String {
+    const FIELDS: &str = "total energy tax startsAt level currency";
+    format!(
+        "{{ viewer {{ {} {{ address {{ postalCode city country }} currentSubscription {{ priceInfo(resolution: {}) {{ current {{ {f} }} today {{ {f} }} tomorrow {{ {f} }} }} }} }} }} }}",
+        crate::home_selection(home_id),
+        resolution.as_str(),
+        f = FIELDS
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_and_sorts_prices() {
+        let body = br#"{"data": {"viewer": {"homes": [{"currentSubscription": {"priceInfo": {
+            "current": {"total": 0.25, "energy": 0.1, "tax": 0.15, "startsAt": "2024-01-01T00:00:00+01:00", "level": "NORMAL", "currency": "EUR"},
+            "today": [
+                {"total": 0.30, "energy": 0.15, "tax": 0.15, "startsAt": "2024-01-01T00:15:00+01:00", "level": "NEW_LEVEL", "currency": "EUR"},
+                {"total": 0.25, "energy": 0.1, "tax": 0.15, "startsAt": "2024-01-01T00:00:00+01:00", "level": "VERY_CHEAP", "currency": "EUR"}
+            ],
+            "tomorrow": []
+        }}}]}}}"#;
+        let price_info = PriceInfo::from_response(body).unwrap();
+        assert_eq!(HomePrices::from_response(body).unwrap().address, None);
+        assert_eq!(price_info.current.unwrap().level, Some(PriceLevel::Normal));
+        assert_eq!(price_info.today[0].level, Some(PriceLevel::VeryCheap));
+        assert_eq!(price_info.today[1].level, Some(PriceLevel::Other));
+        assert!(price_info.tomorrow.is_empty());
+    }
+
+    #[test]
+    fn reports_graphql_errors_and_missing_subscription() {
+        let body = br#"{"data": {"viewer": null}, "errors": [{"message": "invalid token"}]}"#;
+        assert!(matches!(PriceInfo::from_response(body), Err(Error::GraphQl(errors)) if errors == ["invalid token"]));
+
+        let body = br#"{"data": {"viewer": {"homes": [{"currentSubscription": null}]}}}"#;
+        assert!(matches!(PriceInfo::from_response(body), Err(Error::NoSubscription)));
+
+        let body = br#"{"data": {"viewer": {"home": null}}}"#;
+        assert!(matches!(PriceInfo::from_response(body), Err(Error::NoHomes)));
+    }
+
+    #[test]
+    fn queries_a_selected_home() {
+        let query = home_price_info_query(PriceResolution::Hourly, Some("96a14971-525a-4420-aae9-e5aedaa129ff"));
+        assert!(query.starts_with(r#"{ viewer { home(id: "96a14971-525a-4420-aae9-e5aedaa129ff") { address"#));
+
+        let body = br#"{"data": {"viewer": {"home": {"address": {"postalCode": "1234 AB", "city": "Urk", "country": "NL"},
+            "currentSubscription": {"priceInfo": {"current": null, "today": [], "tomorrow": []}}}}}}"#;
+        let prices = HomePrices::from_response(body).unwrap();
+        assert_eq!(prices.address.unwrap().city.as_deref(), Some("Urk"));
+    }
+}
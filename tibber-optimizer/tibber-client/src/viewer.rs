@@ -0,0 +1,102 @@
+use std::fmt;
+
+use serde::Deserialize;
+
+use crate::Error;
+
+pub(crate) const QUERY: &str = "{ viewer { name websocketSubscriptionUrl homes { id address { postalCode city country } features { realTimeConsumptionEnabled } } } }";
+
+/// The account behind the API token
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Viewer {
+    pub name: Option<String>,
+    /// Endpoint of the live measurement subscription
+    pub websocket_subscription_url: Option<String>,
+    pub homes: Vec<Home>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Home {
+    pub id: String,
+    #[serde(default)]
+    pub address: Option<Address>,
+    #[serde(default)]
+    pub features: Option<HomeFeatures>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Address {
+    pub postal_code: Option<String>,
+    pub city: Option<String>,
+    /// ISO 3166-1 alpha-2 code, e.g. `NL`
+    pub country: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HomeFeatures {
+    /// A Pulse or Watty streams this home's meter readings
+    #[serde(default)]
+    pub real_time_consumption_enabled: Option<bool>,
+}
+
+/// `postal code city, country`, leaving out what is unknown
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let place = [self.postal_code.as_deref(), self.city.as_deref()]
+            .into_iter()
+            .flatten()
+            .collect::<Vec<_>>()
+            .join(" ");
+        match (place.is_empty(), &self.country) {
+            (false, Some(country)) => write!(f, "{}, {}", place, country),
+            (true, Some(country)) => write!(f, "{}", country),
+            (_, None) => write!(f, "{}", place),
+        }
+    }
+}
+
+impl Home {
+    pub fn has_real_time_consumption(&self) -> bool {
+        self.features
+            .as_ref()
+            .and_then(|features| features.real_time_consumption_enabled)
+            .unwrap_or(false)
+    }
+}
+
+impl Viewer {
+    /// Decode the response to a [`Client::viewer`](crate::Client::viewer) query
+    pub fn from_response(body: &[u8]) -> Result<Self, Error> {
+        crate::decode_viewer(body)
+    }
+
+    pub fn home(&self, id: &str) -> Option<&Home> {
+        self.homes.iter().find(|home| home.id == id)
+    }
+
+    /// The first home with real-time consumption enabled
+    pub fn live_home(&self) -> Option<&Home> {
+        self.homes.iter().find(|home| home.has_real_time_consumption())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_the_home_with_a_pulse() {
+        let body = br#"{"data": {"viewer": {"name": "Jan", "websocketSubscriptionUrl": "wss://websocket-api.tibber.com/v1-beta/gql/subscriptions",
+            "homes": [
+                {"id": "a", "address": {"postalCode": null, "city": "Urk", "country": "NL"}, "features": {"realTimeConsumptionEnabled": false}},
+                {"id": "b", "address": null, "features": {"realTimeConsumptionEnabled": true}}
+            ]}}}"#;
+        let viewer = Viewer::from_response(body).unwrap();
+        assert_eq!(viewer.live_home().map(|home| home.id.as_str()), Some("b"));
+        assert_eq!(viewer.home("a").unwrap().address.as_ref().unwrap().to_string(), "Urk, NL");
+        assert!(viewer.home("c").is_none());
+    }
+}